@@ -0,0 +1,128 @@
+use std::io::Write;
+
+use chronicle_proxy::{
+    format::{ChatMessage, ChatRequest, StreamingResponse},
+    ProxyRequestOptions,
+};
+use error_stack::{Report, ResultExt};
+
+use crate::{
+    config::{Configs, LocalServerConfig},
+    database::init_database,
+    proxy::build_proxy,
+    Error,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct ChatArgs {
+    /// The message to send
+    prompt: String,
+
+    /// The model or alias to use
+    #[clap(long, short = 'm')]
+    model: Option<String>,
+
+    /// The provider to use. If omitted, the provider is chosen based on the model.
+    #[clap(long, short = 'p')]
+    provider: Option<String>,
+
+    /// A system message to send before the prompt
+    #[clap(long, short = 's')]
+    system: Option<String>,
+
+    /// The sampling temperature
+    #[clap(long)]
+    temperature: Option<f32>,
+
+    /// The maximum number of tokens to generate
+    #[clap(long)]
+    max_tokens: Option<u32>,
+
+    /// Wait for the entire response instead of streaming it
+    #[clap(long)]
+    no_stream: bool,
+}
+
+/// Send a single prompt through the proxy and print the response to stdout.
+pub async fn chat(
+    args: ChatArgs,
+    server_config: LocalServerConfig,
+    configs: Configs,
+) -> Result<(), Report<Error>> {
+    let tables = server_config.table_names()?;
+    let db = init_database(server_config.database, &tables)
+        .await
+        .change_context(Error::Db)?;
+    let mut proxy = build_proxy(db, configs).await?;
+
+    let mut messages = Vec::with_capacity(2);
+    if let Some(system) = args.system {
+        messages.push(ChatMessage {
+            role: Some("system".to_string()),
+            content: Some(system),
+            ..Default::default()
+        });
+    }
+    messages.push(ChatMessage {
+        role: Some("user".to_string()),
+        content: Some(args.prompt),
+        ..Default::default()
+    });
+
+    let body = ChatRequest {
+        messages,
+        temperature: args.temperature,
+        max_tokens: args.max_tokens,
+        stream: !args.no_stream,
+        ..Default::default()
+    };
+
+    let options = ProxyRequestOptions {
+        model: args.model,
+        provider: args.provider,
+        ..Default::default()
+    };
+
+    let result = proxy
+        .send(options, body)
+        .await
+        .change_context(Error::Proxy)?;
+
+    let mut stdout = std::io::stdout();
+    while let Ok(chunk) = result.recv_async().await {
+        match chunk.change_context(Error::Proxy)? {
+            StreamingResponse::RequestInfo(info) => {
+                eprintln!("Using {} model {}", info.provider, info.model);
+                if info.num_retries > 0 {
+                    eprintln!("Succeeded after {} retries", info.num_retries);
+                }
+            }
+            StreamingResponse::Chunk(chunk) => {
+                for content in chunk
+                    .choices
+                    .iter()
+                    .filter_map(|c| c.delta.content.as_deref())
+                {
+                    write!(stdout, "{content}").ok();
+                }
+                stdout.flush().ok();
+            }
+            StreamingResponse::Single(response) => {
+                for content in response
+                    .choices
+                    .iter()
+                    .filter_map(|c| c.message.content.as_deref())
+                {
+                    write!(stdout, "{content}").ok();
+                }
+            }
+            StreamingResponse::ResponseInfo(_) => {}
+        }
+    }
+    writeln!(stdout).ok();
+
+    // Make sure the request is logged before exiting
+    proxy.shutdown().await;
+
+    Ok(())
+}
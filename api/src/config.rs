@@ -1,6 +1,9 @@
 use std::path::{Path, PathBuf};
 
-use chronicle_proxy::config::ProxyConfig;
+use chronicle_proxy::{
+    config::ProxyConfig,
+    database::tables::{TableConfig, TableNames},
+};
 use error_stack::{Report, ResultExt};
 use etcetera::BaseStrategy;
 use serde::Deserialize;
@@ -22,6 +25,9 @@ pub struct LocalServerConfig {
     /// This can either be a file path, an sqlite:// URL, or a postgresql:// URL
     pub database: Option<String>,
 
+    /// The schema and table name prefix for Chronicle's tables in the database
+    pub database_tables: Option<TableConfig>,
+
     /// The port to listen on
     pub port: Option<u16>,
 
@@ -32,9 +38,18 @@ pub struct LocalServerConfig {
     pub dotenv: Option<bool>,
 }
 
+impl LocalServerConfig {
+    /// The names of the database tables, from `database_tables`
+    pub fn table_names(&self) -> Result<TableNames, Report<Error>> {
+        let config = self.database_tables.clone().unwrap_or_default();
+        TableNames::from_config(&config).change_context(Error::Config)
+    }
+}
+
 pub fn merge_server_config(configs: &Configs) -> LocalServerConfig {
     let mut output = LocalServerConfig {
         database: None,
+        database_tables: None,
         port: None,
         host: None,
         dotenv: None,
@@ -47,6 +62,16 @@ pub fn merge_server_config(configs: &Configs) -> LocalServerConfig {
             output.database = Some(full_path.to_string_lossy().to_string());
         }
 
+        if let Some(tables) = &config.1.server_config.database_tables {
+            let output_tables = output.database_tables.get_or_insert_with(Default::default);
+            if let Some(schema) = &tables.schema {
+                output_tables.schema = Some(schema.clone());
+            }
+            if let Some(prefix) = &tables.prefix {
+                output_tables.prefix = Some(prefix.clone());
+            }
+        }
+
         if let Some(host) = &config.1.server_config.host {
             output.host = Some(host.clone());
         }
@@ -1,54 +1,187 @@
 use std::str::FromStr;
 
-use chronicle_proxy::database::Database;
+use chronicle_proxy::database::{
+    partitions::PartitionInterval, postgres::PostgresOptions, tables::TableNames, Database,
+};
+use chrono::Utc;
 use error_stack::Report;
 use sqlx::{sqlite::SqliteConnectOptions, PgPool, SqlitePool};
 
-pub async fn init_database(db: Option<String>) -> Result<Option<Database>, Report<sqlx::Error>> {
+enum Pool {
+    Postgres(PgPool),
+    Sqlite(SqlitePool),
+}
+
+pub async fn init_database(
+    db: Option<String>,
+    tables: &TableNames,
+) -> Result<Option<Database>, Report<sqlx::Error>> {
     let Some(db) = db else {
         tracing::info!("No database configured");
         return Ok(None);
     };
 
+    match connect(&db).await? {
+        Pool::Postgres(pool) => Ok(Some(init_pg(pool, tables).await?)),
+        Pool::Sqlite(pool) => Ok(Some(init_sqlite(pool, tables).await?)),
+    }
+}
+
+/// Connect to the database without running the migrations.
+pub async fn open_database(db: &str, tables: &TableNames) -> Result<Database, Report<sqlx::Error>> {
+    match connect(db).await? {
+        Pool::Postgres(pool) => Ok(open_pg(pool, tables)),
+        Pool::Sqlite(pool) => open_sqlite(pool, tables),
+    }
+}
+
+/// Run the database migrations, and return the ID of the latest applied migration.
+pub async fn migrate_database(
+    db: &str,
+    tables: &TableNames,
+) -> Result<Option<String>, Report<sqlx::Error>> {
+    let applied = match connect(db).await? {
+        Pool::Postgres(pool) => {
+            chronicle_proxy::database::postgres::run_migrations(&pool, tables).await?;
+            chronicle_proxy::database::postgres::applied_migrations(&pool, tables).await?
+        }
+        Pool::Sqlite(pool) => {
+            chronicle_proxy::database::sqlite::run_migrations(&pool, tables).await?;
+            chronicle_proxy::database::sqlite::applied_migrations(&pool, tables).await?
+        }
+    };
+
+    Ok(applied.into_iter().last().map(|m| m.id))
+}
+
+/// Revert the migrations applied after the migration `target`, and return the IDs of the reverted
+/// migrations.
+pub async fn revert_database(
+    db: &str,
+    tables: &TableNames,
+    target: &str,
+) -> Result<Vec<&'static str>, Report<sqlx::Error>> {
+    let reverted = match connect(db).await? {
+        Pool::Postgres(pool) => {
+            chronicle_proxy::database::postgres::revert_migrations(&pool, tables, Some(target))
+                .await?
+        }
+        Pool::Sqlite(pool) => {
+            chronicle_proxy::database::sqlite::revert_migrations(&pool, tables, Some(target))
+                .await?
+        }
+    };
+
+    Ok(reverted)
+}
+
+/// Convert the events table into a table partitioned by time. Returns `false` if it was already
+/// partitioned.
+pub async fn partition_events(
+    db: &str,
+    tables: &TableNames,
+    interval: PartitionInterval,
+) -> Result<bool, Report<sqlx::Error>> {
+    match connect(db).await? {
+        Pool::Postgres(pool) => Ok(chronicle_proxy::database::postgres::partition_events_table(
+            &pool,
+            tables,
+            interval,
+            Utc::now(),
+        )
+        .await?),
+        Pool::Sqlite(_) => Err(Report::new(sqlx::Error::Configuration(
+            "Partitioning is only supported on PostgreSQL".into(),
+        ))),
+    }
+}
+
+/// Add or remove the full-text search index. Returns `false` if it was already in that state.
+pub async fn set_search_index(
+    db: &str,
+    tables: &TableNames,
+    enable: bool,
+) -> Result<bool, Report<sqlx::Error>> {
+    let changed = match connect(db).await? {
+        Pool::Postgres(pool) if enable => {
+            chronicle_proxy::database::postgres::enable_search_index(&pool, tables).await?
+        }
+        Pool::Postgres(pool) => {
+            chronicle_proxy::database::postgres::disable_search_index(&pool, tables).await?
+        }
+        Pool::Sqlite(pool) if enable => {
+            chronicle_proxy::database::sqlite::enable_search_index(&pool, tables).await?
+        }
+        Pool::Sqlite(pool) => {
+            chronicle_proxy::database::sqlite::disable_search_index(&pool, tables).await?
+        }
+    };
+
+    Ok(changed)
+}
+
+async fn connect(db: &str) -> Result<Pool, Report<sqlx::Error>> {
     let pg = db.starts_with("postgresql://") || db.starts_with("postgres://");
 
     if pg {
         // Print the connection string without the password
-        let ops = sqlx::postgres::PgConnectOptions::from_str(&db)?;
+        let ops = sqlx::postgres::PgConnectOptions::from_str(db)?;
         let connection_string = reconstruct_pg_connstr(&ops);
         tracing::info!("Connecting to PostgreSQL database at {connection_string}");
         let pool = sqlx::postgres::PgPoolOptions::new()
             .connect_with(ops)
             .await?;
-        Ok(Some(init_pg(pool).await?))
+        Ok(Pool::Postgres(pool))
     } else {
         tracing::info!("Opening SQLite database at {db}");
 
         let pool = sqlx::sqlite::SqlitePoolOptions::new()
             .connect_with(
-                SqliteConnectOptions::from_str(&db)?
+                SqliteConnectOptions::from_str(db)?
                     .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal)
                     .synchronous(sqlx::sqlite::SqliteSynchronous::Normal)
                     .create_if_missing(true),
             )
             .await?;
 
-        Ok(Some(init_sqlite(pool).await?))
+        Ok(Pool::Sqlite(pool))
     }
 }
 
-pub(crate) async fn init_pg(pool: PgPool) -> Result<Database, Report<sqlx::Error>> {
-    chronicle_proxy::database::postgres::run_default_migrations(&pool).await?;
+pub(crate) async fn init_pg(
+    pool: PgPool,
+    tables: &TableNames,
+) -> Result<Database, Report<sqlx::Error>> {
+    chronicle_proxy::database::postgres::run_migrations(&pool, tables).await?;
+    Ok(open_pg(pool, tables))
+}
 
-    Ok(chronicle_proxy::database::postgres::PostgresDatabase::new(
+fn open_pg(pool: PgPool, tables: &TableNames) -> Database {
+    chronicle_proxy::database::postgres::PostgresDatabase::with_options(
         pool,
-    ))
+        PostgresOptions {
+            tables: tables.clone(),
+            ..Default::default()
+        },
+    )
 }
 
-pub(crate) async fn init_sqlite(pool: SqlitePool) -> Result<Database, Report<sqlx::Error>> {
-    chronicle_proxy::database::sqlite::run_default_migrations(&pool).await?;
+pub(crate) async fn init_sqlite(
+    pool: SqlitePool,
+    tables: &TableNames,
+) -> Result<Database, Report<sqlx::Error>> {
+    chronicle_proxy::database::sqlite::run_migrations(&pool, tables).await?;
+    open_sqlite(pool, tables)
+}
 
-    Ok(chronicle_proxy::database::sqlite::SqliteDatabase::new(pool))
+fn open_sqlite(pool: SqlitePool, tables: &TableNames) -> Result<Database, Report<sqlx::Error>> {
+    chronicle_proxy::database::sqlite::SqliteDatabase::with_tables(pool, tables.clone()).map_err(
+        |e| {
+            Report::new(sqlx::Error::Configuration(
+                e.current_context().to_string().into(),
+            ))
+        },
+    )
 }
 
 fn reconstruct_pg_connstr(ops: &sqlx::postgres::PgConnectOptions) -> String {
@@ -100,7 +233,9 @@ mod tests {
     #[sqlx::test]
     async fn test_postgres(pool: PgPool) {
         filigree::tracing_config::test::init();
-        let db = init_pg(pool.clone()).await.expect("Creating database");
+        let db = init_pg(pool.clone(), &TableNames::default())
+            .await
+            .expect("Creating database");
         test_proxy(10034, db).await;
         let events = sqlx::query("SELECT * FROM chronicle_events")
             .fetch_all(&pool)
@@ -303,7 +438,9 @@ mod tests {
         )
         .await
         .unwrap();
-        let db = init_sqlite(pool.clone()).await.unwrap();
+        let db = init_sqlite(pool.clone(), &TableNames::default())
+            .await
+            .unwrap();
         test_proxy(10035, db).await;
         verify_sqlite(pool).await;
     }
@@ -320,7 +457,9 @@ mod tests {
         )
         .await
         .unwrap();
-        let db = init_sqlite(pool.clone()).await.unwrap();
+        let db = init_sqlite(pool.clone(), &TableNames::default())
+            .await
+            .unwrap();
         test_proxy(10036, db).await;
         verify_sqlite(pool).await;
     }
@@ -333,6 +472,7 @@ mod tests {
             serve(
                 LocalServerConfig {
                     database: None,
+                    database_tables: None,
                     port: Some(port),
                     dotenv: Some(false),
                     host: None,
@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use chronicle_proxy::database::{
+    datasets::{items_to_jsonl, Dataset, DatasetItem, DatasetItemSource, NewDataset},
+    ProxyDatabase,
+};
+use error_stack::ResultExt;
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{error::Error, proxy::ServerState};
+
+#[derive(Serialize, Debug)]
+struct AddItemsResponse {
+    /// The number of events added to the dataset
+    added: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct UpdateItemBody {
+    expected_response: serde_json::Value,
+}
+
+async fn load_dataset(db: &dyn ProxyDatabase, id: Uuid) -> Result<Dataset, Error> {
+    let dataset = db
+        .get_dataset(id)
+        .await
+        .change_context(Error::Db)?
+        .ok_or(Error::NotFound("Dataset"))?;
+    Ok(dataset)
+}
+
+async fn create_dataset(
+    State(state): State<Arc<ServerState>>,
+    Json(body): Json<NewDataset>,
+) -> Result<Json<Dataset>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let dataset = db.create_dataset(&body).await.change_context(Error::Db)?;
+    Ok(Json(dataset))
+}
+
+async fn list_datasets(State(state): State<Arc<ServerState>>) -> Result<Json<Vec<Dataset>>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let datasets = db.list_datasets().await.change_context(Error::Db)?;
+    Ok(Json(datasets))
+}
+
+async fn get_dataset(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Dataset>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    Ok(Json(load_dataset(db.as_ref(), id).await?))
+}
+
+async fn list_items(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<DatasetItem>>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    load_dataset(db.as_ref(), id).await?;
+    let items = db.list_dataset_items(id).await.change_context(Error::Db)?;
+    Ok(Json(items))
+}
+
+/// Add events to a dataset, either by ID with `{"events": [...]}` or by filter with
+/// `{"query": {...}}`
+async fn add_items(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<DatasetItemSource>,
+) -> Result<Json<AddItemsResponse>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    load_dataset(db.as_ref(), id).await?;
+    let added = db
+        .add_dataset_items(id, &body)
+        .await
+        .change_context(Error::Db)?;
+    Ok(Json(AddItemsResponse { added }))
+}
+
+async fn update_item(
+    State(state): State<Arc<ServerState>>,
+    Path((id, item_id)): Path<(Uuid, Uuid)>,
+    Json(body): Json<UpdateItemBody>,
+) -> Result<(), Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let updated = db
+        .update_dataset_item(id, item_id, &body.expected_response)
+        .await
+        .change_context(Error::Db)?;
+    if !updated {
+        return Err(Error::NotFound("Dataset item"));
+    }
+    Ok(())
+}
+
+/// Download the dataset's items as JSON lines
+async fn export_dataset(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let dataset = load_dataset(db.as_ref(), id).await?;
+    let items = db.list_dataset_items(id).await.change_context(Error::Db)?;
+    let body = items_to_jsonl(&items).change_context(Error::Db)?;
+
+    let disposition = format!(
+        "attachment; filename=\"{}-v{}.jsonl\"",
+        dataset
+            .name
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+        dataset.version
+    );
+    Ok((
+        [
+            (CONTENT_TYPE, "application/jsonl".to_string()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    ))
+}
+
+pub fn create_routes() -> axum::Router<Arc<ServerState>> {
+    axum::Router::new()
+        .route(
+            "/v1/datasets",
+            axum::routing::get(list_datasets).post(create_dataset),
+        )
+        .route("/v1/datasets/:id", axum::routing::get(get_dataset))
+        .route(
+            "/v1/datasets/:id/items",
+            axum::routing::get(list_items).post(add_items),
+        )
+        .route(
+            "/v1/datasets/:id/items/:item_id",
+            axum::routing::put(update_item),
+        )
+        .route(
+            "/v1/datasets/:id/export",
+            axum::routing::get(export_dataset),
+        )
+}
@@ -14,6 +14,9 @@ pub enum Error {
     /// Database error not otherwise handled
     #[error("Database error")]
     Db,
+    /// The request requires a database, but none is configured
+    #[error("No database configured")]
+    NoDatabase,
     /// Configuration error
     #[error("Configuration error")]
     Config,
@@ -44,6 +47,24 @@ pub enum Error {
     BuildingProxy,
     #[error("Failed to read proxy request options")]
     InvalidProxyHeader,
+    #[error("Failed to gather metrics")]
+    Metrics,
+    #[error("Failed to stream events")]
+    LiveTail,
+    #[error("Failed to purge old data")]
+    Purge,
+    /// The database's full-text search index has not been enabled
+    #[error("Full-text search is not enabled. Run `chronicle migrate --search-index true` first.")]
+    SearchNotEnabled,
+    #[error("Failed to run eval")]
+    Eval,
+    /// Scores in an eval were lower than in the baseline run
+    #[error("{0} scores regressed from the baseline")]
+    EvalRegression(usize),
+    #[error("Failed to manage prompts")]
+    Prompt,
+    #[error("Failed to export fine-tuning data")]
+    FineTuning,
 }
 
 impl From<Report<Error>> for Error {
@@ -108,6 +129,7 @@ impl HttpError for Error {
             Error::WrapReport(e) => e.current_context().error_kind(),
             Error::DbInit => "db_init",
             Error::Db => "db",
+            Error::NoDatabase => "no_database",
             Error::ServerStart => "server_start",
             Error::NotFound(_) => "not_found",
             Error::Shutdown => "shutdown",
@@ -118,6 +140,14 @@ impl HttpError for Error {
             Error::InvalidProxyHeader => "invalid_proxy_headers",
             Error::Config => "config",
             Error::InvalidEventPayload(_, _) => "invalid_event_payload",
+            Error::Metrics => "metrics",
+            Error::LiveTail => "live_tail",
+            Error::Purge => "purge",
+            Error::SearchNotEnabled => "search_not_enabled",
+            Error::Eval => "eval",
+            Error::EvalRegression(_) => "eval_regression",
+            Error::Prompt => "prompt",
+            Error::FineTuning => "fine_tuning",
         }
     }
 
@@ -130,6 +160,7 @@ impl HttpError for Error {
             Error::WrapReport(e) => e.current_context().status_code(),
             Error::DbInit => StatusCode::INTERNAL_SERVER_ERROR,
             Error::Db => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::NoDatabase => StatusCode::NOT_IMPLEMENTED,
             Error::ServerStart => StatusCode::INTERNAL_SERVER_ERROR,
             Error::NotFound(_) => StatusCode::NOT_FOUND,
             Error::Shutdown => StatusCode::INTERNAL_SERVER_ERROR,
@@ -140,6 +171,14 @@ impl HttpError for Error {
             Error::Proxy => StatusCode::INTERNAL_SERVER_ERROR,
             Error::BuildingProxy => StatusCode::INTERNAL_SERVER_ERROR,
             Error::InvalidEventPayload(_, _) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Metrics => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::LiveTail => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Purge => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::SearchNotEnabled => StatusCode::NOT_IMPLEMENTED,
+            Error::Eval => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::EvalRegression(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Prompt => StatusCode::BAD_REQUEST,
+            Error::FineTuning => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
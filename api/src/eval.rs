@@ -0,0 +1,305 @@
+use chronicle_proxy::{
+    database::{
+        datasets::Dataset,
+        scores::{summarize_scores, ScoreTarget},
+        Database,
+    },
+    eval::{
+        compare_to_baseline, run_eval, scorers::ScorerConfig, EvalOptions, EvalResult,
+        ScoreComparison,
+    },
+    ModelAndProvider, ProxyRequestOptions,
+};
+use error_stack::{Report, ResultExt};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    config::{Configs, LocalServerConfig},
+    database::init_database,
+    proxy::build_proxy,
+    Error,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct EvalArgs {
+    /// The ID or name of the dataset to run. A name uses the latest version of the dataset,
+    /// unless `--version` is given.
+    #[clap(long, short = 'd')]
+    dataset: String,
+
+    /// The version of the dataset to run, when `--dataset` is a name
+    #[clap(long)]
+    version: Option<i32>,
+
+    /// The model or alias to send the items to
+    #[clap(long, short = 'm')]
+    model: Option<String>,
+
+    /// The provider to use. If omitted, the provider is chosen based on the model.
+    #[clap(long, short = 'p')]
+    provider: Option<String>,
+
+    /// A JSON array of models to try in order, as in the x-chronicle-models header
+    #[clap(long, conflicts_with_all = ["model", "provider"])]
+    models: Option<String>,
+
+    /// How many items to send at once
+    #[clap(long)]
+    concurrency: Option<usize>,
+
+    /// A scorer to run on each response. This can be given multiple times.
+    /// The scorers are `exact_match`, `contains` or `contains=TEXT`, `regex=PATTERN`,
+    /// `numeric=TOLERANCE`, and `json_schema=PATH` to validate against a JSON schema file.
+    #[clap(long = "scorer", short = 's', required = true)]
+    scorers: Vec<String>,
+
+    /// The ID of an earlier run to compare against. The command fails if any score's mean is
+    /// lower than it was in this run.
+    #[clap(long)]
+    baseline: Option<Uuid>,
+
+    /// How much a score's mean can drop below the baseline before it counts as a regression
+    #[clap(long, default_value_t = 0.0)]
+    tolerance: f64,
+
+    /// Print the results as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+/// Parse a scorer from the command line
+fn parse_scorer(spec: &str) -> Result<ScorerConfig, Report<Error>> {
+    let (name, arg) = match spec.split_once('=') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (spec, None),
+    };
+
+    let missing_arg =
+        || Report::new(Error::Eval).attach_printable(format!("Scorer {name} requires an argument"));
+
+    let config = match name {
+        "exact_match" => ScorerConfig::ExactMatch,
+        "contains" => ScorerConfig::Contains {
+            text: arg.map(|arg| arg.to_string()),
+        },
+        "regex" => ScorerConfig::Regex {
+            pattern: arg.ok_or_else(missing_arg)?.to_string(),
+        },
+        "numeric" | "numeric_tolerance" => ScorerConfig::NumericTolerance {
+            tolerance: arg
+                .ok_or_else(missing_arg)?
+                .parse::<f64>()
+                .change_context(Error::Eval)
+                .attach_printable("Numeric tolerance must be a number")?,
+        },
+        "json_schema" => {
+            let path = arg.ok_or_else(missing_arg)?;
+            let schema = std::fs::read_to_string(path)
+                .change_context(Error::Eval)
+                .attach_printable_lazy(|| format!("Failed to read JSON schema {path}"))?;
+            ScorerConfig::JsonSchema {
+                schema: serde_json::from_str(&schema)
+                    .change_context(Error::Eval)
+                    .attach_printable_lazy(|| format!("Failed to parse JSON schema {path}"))?,
+            }
+        }
+        _ => {
+            return Err(Report::new(Error::Eval).attach_printable(format!("Unknown scorer {name}")))
+        }
+    };
+
+    Ok(config)
+}
+
+/// Find the dataset by ID, or by name and version
+async fn find_dataset(
+    db: &Database,
+    dataset: &str,
+    version: Option<i32>,
+) -> Result<Dataset, Report<Error>> {
+    if let Ok(id) = dataset.parse::<Uuid>() {
+        return db
+            .get_dataset(id)
+            .await
+            .change_context(Error::Db)?
+            .ok_or(Error::NotFound("Dataset").into());
+    }
+
+    let datasets = db.list_datasets().await.change_context(Error::Db)?;
+    datasets
+        .into_iter()
+        .filter(|d| d.name == dataset && version.map(|v| v == d.version).unwrap_or(true))
+        .max_by_key(|d| d.version)
+        .ok_or(Error::NotFound("Dataset").into())
+}
+
+/// Run the items in a dataset through the proxy, score the responses, and optionally compare
+/// the scores to a baseline run.
+pub async fn eval(
+    args: EvalArgs,
+    server_config: LocalServerConfig,
+    configs: Configs,
+) -> Result<(), Report<Error>> {
+    let scorers = args
+        .scorers
+        .iter()
+        .map(|spec| parse_scorer(spec)?.build().change_context(Error::Eval))
+        .collect::<Result<Vec<_>, _>>()?;
+    let models = args
+        .models
+        .as_deref()
+        .map(serde_json::from_str::<Vec<ModelAndProvider>>)
+        .transpose()
+        .change_context(Error::Eval)
+        .attach_printable("Failed to parse --models")?
+        .unwrap_or_default();
+
+    let tables = server_config.table_names()?;
+    let db = init_database(server_config.database, &tables)
+        .await
+        .change_context(Error::Db)?
+        .ok_or(Error::NoDatabase)?;
+    let mut proxy = build_proxy(Some(db), configs).await?;
+    // Use the proxy's database, which resolves references to bodies in the blob store.
+    let db = proxy.database().cloned().ok_or(Error::NoDatabase)?;
+
+    let dataset = find_dataset(&db, &args.dataset, args.version).await?;
+    let items = db
+        .list_dataset_items(dataset.id)
+        .await
+        .change_context(Error::Db)?;
+    if !args.json {
+        eprintln!(
+            "Running {} items from {} v{}",
+            items.len(),
+            dataset.name,
+            dataset.version
+        );
+    }
+
+    let options = EvalOptions {
+        request_options: ProxyRequestOptions {
+            model: args.model,
+            provider: args.provider,
+            models,
+            ..Default::default()
+        },
+        concurrency: args.concurrency,
+        scorers,
+        ..Default::default()
+    };
+    let result = run_eval(&proxy, &dataset, &items, &options).await;
+
+    let comparisons = match args.baseline {
+        Some(baseline) => {
+            let scores = db
+                .list_scores(ScoreTarget::Run(baseline))
+                .await
+                .change_context(Error::Db)?;
+            // Only compare the scores that this eval produces.
+            let baseline = summarize_scores(&scores)
+                .into_iter()
+                .filter(|s| options.scorers.iter().any(|scorer| scorer.name() == s.name))
+                .collect::<Vec<_>>();
+            compare_to_baseline(&result.summary, &baseline, args.tolerance)
+        }
+        None => Vec::new(),
+    };
+
+    // Make sure the run is logged before exiting
+    proxy.shutdown().await;
+
+    if args.json {
+        let output = json!({
+            "result": result,
+            "comparisons": comparisons,
+        });
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&output).unwrap_or_default()
+        );
+    } else {
+        print_summary(&result, &comparisons);
+    }
+
+    let regressions = comparisons.iter().filter(|c| c.regressed).count();
+    if regressions > 0 {
+        return Err(Report::new(Error::EvalRegression(regressions)));
+    }
+
+    Ok(())
+}
+
+fn print_summary(result: &EvalResult, comparisons: &[ScoreComparison]) {
+    println!("Run {}", result.run_id);
+    println!("{} items, {} errors", result.items.len(), result.errors());
+    for item in result.items.iter().filter(|item| item.error.is_some()) {
+        println!(
+            "  Item {} failed: {}",
+            item.item_id,
+            item.error.as_deref().unwrap_or_default()
+        );
+    }
+
+    let format_mean = |mean: Option<f64>| {
+        mean.map(|mean| format!("{mean:.3}"))
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    for summary in &result.summary {
+        println!(
+            "{}: mean {} over {} items",
+            summary.name,
+            format_mean(summary.mean),
+            summary.count
+        );
+    }
+
+    for comparison in comparisons {
+        println!(
+            "{} {}: {} -> {}",
+            if comparison.regressed {
+                "REGRESSED"
+            } else {
+                "ok"
+            },
+            comparison.name,
+            format_mean(comparison.baseline),
+            format_mean(comparison.current)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scorer_specs() {
+        assert_eq!(
+            parse_scorer("exact_match").unwrap(),
+            ScorerConfig::ExactMatch
+        );
+        assert_eq!(
+            parse_scorer("contains").unwrap(),
+            ScorerConfig::Contains { text: None }
+        );
+        assert_eq!(
+            parse_scorer("contains=a=b").unwrap(),
+            ScorerConfig::Contains {
+                text: Some("a=b".to_string())
+            }
+        );
+        assert_eq!(
+            parse_scorer("numeric=0.5").unwrap(),
+            ScorerConfig::NumericTolerance { tolerance: 0.5 }
+        );
+        assert!(parse_scorer("regex").is_err(), "regex without a pattern");
+        assert!(
+            parse_scorer("numeric=abc").is_err(),
+            "non-numeric tolerance"
+        );
+        assert!(parse_scorer("llm_judge").is_err(), "unknown scorer");
+    }
+}
@@ -1,14 +1,21 @@
-use std::sync::Arc;
+use std::{future::ready, sync::Arc};
 
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{
+    extract::{Query, State},
+    response::{sse, IntoResponse, Sse},
+    Json,
+};
 use chronicle_proxy::{
-    workflow_events::{EventPayload, WorkflowEvent},
+    database::live_tail::LiveEventFilter,
+    workflow_events::{EventPayload, ScoreEvent, WorkflowEvent},
     ProxyRequestMetadata,
 };
 use error_stack::ResultExt;
+use futures::StreamExt;
 use http::{HeaderMap, StatusCode};
 use serde::Deserialize;
 use smallvec::{smallvec, SmallVec};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 
 use crate::{error::Error, proxy::ServerState};
 
@@ -25,6 +32,7 @@ async fn record_event(
                 .change_context(Error::InvalidProxyHeader)?;
             event.merge_metadata(&metadata);
         }
+        WorkflowEvent::Score(score) => check_score(score)?,
         _ => {}
     }
 
@@ -41,7 +49,8 @@ struct EventsPayload {
 fn check_invalid_fixed_payload(e: &EventPayload) -> Result<(), Error> {
     match e.typ.as_str() {
         // Catch events that use the fixed event types but didn't serialize properly
-        "run:start" | "run:update" | "step:start" | "step:end" | "step:error" | "step:state" => {
+        "run:start" | "run:update" | "step:start" | "step:end" | "step:error" | "step:state"
+        | "score" => {
             tracing::warn!(event=?e, "Invalid fixed payload");
             Err(Error::InvalidEventPayload(
                 e.typ.clone(),
@@ -52,6 +61,16 @@ fn check_invalid_fixed_payload(e: &EventPayload) -> Result<(), Error> {
     }
 }
 
+fn check_score(score: &ScoreEvent) -> Result<(), Error> {
+    score.validate().map_err(|e| {
+        tracing::warn!(?score, "Invalid score");
+        Error::InvalidEventPayload(
+            "score".to_string(),
+            serde_json::json!({ "message": e.to_string() }),
+        )
+    })
+}
+
 async fn record_events(
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
@@ -68,6 +87,7 @@ async fn record_events(
                 event.merge_metadata(&metadata);
             }
             WorkflowEvent::Event(e) => check_invalid_fixed_payload(e)?,
+            WorkflowEvent::Score(score) => check_score(score)?,
             _ => {}
         }
     }
@@ -77,6 +97,33 @@ async fn record_events(
     Ok(StatusCode::ACCEPTED)
 }
 
+/// Stream events as they are logged. Each event is sent as a JSON-encoded `LiveEvent`. If the
+/// client falls too far behind, a `lagged` event is sent with the number of events that were
+/// skipped.
+async fn stream_events(
+    State(state): State<Arc<ServerState>>,
+    Query(filter): Query<LiveEventFilter>,
+) -> Result<impl IntoResponse, Error> {
+    // Events only flow through the logger when there is a database.
+    state.db.as_ref().ok_or(Error::NoDatabase)?;
+
+    let stream =
+        BroadcastStream::new(state.proxy.subscribe_live_events()).filter_map(move |event| {
+            let result = match event {
+                Ok(event) => filter
+                    .matches(&event)
+                    .then(|| sse::Event::default().json_data(event.as_ref())),
+                Err(BroadcastStreamRecvError::Lagged(n)) => Some(Ok(sse::Event::default()
+                    .event("lagged")
+                    .data(n.to_string()))),
+            };
+
+            ready(result)
+        });
+
+    Ok(Sse::new(stream).keep_alive(sse::KeepAlive::default()))
+}
+
 pub fn create_routes() -> axum::Router<Arc<ServerState>> {
     axum::Router::new()
         .route(
@@ -87,6 +134,7 @@ pub fn create_routes() -> axum::Router<Arc<ServerState>> {
         .route("/events", axum::routing::post(record_events))
         .route("/v1/event", axum::routing::post(record_event))
         .route("/v1/events", axum::routing::post(record_events))
+        .route("/v1/events/stream", axum::routing::get(stream_events))
         .route(
             "/healthz",
             axum::routing::get(|| async { axum::Json(serde_json::json!({ "status": "ok" })) }),
@@ -109,6 +157,7 @@ mod test {
             step_id: Uuid::new_v4(),
             error: None,
             time: None,
+            event_id: None,
             internal_metadata: None,
         };
 
@@ -0,0 +1,258 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use axum::{body::Body, extract::State, response::IntoResponse, Json};
+use bytes::Bytes;
+use chronicle_proxy::{
+    database::export::ExportEventQuery,
+    fine_tuning::{export_fine_tuning, FineTuningFormat, FineTuningOptions, FineTuningSplit},
+};
+use error_stack::{Report, ResultExt};
+use futures::{future::ready, StreamExt, TryStreamExt};
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use serde::Deserialize;
+
+use crate::{
+    config::{Configs, LocalServerConfig},
+    database::init_database,
+    error::Error,
+    proxy::{build_proxy, ServerState},
+};
+
+#[derive(Deserialize, Debug)]
+struct ExportBody {
+    #[serde(flatten)]
+    options: FineTuningOptions,
+    /// Only return the examples in this split. By default every example is returned.
+    split: Option<FineTuningSplit>,
+}
+
+/// Stream the matching events as fine-tuning examples in JSON lines format
+async fn export(
+    State(state): State<Arc<ServerState>>,
+    Json(body): Json<ExportBody>,
+) -> Result<impl IntoResponse, Error> {
+    // Use the proxy's database, which resolves references to bodies in the blob store.
+    let db = state.proxy.database().cloned().ok_or(Error::NoDatabase)?;
+
+    let split = body.split;
+    let lines = export_fine_tuning(db, body.options)
+        .try_filter(move |example| ready(split.is_none() || split == Some(example.split)))
+        .map_ok(|example| Bytes::from(example.line + "\n"))
+        .map_err(|e| {
+            tracing::error!(error = ?e, "Failed to export fine-tuning data");
+            std::io::Error::other(e.to_string())
+        });
+
+    let filename = match split {
+        Some(FineTuningSplit::Train) => "train.jsonl",
+        Some(FineTuningSplit::Validation) => "validation.jsonl",
+        None => "fine_tuning.jsonl",
+    };
+    Ok((
+        [
+            (CONTENT_TYPE, "application/jsonl".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        Body::from_stream(lines),
+    ))
+}
+
+pub fn create_routes() -> axum::Router<Arc<ServerState>> {
+    axum::Router::new().route("/v1/events/export/fine_tuning", axum::routing::post(export))
+}
+
+#[derive(Debug, clap::Args)]
+pub struct FineTuneArgs {
+    /// The format of the examples: `openai`, or `anthropic` (also accepted as `bedrock`)
+    #[clap(long, short = 'f', default_value = "openai")]
+    format: FineTuningFormat,
+
+    /// Write the training examples to this file instead of stdout
+    #[clap(long, short = 'o')]
+    output: Option<PathBuf>,
+
+    /// Hold out this fraction of the examples, between 0 and 1, for validation
+    #[clap(long, requires = "validation_output")]
+    validation_fraction: Option<f64>,
+
+    /// The file to write the validation examples to
+    #[clap(long, requires = "validation_fraction")]
+    validation_output: Option<PathBuf>,
+
+    /// Only export events from this application
+    #[clap(long)]
+    application: Option<String>,
+
+    /// Only export events from this environment
+    #[clap(long)]
+    environment: Option<String>,
+
+    /// Only export events that used this provider
+    #[clap(long)]
+    provider: Option<String>,
+
+    /// Only export events that used this model
+    #[clap(long)]
+    model: Option<String>,
+
+    /// Only export events that used this prompt
+    #[clap(long)]
+    prompt_id: Option<String>,
+
+    /// Only export events that used this version of the prompt
+    #[clap(long, requires = "prompt_id")]
+    prompt_version: Option<u32>,
+
+    /// Only export events whose metadata has this value, as `KEY=VALUE`. This can be given
+    /// multiple times.
+    #[clap(long = "meta")]
+    metadata: Vec<String>,
+
+    /// Also export events that returned an error
+    #[clap(long)]
+    include_errors: bool,
+
+    /// Export every matching event, even when it produces the same example as another event
+    #[clap(long)]
+    keep_duplicates: bool,
+
+    /// The maximum number of examples to export
+    #[clap(long)]
+    limit: Option<u32>,
+}
+
+/// Parse a `KEY=VALUE` metadata filter from the command line
+fn parse_metadata(filter: &str) -> Result<(String, String), Report<Error>> {
+    let (key, value) = filter
+        .split_once('=')
+        .ok_or(Error::FineTuning)
+        .attach_printable_lazy(|| format!("Metadata {filter} should be in the form KEY=VALUE"))?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn create_output(path: Option<&PathBuf>) -> Result<BufWriter<Box<dyn Write>>, Report<Error>> {
+    let output: Box<dyn Write> = match path {
+        Some(path) => Box::new(
+            File::create(path)
+                .change_context(Error::FineTuning)
+                .attach_printable_lazy(|| format!("Failed to create {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout()),
+    };
+    Ok(BufWriter::new(output))
+}
+
+/// Export logged events from the database as fine-tuning files
+pub async fn fine_tune(
+    args: FineTuneArgs,
+    server_config: LocalServerConfig,
+    configs: Configs,
+) -> Result<(), Report<Error>> {
+    let metadata = args
+        .metadata
+        .iter()
+        .map(|filter| parse_metadata(filter))
+        .collect::<Result<_, _>>()?;
+    let options = FineTuningOptions {
+        query: ExportEventQuery {
+            application: args.application,
+            environment: args.environment,
+            provider: args.provider,
+            model: args.model,
+            prompt_id: args.prompt_id,
+            prompt_version: args.prompt_version,
+            metadata,
+            include_errors: args.include_errors,
+            limit: args.limit,
+            ..Default::default()
+        },
+        format: args.format,
+        validation_fraction: args.validation_fraction,
+        keep_duplicates: args.keep_duplicates,
+    };
+
+    let tables = server_config.table_names()?;
+    let db = init_database(server_config.database, &tables)
+        .await
+        .change_context(Error::Db)?
+        .ok_or(Error::NoDatabase)?;
+    let proxy = build_proxy(Some(db), configs).await?;
+    // Use the proxy's database, which resolves references to bodies in the blob store.
+    let db = proxy.database().cloned().ok_or(Error::NoDatabase)?;
+
+    let mut train = create_output(args.output.as_ref())?;
+    let mut validation = args
+        .validation_output
+        .as_ref()
+        .map(|path| create_output(Some(path)))
+        .transpose()?;
+
+    let mut counts = (0, 0);
+    let mut examples = std::pin::pin!(export_fine_tuning(db, options));
+    while let Some(example) = examples.next().await {
+        let example = example.change_context(Error::FineTuning)?;
+        let output = match (example.split, validation.as_mut()) {
+            (FineTuningSplit::Validation, Some(validation)) => {
+                counts.1 += 1;
+                validation
+            }
+            _ => {
+                counts.0 += 1;
+                &mut train
+            }
+        };
+        writeln!(output, "{}", example.line).change_context(Error::FineTuning)?;
+    }
+
+    train.flush().change_context(Error::FineTuning)?;
+    if let Some(validation) = validation.as_mut() {
+        validation.flush().change_context(Error::FineTuning)?;
+    }
+
+    eprintln!(
+        "Exported {} training and {} validation examples",
+        counts.0, counts.1
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn metadata_filters() {
+        assert_eq!(
+            parse_metadata("label=a=b").unwrap(),
+            ("label".to_string(), "a=b".to_string())
+        );
+        assert!(parse_metadata("label").is_err(), "missing value");
+    }
+
+    #[test]
+    fn export_body() {
+        let body: ExportBody = serde_json::from_value(serde_json::json!({
+            "application": "support",
+            "metadata": { "label": "good" },
+            "format": "bedrock",
+            "validation_fraction": 0.1,
+            "split": "validation",
+            "limit": 100,
+        }))
+        .unwrap();
+        assert_eq!(body.options.query.application.as_deref(), Some("support"));
+        assert_eq!(body.options.query.metadata["label"], "good");
+        assert_eq!(body.options.query.limit, Some(100));
+        assert_eq!(body.options.format, FineTuningFormat::Anthropic);
+        assert_eq!(body.options.validation_fraction, Some(0.1));
+        assert_eq!(body.split, Some(FineTuningSplit::Validation));
+    }
+}
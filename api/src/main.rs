@@ -5,10 +5,15 @@ use std::{
 };
 
 use axum::Router;
-use chronicle_proxy::database::Database;
-use clap::Parser;
+use chronicle_proxy::database::{
+    load_config_from_database, partitions::PartitionInterval, Database,
+};
+use clap::{Args, Parser, Subcommand};
 use config::{Configs, LocalServerConfig};
-use database::init_database;
+use database::{
+    init_database, migrate_database, open_database, partition_events, revert_database,
+    set_search_index,
+};
 use error_stack::{Report, ResultExt};
 use filigree::{
     errors::panic_handler,
@@ -29,11 +34,23 @@ use crate::{
     proxy::ServerState,
 };
 
+mod chat;
 mod config;
 mod database;
+mod datasets;
 mod error;
+mod eval;
 mod events;
+mod fine_tuning;
+mod metrics;
+mod prompts;
 mod proxy;
+mod purge;
+mod replay;
+mod runs;
+mod scores;
+mod search;
+mod tail;
 
 use error::Error;
 
@@ -42,25 +59,70 @@ use error::Error;
 pub(crate) struct Cli {
     /// The path to the configuration file or a directory containing it. If omitted,
     /// the default configuration path will be checked.
-    #[clap(long, short = 'c')]
+    #[clap(long, short = 'c', global = true)]
     config: Option<String>,
 
     /// Do not read the .env file
-    #[clap(long)]
+    #[clap(long, global = true)]
     no_dotenv: bool,
 
     /// The SQLite or PostgreSQL database to use, if any. This can also be set in the configuration file.
     /// Takes a file path for SQLite or a connection string for PostgreSQL
-    #[clap(long = "db", env = "DATABASE_URL")]
+    #[clap(long = "db", env = "DATABASE_URL", global = true)]
     database: Option<String>,
 
     /// The IP host to bind to
-    #[clap(long, env = "HOST")]
+    #[clap(long, env = "HOST", global = true)]
     host: Option<String>,
 
     /// The TCP port to listen on
-    #[clap(long, env = "PORT")]
+    #[clap(long, env = "PORT", global = true)]
     port: Option<u16>,
+
+    /// The command to run. Defaults to `serve`.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Run the proxy server
+    Serve,
+    /// Run the database migrations and print the latest applied migration
+    Migrate(MigrateArgs),
+    /// Load and validate the configuration without starting the server
+    Check,
+    /// Send a single prompt through the proxy and print the response
+    Chat(chat::ChatArgs),
+    /// Print events from a running server as they are logged
+    Tail(tail::TailArgs),
+    /// Delete events and runs older than the configured retention policy
+    Purge(purge::PurgeArgs),
+    /// Run a dataset through a model, score the responses, and compare them to a baseline run
+    Eval(eval::EvalArgs),
+    /// Manage prompt templates stored in the database
+    Prompt(prompts::PromptArgs),
+    /// Export logged conversations as fine-tuning JSONL files
+    FineTune(fine_tuning::FineTuneArgs),
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct MigrateArgs {
+    /// Convert the events table on PostgreSQL into a table partitioned by time, with one
+    /// partition per `day`, `week`, or `month`. Existing events are copied into the new
+    /// partitions, so this can take a while on a large table.
+    #[clap(long)]
+    partition_events: Option<PartitionInterval>,
+
+    /// Enable or disable the full-text search index over requests and responses. Enabling it
+    /// indexes all existing events, and makes writes somewhat slower.
+    #[clap(long)]
+    search_index: Option<bool>,
+
+    /// Revert the migrations applied after this migration ID instead of running migrations.
+    /// Fails without changing anything if one of them can not be reverted.
+    #[clap(long, conflicts_with_all = ["partition_events", "search_index"])]
+    revert_to: Option<String>,
 }
 
 pub(crate) async fn run(cmd: Cli) -> Result<(), Report<Error>> {
@@ -101,6 +163,22 @@ pub(crate) async fn run(cmd: Cli) -> Result<(), Report<Error>> {
         }
     }
 
+    // Only the server logs through tracing, so that the other commands can use stdout for their
+    // own output.
+    match cmd.command.unwrap_or(Command::Serve) {
+        Command::Serve => {}
+        Command::Migrate(args) => return migrate(args, &server_config).await,
+        Command::Check => return check(configs, &server_config).await,
+        Command::Chat(args) => return chat::chat(args, server_config, configs).await,
+        Command::Tail(args) => return tail::tail(args, &server_config).await,
+        Command::Purge(args) => return purge::purge(args, &server_config, configs).await,
+        Command::Eval(args) => return eval::eval(args, server_config, configs).await,
+        Command::Prompt(args) => return prompts::prompt(args, server_config, configs).await,
+        Command::FineTune(args) => {
+            return fine_tuning::fine_tune(args, server_config, configs).await
+        }
+    }
+
     let tracing_config = create_tracing_config(
         "",
         "CHRONICLE_",
@@ -121,7 +199,8 @@ pub(crate) async fn run(cmd: Cli) -> Result<(), Report<Error>> {
     for (dir, _) in configs.global.iter().chain(configs.cwd.iter()) {
         tracing::info!("Loaded config from {}", dir.display());
     }
-    let db = init_database(server_config.database.clone())
+    let tables = server_config.table_names()?;
+    let db = init_database(server_config.database.clone(), &tables)
         .await
         .change_context(Error::Db)?;
 
@@ -129,6 +208,93 @@ pub(crate) async fn run(cmd: Cli) -> Result<(), Report<Error>> {
     serve(server_config, configs, db, shutdown_signal).await
 }
 
+async fn migrate(
+    args: MigrateArgs,
+    server_config: &LocalServerConfig,
+) -> Result<(), Report<Error>> {
+    let db = server_config.database.as_deref().ok_or(Error::NoDatabase)?;
+    let tables = server_config.table_names()?;
+
+    if let Some(target) = args.revert_to {
+        let reverted = revert_database(db, &tables, &target)
+            .await
+            .change_context(Error::DbInit)?;
+        for id in &reverted {
+            println!("Reverted migration {id}");
+        }
+        println!("Database schema is at migration {target}");
+        return Ok(());
+    }
+
+    let latest = migrate_database(db, &tables)
+        .await
+        .change_context(Error::DbInit)?;
+
+    match latest {
+        Some(id) => println!("Database schema is at migration {id}"),
+        None => println!("Migrations ran, but none were recorded"),
+    }
+
+    if let Some(interval) = args.partition_events {
+        let converted = partition_events(db, &tables, interval)
+            .await
+            .change_context(Error::DbInit)?;
+        if converted {
+            println!("Partitioned the events table by {interval}");
+        } else {
+            println!("The events table is already partitioned");
+        }
+    }
+
+    if let Some(enable) = args.search_index {
+        let changed = set_search_index(db, &tables, enable)
+            .await
+            .change_context(Error::DbInit)?;
+        match (enable, changed) {
+            (true, true) => println!("Enabled the full-text search index"),
+            (true, false) => println!("The full-text search index is already enabled"),
+            (false, true) => println!("Removed the full-text search index"),
+            (false, false) => println!("The full-text search index is not enabled"),
+        }
+    }
+
+    Ok(())
+}
+
+async fn check(configs: Configs, server_config: &LocalServerConfig) -> Result<(), Report<Error>> {
+    for (dir, _) in configs.global.iter().chain(configs.cwd.iter()) {
+        println!("Loaded config from {}", dir.display());
+    }
+
+    let mut builder = proxy::proxy_builder(configs);
+    if let Some(db) = server_config.database.as_deref() {
+        // Read the stored configuration without running the migrations or giving the database to
+        // the proxy, which would start the background tasks.
+        let tables = server_config.table_names()?;
+        let db = open_database(db, &tables).await.change_context(Error::Db)?;
+        let db_config = load_config_from_database(db.as_ref())
+            .await
+            .change_context(Error::Db)
+            .attach_printable("Failed to load the configuration stored in the database")?;
+        println!("Loaded config from the database");
+        builder = builder.with_config(db_config);
+    }
+
+    let proxy = builder.build().await.change_context(Error::BuildingProxy)?;
+    let problems = proxy.validate();
+    if problems.is_empty() {
+        println!("Configuration is valid");
+        return Ok(());
+    }
+
+    for problem in &problems {
+        println!("{problem}");
+    }
+
+    Err(Report::new(Error::Config)
+        .attach_printable(format!("Found {} configuration problems", problems.len())))
+}
+
 pub(crate) async fn serve(
     server_config: LocalServerConfig,
     all_configs: Configs,
@@ -136,12 +302,22 @@ pub(crate) async fn serve(
     shutdown: impl Future<Output = ()> + Send + 'static,
 ) -> Result<(), Report<Error>> {
     let proxy = proxy::build_proxy(db, all_configs).await?;
+    // Use the proxy's database, which resolves references to bodies in the blob store.
+    let db = proxy.database().cloned();
 
-    let mut state = Arc::new(ServerState { proxy });
+    let mut state = Arc::new(ServerState { proxy, db });
 
     let app = Router::new()
+        .merge(datasets::create_routes())
         .merge(events::create_routes())
+        .merge(fine_tuning::create_routes())
+        .merge(prompts::create_routes())
         .merge(proxy::create_routes())
+        .merge(replay::create_routes())
+        .merge(runs::create_routes())
+        .merge(scores::create_routes())
+        .merge(search::create_routes())
+        .merge(metrics::create_routes())
         .with_state(state.clone())
         .layer(
             ServiceBuilder::new()
@@ -0,0 +1,22 @@
+use std::sync::Arc;
+
+use axum::{extract::State, response::IntoResponse};
+use error_stack::ResultExt;
+use http::header::CONTENT_TYPE;
+
+use crate::{error::Error, proxy::ServerState};
+
+/// The content type for the Prometheus text exposition format
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+async fn get_metrics(State(state): State<Arc<ServerState>>) -> Result<impl IntoResponse, Error> {
+    let body = state
+        .proxy
+        .gather_metrics()
+        .change_context(Error::Metrics)?;
+    Ok(([(CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], body))
+}
+
+pub fn create_routes() -> axum::Router<Arc<ServerState>> {
+    axum::Router::new().route("/metrics", axum::routing::get(get_metrics))
+}
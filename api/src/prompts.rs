@@ -0,0 +1,405 @@
+use std::{path::PathBuf, sync::Arc};
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chronicle_proxy::{
+    database::Database,
+    format::ChatRequest,
+    prompts::{NewPrompt, Prompt, PromptVersion},
+    ProxyRequestOptions,
+};
+use error_stack::{Report, ResultExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    config::{Configs, LocalServerConfig},
+    database::init_database,
+    error::Error,
+    proxy::{build_proxy, ServerState},
+};
+
+#[derive(Serialize, Debug)]
+struct DeleteResponse {
+    /// The number of versions deleted
+    deleted: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct SetLabelBody {
+    version: u32,
+}
+
+async fn create_prompt(
+    State(state): State<Arc<ServerState>>,
+    Json(body): Json<NewPrompt>,
+) -> Result<Json<Prompt>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let prompt = db.create_prompt(&body).await.change_context(Error::Db)?;
+    Ok(Json(prompt))
+}
+
+/// List the latest version of each prompt
+async fn list_prompts(State(state): State<Arc<ServerState>>) -> Result<Json<Vec<Prompt>>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let prompts = db.list_prompts().await.change_context(Error::Db)?;
+    Ok(Json(prompts))
+}
+
+/// List every version of a prompt
+async fn list_versions(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<Prompt>>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let versions = db
+        .list_prompt_versions(&id)
+        .await
+        .change_context(Error::Db)?;
+    if versions.is_empty() {
+        return Err(Error::NotFound("Prompt"));
+    }
+    Ok(Json(versions))
+}
+
+/// Get a version of a prompt by number, by label, or `latest`
+async fn get_prompt(
+    State(state): State<Arc<ServerState>>,
+    Path((id, version)): Path<(String, String)>,
+) -> Result<Json<Prompt>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let version = version.parse::<PromptVersion>().unwrap_or_default();
+    let prompt = db
+        .get_prompt(&id, &version)
+        .await
+        .change_context(Error::Db)?
+        .ok_or(Error::NotFound("Prompt"))?;
+    Ok(Json(prompt))
+}
+
+async fn delete_prompt(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<String>,
+) -> Result<Json<DeleteResponse>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let deleted = db
+        .delete_prompt(&id, None)
+        .await
+        .change_context(Error::Db)?;
+    if deleted == 0 {
+        return Err(Error::NotFound("Prompt"));
+    }
+    Ok(Json(DeleteResponse { deleted }))
+}
+
+async fn delete_version(
+    State(state): State<Arc<ServerState>>,
+    Path((id, version)): Path<(String, u32)>,
+) -> Result<Json<DeleteResponse>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let deleted = db
+        .delete_prompt(&id, Some(version))
+        .await
+        .change_context(Error::Db)?;
+    if deleted == 0 {
+        return Err(Error::NotFound("Prompt version"));
+    }
+    Ok(Json(DeleteResponse { deleted }))
+}
+
+/// Point a label at a version of the prompt
+async fn set_label(
+    State(state): State<Arc<ServerState>>,
+    Path((id, label)): Path<(String, String)>,
+    Json(body): Json<SetLabelBody>,
+) -> Result<(), Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let updated = db
+        .set_prompt_label(&id, &label, body.version)
+        .await
+        .change_context(Error::Db)?;
+    if !updated {
+        return Err(Error::NotFound("Prompt version"));
+    }
+    Ok(())
+}
+
+async fn remove_label(
+    State(state): State<Arc<ServerState>>,
+    Path((id, label)): Path<(String, String)>,
+) -> Result<(), Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let removed = db
+        .remove_prompt_label(&id, &label)
+        .await
+        .change_context(Error::Db)?;
+    if !removed {
+        return Err(Error::NotFound("Prompt label"));
+    }
+    Ok(())
+}
+
+pub fn create_routes() -> axum::Router<Arc<ServerState>> {
+    axum::Router::new()
+        .route(
+            "/v1/prompts",
+            axum::routing::get(list_prompts).post(create_prompt),
+        )
+        .route(
+            "/v1/prompts/:id",
+            axum::routing::get(list_versions).delete(delete_prompt),
+        )
+        .route(
+            "/v1/prompts/:id/:version",
+            axum::routing::get(get_prompt).delete(delete_version),
+        )
+        .route(
+            "/v1/prompts/:id/labels/:label",
+            axum::routing::put(set_label).delete(remove_label),
+        )
+}
+
+#[derive(Debug, clap::Args)]
+pub struct PromptArgs {
+    #[command(subcommand)]
+    command: PromptCommand,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum PromptCommand {
+    /// List the latest version of each prompt in the database
+    List,
+    /// Print a version of a prompt as JSON
+    Show {
+        id: String,
+        /// A version number, a label, or `latest`
+        #[clap(default_value = "latest")]
+        version: String,
+    },
+    /// Create a prompt, or the next version of an existing prompt, from a JSON or TOML file
+    Create {
+        file: PathBuf,
+        /// Point this label at the new version. This can be given multiple times.
+        #[clap(long = "label", short = 'l')]
+        labels: Vec<String>,
+    },
+    /// Point a label at a version of a prompt
+    Label {
+        id: String,
+        label: String,
+        version: u32,
+    },
+    /// Remove a label from a prompt
+    Unlabel { id: String, label: String },
+    /// Delete a version of a prompt, or every version if `--version` is omitted
+    Delete {
+        id: String,
+        #[clap(long)]
+        version: Option<u32>,
+    },
+    /// Render a prompt with variables and print the resulting request. This includes prompts
+    /// from the configuration.
+    Render {
+        id: String,
+        /// A version number, a label, or `latest`
+        #[clap(long, default_value = "latest")]
+        version: String,
+        /// Set a variable, as `NAME=VALUE`. This can be given multiple times.
+        #[clap(long = "var", short = 'v')]
+        variables: Vec<String>,
+    },
+}
+
+/// Parse a `NAME=VALUE` variable from the command line
+fn parse_variable(var: &str) -> Result<(String, serde_json::Value), Report<Error>> {
+    let (name, value) = var
+        .split_once('=')
+        .ok_or(Error::Prompt)
+        .attach_printable_lazy(|| format!("Variable {var} should be in the form NAME=VALUE"))?;
+    Ok((
+        name.to_string(),
+        serde_json::Value::String(value.to_string()),
+    ))
+}
+
+/// Read a new prompt from a JSON or TOML file
+fn read_prompt_file(path: &std::path::Path) -> Result<NewPrompt, Report<Error>> {
+    let data = std::fs::read_to_string(path)
+        .change_context(Error::Prompt)
+        .attach_printable_lazy(|| format!("Failed to read {}", path.display()))?;
+    let prompt = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&data).change_context(Error::Prompt)?
+    } else {
+        serde_json::from_str(&data).change_context(Error::Prompt)?
+    };
+    Ok(prompt)
+}
+
+fn print_json(value: &impl Serialize) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).unwrap_or_default()
+    );
+}
+
+/// Render a prompt, using the prompts from the configuration as well as the database, and print
+/// the request that it produces
+async fn render(
+    db: Option<Database>,
+    configs: Configs,
+    id: String,
+    version: &str,
+    variables: &[String],
+) -> Result<(), Report<Error>> {
+    let proxy = build_proxy(db, configs).await?;
+    let version = version.parse::<PromptVersion>().unwrap_or_default();
+    let prompt = proxy
+        .get_prompt(&id, &version)
+        .await
+        .change_context(Error::Prompt)?
+        .ok_or(Error::NotFound("Prompt"))?;
+    let variables = variables
+        .iter()
+        .map(|var| parse_variable(var))
+        .collect::<Result<_, _>>()?;
+
+    let mut options = ProxyRequestOptions::default();
+    let mut request = ChatRequest::default();
+    prompt
+        .apply(&variables, &mut request, &mut options)
+        .change_context(Error::Prompt)?;
+    print_json(&json!({
+        "prompt_id": prompt.id,
+        "prompt_version": prompt.version,
+        "model": options.model,
+        "provider": options.provider,
+        "request": request,
+    }));
+    Ok(())
+}
+
+/// Manage the prompts stored in the database
+pub async fn prompt(
+    args: PromptArgs,
+    server_config: LocalServerConfig,
+    configs: Configs,
+) -> Result<(), Report<Error>> {
+    let tables = server_config.table_names()?;
+    let db = init_database(server_config.database, &tables)
+        .await
+        .change_context(Error::Db)?;
+
+    let db = match args.command {
+        PromptCommand::Render {
+            id,
+            version,
+            variables,
+        } => return render(db, configs, id, &version, &variables).await,
+        _ => db.ok_or(Error::NoDatabase)?,
+    };
+
+    match args.command {
+        PromptCommand::List => {
+            let prompts = db.list_prompts().await.change_context(Error::Db)?;
+            for prompt in prompts {
+                let labels = if prompt.labels.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", prompt.labels.join(", "))
+                };
+                println!(
+                    "{} v{}{labels}{}",
+                    prompt.id,
+                    prompt.version,
+                    prompt
+                        .description
+                        .map(|d| format!(": {d}"))
+                        .unwrap_or_default()
+                );
+            }
+        }
+        PromptCommand::Show { id, version } => {
+            let version = version.parse::<PromptVersion>().unwrap_or_default();
+            let prompt = db
+                .get_prompt(&id, &version)
+                .await
+                .change_context(Error::Db)?
+                .ok_or(Error::NotFound("Prompt"))?;
+            print_json(&prompt);
+        }
+        PromptCommand::Create { file, labels } => {
+            let mut prompt = read_prompt_file(&file)?;
+            prompt.labels.extend(labels);
+            let prompt = db.create_prompt(&prompt).await.change_context(Error::Db)?;
+            println!("Created {} v{}", prompt.id, prompt.version);
+        }
+        PromptCommand::Label { id, label, version } => {
+            let updated = db
+                .set_prompt_label(&id, &label, version)
+                .await
+                .change_context(Error::Db)?;
+            if !updated {
+                return Err(Report::new(Error::NotFound("Prompt version")));
+            }
+        }
+        PromptCommand::Unlabel { id, label } => {
+            let removed = db
+                .remove_prompt_label(&id, &label)
+                .await
+                .change_context(Error::Db)?;
+            if !removed {
+                return Err(Report::new(Error::NotFound("Prompt label")));
+            }
+        }
+        PromptCommand::Delete { id, version } => {
+            let deleted = db
+                .delete_prompt(&id, version)
+                .await
+                .change_context(Error::Db)?;
+            if deleted == 0 {
+                return Err(Report::new(Error::NotFound("Prompt")));
+            }
+            println!("Deleted {deleted} versions of {id}");
+        }
+        PromptCommand::Render { .. } => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn variables() {
+        assert_eq!(
+            parse_variable("name=Ada=Lovelace").unwrap(),
+            ("name".to_string(), json!("Ada=Lovelace"))
+        );
+        assert!(parse_variable("name").is_err(), "missing value");
+    }
+
+    #[test]
+    fn toml_prompt() {
+        let prompt: NewPrompt = toml::from_str(
+            r#"
+            id = "summarize"
+            system = "Summarize text about {{topic}}"
+            model = "gpt-4o"
+            labels = ["production"]
+
+            [[messages]]
+            role = "user"
+            content = "{{text}}"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(prompt.id, "summarize");
+        assert_eq!(prompt.template.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(prompt.template.messages[0].content, "{{text}}");
+        assert_eq!(prompt.labels, vec!["production"]);
+    }
+}
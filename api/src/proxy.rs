@@ -7,6 +7,7 @@ use axum::{
     Json,
 };
 use chronicle_proxy::{
+    builder::ProxyBuilder,
     collect_response,
     database::Database,
     format::{
@@ -21,7 +22,7 @@ use serde::{Deserialize, Serialize};
 use crate::{config::Configs, Error};
 
 pub async fn build_proxy(db: Option<Database>, configs: Configs) -> Result<Proxy, Report<Error>> {
-    let mut builder = Proxy::builder();
+    let mut builder = proxy_builder(configs);
 
     if let Some(db) = db {
         builder = builder
@@ -30,15 +31,23 @@ pub async fn build_proxy(db: Option<Database>, configs: Configs) -> Result<Proxy
             .load_config_from_database(true);
     }
 
+    builder.build().await.change_context(Error::BuildingProxy)
+}
+
+/// Create a proxy builder with the configuration from the config files.
+pub fn proxy_builder(configs: Configs) -> ProxyBuilder {
+    let mut builder = Proxy::builder();
+
     for (_, config) in configs.global.into_iter().chain(configs.cwd.into_iter()) {
         builder = builder.with_config(config.proxy_config);
     }
 
-    builder.build().await.change_context(Error::BuildingProxy)
+    builder
 }
 
 pub struct ServerState {
     pub proxy: Proxy,
+    pub db: Option<Database>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -85,7 +94,10 @@ async fn proxy_request(
         .proxy
         .send(body.options, body.request)
         .await
-        .change_context(Error::Proxy)?;
+        .map_err(|e| match e.current_context() {
+            chronicle_proxy::Error::PromptNotFound(_) => Error::NotFound("Prompt"),
+            _ => Error::from(e.change_context(Error::Proxy)),
+        })?;
 
     if stream {
         // The first item will always be a RequestInfo or an error. We pull it off here so that if the
@@ -0,0 +1,75 @@
+use chronicle_proxy::database::blobs::offload_to_blob_store;
+use chrono::Utc;
+use error_stack::{Report, ResultExt};
+
+use crate::{
+    config::{Configs, LocalServerConfig},
+    database::init_database,
+    Error,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct PurgeArgs {
+    /// Print how many rows would be deleted, without deleting anything
+    #[clap(long)]
+    dry_run: bool,
+}
+
+/// Delete data older than the retention policy in the configuration
+pub async fn purge(
+    args: PurgeArgs,
+    server_config: &LocalServerConfig,
+    configs: Configs,
+) -> Result<(), Report<Error>> {
+    // Later configs take precedence, as when building the proxy.
+    let proxy_configs = configs
+        .global
+        .into_iter()
+        .chain(configs.cwd)
+        .map(|(_, config)| config.proxy_config)
+        .collect::<Vec<_>>();
+    let retention = proxy_configs
+        .iter()
+        .filter_map(|config| config.retention.clone())
+        .last()
+        .ok_or(Error::Config)
+        .attach_printable("No retention policy is configured")?;
+    let blob_storage = proxy_configs
+        .into_iter()
+        .filter_map(|config| config.blob_storage)
+        .last();
+
+    let tables = server_config.table_names()?;
+    let mut db = init_database(server_config.database.clone(), &tables)
+        .await
+        .change_context(Error::Db)?
+        .ok_or(Error::NoDatabase)?;
+    if let Some(blob_storage) = blob_storage {
+        // Archives should contain the bodies, not references to them.
+        db = offload_to_blob_store(db, blob_storage);
+    }
+
+    let counts = chronicle_proxy::database::retention::purge(
+        db.as_ref(),
+        &retention,
+        Utc::now(),
+        args.dry_run,
+    )
+    .await
+    .change_context(Error::Purge)?;
+
+    let verb = if args.dry_run {
+        "Would delete"
+    } else {
+        "Deleted"
+    };
+    println!(
+        "{verb} {} events, {} runs, and {} steps",
+        counts.events, counts.runs, counts.steps
+    );
+    if counts.partitions > 0 {
+        println!("Dropped {} expired event partitions", counts.partitions);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    Json,
+};
+use chronicle_proxy::{
+    replay::{ReplayOverrides, ReplayResult},
+    ProxyRequestMetadata, ProxyRequestOptions,
+};
+use error_stack::ResultExt;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{error::Error, proxy::ServerState};
+
+#[derive(Deserialize, Debug)]
+struct ReplayBody {
+    #[serde(flatten)]
+    overrides: ReplayOverrides,
+    /// Metadata to record for the new event
+    #[serde(default)]
+    metadata: ProxyRequestMetadata,
+}
+
+/// Send a logged request again with some of its settings changed, and return the new response
+/// alongside the original
+async fn replay_event(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(body): Json<ReplayBody>,
+) -> Result<Json<ReplayResult>, Error> {
+    state.db.as_ref().ok_or(Error::NoDatabase)?;
+
+    let mut options = ProxyRequestOptions {
+        metadata: body.metadata,
+        ..Default::default()
+    };
+    options
+        .merge_request_headers(&headers)
+        .change_context(Error::InvalidProxyHeader)?;
+
+    let result = state
+        .proxy
+        .replay(id, &body.overrides, options)
+        .await
+        .map_err(|e| match e.current_context() {
+            chronicle_proxy::Error::EventNotFound(_) => Error::NotFound("Event"),
+            _ => Error::from(e.change_context(Error::Proxy)),
+        })?;
+    Ok(Json(result))
+}
+
+pub fn create_routes() -> axum::Router<Arc<ServerState>> {
+    axum::Router::new().route(
+        "/v1/llm_events/:id/replay",
+        axum::routing::post(replay_event),
+    )
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chronicle_proxy::database::runs::{RunDetail, RunQuery, RunSummary};
+use chrono::{DateTime, Utc};
+use error_stack::ResultExt;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{error::Error, proxy::ServerState};
+
+#[derive(Deserialize, Debug)]
+struct ListRunsQuery {
+    name: Option<String>,
+    application: Option<String>,
+    environment: Option<String>,
+    status: Option<String>,
+    /// A comma-separated list of tags. Only runs with all of the tags are returned.
+    tags: Option<String>,
+    start_time: Option<DateTime<Utc>>,
+    end_time: Option<DateTime<Utc>>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+impl From<ListRunsQuery> for RunQuery {
+    fn from(query: ListRunsQuery) -> Self {
+        RunQuery {
+            name: query.name,
+            application: query.application,
+            environment: query.environment,
+            status: query.status,
+            tags: query
+                .tags
+                .map(|tags| {
+                    tags.split(',')
+                        .map(|t| t.trim())
+                        .filter(|t| !t.is_empty())
+                        .map(|t| t.to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            start_time: query.start_time,
+            end_time: query.end_time,
+            limit: query.limit,
+            offset: query.offset,
+        }
+    }
+}
+
+async fn list_runs(
+    State(state): State<Arc<ServerState>>,
+    Query(query): Query<ListRunsQuery>,
+) -> Result<Json<Vec<RunSummary>>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let runs = db
+        .list_runs(&RunQuery::from(query))
+        .await
+        .change_context(Error::Db)?;
+    Ok(Json(runs))
+}
+
+async fn get_run(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<RunDetail>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let run = db
+        .get_run(id)
+        .await
+        .change_context(Error::Db)?
+        .ok_or(Error::NotFound("Run"))?;
+    Ok(Json(run))
+}
+
+pub fn create_routes() -> axum::Router<Arc<ServerState>> {
+    axum::Router::new()
+        .route("/v1/runs", axum::routing::get(list_runs))
+        .route("/v1/runs/:id", axum::routing::get(get_run))
+}
@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chronicle_proxy::database::scores::{summarize_scores, Score, ScoreSummary, ScoreTarget};
+use error_stack::ResultExt;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{error::Error, proxy::ServerState};
+
+#[derive(Serialize, Debug)]
+struct ScoresResponse {
+    /// Statistics for each score name and source
+    summary: Vec<ScoreSummary>,
+    /// The individual scores, oldest first
+    scores: Vec<Score>,
+}
+
+async fn list_scores(state: &ServerState, target: ScoreTarget) -> Result<ScoresResponse, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let scores = db.list_scores(target).await.change_context(Error::Db)?;
+    Ok(ScoresResponse {
+        summary: summarize_scores(&scores),
+        scores,
+    })
+}
+
+/// Scores for a run, including the scores for its steps and LLM calls
+async fn get_run_scores(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ScoresResponse>, Error> {
+    Ok(Json(list_scores(&state, ScoreTarget::Run(id)).await?))
+}
+
+/// Scores for a single LLM call
+async fn get_event_scores(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ScoresResponse>, Error> {
+    Ok(Json(list_scores(&state, ScoreTarget::Event(id)).await?))
+}
+
+pub fn create_routes() -> axum::Router<Arc<ServerState>> {
+    axum::Router::new()
+        .route("/v1/runs/:id/scores", axum::routing::get(get_run_scores))
+        .route(
+            "/v1/events/:id/scores",
+            axum::routing::get(get_event_scores),
+        )
+}
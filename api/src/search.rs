@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use chronicle_proxy::database::search::{EventSearchQuery, EventSearchResult};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::{error::Error, proxy::ServerState};
+
+#[derive(Deserialize, Debug)]
+struct SearchEventsQuery {
+    /// The words to search for
+    q: String,
+    application: Option<String>,
+    environment: Option<String>,
+    start_time: Option<DateTime<Utc>>,
+    end_time: Option<DateTime<Utc>>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+impl From<SearchEventsQuery> for EventSearchQuery {
+    fn from(query: SearchEventsQuery) -> Self {
+        EventSearchQuery {
+            query: query.q,
+            application: query.application,
+            environment: query.environment,
+            start_time: query.start_time,
+            end_time: query.end_time,
+            limit: query.limit,
+            offset: query.offset,
+        }
+    }
+}
+
+async fn search_events(
+    State(state): State<Arc<ServerState>>,
+    Query(query): Query<SearchEventsQuery>,
+) -> Result<Json<Vec<EventSearchResult>>, Error> {
+    let db = state.db.as_ref().ok_or(Error::NoDatabase)?;
+    let events = db
+        .search_events(&EventSearchQuery::from(query))
+        .await
+        .map_err(|e| match e.current_context() {
+            chronicle_proxy::Error::SearchNotEnabled => Error::SearchNotEnabled,
+            _ => e.change_context(Error::Db).into(),
+        })?;
+    Ok(Json(events))
+}
+
+pub fn create_routes() -> axum::Router<Arc<ServerState>> {
+    axum::Router::new().route("/v1/events/search", axum::routing::get(search_events))
+}
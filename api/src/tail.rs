@@ -0,0 +1,160 @@
+use chronicle_proxy::database::live_tail::{LiveEvent, LiveEventFilter};
+use error_stack::{Report, ResultExt};
+use eventsource_stream::Eventsource;
+use futures::StreamExt;
+use uuid::Uuid;
+
+use crate::{config::LocalServerConfig, Error};
+
+#[derive(Debug, clap::Args)]
+pub struct TailArgs {
+    /// The URL of the Chronicle server. If omitted, the host and port from the configuration
+    /// are used.
+    #[clap(long)]
+    url: Option<String>,
+
+    /// Only show events from this run
+    #[clap(long)]
+    run_id: Option<Uuid>,
+
+    /// Only show events from this application
+    #[clap(long)]
+    application: Option<String>,
+
+    /// Only show events from this user
+    #[clap(long)]
+    user_id: Option<String>,
+
+    /// Print each event as a line of JSON
+    #[clap(long)]
+    json: bool,
+}
+
+/// Connect to a running server and print events as they are logged
+pub async fn tail(args: TailArgs, server_config: &LocalServerConfig) -> Result<(), Report<Error>> {
+    let base_url = args.url.clone().unwrap_or_else(|| {
+        let host = server_config.host.as_deref().unwrap_or("::1");
+        let port = server_config.port.unwrap_or(9782);
+        if host.contains(':') {
+            format!("http://[{host}]:{port}")
+        } else {
+            format!("http://{host}:{port}")
+        }
+    });
+
+    let filter = LiveEventFilter {
+        run_id: args.run_id,
+        application: args.application,
+        user_id: args.user_id,
+    };
+
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/v1/events/stream",
+            base_url.trim_end_matches('/')
+        ))
+        .query(&filter)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .change_context(Error::LiveTail)
+        .attach_printable_lazy(|| format!("Failed to connect to {base_url}"))?;
+
+    let mut stream = response.bytes_stream().eventsource();
+    while let Some(event) = stream.next().await {
+        let event = event.change_context(Error::LiveTail)?;
+        if event.event == "lagged" {
+            eprintln!("Fell behind, skipped {} events", event.data);
+            continue;
+        }
+
+        if args.json {
+            println!("{}", event.data);
+            continue;
+        }
+
+        match serde_json::from_str::<LiveEvent>(&event.data) {
+            Ok(event) => println!("{}", format_event(&event)),
+            Err(e) => eprintln!("Failed to parse event: {e}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn format_event(event: &LiveEvent) -> String {
+    let mut parts = vec![
+        event.time.format("%H:%M:%S%.3f").to_string(),
+        event.typ.clone(),
+    ];
+
+    if let Some(name) = &event.name {
+        parts.push(name.clone());
+    }
+
+    match (&event.provider, &event.model) {
+        (Some(provider), Some(model)) => parts.push(format!("{provider}/{model}")),
+        (Some(s), None) | (None, Some(s)) => parts.push(s.clone()),
+        (None, None) => {}
+    }
+
+    if let Some(status) = &event.status {
+        parts.push(format!("status={status}"));
+    }
+
+    if let Some(latency) = event.latency_ms {
+        parts.push(format!("{latency}ms"));
+    }
+
+    if event.prompt_tokens.is_some() || event.completion_tokens.is_some() {
+        parts.push(format!(
+            "tokens={}/{}",
+            event.prompt_tokens.unwrap_or(0),
+            event.completion_tokens.unwrap_or(0)
+        ));
+    }
+
+    if let Some(run_id) = event.run_id {
+        parts.push(format!("run={run_id}"));
+    }
+
+    if let Some(step_id) = event.step_id {
+        parts.push(format!("step={step_id}"));
+    }
+
+    if let Some(error) = &event.error {
+        parts.push(format!("error={error}"));
+    }
+
+    parts.join("  ")
+}
+
+#[cfg(test)]
+mod test {
+    use chronicle_proxy::database::live_tail::LiveEvent;
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::format_event;
+
+    #[test]
+    fn format_llm_event() {
+        let event: LiveEvent = serde_json::from_value(serde_json::json!({
+            "type": "chronicle_llm_request",
+            "id": Uuid::nil(),
+            "run_id": Uuid::from_u128(1),
+            "provider": "openai",
+            "model": "gpt-4o",
+            "latency_ms": 1234,
+            "prompt_tokens": 10,
+            "completion_tokens": 5,
+            "time": Utc.timestamp_opt(1, 0).unwrap(),
+        }))
+        .unwrap();
+
+        assert_eq!(
+            format_event(&event),
+            "00:00:01.000  chronicle_llm_request  openai/gpt-4o  1234ms  tokens=10/5  run=00000000-0000-0000-0000-000000000001"
+        );
+    }
+}
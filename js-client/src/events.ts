@@ -27,10 +27,14 @@ export interface RunStartEvent {
   span_id?: string;
   /** Array of tags associated with the run */
   tags?: string[];
-  /** Optional additional information about the run */
+  /** Optional additional information about the run. A numeric `stale_timeout` sets how many
+   * seconds the run can go without updates before the proxy marks it as timed out. */
   info?: object;
   /** Optional timestamp of when the event occurred */
   time?: Date;
+  /** Optional UUIDv7 identifying this event. An event sent more than once with the same ID is only
+   * applied once, and if `time` is omitted the time is taken from the ID. */
+  event_id?: Uuid;
 }
 
 /** Updates a run in a workflow */
@@ -47,6 +51,9 @@ export interface RunUpdateEvent {
   info?: object;
   /** Optional timestamp of when the event occurred */
   time?: Date;
+  /** Optional UUIDv7 identifying this event. An event sent more than once with the same ID is only
+   * applied once, and if `time` is omitted the time is taken from the ID. */
+  event_id?: Uuid;
 }
 
 /** Step event in a workflow */
@@ -59,6 +66,9 @@ export interface StepEventData<TYPE, T> {
   run_id?: Uuid;
   /** Optional timestamp of when the event occurred */
   time?: Date;
+  /** Optional UUIDv7 identifying this event. An event sent more than once with the same ID is only
+   * applied once, and if `time` is omitted the time is taken from the ID. */
+  event_id?: Uuid;
   /** The data associated with this step event */
   data: T;
 }
@@ -113,13 +123,39 @@ export type StepErrorEvent = StepEventData<'step:error', ErrorData>;
 /** Represents a step state change event */
 export type StepStateEvent = StepEventData<'step:state', StepStateData>;
 
+/** A score for an LLM call, step, or run, such as user feedback or an automated evaluation */
+export interface ScoreEvent {
+  /** The type of the event */
+  type: 'score';
+  /** Optional unique identifier for the score. Scores with the same ID are only recorded once. */
+  id?: Uuid;
+  /** The run that the score is for. If not supplied, this will be filled in from the context,
+   * or from the step or LLM call. */
+  run_id?: Uuid;
+  /** The step that the score is for */
+  step_id?: Uuid;
+  /** The LLM call that the score is for, from the `meta.id` of the response */
+  event_id?: Uuid;
+  /** What the score measures, such as `helpfulness` or `thumbs_up` */
+  name: string;
+  /** A number, a pass/fail boolean, or a category label */
+  value: number | boolean | string;
+  /** Where the score came from */
+  source: 'human' | 'model' | 'heuristic';
+  /** Optional comment explaining the score */
+  comment?: string;
+  /** Optional timestamp of when the event occurred */
+  time?: Date;
+}
+
 export type WorkflowEventTypes =
   | 'run:start'
   | 'run:update'
   | 'step:start'
   | 'step:end'
   | 'step:error'
-  | 'step:state';
+  | 'step:state'
+  | 'score';
 
 /** Represents a generic event in the system */
 export interface GenericEvent<
@@ -140,6 +176,9 @@ export interface GenericEvent<
   step_id?: Uuid;
   /** Timestamp of when the event occurred. If not supplied, `new Date()` will be used. */
   time?: Date;
+  /** Optional UUIDv7 identifying this event. An event sent more than once with the same ID is only
+   * applied once, and if `time` is omitted the time is taken from the ID. */
+  event_id?: Uuid;
 }
 
 export type ChronicleWorkflowEvent =
@@ -148,7 +187,8 @@ export type ChronicleWorkflowEvent =
   | StepStartEvent
   | StepEndEvent
   | StepErrorEvent
-  | StepStateEvent;
+  | StepStateEvent
+  | ScoreEvent;
 
 /** Represents any type of event that can be submitted to Chronicle */
 export type ChronicleEvent = ChronicleWorkflowEvent | GenericEvent;
@@ -161,7 +201,8 @@ export function isWorkflowEvent(event: ChronicleEvent): event is ChronicleWorkfl
     event.type === 'step:start' ||
     event.type === 'step:end' ||
     event.type === 'step:error' ||
-    event.type === 'step:state'
+    event.type === 'step:state' ||
+    event.type === 'score'
   );
 }
 
@@ -199,6 +240,9 @@ export function fillInEvents(
         case 'run:start':
           event.span_id ??= spanCtx?.spanId;
           event.trace_id ??= spanCtx?.traceId;
+          break;
+        case 'score':
+          event.run_id ??= runId;
       }
     } else {
       event.run_id ??= runId ?? NIL_UUID;
@@ -0,0 +1,2 @@
+-- Reverts 20240801_chronicle_proxy_retention_postgresql.sql
+DROP INDEX IF EXISTS chronicle_events_created_at_idx;
@@ -0,0 +1,2 @@
+-- Supports deleting old events
+CREATE INDEX chronicle_events_created_at_idx ON chronicle_events (created_at);
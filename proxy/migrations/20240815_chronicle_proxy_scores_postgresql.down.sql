@@ -0,0 +1,2 @@
+-- Reverts 20240815_chronicle_proxy_scores_postgresql.sql
+DROP TABLE IF EXISTS chronicle_scores;
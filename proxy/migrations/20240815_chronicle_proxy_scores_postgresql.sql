@@ -0,0 +1,17 @@
+CREATE TABLE chronicle_scores (
+  id uuid PRIMARY KEY,
+  run_id uuid,
+  step_id uuid,
+  event_id uuid,
+  name text NOT NULL,
+  value jsonb NOT NULL,
+  source text NOT NULL,
+  comment text,
+  created_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+CREATE INDEX chronicle_scores_run_id_idx ON chronicle_scores (run_id);
+
+CREATE INDEX chronicle_scores_step_id_idx ON chronicle_scores (step_id);
+
+CREATE INDEX chronicle_scores_event_id_idx ON chronicle_scores (event_id);
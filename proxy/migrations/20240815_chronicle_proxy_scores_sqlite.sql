@@ -0,0 +1,17 @@
+CREATE TABLE chronicle_scores (
+  id text PRIMARY KEY,
+  run_id text,
+  step_id text,
+  event_id text,
+  name text NOT NULL,
+  value text NOT NULL,
+  source text NOT NULL,
+  comment text,
+  created_at int NOT NULL
+);
+
+CREATE INDEX chronicle_scores_run_id_idx ON chronicle_scores (run_id);
+
+CREATE INDEX chronicle_scores_step_id_idx ON chronicle_scores (step_id);
+
+CREATE INDEX chronicle_scores_event_id_idx ON chronicle_scores (event_id);
@@ -0,0 +1,19 @@
+CREATE TABLE chronicle_datasets (
+  id uuid PRIMARY KEY,
+  name text NOT NULL,
+  version int NOT NULL,
+  description text,
+  created_at timestamp with time zone NOT NULL DEFAULT now(),
+  UNIQUE (name, version)
+);
+
+CREATE TABLE chronicle_dataset_items (
+  id uuid PRIMARY KEY,
+  dataset_id uuid NOT NULL REFERENCES chronicle_datasets (id) ON DELETE CASCADE,
+  event_id uuid,
+  request jsonb NOT NULL,
+  expected_response jsonb,
+  created_at timestamp with time zone NOT NULL DEFAULT now(),
+  updated_at timestamp with time zone NOT NULL DEFAULT now(),
+  UNIQUE (dataset_id, event_id)
+);
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS chronicle_dataset_items;
+
+DROP TABLE IF EXISTS chronicle_datasets;
@@ -0,0 +1,19 @@
+CREATE TABLE chronicle_datasets (
+  id text PRIMARY KEY,
+  name text NOT NULL,
+  version int NOT NULL,
+  description text,
+  created_at int NOT NULL,
+  UNIQUE (name, version)
+);
+
+CREATE TABLE chronicle_dataset_items (
+  id text PRIMARY KEY,
+  dataset_id text NOT NULL REFERENCES chronicle_datasets (id) ON DELETE CASCADE,
+  event_id text,
+  request text NOT NULL,
+  expected_response text,
+  created_at int NOT NULL,
+  updated_at int NOT NULL,
+  UNIQUE (dataset_id, event_id)
+);
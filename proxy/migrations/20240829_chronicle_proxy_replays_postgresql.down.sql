@@ -0,0 +1,2 @@
+-- Reverts 20240829_chronicle_proxy_replays_postgresql.sql
+ALTER TABLE chronicle_events DROP COLUMN IF EXISTS replay_of;
@@ -0,0 +1,2 @@
+-- Links an event to the event that it replays
+ALTER TABLE chronicle_events ADD COLUMN replay_of uuid;
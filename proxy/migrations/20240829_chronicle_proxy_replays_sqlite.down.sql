@@ -0,0 +1,2 @@
+-- Reverts 20240829_chronicle_proxy_replays_sqlite.sql
+ALTER TABLE chronicle_events DROP COLUMN replay_of;
@@ -0,0 +1,18 @@
+CREATE TABLE chronicle_prompts (
+  prompt_id text NOT NULL,
+  version int NOT NULL,
+  description text,
+  template jsonb NOT NULL,
+  created_at timestamp with time zone NOT NULL DEFAULT now(),
+  PRIMARY KEY (prompt_id, version)
+);
+
+CREATE TABLE chronicle_prompt_labels (
+  prompt_id text NOT NULL,
+  label text NOT NULL,
+  version int NOT NULL,
+  updated_at timestamp with time zone NOT NULL DEFAULT now(),
+  PRIMARY KEY (prompt_id, label),
+  FOREIGN KEY (prompt_id, version)
+    REFERENCES chronicle_prompts (prompt_id, version) ON DELETE CASCADE
+);
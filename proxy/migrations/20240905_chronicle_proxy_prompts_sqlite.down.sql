@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS chronicle_prompt_labels;
+
+DROP TABLE IF EXISTS chronicle_prompts;
@@ -0,0 +1,14 @@
+-- Reverts 20240912_chronicle_proxy_usage_rollups_postgresql.sql
+ALTER TABLE chronicle_runs
+  DROP COLUMN IF EXISTS llm_calls,
+  DROP COLUMN IF EXISTS prompt_tokens,
+  DROP COLUMN IF EXISTS completion_tokens,
+  DROP COLUMN IF EXISTS llm_errors,
+  DROP COLUMN IF EXISTS llm_latency_ms;
+
+ALTER TABLE chronicle_steps
+  DROP COLUMN IF EXISTS llm_calls,
+  DROP COLUMN IF EXISTS prompt_tokens,
+  DROP COLUMN IF EXISTS completion_tokens,
+  DROP COLUMN IF EXISTS llm_errors,
+  DROP COLUMN IF EXISTS llm_latency_ms;
@@ -0,0 +1,14 @@
+-- Totals of the LLM calls in each step and run. A step's totals include its child steps.
+ALTER TABLE chronicle_steps
+  ADD COLUMN llm_calls bigint NOT NULL DEFAULT 0,
+  ADD COLUMN prompt_tokens bigint NOT NULL DEFAULT 0,
+  ADD COLUMN completion_tokens bigint NOT NULL DEFAULT 0,
+  ADD COLUMN llm_errors bigint NOT NULL DEFAULT 0,
+  ADD COLUMN llm_latency_ms bigint NOT NULL DEFAULT 0;
+
+ALTER TABLE chronicle_runs
+  ADD COLUMN llm_calls bigint NOT NULL DEFAULT 0,
+  ADD COLUMN prompt_tokens bigint NOT NULL DEFAULT 0,
+  ADD COLUMN completion_tokens bigint NOT NULL DEFAULT 0,
+  ADD COLUMN llm_errors bigint NOT NULL DEFAULT 0,
+  ADD COLUMN llm_latency_ms bigint NOT NULL DEFAULT 0;
@@ -0,0 +1,12 @@
+-- Reverts 20240912_chronicle_proxy_usage_rollups_sqlite.sql
+ALTER TABLE chronicle_runs DROP COLUMN llm_calls;
+ALTER TABLE chronicle_runs DROP COLUMN prompt_tokens;
+ALTER TABLE chronicle_runs DROP COLUMN completion_tokens;
+ALTER TABLE chronicle_runs DROP COLUMN llm_errors;
+ALTER TABLE chronicle_runs DROP COLUMN llm_latency_ms;
+
+ALTER TABLE chronicle_steps DROP COLUMN llm_calls;
+ALTER TABLE chronicle_steps DROP COLUMN prompt_tokens;
+ALTER TABLE chronicle_steps DROP COLUMN completion_tokens;
+ALTER TABLE chronicle_steps DROP COLUMN llm_errors;
+ALTER TABLE chronicle_steps DROP COLUMN llm_latency_ms;
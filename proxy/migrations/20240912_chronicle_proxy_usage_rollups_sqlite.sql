@@ -0,0 +1,12 @@
+-- Totals of the LLM calls in each step and run. A step's totals include its child steps.
+ALTER TABLE chronicle_steps ADD COLUMN llm_calls int NOT NULL DEFAULT 0;
+ALTER TABLE chronicle_steps ADD COLUMN prompt_tokens int NOT NULL DEFAULT 0;
+ALTER TABLE chronicle_steps ADD COLUMN completion_tokens int NOT NULL DEFAULT 0;
+ALTER TABLE chronicle_steps ADD COLUMN llm_errors int NOT NULL DEFAULT 0;
+ALTER TABLE chronicle_steps ADD COLUMN llm_latency_ms int NOT NULL DEFAULT 0;
+
+ALTER TABLE chronicle_runs ADD COLUMN llm_calls int NOT NULL DEFAULT 0;
+ALTER TABLE chronicle_runs ADD COLUMN prompt_tokens int NOT NULL DEFAULT 0;
+ALTER TABLE chronicle_runs ADD COLUMN completion_tokens int NOT NULL DEFAULT 0;
+ALTER TABLE chronicle_runs ADD COLUMN llm_errors int NOT NULL DEFAULT 0;
+ALTER TABLE chronicle_runs ADD COLUMN llm_latency_ms int NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- Reverts 20240919_chronicle_proxy_step_updates_postgresql.sql
+ALTER TABLE chronicle_steps
+  DROP COLUMN IF EXISTS updated_at;
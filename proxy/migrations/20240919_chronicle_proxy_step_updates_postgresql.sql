@@ -0,0 +1,10 @@
+-- The time of the latest event applied to each step, used to merge events that arrive out of order
+ALTER TABLE chronicle_steps
+  ADD COLUMN updated_at timestamp with time zone;
+
+UPDATE chronicle_steps
+SET updated_at = COALESCE(end_time, start_time);
+
+ALTER TABLE chronicle_steps
+  ALTER COLUMN updated_at SET NOT NULL,
+  ALTER COLUMN updated_at SET DEFAULT now();
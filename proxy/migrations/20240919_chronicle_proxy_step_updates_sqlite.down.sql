@@ -0,0 +1,2 @@
+-- Reverts 20240919_chronicle_proxy_step_updates_sqlite.sql
+ALTER TABLE chronicle_steps DROP COLUMN updated_at;
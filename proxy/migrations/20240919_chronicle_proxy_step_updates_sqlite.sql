@@ -0,0 +1,5 @@
+-- The time of the latest event applied to each step, used to merge events that arrive out of order
+ALTER TABLE chronicle_steps ADD COLUMN updated_at int;
+
+UPDATE chronicle_steps
+SET updated_at = COALESCE(end_time, start_time);
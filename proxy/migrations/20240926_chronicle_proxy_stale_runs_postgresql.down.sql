@@ -0,0 +1,2 @@
+-- Reverts 20240926_chronicle_proxy_stale_runs_postgresql.sql
+DROP INDEX IF EXISTS chronicle_runs_started_idx;
@@ -0,0 +1,2 @@
+-- Supports finding runs that have stopped sending updates
+CREATE INDEX chronicle_runs_started_idx ON chronicle_runs (updated_at) WHERE status = 'started';
@@ -0,0 +1,4 @@
+-- Removes the full-text search column and its index. This runs from
+-- `postgres::disable_search_index`.
+DROP INDEX IF EXISTS chronicle_events_search_text_idx;
+ALTER TABLE chronicle_events DROP COLUMN IF EXISTS search_text;
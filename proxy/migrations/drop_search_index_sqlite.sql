@@ -0,0 +1,7 @@
+-- Removes the full-text search index. This runs from `sqlite::disable_search_index`.
+DROP TRIGGER IF EXISTS chronicle_events_fts_insert;
+DROP TRIGGER IF EXISTS chronicle_events_fts_update;
+DROP TRIGGER IF EXISTS chronicle_events_fts_delete;
+DROP TABLE IF EXISTS chronicle_events_fts;
+DROP TABLE IF EXISTS chronicle_events_search;
+DROP VIEW IF EXISTS chronicle_events_search_text;
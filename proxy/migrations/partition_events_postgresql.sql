@@ -0,0 +1,25 @@
+-- Converts chronicle_events into a table partitioned by created_at. This is not one of the default
+-- migrations. It runs from `postgres::partition_events_table`, which also creates the partitions
+-- and copies the existing rows from chronicle_events_unpartitioned.
+LOCK TABLE chronicle_events IN ACCESS EXCLUSIVE MODE;
+
+ALTER TABLE chronicle_events RENAME TO chronicle_events_unpartitioned;
+ALTER TABLE chronicle_events_unpartitioned
+  RENAME CONSTRAINT chronicle_events_pkey TO chronicle_events_unpartitioned_pkey;
+DROP INDEX chronicle_events_workflow_id_idx;
+DROP INDEX chronicle_events_run_id_created_at_idx;
+DROP INDEX chronicle_events_created_at_idx;
+
+CREATE TABLE chronicle_events (
+  LIKE chronicle_events_unpartitioned INCLUDING DEFAULTS INCLUDING CONSTRAINTS,
+  -- The primary key of a partitioned table must include the partition key.
+  PRIMARY KEY (id, created_at),
+  FOREIGN KEY (pricing_plan) REFERENCES chronicle_pricing_plans (id)
+) PARTITION BY RANGE (created_at);
+
+-- Holds rows outside the range of the other partitions, so that writes never fail.
+CREATE TABLE chronicle_events_default PARTITION OF chronicle_events DEFAULT;
+
+CREATE INDEX chronicle_events_workflow_id_idx ON chronicle_events (workflow_id);
+CREATE INDEX chronicle_events_run_id_created_at_idx ON chronicle_events (run_id, created_at DESC);
+CREATE INDEX chronicle_events_created_at_idx ON chronicle_events (created_at);
@@ -0,0 +1,14 @@
+-- Adds full-text search over the message contents of chat_request and chat_response. This is not
+-- one of the default migrations, since adding the column rewrites chronicle_events and the index
+-- slows down writes. It runs from `postgres::enable_search_index`.
+ALTER TABLE chronicle_events ADD COLUMN search_text tsvector GENERATED ALWAYS AS (
+  jsonb_to_tsvector(
+    'english',
+    COALESCE(jsonb_path_query_array(chat_request, '$.system'), '[]')
+      || COALESCE(jsonb_path_query_array(chat_request, '$.messages[*].content'), '[]')
+      || COALESCE(jsonb_path_query_array(chat_response, '$.choices[*].message.content'), '[]'),
+    '["string"]'
+  )
+) STORED;
+
+CREATE INDEX chronicle_events_search_text_idx ON chronicle_events USING GIN (search_text);
@@ -0,0 +1,64 @@
+-- Adds full-text search over the message contents of chat_request and chat_response. This is not
+-- one of the default migrations, since the index slows down writes. It runs from
+-- `sqlite::enable_search_index`.
+
+-- The text to index for each event
+CREATE VIEW chronicle_events_search_text AS
+SELECT
+  id AS event_id,
+  ifnull(json_extract(chat_request, '$.system'), '') || ' ' ||
+  ifnull(
+    (SELECT group_concat(json_extract(m.value, '$.content'), ' ')
+      FROM json_each(chat_request, '$.messages') m),
+    ''
+  ) || ' ' ||
+  ifnull(
+    (SELECT group_concat(json_extract(c.value, '$.message.content'), ' ')
+      FROM json_each(chat_response, '$.choices') c),
+    ''
+  ) AS body
+FROM chronicle_events;
+
+-- Maps each event to the rowid of its entry in the index. The events table has no integer key of
+-- its own, and its implicit rowids can change when the database is vacuumed.
+CREATE TABLE chronicle_events_search (
+  rowid integer PRIMARY KEY,
+  event_id text NOT NULL UNIQUE
+);
+
+CREATE VIRTUAL TABLE chronicle_events_fts USING fts5 (body);
+
+INSERT INTO chronicle_events_search (event_id) SELECT id FROM chronicle_events;
+INSERT INTO chronicle_events_fts (rowid, body)
+SELECT s.rowid, t.body
+FROM chronicle_events_search s
+JOIN chronicle_events_search_text t ON t.event_id = s.event_id;
+
+CREATE TRIGGER chronicle_events_fts_insert AFTER INSERT ON chronicle_events
+BEGIN
+  INSERT INTO chronicle_events_search (event_id) VALUES (new.id);
+  INSERT INTO chronicle_events_fts (rowid, body)
+  SELECT s.rowid, t.body
+  FROM chronicle_events_search s
+  JOIN chronicle_events_search_text t ON t.event_id = s.event_id
+  WHERE s.event_id = new.id;
+END;
+
+CREATE TRIGGER chronicle_events_fts_update AFTER UPDATE OF chat_request, chat_response
+  ON chronicle_events
+BEGIN
+  DELETE FROM chronicle_events_fts
+  WHERE rowid = (SELECT rowid FROM chronicle_events_search WHERE event_id = old.id);
+  INSERT INTO chronicle_events_fts (rowid, body)
+  SELECT s.rowid, t.body
+  FROM chronicle_events_search s
+  JOIN chronicle_events_search_text t ON t.event_id = s.event_id
+  WHERE s.event_id = new.id;
+END;
+
+CREATE TRIGGER chronicle_events_fts_delete AFTER DELETE ON chronicle_events
+BEGIN
+  DELETE FROM chronicle_events_fts
+  WHERE rowid = (SELECT rowid FROM chronicle_events_search WHERE event_id = old.id);
+  DELETE FROM chronicle_events_search WHERE event_id = old.id;
+END;
@@ -8,12 +8,29 @@ use crate::database::postgres::PostgresDatabase;
 use crate::database::sqlite::SqliteDatabase;
 use crate::{
     config::{AliasConfig, ApiKeyConfig, CustomProviderConfig, ProxyConfig},
-    database::{load_providers_from_database, logging::start_database_logger, Database},
+    database::{
+        blobs::{BlobOffloadingDatabase, BlobStorageConfig, BlobStore, DEFAULT_BLOB_THRESHOLD},
+        live_tail::{LiveTail, LIVE_EVENT_BUFFER},
+        load_config_from_database,
+        logging::{
+            start_logger, DatabaseSink, LogDestination, LogOverflowPolicy, LogQueueOptions,
+            LogSink, DEFAULT_BATCH_SIZE, DEFAULT_DEBOUNCE_TIME,
+        },
+        partitions::start_partition_task,
+        retention::{start_retention_task, RetentionConfig},
+        spill::RetryingSink,
+        stale::{start_stale_run_task, StaleRunConfig},
+        Database,
+    },
+    metrics::ProxyMetrics,
+    prompts::Prompt,
     providers::{
         anthropic::Anthropic, anyscale::Anyscale, deepinfra::DeepInfra, fireworks::Fireworks,
         groq::Groq, mistral::Mistral, ollama::Ollama, openai::OpenAi, together::Together,
         ChatModelProvider,
     },
+    redact::{RedactionConfig, Redactor},
+    validate::{self, ValidationStrictness},
     Error, ProviderLookup, Proxy,
 };
 
@@ -42,10 +59,13 @@ impl<T> MaybeEmpty<T> {
 /// A builder for [Proxy]
 pub struct ProxyBuilder {
     database: Option<Database>,
+    blob_store: Option<Arc<dyn BlobStore>>,
     config: ProxyConfig,
     load_config_from_database: bool,
     client: Option<reqwest::Client>,
     providers: Vec<Arc<dyn ChatModelProvider>>,
+    metrics_registry: Option<prometheus::Registry>,
+    log_sinks: Vec<LogDestination>,
 
     anthropic: Option<String>,
     anyscale: Option<String>,
@@ -65,10 +85,13 @@ impl ProxyBuilder {
     pub fn new() -> Self {
         Self {
             database: None,
+            blob_store: None,
             config: ProxyConfig::default(),
             load_config_from_database: true,
             client: None,
             providers: Vec::new(),
+            metrics_registry: None,
+            log_sinks: Vec::new(),
 
             anthropic: Some(String::new()),
             anyscale: Some(String::new()),
@@ -118,16 +141,93 @@ impl ProxyBuilder {
         self
     }
 
+    /// Set how [build](ProxyBuilder::build) handles problems in the configuration. By default,
+    /// a warning is logged for each problem.
+    pub fn validation_strictness(mut self, strictness: ValidationStrictness) -> Self {
+        self.config.validation = Some(strictness);
+        self
+    }
+
+    /// Limit the number of messages waiting to be logged, and set what happens to new events when
+    /// the queue is full. By default the queue is unbounded.
+    pub fn log_queue(mut self, capacity: usize, overflow: LogOverflowPolicy) -> Self {
+        self.config.log_queue = Some(LogQueueOptions {
+            capacity: Some(capacity),
+            overflow,
+        });
+        self
+    }
+
+    /// Redact sensitive data from events before they are logged.
+    pub fn redaction(mut self, config: RedactionConfig) -> Self {
+        self.config.redaction = Some(config);
+        self
+    }
+
+    /// Delete old events and runs from the database. Unless disabled in the configuration, a
+    /// background task purges the data periodically.
+    pub fn retention(mut self, config: RetentionConfig) -> Self {
+        self.config.retention = Some(config);
+        self
+    }
+
+    /// Mark runs that have had no updates for too long as timed out, along with their unfinished
+    /// steps. Unless disabled in the configuration, a background task checks for these runs
+    /// periodically.
+    pub fn stale_runs(mut self, config: StaleRunConfig) -> Self {
+        self.config.stale_runs = Some(config);
+        self
+    }
+
+    /// Store request and response bodies that are larger than the configured threshold in a
+    /// blob store, instead of the database.
+    pub fn blob_storage(mut self, config: BlobStorageConfig) -> Self {
+        self.config.blob_storage = Some(config);
+        self
+    }
+
+    /// Store large request and response bodies in a custom [BlobStore]. This takes precedence
+    /// over the store in the blob storage configuration, but the threshold from the
+    /// configuration is still used if present.
+    pub fn with_blob_store(mut self, store: Arc<dyn BlobStore>) -> Self {
+        self.blob_store = Some(store);
+        self
+    }
+
     /// Merge this configuration into the current one.
     pub fn with_config(mut self, config: ProxyConfig) -> Self {
         self.config.default_timeout = config.default_timeout.or(self.config.default_timeout);
         self.config.log_to_database = config.log_to_database.or(self.config.log_to_database);
+        self.config.validation = config.validation.or(self.config.validation);
         if config.user_agent.is_some() {
             self.config.user_agent = config.user_agent;
         }
         self.config.providers.extend(config.providers);
         self.config.aliases.extend(config.aliases);
         self.config.api_keys.extend(config.api_keys);
+        self.config.prompts.extend(config.prompts);
+        self.config.log_sinks.extend(config.log_sinks);
+        if config.log_retry.is_some() {
+            self.config.log_retry = config.log_retry;
+        }
+        if config.log_spill.is_some() {
+            self.config.log_spill = config.log_spill;
+        }
+        if config.log_queue.is_some() {
+            self.config.log_queue = config.log_queue;
+        }
+        if config.redaction.is_some() {
+            self.config.redaction = config.redaction;
+        }
+        if config.retention.is_some() {
+            self.config.retention = config.retention;
+        }
+        if config.stale_runs.is_some() {
+            self.config.stale_runs = config.stale_runs;
+        }
+        if config.blob_storage.is_some() {
+            self.config.blob_storage = config.blob_storage;
+        }
         self
     }
 
@@ -165,6 +265,18 @@ impl ProxyBuilder {
         self
     }
 
+    /// Add a prompt template to the proxy
+    pub fn with_prompt(mut self, prompt: Prompt) -> Self {
+        self.config.prompts.push(prompt);
+        self
+    }
+
+    /// Add multiple prompt templates to the proxy
+    pub fn with_prompts(mut self, prompts: Vec<Prompt>) -> Self {
+        self.config.prompts.extend(prompts);
+        self
+    }
+
     /// Add a custom provider to the list of providers
     pub fn with_custom_provider(mut self, config: CustomProviderConfig) -> Self {
         self.config.providers.push(config);
@@ -279,42 +391,77 @@ impl ProxyBuilder {
         self
     }
 
+    /// Send logged events to this sink, in addition to the database and any sinks in the
+    /// configuration. Entries are written in batches of up to `batch_size`, or once no new
+    /// entries have arrived for `debounce_time`.
+    pub fn with_log_sink(
+        mut self,
+        sink: Arc<dyn LogSink>,
+        batch_size: usize,
+        debounce_time: Duration,
+    ) -> Self {
+        self.log_sinks.push(LogDestination {
+            sink,
+            batch_size,
+            debounce_time,
+        });
+        self
+    }
+
+    /// Register the proxy's metrics in this Prometheus registry instead of creating a new one.
+    pub fn with_metrics_registry(mut self, registry: prometheus::Registry) -> Self {
+        self.metrics_registry = Some(registry);
+        self
+    }
+
     /// Build the proxy from the supplied options.
-    pub async fn build(self) -> Result<Proxy, Report<Error>> {
+    pub async fn build(mut self) -> Result<Proxy, Report<Error>> {
+        let threshold = self
+            .config
+            .blob_storage
+            .as_ref()
+            .and_then(|c| c.threshold_bytes)
+            .unwrap_or(DEFAULT_BLOB_THRESHOLD);
+        let blob_store = self.blob_store.take().or_else(|| {
+            self.config
+                .blob_storage
+                .take()
+                .map(|c| c.store.into_store())
+        });
+        if let Some(store) = blob_store {
+            self.database = self
+                .database
+                .map(|db| BlobOffloadingDatabase::new(db, store, threshold));
+        }
+
         let mut providers = self.providers;
         let mut provider_configs = self.config.providers;
         let mut api_keys = self.config.api_keys;
         let mut aliases = self.config.aliases;
-        let logger = if let Some(db) = &self.database {
+        let metrics = Arc::new(ProxyMetrics::with_registry(
+            self.metrics_registry.unwrap_or_default(),
+        )?);
+        let strictness = self.config.validation.unwrap_or_default();
+        if let Some(db) = &self.database {
             if self.load_config_from_database {
-                let db_providers =
-                    load_providers_from_database(db.as_ref(), "chronicle_custom_providers").await?;
-                let db_aliases = db
-                    .load_aliases_from_database("chronicle_aliases", "chronicle_alias_providers")
-                    .await?;
-                let db_api_keys = db
-                    .load_api_key_configs_from_database("chronicle_api_keys")
-                    .await?;
-
-                provider_configs.extend(db_providers);
-                aliases.extend(db_aliases);
-                api_keys.extend(db_api_keys);
+                let db_config = load_config_from_database(db.as_ref()).await?;
+                provider_configs.extend(db_config.providers);
+                aliases.extend(db_config.aliases);
+                api_keys.extend(db_config.api_keys);
             }
+        }
 
-            let logger = if self.config.log_to_database.unwrap_or(false) {
-                Some(start_database_logger(
-                    db.clone(),
-                    100,
-                    Duration::from_secs(1),
-                ))
-            } else {
-                None
-            };
-
-            logger
-        } else {
-            None
-        };
+        let mut problems = validate::check_custom_providers(&provider_configs);
+        problems.extend(validate::check_api_keys(&api_keys));
+        problems.extend(validate::check_duplicate_names(
+            &provider_configs,
+            &aliases,
+            &api_keys,
+        ));
+        let prefixes = provider_configs
+            .iter()
+            .filter_map(|c| Some((c.name.clone(), c.prefix.clone()?)))
+            .collect::<Vec<_>>();
 
         let client = self.client.unwrap_or_else(|| {
             reqwest::Client::builder()
@@ -404,30 +551,140 @@ impl ProxyBuilder {
             );
         }
 
-        let (log_tx, log_task) = logger.unzip();
+        problems.extend(validate::check_prefixes(&prefixes, &providers));
+        problems.extend(validate::check_prompts(&self.config.prompts));
 
+        // Keys with a missing environment variable were already reported above.
         let api_keys = api_keys
             .into_iter()
-            .map(|mut config| {
+            .filter_map(|mut config| {
                 if config.source == "env" {
-                    let value = std::env::var(&config.value).map_err(|_| {
-                        Error::MissingApiKeyEnv(config.name.clone(), config.value.clone())
-                    })?;
-
-                    config.value = value;
+                    config.value = std::env::var(&config.value).ok()?;
                 }
 
-                Ok::<_, Error>(config)
+                Some(config)
             })
-            .collect::<Result<Vec<_>, Error>>()?;
+            .collect::<Vec<_>>();
 
         let lookup = ProviderLookup::new(providers, aliases, api_keys);
 
+        let all_problems = problems.iter().cloned().chain(lookup.validate());
+        match strictness {
+            ValidationStrictness::Ignore => {}
+            ValidationStrictness::Warn => {
+                for problem in all_problems {
+                    tracing::warn!(?problem, "{problem}");
+                }
+            }
+            ValidationStrictness::Error => {
+                let all_problems = all_problems.collect::<Vec<_>>();
+                if !all_problems.is_empty() {
+                    let message = all_problems
+                        .iter()
+                        .map(|p| p.to_string())
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    return Err(
+                        Report::new(Error::InvalidConfig(all_problems)).attach_printable(message)
+                    );
+                }
+            }
+        }
+
+        let redactor = self
+            .config
+            .redaction
+            .as_ref()
+            .map(Redactor::new)
+            .transpose()?
+            .map(Arc::new);
+
+        let mut log_destinations = self.log_sinks;
+        for config in self.config.log_sinks {
+            log_destinations.push(config.into_destination(client.clone(), Some(metrics.clone()))?);
+        }
+
+        let (live_tx, _) = tokio::sync::broadcast::channel(LIVE_EVENT_BUFFER);
+        let mut live_listener_task = None;
+        if let Some(db) = &self.database {
+            if self.config.log_to_database.unwrap_or(false) {
+                // Live tail is a debugging aid, so don't fail if the listener can't start.
+                live_listener_task =
+                    db.listen_live_events(live_tx.clone())
+                        .await
+                        .unwrap_or_else(|e| {
+                            tracing::error!(error = ?e, "Failed to listen for live events");
+                            None
+                        });
+
+                let live_tail = LiveTail {
+                    tx: live_tx.clone(),
+                    listening_to_database: live_listener_task.is_some(),
+                };
+
+                let db_sink = Arc::new(DatabaseSink::new(
+                    db.clone(),
+                    Some(metrics.clone()),
+                    Some(live_tail),
+                ));
+                log_destinations.insert(
+                    0,
+                    LogDestination {
+                        sink: Arc::new(RetryingSink::new(
+                            db_sink,
+                            self.config.log_retry.unwrap_or_default(),
+                            self.config.log_spill,
+                            Some(metrics.clone()),
+                        )),
+                        batch_size: DEFAULT_BATCH_SIZE,
+                        debounce_time: DEFAULT_DEBOUNCE_TIME,
+                    },
+                );
+            }
+        }
+
+        let logger = if log_destinations.is_empty() {
+            None
+        } else {
+            Some(start_logger(
+                log_destinations,
+                self.config.log_queue.unwrap_or_default(),
+                redactor,
+                Some(metrics.clone()),
+            ))
+        };
+        let (log_tx, log_task) = logger.unzip();
+
+        let retention_task = match (&self.database, self.config.retention) {
+            (Some(db), Some(retention)) if retention.background.unwrap_or(true) => {
+                Some(start_retention_task(db.clone(), retention))
+            }
+            _ => None,
+        };
+
+        let stale_run_task = match (&self.database, self.config.stale_runs) {
+            (Some(db), Some(stale_runs)) if stale_runs.background.unwrap_or(true) => {
+                Some(start_stale_run_task(db.clone(), stale_runs, log_tx.clone()))
+            }
+            _ => None,
+        };
+
+        let partition_task = self.database.clone().map(start_partition_task);
+
         Ok(Proxy {
             lookup,
             default_timeout: self.config.default_timeout,
             log_tx,
             log_task,
+            metrics,
+            live_tx,
+            live_listener_task,
+            retention_task,
+            stale_run_task,
+            partition_task,
+            database: self.database,
+            prompts: self.config.prompts,
+            config_problems: problems,
         })
     }
 }
@@ -1,8 +1,29 @@
-use std::{collections::BTreeMap, time::Duration};
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc, time::Duration};
 
+use error_stack::{Report, ResultExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationMilliSeconds};
 
-use crate::providers::custom::{CustomProvider, ProviderRequestFormat};
+use crate::{
+    database::{
+        blobs::BlobStorageConfig,
+        logging::{
+            LogDestination, LogQueueOptions, LogSink, DEFAULT_BATCH_SIZE, DEFAULT_DEBOUNCE_TIME,
+        },
+        retention::RetentionConfig,
+        sinks::{FileSink, StdoutSink, WebhookSink},
+        spill::{RetryingSink, SpillOptions},
+        stale::StaleRunConfig,
+    },
+    metrics::ProxyMetrics,
+    prompts::Prompt,
+    providers::custom::{CustomProvider, ProviderRequestFormat},
+    redact::RedactionConfig,
+    request::RetryOptions,
+    validate::ValidationStrictness,
+    Error,
+};
 
 /// Configuration for the proxy
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -16,12 +37,137 @@ pub struct ProxyConfig {
     /// API keys that the proxy should use
     #[serde(default)]
     pub api_keys: Vec<ApiKeyConfig>,
+    /// Prompt templates that requests can render by ID. A prompt ID defined here takes
+    /// precedence over prompts with the same ID in the database.
+    #[serde(default)]
+    pub prompts: Vec<Prompt>,
     /// The default timeout for requests
     pub default_timeout: Option<Duration>,
     /// Whether to log to the database or not.
     pub log_to_database: Option<bool>,
     /// The user agent to use when making requests
     pub user_agent: Option<String>,
+    /// How to handle problems found when validating the configuration. Defaults to logging
+    /// a warning.
+    pub validation: Option<ValidationStrictness>,
+    /// Extra destinations for logged events, in addition to the database
+    #[serde(default)]
+    pub log_sinks: Vec<LogSinkConfig>,
+    /// How to retry failed database writes. Defaults to the same behavior as provider
+    /// requests.
+    pub log_retry: Option<RetryOptions>,
+    /// Where to write log entries that could not be written to the database. If omitted,
+    /// entries are dropped once the retries are exhausted.
+    pub log_spill: Option<SpillOptions>,
+    /// Limits on the queue of events waiting to be logged. By default the queue is unbounded.
+    pub log_queue: Option<LogQueueOptions>,
+    /// Redact sensitive data from events before they are logged
+    pub redaction: Option<RedactionConfig>,
+    /// How long to keep events and runs in the database. By default, data is kept forever.
+    pub retention: Option<RetentionConfig>,
+    /// Mark runs that have stopped sending updates as timed out. By default, runs stay
+    /// `started` until they send an update.
+    pub stale_runs: Option<StaleRunConfig>,
+    /// Store large request and response bodies outside the database. By default, all bodies
+    /// are stored in the database.
+    pub blob_storage: Option<BlobStorageConfig>,
+}
+
+/// Configuration for a log sink
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogSinkConfig {
+    /// Where to send the events
+    #[serde(flatten)]
+    pub sink: LogSinkType,
+    /// Write a batch once it has this many entries. Defaults to 100.
+    pub batch_size: Option<usize>,
+    /// Write a partial batch once no new entries have arrived for this many milliseconds.
+    /// Defaults to 1000.
+    #[serde_as(as = "Option<DurationMilliSeconds>")]
+    pub debounce: Option<Duration>,
+    /// Retry failed writes. If omitted, failed writes are not retried unless `spill` is set.
+    pub retry: Option<RetryOptions>,
+    /// Where to write entries that could not be written to the sink
+    pub spill: Option<SpillOptions>,
+}
+
+/// The types of log sinks that can be created from configuration
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogSinkType {
+    /// Write each event as a line of JSON to stdout
+    Stdout,
+    /// Write each event as a line of JSON to a file, rotating it when it gets too large
+    File {
+        /// The file to write
+        path: PathBuf,
+        /// Rotate the file when it grows larger than this. Defaults to 100MiB.
+        max_bytes: Option<u64>,
+        /// How many rotated files to keep. Defaults to 5.
+        max_files: Option<usize>,
+    },
+    /// Send each batch of events to a URL as a JSON array
+    Webhook {
+        /// The URL to POST the events to
+        url: String,
+        /// Extra headers to pass with the request
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+}
+
+impl LogSinkConfig {
+    /// Create the sink described by this configuration. If `metrics` is provided, dropped
+    /// entries are counted there.
+    pub fn into_destination(
+        self,
+        client: reqwest::Client,
+        metrics: Option<Arc<ProxyMetrics>>,
+    ) -> Result<LogDestination, Report<Error>> {
+        let mut sink: Arc<dyn LogSink> = match self.sink {
+            LogSinkType::Stdout => Arc::new(StdoutSink),
+            LogSinkType::File {
+                path,
+                max_bytes,
+                max_files,
+            } => Arc::new(FileSink::new(
+                path,
+                max_bytes.unwrap_or(FileSink::DEFAULT_MAX_BYTES),
+                max_files.unwrap_or(FileSink::DEFAULT_MAX_FILES),
+            )),
+            LogSinkType::Webhook { url, headers } => {
+                let headers = headers
+                    .iter()
+                    .map(|(name, value)| {
+                        let name = HeaderName::from_bytes(name.as_bytes());
+                        let value = HeaderValue::from_str(value);
+                        Ok((
+                            name.change_context(Error::ReadingConfig)?,
+                            value.change_context(Error::ReadingConfig)?,
+                        ))
+                    })
+                    .collect::<Result<HeaderMap, Report<Error>>>()
+                    .attach_printable_lazy(|| format!("Invalid header for webhook {url}"))?;
+                Arc::new(WebhookSink::new(client, url, headers))
+            }
+        };
+
+        if self.retry.is_some() || self.spill.is_some() {
+            sink = Arc::new(RetryingSink::new(
+                sink,
+                self.retry.unwrap_or_default(),
+                self.spill,
+                metrics,
+            ));
+        }
+
+        Ok(LogDestination {
+            sink,
+            batch_size: self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+            debounce_time: self.debounce.unwrap_or(DEFAULT_DEBOUNCE_TIME),
+        })
+    }
 }
 
 /// An alias configuration mape a single name to a list of provider-model pairs
@@ -1,25 +1,57 @@
 use std::{collections::BTreeMap, sync::Arc};
 
+use chrono::{DateTime, Utc};
+use datasets::{Dataset, DatasetItem, DatasetItemSource, NewDataset};
 use error_stack::Report;
+use export::{ExportEventQuery, ExportPage};
+use live_tail::{LiveEvent, LiveEventSender};
 use logging::ProxyLogEntry;
+use retention::{ArchivedRow, PurgeCounts, PurgeTable, RetentionCutoffs};
+use runs::{RunDetail, RunEvent, RunQuery, RunSummary};
+use scores::{Score, ScoreTarget};
+use search::{EventSearchQuery, EventSearchResult};
+use stale::{MarkedStaleRuns, StaleCutoffs, StaleRun};
+use tables::TableNames;
+use uuid::Uuid;
 
 use crate::{
-    config::{AliasConfig, ApiKeyConfig, CustomProviderConfig},
+    config::{AliasConfig, ApiKeyConfig, CustomProviderConfig, ProxyConfig},
+    prompts::{NewPrompt, Prompt, PromptVersion},
     providers::custom::ProviderRequestFormat,
     Error,
 };
 
+pub mod blobs;
+pub mod datasets;
+pub mod export;
+pub mod live_tail;
 pub mod logging;
+pub mod migrations;
+pub mod partitions;
 #[cfg(feature = "postgres")]
 pub mod postgres;
+pub mod retention;
+pub mod runs;
+pub mod scores;
+pub mod search;
+pub mod sinks;
+pub mod spill;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
+pub mod stale;
+pub mod tables;
 #[cfg(test)]
 mod testing;
+pub mod usage;
 
 /// A DBMS-agnostic interface to a database
 #[async_trait::async_trait]
 pub trait ProxyDatabase: std::fmt::Debug + Send + Sync {
+    /// The names of the tables that this database uses
+    fn tables(&self) -> TableNames {
+        TableNames::default()
+    }
+
     /// Load provider configuration from the database
     async fn load_providers_from_database(
         &self,
@@ -41,6 +73,175 @@ pub trait ProxyDatabase: std::fmt::Debug + Send + Sync {
 
     /// Write a batch of log entries to the database
     async fn write_log_batch(&self, items: Vec<ProxyLogEntry>) -> Result<(), sqlx::Error>;
+
+    /// List runs matching the query, newest first. Runs that are only known from the metadata
+    /// of LLM calls, which have the status `pending`, are not included.
+    async fn list_runs(&self, query: &RunQuery) -> Result<Vec<RunSummary>, Report<Error>>;
+
+    /// Load a run along with its steps and events
+    async fn get_run(&self, id: Uuid) -> Result<Option<RunDetail>, Report<Error>>;
+
+    /// Load a single logged event
+    async fn get_event(&self, id: Uuid) -> Result<Option<RunEvent>, Report<Error>>;
+
+    /// List the scores for a run, step, or LLM call, oldest first
+    async fn list_scores(&self, target: ScoreTarget) -> Result<Vec<Score>, Report<Error>>;
+
+    /// Create a dataset. If a dataset with the same name exists, this creates the next version of
+    /// it, starting with a copy of the items in the latest version.
+    async fn create_dataset(&self, dataset: &NewDataset) -> Result<Dataset, Report<Error>>;
+
+    /// List every version of every dataset, ordered by name and then version
+    async fn list_datasets(&self) -> Result<Vec<Dataset>, Report<Error>>;
+
+    /// Load a version of a dataset
+    async fn get_dataset(&self, id: Uuid) -> Result<Option<Dataset>, Report<Error>>;
+
+    /// Copy events into a dataset, using the response to each event as its expected response.
+    /// Events which are already in the dataset or have no request are skipped. Returns the number
+    /// of items added.
+    async fn add_dataset_items(
+        &self,
+        dataset_id: Uuid,
+        source: &DatasetItemSource,
+    ) -> Result<u64, Report<Error>>;
+
+    /// Replace the expected response of an item in a dataset. Returns `false` if the item does
+    /// not exist.
+    async fn update_dataset_item(
+        &self,
+        dataset_id: Uuid,
+        item_id: Uuid,
+        expected_response: &serde_json::Value,
+    ) -> Result<bool, Report<Error>>;
+
+    /// List the items in a dataset, oldest first
+    async fn list_dataset_items(&self, dataset_id: Uuid)
+        -> Result<Vec<DatasetItem>, Report<Error>>;
+
+    /// Load a page of the events which match an export query, ordered by ID. Events without a
+    /// request or response are skipped. `query.limit` is not applied here.
+    async fn list_events_for_export(
+        &self,
+        query: &ExportEventQuery,
+        page: ExportPage,
+    ) -> Result<Vec<RunEvent>, Report<Error>>;
+
+    /// Create a prompt. If a prompt with the same ID exists, this creates its next version.
+    async fn create_prompt(&self, prompt: &NewPrompt) -> Result<Prompt, Report<Error>>;
+
+    /// List the latest version of every prompt, ordered by ID
+    async fn list_prompts(&self) -> Result<Vec<Prompt>, Report<Error>>;
+
+    /// List every version of a prompt, oldest first
+    async fn list_prompt_versions(&self, id: &str) -> Result<Vec<Prompt>, Report<Error>>;
+
+    /// Load a version of a prompt
+    async fn get_prompt(
+        &self,
+        id: &str,
+        version: &PromptVersion,
+    ) -> Result<Option<Prompt>, Report<Error>>;
+
+    /// Point a label at a version of a prompt, moving it from any other version. Returns `false`
+    /// if the version does not exist.
+    async fn set_prompt_label(
+        &self,
+        id: &str,
+        label: &str,
+        version: u32,
+    ) -> Result<bool, Report<Error>>;
+
+    /// Remove a label from a prompt. Returns `false` if the label did not exist.
+    async fn remove_prompt_label(&self, id: &str, label: &str) -> Result<bool, Report<Error>>;
+
+    /// Delete a version of a prompt, or every version if `version` is `None`, along with the
+    /// labels that point to them. Returns the number of versions deleted.
+    async fn delete_prompt(&self, id: &str, version: Option<u32>) -> Result<u64, Report<Error>>;
+
+    /// Search the contents of requests and responses, with the best matches first. This
+    /// requires the database's full-text index to be enabled, and returns
+    /// [Error::SearchNotEnabled] otherwise.
+    async fn search_events(
+        &self,
+        _query: &EventSearchQuery,
+    ) -> Result<Vec<EventSearchResult>, Report<Error>> {
+        Err(Report::new(Error::SearchNotEnabled))
+    }
+
+    /// Find up to `limit` rows in `table` which are older than the retention cutoffs allow
+    async fn find_expired(
+        &self,
+        table: PurgeTable,
+        cutoffs: &RetentionCutoffs,
+        limit: usize,
+    ) -> Result<Vec<Uuid>, Report<Error>>;
+
+    /// Count the rows in each table which are older than the retention cutoffs allow. Steps are
+    /// counted if their run is expired.
+    async fn count_expired(&self, cutoffs: &RetentionCutoffs)
+        -> Result<PurgeCounts, Report<Error>>;
+
+    /// Load rows as JSON so that they can be archived. For runs, this also loads their steps and
+    /// scores.
+    async fn load_rows_for_archive(
+        &self,
+        table: PurgeTable,
+        ids: &[Uuid],
+    ) -> Result<Vec<ArchivedRow>, Report<Error>>;
+
+    /// Delete rows from a table. For runs, this also deletes their steps and scores.
+    async fn delete_rows(
+        &self,
+        table: PurgeTable,
+        ids: &[Uuid],
+    ) -> Result<PurgeCounts, Report<Error>>;
+
+    /// Find up to `limit` runs which are still `started` but have had no updates since their
+    /// cutoff, with the least recently updated runs first. A timeout set in the run's info
+    /// takes precedence over the cutoffs.
+    async fn find_stale_runs(
+        &self,
+        cutoffs: &StaleCutoffs,
+        limit: usize,
+    ) -> Result<Vec<StaleRun>, Report<Error>>;
+
+    /// Set the status of runs which are still `started`, and of their unfinished steps, which
+    /// also get an end time of their last update.
+    async fn mark_stale_runs(
+        &self,
+        ids: &[Uuid],
+        run_status: &str,
+        step_status: &str,
+    ) -> Result<MarkedStaleRuns, Report<Error>>;
+
+    /// Create the partitions that will be needed soon, for databases that partition the events
+    /// table by time.
+    async fn create_partitions(&self, _now: DateTime<Utc>) -> Result<(), Report<Error>> {
+        Ok(())
+    }
+
+    /// Drop partitions of the events table that only contain data from before `before`.
+    /// Returns the number of partitions dropped.
+    async fn drop_expired_partitions(&self, _before: DateTime<Utc>) -> Result<u64, Report<Error>> {
+        Ok(0)
+    }
+
+    /// Send live events through the database, so that they reach listeners on every server
+    /// using the database. Returns `false` if the database doesn't support this, in which case
+    /// the events should be delivered to local listeners directly.
+    async fn notify_live_events(&self, _events: &[LiveEvent]) -> Result<bool, Report<Error>> {
+        Ok(false)
+    }
+
+    /// Start a task that forwards live events sent by [ProxyDatabase::notify_live_events] to
+    /// `tx`. Returns `None` if the database doesn't support this.
+    async fn listen_live_events(
+        &self,
+        _tx: LiveEventSender,
+    ) -> Result<Option<tokio::task::JoinHandle<()>>, Report<Error>> {
+        Ok(None)
+    }
 }
 
 /// A [ProxyDatabase] wrapped in an [Arc]
@@ -80,3 +281,24 @@ pub async fn load_providers_from_database(
         .collect();
     Ok(providers)
 }
+
+/// Load the providers, aliases, and API keys stored in the database's configuration tables
+pub async fn load_config_from_database(
+    db: &dyn ProxyDatabase,
+) -> Result<ProxyConfig, Report<Error>> {
+    let tables = db.tables();
+    let providers = load_providers_from_database(db, &tables.table("custom_providers")).await?;
+    let aliases = db
+        .load_aliases_from_database(&tables.table("aliases"), &tables.table("alias_providers"))
+        .await?;
+    let api_keys = db
+        .load_api_key_configs_from_database(&tables.table("api_keys"))
+        .await?;
+
+    Ok(ProxyConfig {
+        providers,
+        aliases,
+        api_keys,
+        ..Default::default()
+    })
+}
@@ -0,0 +1,623 @@
+//! Moving large request and response bodies out of the database
+use std::{path::PathBuf, sync::Arc};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use error_stack::{Report, ResultExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::{
+    datasets::{Dataset, DatasetItem, DatasetItemSource, NewDataset},
+    export::{ExportEventQuery, ExportPage},
+    live_tail::{LiveEvent, LiveEventSender},
+    logging::{ProxyLogEntry, ProxyLogEvent},
+    retention::{ArchivedRow, PurgeCounts, PurgeTable, RetentionCutoffs},
+    runs::{RunDetail, RunEvent, RunQuery, RunSummary, StepDetail},
+    scores::{Score, ScoreTarget},
+    search::{EventSearchQuery, EventSearchResult},
+    stale::{MarkedStaleRuns, StaleCutoffs, StaleRun},
+    tables::TableNames,
+    Database, DbProvider, ProxyDatabase,
+};
+use crate::{
+    config::{AliasConfig, ApiKeyConfig},
+    prompts::{NewPrompt, Prompt, PromptVersion},
+    Error,
+};
+
+/// The default size above which bodies are moved to the blob store
+pub const DEFAULT_BLOB_THRESHOLD: usize = 256 * 1024;
+
+/// Content-addressed storage for large bodies. Blobs are never modified once written, so
+/// implementations can skip writing a blob that already exists.
+#[async_trait::async_trait]
+pub trait BlobStore: std::fmt::Debug + Send + Sync {
+    /// Save a blob under `key`
+    async fn put(&self, key: &str, data: Bytes) -> Result<(), Report<Error>>;
+
+    /// Load a blob, or return `None` if it does not exist
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, Report<Error>>;
+}
+
+/// A [BlobStore] that saves each blob as a file in a directory
+#[derive(Debug)]
+pub struct FilesystemBlobStore {
+    dir: PathBuf,
+}
+
+impl FilesystemBlobStore {
+    /// Store blobs in `dir`, which is created if it does not exist.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, Report<Error>> {
+        let hash = key.strip_prefix("sha256:").unwrap_or(key);
+        // Keys come from the database, so make sure they can't point outside the directory.
+        if hash.len() < 3 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Report::new(Error::BlobStore))
+                .attach_printable_lazy(|| format!("Invalid blob key {key}"));
+        }
+
+        // Spread the files across subdirectories so that no one directory gets too large.
+        Ok(self.dir.join(&hash[0..2]).join(hash))
+    }
+}
+
+#[async_trait::async_trait]
+impl BlobStore for FilesystemBlobStore {
+    async fn put(&self, key: &str, data: Bytes) -> Result<(), Report<Error>> {
+        let path = self.path(key)?;
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(());
+        }
+
+        let dir = path.parent().unwrap_or(&self.dir);
+        tokio::fs::create_dir_all(dir)
+            .await
+            .change_context(Error::BlobStore)
+            .attach_printable_lazy(|| format!("Failed to create {}", dir.display()))?;
+
+        // Write to a temporary file first so that readers never see a partial blob.
+        let temp_path = dir.join(format!(".{}.tmp", Uuid::now_v7()));
+        tokio::fs::write(&temp_path, &data)
+            .await
+            .change_context(Error::BlobStore)
+            .attach_printable_lazy(|| format!("Failed to write {}", temp_path.display()))?;
+        tokio::fs::rename(&temp_path, &path)
+            .await
+            .change_context(Error::BlobStore)
+            .attach_printable_lazy(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, Report<Error>> {
+        let path = self.path(key)?;
+        match tokio::fs::read(&path).await {
+            Ok(data) => Ok(Some(Bytes::from(data))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Report::new(e)
+                .change_context(Error::BlobStore)
+                .attach_printable(format!("Failed to read {}", path.display()))),
+        }
+    }
+}
+
+/// Configuration for moving large bodies to a blob store
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlobStorageConfig {
+    /// Request and response bodies larger than this many bytes, when serialized as JSON, are
+    /// moved to the blob store. Defaults to 256 KiB.
+    pub threshold_bytes: Option<usize>,
+    /// Where to store the bodies
+    #[serde(flatten)]
+    pub store: BlobStoreConfig,
+}
+
+/// The built-in blob stores
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlobStoreConfig {
+    /// Store blobs as files in a directory
+    Filesystem {
+        /// The directory to store the blobs in
+        path: PathBuf,
+    },
+}
+
+impl BlobStoreConfig {
+    /// Create the configured [BlobStore]
+    pub fn into_store(self) -> Arc<dyn BlobStore> {
+        match self {
+            BlobStoreConfig::Filesystem { path } => Arc::new(FilesystemBlobStore::new(path)),
+        }
+    }
+}
+
+/// A reference to a body in the blob store, saved in the database in place of the body
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct BlobRef {
+    /// The key of the blob, which is the SHA-256 hash of its contents
+    #[serde(rename = "$blob")]
+    pub key: String,
+    /// The size of the body in bytes
+    pub size: usize,
+}
+
+impl BlobRef {
+    /// Parse a reference from a JSON value. This also accepts references that are stored as
+    /// strings of JSON, as SQLite returns them.
+    pub fn from_value(value: &serde_json::Value) -> Option<Self> {
+        match value {
+            serde_json::Value::Object(map) if map.contains_key("$blob") => {
+                serde_json::from_value(value.clone()).ok()
+            }
+            serde_json::Value::String(s) if s.starts_with("{\"$blob\"") => {
+                serde_json::from_str(s).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The bodies of an event which were moved to the blob store
+#[derive(Debug, Clone, Default)]
+pub struct OffloadedBodies {
+    /// The reference to the request body
+    pub request: Option<BlobRef>,
+    /// The reference to the response body
+    pub response: Option<BlobRef>,
+}
+
+/// A body to be written to the database, or a reference to it if it was moved to the blob store
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum StoredBody<T> {
+    /// The body itself
+    Inline(T),
+    /// The reference to the body in the blob store
+    Blob(BlobRef),
+}
+
+impl<T> StoredBody<T> {
+    /// Store `body`, unless it was moved to the blob store
+    pub fn new(body: T, blob: Option<BlobRef>) -> Self {
+        match blob {
+            Some(blob) => StoredBody::Blob(blob),
+            None => StoredBody::Inline(body),
+        }
+    }
+}
+
+/// A [ProxyDatabase] that moves large request and response bodies to a [BlobStore] before
+/// writing events, and loads them again when events are read.
+///
+/// Blobs are shared by identical bodies, so they are not deleted when events are purged.
+#[derive(Debug)]
+pub struct BlobOffloadingDatabase {
+    inner: Database,
+    store: Arc<dyn BlobStore>,
+    threshold: usize,
+}
+
+impl BlobOffloadingDatabase {
+    /// Wrap `inner` so that bodies larger than `threshold` bytes are saved in `store`.
+    pub fn new(inner: Database, store: Arc<dyn BlobStore>, threshold: usize) -> Database {
+        Arc::new(Self {
+            inner,
+            store,
+            threshold,
+        })
+    }
+
+    /// Move a body to the blob store if it is too large. If the blob store fails, the body is
+    /// kept in the database instead.
+    async fn offload(&self, body: &impl Serialize) -> Option<BlobRef> {
+        let data = serde_json::to_vec(body).ok()?;
+        if data.len() <= self.threshold {
+            return None;
+        }
+
+        let blob = BlobRef {
+            key: format!("sha256:{:x}", Sha256::digest(&data)),
+            size: data.len(),
+        };
+
+        match self.store.put(&blob.key, Bytes::from(data)).await {
+            Ok(()) => Some(blob),
+            Err(e) => {
+                tracing::error!(error = ?e, "Failed to write body to blob store");
+                None
+            }
+        }
+    }
+
+    async fn offload_event(&self, event: &mut ProxyLogEvent) {
+        if let Some(request) = &event.request {
+            event.offloaded.request = self.offload(request).await;
+        }
+
+        if let Some(response) = &event.response {
+            event.offloaded.response = self.offload(&response.body).await;
+        }
+    }
+
+    /// Replace a blob reference with the body it refers to. References stored as JSON strings
+    /// are replaced with a string.
+    async fn resolve(&self, value: &mut serde_json::Value) -> Result<(), Report<Error>> {
+        let Some(blob) = BlobRef::from_value(value) else {
+            return Ok(());
+        };
+
+        let data = self
+            .store
+            .get(&blob.key)
+            .await?
+            .ok_or(Error::BlobStore)
+            .attach_printable_lazy(|| format!("Blob {} not found", blob.key))?;
+
+        *value = if value.is_string() {
+            serde_json::Value::String(
+                String::from_utf8(data.to_vec()).change_context(Error::BlobStore)?,
+            )
+        } else {
+            serde_json::from_slice(&data).change_context(Error::BlobStore)?
+        };
+
+        Ok(())
+    }
+
+    async fn resolve_event(&self, event: &mut RunEvent) -> Result<(), Report<Error>> {
+        if let Some(request) = &mut event.chat_request {
+            self.resolve(request).await?;
+        }
+        if let Some(response) = &mut event.chat_response {
+            self.resolve(response).await?;
+        }
+        Ok(())
+    }
+
+    async fn resolve_steps(&self, steps: &mut [StepDetail]) -> Result<(), Report<Error>> {
+        let mut pending = steps.iter_mut().collect::<Vec<_>>();
+        while let Some(step) = pending.pop() {
+            for event in &mut step.events {
+                self.resolve_event(event).await?;
+            }
+            pending.extend(step.steps.iter_mut());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl ProxyDatabase for BlobOffloadingDatabase {
+    fn tables(&self) -> TableNames {
+        self.inner.tables()
+    }
+
+    async fn load_providers_from_database(
+        &self,
+        providers_table: &str,
+    ) -> Result<Vec<DbProvider>, Report<Error>> {
+        self.inner
+            .load_providers_from_database(providers_table)
+            .await
+    }
+
+    async fn load_aliases_from_database(
+        &self,
+        alias_table: &str,
+        providers_table: &str,
+    ) -> Result<Vec<AliasConfig>, Report<Error>> {
+        self.inner
+            .load_aliases_from_database(alias_table, providers_table)
+            .await
+    }
+
+    async fn load_api_key_configs_from_database(
+        &self,
+        table: &str,
+    ) -> Result<Vec<ApiKeyConfig>, Report<Error>> {
+        self.inner.load_api_key_configs_from_database(table).await
+    }
+
+    async fn write_log_batch(&self, mut items: Vec<ProxyLogEntry>) -> Result<(), sqlx::Error> {
+        for item in &mut items {
+            if let ProxyLogEntry::Proxied(event) = item {
+                self.offload_event(event).await;
+            }
+        }
+
+        self.inner.write_log_batch(items).await
+    }
+
+    async fn list_runs(&self, query: &RunQuery) -> Result<Vec<RunSummary>, Report<Error>> {
+        self.inner.list_runs(query).await
+    }
+
+    async fn get_run(&self, id: Uuid) -> Result<Option<RunDetail>, Report<Error>> {
+        let Some(mut run) = self.inner.get_run(id).await? else {
+            return Ok(None);
+        };
+
+        for event in &mut run.events {
+            self.resolve_event(event).await?;
+        }
+        self.resolve_steps(&mut run.steps).await?;
+
+        Ok(Some(run))
+    }
+
+    async fn get_event(&self, id: Uuid) -> Result<Option<RunEvent>, Report<Error>> {
+        let Some(mut event) = self.inner.get_event(id).await? else {
+            return Ok(None);
+        };
+
+        self.resolve_event(&mut event).await?;
+        Ok(Some(event))
+    }
+
+    async fn list_scores(&self, target: ScoreTarget) -> Result<Vec<Score>, Report<Error>> {
+        self.inner.list_scores(target).await
+    }
+
+    async fn create_dataset(&self, dataset: &NewDataset) -> Result<Dataset, Report<Error>> {
+        self.inner.create_dataset(dataset).await
+    }
+
+    async fn list_datasets(&self) -> Result<Vec<Dataset>, Report<Error>> {
+        self.inner.list_datasets().await
+    }
+
+    async fn get_dataset(&self, id: Uuid) -> Result<Option<Dataset>, Report<Error>> {
+        self.inner.get_dataset(id).await
+    }
+
+    async fn add_dataset_items(
+        &self,
+        dataset_id: Uuid,
+        source: &DatasetItemSource,
+    ) -> Result<u64, Report<Error>> {
+        self.inner.add_dataset_items(dataset_id, source).await
+    }
+
+    async fn update_dataset_item(
+        &self,
+        dataset_id: Uuid,
+        item_id: Uuid,
+        expected_response: &serde_json::Value,
+    ) -> Result<bool, Report<Error>> {
+        self.inner
+            .update_dataset_item(dataset_id, item_id, expected_response)
+            .await
+    }
+
+    async fn list_dataset_items(
+        &self,
+        dataset_id: Uuid,
+    ) -> Result<Vec<DatasetItem>, Report<Error>> {
+        // Items are copied from events, so they can refer to bodies in the blob store too.
+        let mut items = self.inner.list_dataset_items(dataset_id).await?;
+        for item in &mut items {
+            self.resolve(&mut item.request).await?;
+            if let Some(expected) = &mut item.expected_response {
+                self.resolve(expected).await?;
+            }
+        }
+
+        Ok(items)
+    }
+
+    async fn list_events_for_export(
+        &self,
+        query: &ExportEventQuery,
+        page: ExportPage,
+    ) -> Result<Vec<RunEvent>, Report<Error>> {
+        let mut events = self.inner.list_events_for_export(query, page).await?;
+        for event in &mut events {
+            self.resolve_event(event).await?;
+        }
+
+        Ok(events)
+    }
+
+    async fn create_prompt(&self, prompt: &NewPrompt) -> Result<Prompt, Report<Error>> {
+        self.inner.create_prompt(prompt).await
+    }
+
+    async fn list_prompts(&self) -> Result<Vec<Prompt>, Report<Error>> {
+        self.inner.list_prompts().await
+    }
+
+    async fn list_prompt_versions(&self, id: &str) -> Result<Vec<Prompt>, Report<Error>> {
+        self.inner.list_prompt_versions(id).await
+    }
+
+    async fn get_prompt(
+        &self,
+        id: &str,
+        version: &PromptVersion,
+    ) -> Result<Option<Prompt>, Report<Error>> {
+        self.inner.get_prompt(id, version).await
+    }
+
+    async fn set_prompt_label(
+        &self,
+        id: &str,
+        label: &str,
+        version: u32,
+    ) -> Result<bool, Report<Error>> {
+        self.inner.set_prompt_label(id, label, version).await
+    }
+
+    async fn remove_prompt_label(&self, id: &str, label: &str) -> Result<bool, Report<Error>> {
+        self.inner.remove_prompt_label(id, label).await
+    }
+
+    async fn delete_prompt(&self, id: &str, version: Option<u32>) -> Result<u64, Report<Error>> {
+        self.inner.delete_prompt(id, version).await
+    }
+
+    async fn search_events(
+        &self,
+        query: &EventSearchQuery,
+    ) -> Result<Vec<EventSearchResult>, Report<Error>> {
+        let mut results = self.inner.search_events(query).await?;
+        for result in &mut results {
+            self.resolve_event(&mut result.event).await?;
+        }
+
+        Ok(results)
+    }
+
+    async fn find_expired(
+        &self,
+        table: PurgeTable,
+        cutoffs: &RetentionCutoffs,
+        limit: usize,
+    ) -> Result<Vec<Uuid>, Report<Error>> {
+        self.inner.find_expired(table, cutoffs, limit).await
+    }
+
+    async fn count_expired(
+        &self,
+        cutoffs: &RetentionCutoffs,
+    ) -> Result<PurgeCounts, Report<Error>> {
+        self.inner.count_expired(cutoffs).await
+    }
+
+    async fn load_rows_for_archive(
+        &self,
+        table: PurgeTable,
+        ids: &[Uuid],
+    ) -> Result<Vec<ArchivedRow>, Report<Error>> {
+        let mut rows = self.inner.load_rows_for_archive(table, ids).await?;
+        for row in &mut rows {
+            for column in ["chat_request", "chat_response"] {
+                if let Some(value) = row.row.get_mut(column) {
+                    self.resolve(value).await?;
+                }
+            }
+        }
+
+        Ok(rows)
+    }
+
+    async fn delete_rows(
+        &self,
+        table: PurgeTable,
+        ids: &[Uuid],
+    ) -> Result<PurgeCounts, Report<Error>> {
+        self.inner.delete_rows(table, ids).await
+    }
+
+    async fn find_stale_runs(
+        &self,
+        cutoffs: &StaleCutoffs,
+        limit: usize,
+    ) -> Result<Vec<StaleRun>, Report<Error>> {
+        self.inner.find_stale_runs(cutoffs, limit).await
+    }
+
+    async fn mark_stale_runs(
+        &self,
+        ids: &[Uuid],
+        run_status: &str,
+        step_status: &str,
+    ) -> Result<MarkedStaleRuns, Report<Error>> {
+        self.inner
+            .mark_stale_runs(ids, run_status, step_status)
+            .await
+    }
+
+    async fn create_partitions(&self, now: DateTime<Utc>) -> Result<(), Report<Error>> {
+        self.inner.create_partitions(now).await
+    }
+
+    async fn drop_expired_partitions(&self, before: DateTime<Utc>) -> Result<u64, Report<Error>> {
+        self.inner.drop_expired_partitions(before).await
+    }
+
+    async fn notify_live_events(&self, events: &[LiveEvent]) -> Result<bool, Report<Error>> {
+        self.inner.notify_live_events(events).await
+    }
+
+    async fn listen_live_events(
+        &self,
+        tx: LiveEventSender,
+    ) -> Result<Option<tokio::task::JoinHandle<()>>, Report<Error>> {
+        self.inner.listen_live_events(tx).await
+    }
+}
+
+/// Create the blob store described by `config`, and wrap `db` so that it uses the store.
+pub fn offload_to_blob_store(db: Database, config: BlobStorageConfig) -> Database {
+    BlobOffloadingDatabase::new(
+        db,
+        config.store.into_store(),
+        config.threshold_bytes.unwrap_or(DEFAULT_BLOB_THRESHOLD),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("chronicle-blobs-{}", Uuid::now_v7()))
+    }
+
+    #[tokio::test]
+    async fn filesystem_store() {
+        let dir = temp_dir();
+        let store = FilesystemBlobStore::new(&dir);
+        let key = "sha256:0123456789abcdef";
+
+        assert_eq!(store.get(key).await.unwrap(), None);
+        store.put(key, Bytes::from_static(b"data")).await.unwrap();
+        store.put(key, Bytes::from_static(b"data")).await.unwrap();
+        assert_eq!(
+            store.get(key).await.unwrap(),
+            Some(Bytes::from_static(b"data"))
+        );
+        assert!(dir.join("01").join("0123456789abcdef").exists());
+
+        assert!(
+            store.get("sha256:../../etc/passwd").await.is_err(),
+            "keys must be hex"
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn parse_blob_ref() {
+        let blob = BlobRef {
+            key: "sha256:abc".to_string(),
+            size: 10,
+        };
+        let value = serde_json::to_value(&blob).unwrap();
+        assert_eq!(value, json!({ "$blob": "sha256:abc", "size": 10 }));
+        assert_eq!(BlobRef::from_value(&value), Some(blob.clone()));
+        assert_eq!(
+            BlobRef::from_value(&json!(value.to_string())),
+            Some(blob.clone())
+        );
+        assert_eq!(
+            BlobRef::from_value(&json!({ "$blob": "sha256:abc", "size": 10, "other": 1 })),
+            None,
+            "objects with other fields are not references"
+        );
+
+        let stored = serde_json::to_value(StoredBody::new(json!({ "a": 1 }), None)).unwrap();
+        assert_eq!(stored, json!({ "a": 1 }));
+        let stored = serde_json::to_value(StoredBody::new(json!({ "a": 1 }), Some(blob))).unwrap();
+        assert_eq!(stored, value);
+    }
+}
@@ -0,0 +1,152 @@
+//! Datasets of requests and expected responses, curated from logged events
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::tables::TableNames;
+
+pub(super) const DATASET_ITEM_COLUMNS: &str =
+    "id, dataset_id, event_id, request, expected_response, created_at, updated_at";
+
+/// The default number of events added by a [DatasetEventQuery]
+pub const DEFAULT_DATASET_QUERY_LIMIT: u32 = 1000;
+
+/// Select datasets along with the number of items in each
+pub(super) fn select_datasets(tables: &TableNames) -> String {
+    format!(
+        "SELECT d.id, d.name, d.version, d.description, d.created_at,
+            (SELECT count(*) FROM {} i WHERE i.dataset_id = d.id) AS item_count
+        FROM {} d",
+        tables.dataset_items(),
+        tables.datasets()
+    )
+}
+
+/// A dataset to create
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewDataset {
+    /// The name of the dataset. If a dataset with this name already exists, a new version of it
+    /// is created.
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// A version of a dataset
+#[derive(Debug, Clone, Serialize)]
+pub struct Dataset {
+    pub id: Uuid,
+    pub name: String,
+    /// The version of the dataset, starting at 1
+    pub version: i32,
+    pub description: Option<String>,
+    /// The number of items in this version of the dataset
+    pub item_count: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A request in a dataset, along with the response expected for it
+#[derive(Debug, Clone, Serialize)]
+pub struct DatasetItem {
+    pub id: Uuid,
+    pub dataset_id: Uuid,
+    /// The event that the item was copied from
+    pub event_id: Option<Uuid>,
+    /// The [ChatRequest](crate::format::ChatRequest) from the event
+    pub request: serde_json::Value,
+    /// The expected [SingleChatResponse](crate::format::SingleChatResponse). This starts as the
+    /// response that the event received, and can be edited.
+    pub expected_response: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The events to add to a dataset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DatasetItemSource {
+    /// Add the events with these IDs
+    Events(Vec<Uuid>),
+    /// Add the events that match a query
+    Query(DatasetEventQuery),
+}
+
+/// Filters for choosing events to add to a dataset. Events that returned an error are never
+/// matched.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DatasetEventQuery {
+    /// Only add events from this application
+    pub application: Option<String>,
+    /// Only add events from this environment
+    pub environment: Option<String>,
+    /// Only add events that used this provider
+    pub provider: Option<String>,
+    /// Only add events that used this model
+    pub model: Option<String>,
+    /// Only add events from this run
+    pub run_id: Option<Uuid>,
+    /// Only add events created at or after this time
+    pub start_time: Option<DateTime<Utc>>,
+    /// Only add events created before this time
+    pub end_time: Option<DateTime<Utc>>,
+    /// The maximum number of events to add, starting with the newest. Defaults to
+    /// [DEFAULT_DATASET_QUERY_LIMIT].
+    pub limit: Option<u32>,
+}
+
+/// Write dataset items as JSON lines, one item per line
+pub fn items_to_jsonl(items: &[DatasetItem]) -> Result<Vec<u8>, serde_json::Error> {
+    let mut buf = Vec::new();
+    for item in items {
+        serde_json::to_writer(&mut buf, item)?;
+        buf.push(b'\n');
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parse_item_source() {
+        let id = Uuid::now_v7();
+        let source: DatasetItemSource = serde_json::from_value(json!({ "events": [id] })).unwrap();
+        assert!(matches!(source, DatasetItemSource::Events(ids) if ids == vec![id]));
+
+        let source: DatasetItemSource =
+            serde_json::from_value(json!({ "query": { "application": "app", "limit": 10 } }))
+                .unwrap();
+        let DatasetItemSource::Query(query) = source else {
+            panic!("Expected a query");
+        };
+        assert_eq!(query.application.as_deref(), Some("app"));
+        assert_eq!(query.limit, Some(10));
+    }
+
+    #[test]
+    fn jsonl() {
+        let item = |n: u128| DatasetItem {
+            id: Uuid::from_u128(n),
+            dataset_id: Uuid::from_u128(100),
+            event_id: Some(Uuid::from_u128(n + 10)),
+            request: json!({ "messages": [{ "role": "user", "content": "hi" }] }),
+            expected_response: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        let output = items_to_jsonl(&[item(1), item(2)]).unwrap();
+        let lines = std::str::from_utf8(&output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["id"], json!(Uuid::from_u128(1)));
+        assert_eq!(lines[1]["event_id"], json!(Uuid::from_u128(12)));
+        assert_eq!(lines[1]["request"]["messages"][0]["content"], json!("hi"));
+        assert!(output.ends_with(b"\n"));
+    }
+}
@@ -0,0 +1,50 @@
+//! Selecting logged events to export
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The number of events loaded at a time while exporting
+pub const EXPORT_PAGE_SIZE: u32 = 500;
+
+/// Filters for choosing events to export. Only events with both a request and a response are
+/// matched.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ExportEventQuery {
+    /// Only export events from this application
+    pub application: Option<String>,
+    /// Only export events from this environment
+    pub environment: Option<String>,
+    /// Only export events that used this provider
+    pub provider: Option<String>,
+    /// Only export events that used this model
+    pub model: Option<String>,
+    /// Only export events that used this prompt
+    pub prompt_id: Option<String>,
+    /// Only export events that used this version of the prompt
+    pub prompt_version: Option<u32>,
+    /// Only export events from this run
+    pub run_id: Option<Uuid>,
+    /// Only export events whose extra metadata has each of these values
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// Also export events that returned an error
+    #[serde(default)]
+    pub include_errors: bool,
+    /// Only export events created at or after this time
+    pub start_time: Option<DateTime<Utc>>,
+    /// Only export events created before this time
+    pub end_time: Option<DateTime<Utc>>,
+    /// The maximum number of events to export. By default there is no limit.
+    pub limit: Option<u32>,
+}
+
+/// A page of events to load for an export
+#[derive(Debug, Clone, Copy)]
+pub struct ExportPage {
+    /// Load events with IDs after this one
+    pub after: Option<Uuid>,
+    /// The maximum number of events to load
+    pub limit: u32,
+}
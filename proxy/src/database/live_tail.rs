@@ -0,0 +1,299 @@
+//! Streaming logged events to live listeners
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{logging::ProxyLogEntry, ProxyDatabase};
+use crate::workflow_events::WorkflowEvent;
+
+/// The Postgres NOTIFY channel used to share live events between servers
+pub const LIVE_EVENT_CHANNEL: &str = "chronicle_live_events";
+
+/// How many events a slow listener can fall behind before it starts missing events
+pub const LIVE_EVENT_BUFFER: usize = 1024;
+
+/// Error text longer than this is truncated. Postgres limits NOTIFY payloads to 8000 bytes, so
+/// the events need to stay small.
+const MAX_ERROR_LENGTH: usize = 1000;
+
+/// A channel on which live events are broadcast
+pub type LiveEventSender = tokio::sync::broadcast::Sender<Arc<LiveEvent>>;
+/// A receiver for live events
+pub type LiveEventReceiver = tokio::sync::broadcast::Receiver<Arc<LiveEvent>>;
+
+/// Sends logged events to live tail listeners
+#[derive(Debug, Clone)]
+pub struct LiveTail {
+    /// Listeners in this process receive events from this channel
+    pub tx: LiveEventSender,
+    /// If true, a task is forwarding events from the database to `tx`, so events should be sent
+    /// through the database instead of directly to `tx`. This lets listeners see events from
+    /// every server that uses the database.
+    pub listening_to_database: bool,
+}
+
+impl LiveTail {
+    /// Send events to the live tail listeners
+    pub async fn publish(&self, db: &dyn ProxyDatabase, events: Vec<LiveEvent>) {
+        if self.listening_to_database {
+            match db.notify_live_events(&events).await {
+                // The events will come back through the database listener.
+                Ok(true) => return,
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::error!(error = ?e, "Failed to send live events through the database");
+                }
+            }
+        }
+
+        for event in events {
+            // This only fails if there are no receivers, which is fine.
+            self.tx.send(Arc::new(event)).ok();
+        }
+    }
+}
+
+/// A summary of a logged event, sent to live tail listeners.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveEvent {
+    /// The type of the event, such as `chronicle_llm_request` or `step:start`
+    #[serde(rename = "type")]
+    pub typ: String,
+    /// The event's ID, for events that are stored in the events table
+    pub id: Option<Uuid>,
+    pub run_id: Option<Uuid>,
+    pub step_id: Option<Uuid>,
+    pub application: Option<String>,
+    pub user_id: Option<String>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// The name of the run or step
+    pub name: Option<String>,
+    /// The new status of the run or step
+    pub status: Option<String>,
+    /// The total latency of an LLM request, including retries
+    pub latency_ms: Option<u64>,
+    pub prompt_tokens: Option<usize>,
+    pub completion_tokens: Option<usize>,
+    pub error: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+impl LiveEvent {
+    fn new(typ: impl Into<String>, time: Option<DateTime<Utc>>) -> Self {
+        Self {
+            typ: typ.into(),
+            id: None,
+            run_id: None,
+            step_id: None,
+            application: None,
+            user_id: None,
+            provider: None,
+            model: None,
+            name: None,
+            status: None,
+            latency_ms: None,
+            prompt_tokens: None,
+            completion_tokens: None,
+            error: None,
+            time: time.unwrap_or_else(Utc::now),
+        }
+    }
+
+    /// Summarize a log entry
+    pub fn from_log_entry(entry: &ProxyLogEntry) -> Self {
+        match entry {
+            ProxyLogEntry::Proxied(event) => {
+                let metadata = &event.options.metadata;
+                let usage = event.response.as_ref().and_then(|r| r.body.usage.as_ref());
+                LiveEvent {
+                    id: Some(event.id),
+                    run_id: metadata.run_id,
+                    step_id: metadata.step_id,
+                    application: metadata.application.clone(),
+                    user_id: metadata.user_id.clone(),
+                    provider: event.response.as_ref().map(|r| r.provider.clone()),
+                    model: event
+                        .response
+                        .as_ref()
+                        .and_then(|r| r.body.model.clone())
+                        .or_else(|| event.request.as_ref().and_then(|r| r.model.clone())),
+                    status: event.error.as_ref().map(|_| "error".to_string()),
+                    latency_ms: event.total_latency.map(|l| l.as_millis() as u64),
+                    prompt_tokens: usage.and_then(|u| u.prompt_tokens),
+                    completion_tokens: usage.and_then(|u| u.completion_tokens),
+                    error: event.error.as_ref().map(error_text),
+                    ..LiveEvent::new(event.event_type.as_ref(), Some(event.timestamp))
+                }
+            }
+            ProxyLogEntry::Workflow(WorkflowEvent::RunStart(event)) => LiveEvent {
+                run_id: Some(event.id),
+                name: Some(event.name.clone()),
+                application: event.application.clone(),
+                user_id: event
+                    .info
+                    .as_ref()
+                    .and_then(|info| info["user_id"].as_str())
+                    .map(|s| s.to_string()),
+                status: Some(
+                    event
+                        .status
+                        .clone()
+                        .unwrap_or_else(|| "started".to_string()),
+                ),
+                ..LiveEvent::new("run:start", Some(event.event_time()))
+            },
+            ProxyLogEntry::Workflow(WorkflowEvent::RunUpdate(event)) => LiveEvent {
+                run_id: Some(event.id),
+                status: event.status.clone(),
+                ..LiveEvent::new("run:update", Some(event.event_time()))
+            },
+            ProxyLogEntry::Workflow(WorkflowEvent::StepStart(event)) => LiveEvent {
+                run_id: Some(event.run_id),
+                step_id: Some(event.step_id),
+                name: event.data.name.clone(),
+                status: Some("started".to_string()),
+                ..LiveEvent::new("step:start", Some(event.event_time()))
+            },
+            ProxyLogEntry::Workflow(WorkflowEvent::StepEnd(event)) => LiveEvent {
+                run_id: Some(event.run_id),
+                step_id: Some(event.step_id),
+                status: Some("finished".to_string()),
+                ..LiveEvent::new("step:end", Some(event.event_time()))
+            },
+            ProxyLogEntry::Workflow(WorkflowEvent::StepError(event)) => LiveEvent {
+                run_id: Some(event.run_id),
+                step_id: Some(event.step_id),
+                status: Some("error".to_string()),
+                error: Some(error_text(&event.data.error)),
+                ..LiveEvent::new("step:error", Some(event.event_time()))
+            },
+            ProxyLogEntry::Workflow(WorkflowEvent::StepState(event)) => LiveEvent {
+                run_id: Some(event.run_id),
+                step_id: Some(event.step_id),
+                status: Some(event.data.state.clone()),
+                ..LiveEvent::new("step:state", Some(event.event_time()))
+            },
+            ProxyLogEntry::Workflow(WorkflowEvent::Score(event)) => LiveEvent {
+                id: event.event_id,
+                run_id: event.run_id,
+                step_id: event.step_id,
+                name: Some(event.name.clone()),
+                ..LiveEvent::new("score", event.time)
+            },
+            ProxyLogEntry::Workflow(WorkflowEvent::Event(event)) => LiveEvent {
+                id: event.event_id,
+                run_id: Some(event.run_id),
+                step_id: Some(event.step_id),
+                error: event.error.as_ref().map(error_text),
+                ..LiveEvent::new(event.typ.clone(), Some(event.event_time()))
+            },
+        }
+    }
+}
+
+fn error_text(error: &serde_json::Value) -> String {
+    let mut text = match error {
+        serde_json::Value::String(s) => s.clone(),
+        _ => error.to_string(),
+    };
+
+    if let Some((index, _)) = text.char_indices().nth(MAX_ERROR_LENGTH) {
+        text.truncate(index);
+    }
+
+    text
+}
+
+/// Filters for a stream of live events. Events which don't have a value for a filtered field
+/// are excluded. Notably, step events don't contain an application or user ID.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LiveEventFilter {
+    /// Only return events for this run
+    pub run_id: Option<Uuid>,
+    /// Only return events from this application
+    pub application: Option<String>,
+    /// Only return events from this user
+    pub user_id: Option<String>,
+}
+
+impl LiveEventFilter {
+    /// Return true if the event passes the filter
+    pub fn matches(&self, event: &LiveEvent) -> bool {
+        fn check<T: PartialEq>(filter: &Option<T>, value: &Option<T>) -> bool {
+            filter.is_none() || filter == value
+        }
+
+        check(&self.run_id, &event.run_id)
+            && check(&self.application, &event.application)
+            && check(&self.user_id, &event.user_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use super::{error_text, LiveEvent, LiveEventFilter, MAX_ERROR_LENGTH};
+    use crate::{
+        database::logging::ProxyLogEntry,
+        workflow_events::{StepEventData, StepStateData, WorkflowEvent},
+    };
+
+    #[test]
+    fn filter() {
+        let run_id = Uuid::now_v7();
+        let event = LiveEvent {
+            run_id: Some(run_id),
+            application: Some("app".to_string()),
+            ..LiveEvent::new("chronicle_llm_request", None)
+        };
+
+        assert!(LiveEventFilter::default().matches(&event));
+        assert!(LiveEventFilter {
+            run_id: Some(run_id),
+            application: Some("app".to_string()),
+            user_id: None,
+        }
+        .matches(&event));
+        assert!(!LiveEventFilter {
+            run_id: Some(Uuid::now_v7()),
+            ..Default::default()
+        }
+        .matches(&event));
+        assert!(!LiveEventFilter {
+            user_id: Some("user".to_string()),
+            ..Default::default()
+        }
+        .matches(&event));
+    }
+
+    #[test]
+    fn step_state_event() {
+        let run_id = Uuid::now_v7();
+        let step_id = Uuid::now_v7();
+        let entry = ProxyLogEntry::Workflow(WorkflowEvent::StepState(StepEventData {
+            run_id,
+            step_id,
+            data: StepStateData {
+                state: "pending".to_string(),
+            },
+            time: None,
+            event_id: None,
+        }));
+
+        let event = LiveEvent::from_log_entry(&entry);
+        assert_eq!(event.typ, "step:state");
+        assert_eq!(event.run_id, Some(run_id));
+        assert_eq!(event.step_id, Some(step_id));
+        assert_eq!(event.status.as_deref(), Some("pending"));
+    }
+
+    #[test]
+    fn truncate_error() {
+        let error = serde_json::Value::String("é".repeat(MAX_ERROR_LENGTH + 10));
+        assert_eq!(error_text(&error).chars().count(), MAX_ERROR_LENGTH);
+    }
+}
@@ -1,20 +1,28 @@
 //! Logging events to the database
-use std::{borrow::Cow, time::Duration};
+use std::{borrow::Cow, sync::Arc, time::Duration};
 
 use chrono::Utc;
+use error_stack::{Report, ResultExt};
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use tracing::instrument;
 use uuid::Uuid;
 
-use super::{Database, ProxyDatabase};
+use super::{
+    blobs::OffloadedBodies,
+    live_tail::{LiveEvent, LiveTail},
+    Database,
+};
 use crate::{
     format::{ChatRequest, ResponseInfo, SingleChatResponse},
+    metrics::ProxyMetrics,
+    redact::{LogMode, Redactor},
     workflow_events::{EventPayload, WorkflowEvent},
-    ProxyRequestOptions,
+    Error, ProxyRequestInternalMetadata, ProxyRequestMetadata, ProxyRequestOptions,
 };
 
 /// An event from the proxy.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ProxyLogEvent {
     /// A unique ID for this event
     pub id: Uuid,
@@ -38,11 +46,14 @@ pub struct ProxyLogEvent {
     pub error: Option<serde_json::Value>,
     /// The options that were used for the request
     pub options: ProxyRequestOptions,
+    /// The bodies that were moved to the blob store, if any
+    pub offloaded: OffloadedBodies,
 }
 
 impl ProxyLogEvent {
     /// Create a new event from a submitted payload
     pub fn from_payload(id: Uuid, payload: EventPayload) -> Self {
+        let timestamp = payload.event_time();
         let extra = match payload.data {
             Some(serde_json::Value::Object(m)) => Some(m),
             _ => None,
@@ -51,7 +62,7 @@ impl ProxyLogEvent {
         ProxyLogEvent {
             id,
             event_type: Cow::Owned(payload.typ),
-            timestamp: payload.time.unwrap_or_else(|| Utc::now()),
+            timestamp,
             request: None,
             response: None,
             total_latency: None,
@@ -69,12 +80,13 @@ impl ProxyLogEvent {
                 internal_metadata: payload.internal_metadata.unwrap_or_default(),
                 ..Default::default()
             },
+            offloaded: Default::default(),
         }
     }
 }
 
 /// A response from the model provider, collected into a single body if it was streamed
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CollectedProxiedResult {
     /// The response itself
     pub body: SingleChatResponse,
@@ -85,7 +97,7 @@ pub struct CollectedProxiedResult {
 }
 
 /// An event to be logged
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ProxyLogEntry {
     /// The result of a proxied model request
     Proxied(Box<ProxyLogEvent>),
@@ -93,30 +105,385 @@ pub enum ProxyLogEntry {
     Workflow(WorkflowEvent),
 }
 
+impl ProxyLogEntry {
+    /// Remove the request and response bodies, and the inputs and outputs of workflow events,
+    /// leaving only the metadata.
+    pub fn remove_bodies(&mut self) {
+        match self {
+            ProxyLogEntry::Proxied(event) => {
+                if let Some(request) = &mut event.request {
+                    request.messages.clear();
+                    request.system = None;
+                    request.tools.clear();
+                }
+                if let Some(response) = &mut event.response {
+                    response.body.choices.clear();
+                }
+            }
+            ProxyLogEntry::Workflow(WorkflowEvent::RunStart(event)) => event.input = None,
+            ProxyLogEntry::Workflow(WorkflowEvent::RunUpdate(event)) => event.output = None,
+            ProxyLogEntry::Workflow(WorkflowEvent::StepStart(event)) => {
+                event.data.input = serde_json::Value::Null
+            }
+            ProxyLogEntry::Workflow(WorkflowEvent::StepEnd(event)) => {
+                event.data.output = serde_json::Value::Null
+            }
+            ProxyLogEntry::Workflow(WorkflowEvent::Event(event)) => event.data = None,
+            ProxyLogEntry::Workflow(WorkflowEvent::StepError(_))
+            | ProxyLogEntry::Workflow(WorkflowEvent::StepState(_))
+            | ProxyLogEntry::Workflow(WorkflowEvent::Score(_)) => {}
+        }
+    }
+}
+
+/// The serialized form of a [ProxyLogEvent], used by sinks that write JSON.
+#[derive(Serialize)]
+struct ProxyLogEventRecord<'a> {
+    #[serde(rename = "type")]
+    event_type: &'a str,
+    id: Uuid,
+    timestamp: chrono::DateTime<Utc>,
+    provider: Option<&'a str>,
+    request: Option<&'a ChatRequest>,
+    response: Option<&'a SingleChatResponse>,
+    response_meta: Option<&'a serde_json::Value>,
+    latency_ms: Option<u64>,
+    total_latency_ms: Option<u64>,
+    was_rate_limited: Option<bool>,
+    num_retries: Option<u32>,
+    error: Option<&'a serde_json::Value>,
+    metadata: &'a ProxyRequestMetadata,
+    internal_metadata: &'a ProxyRequestInternalMetadata,
+}
+
+impl Serialize for ProxyLogEntry {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ProxyLogEntry::Proxied(event) => ProxyLogEventRecord {
+                event_type: &event.event_type,
+                id: event.id,
+                timestamp: event.timestamp,
+                provider: event.response.as_ref().map(|r| r.provider.as_str()),
+                request: event.request.as_ref(),
+                response: event.response.as_ref().map(|r| &r.body),
+                response_meta: event.response.as_ref().and_then(|r| r.info.meta.as_ref()),
+                latency_ms: event.latency.map(|l| l.as_millis() as u64),
+                total_latency_ms: event.total_latency.map(|l| l.as_millis() as u64),
+                was_rate_limited: event.was_rate_limited,
+                num_retries: event.num_retries,
+                error: event.error.as_ref(),
+                metadata: &event.options.metadata,
+                internal_metadata: &event.options.internal_metadata,
+            }
+            .serialize(serializer),
+            ProxyLogEntry::Workflow(event) => event.serialize(serializer),
+        }
+    }
+}
+
+/// The default number of entries to write in each batch
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+/// The default time to wait for more entries before writing a partial batch
+pub const DEFAULT_DEBOUNCE_TIME: Duration = Duration::from_secs(1);
+
+/// How often an idle logger calls [LogSink::retry_pending]
+const RETRY_PENDING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A group of entries sent to the logger together
+type LogMessage = SmallVec<[ProxyLogEntry; 1]>;
+
+/// What to do with new log entries when the log queue is full
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogOverflowPolicy {
+    /// Wait until there is room in the queue. No entries are lost, but whatever is logging the
+    /// entry has to wait.
+    #[default]
+    Block,
+    /// Drop the new entries
+    DropNewest,
+    /// Drop the oldest entries in the queue to make room for the new ones
+    DropOldest,
+    /// Remove the request and response bodies from new entries, and queue just the metadata.
+    /// The queue can grow to twice its capacity with these smaller entries, after which new
+    /// entries are dropped.
+    DropBodies,
+}
+
+/// Limits on the queue of entries waiting to be logged
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LogQueueOptions {
+    /// The maximum number of messages waiting to be logged. Events that are sent together, such
+    /// as through [Proxy::record_event_batch](crate::Proxy::record_event_batch), count as a
+    /// single message. If omitted, the queue is unbounded.
+    pub capacity: Option<usize>,
+    /// What to do when the queue is full
+    #[serde(default)]
+    pub overflow: LogOverflowPolicy,
+}
+
+impl LogQueueOptions {
+    fn channel<T>(&self) -> (flume::Sender<T>, flume::Receiver<T>) {
+        match self.capacity {
+            Some(capacity) if self.overflow == LogOverflowPolicy::DropBodies => {
+                flume::bounded(capacity.max(1) * 2)
+            }
+            Some(capacity) => flume::bounded(capacity.max(1)),
+            None => flume::unbounded(),
+        }
+    }
+}
+
 /// A channel on which log events can be sent.
-pub type LogSender = flume::Sender<SmallVec<[ProxyLogEntry; 1]>>;
+#[derive(Debug, Clone)]
+pub struct LogSender {
+    tx: flume::Sender<LogMessage>,
+    /// Used to remove the oldest messages when the overflow policy is
+    /// [LogOverflowPolicy::DropOldest]
+    rx: Option<flume::Receiver<LogMessage>>,
+    capacity: Option<usize>,
+    overflow: LogOverflowPolicy,
+    redactor: Option<Arc<Redactor>>,
+    metrics: Option<Arc<ProxyMetrics>>,
+}
 
-/// Start the database logger task
+impl LogSender {
+    fn new(
+        queue: &LogQueueOptions,
+        redactor: Option<Arc<Redactor>>,
+        metrics: Option<Arc<ProxyMetrics>>,
+    ) -> (Self, flume::Receiver<LogMessage>) {
+        let (tx, rx) = queue.channel();
+        let sender = LogSender {
+            tx,
+            rx: (queue.overflow == LogOverflowPolicy::DropOldest).then(|| rx.clone()),
+            capacity: queue.capacity,
+            overflow: queue.overflow,
+            redactor,
+            metrics,
+        };
+
+        (sender, rx)
+    }
+
+    /// Redact the entries and queue them to be logged, applying the overflow policy if the
+    /// queue is full.
+    pub async fn send(&self, mut entries: LogMessage) {
+        self.redact(&mut entries);
+
+        let Some(capacity) = self.capacity else {
+            // The channel is unbounded so this can't be full.
+            self.tx.send(entries).ok();
+            return;
+        };
+
+        match self.overflow {
+            LogOverflowPolicy::Block => {
+                self.tx.send_async(entries).await.ok();
+            }
+            LogOverflowPolicy::DropNewest => {
+                if let Err(flume::TrySendError::Full(entries)) = self.tx.try_send(entries) {
+                    self.record_dropped(entries.len());
+                }
+            }
+            LogOverflowPolicy::DropOldest => loop {
+                match self.tx.try_send(entries) {
+                    Ok(()) | Err(flume::TrySendError::Disconnected(_)) => break,
+                    Err(flume::TrySendError::Full(returned)) => {
+                        entries = returned;
+                        if let Some(oldest) = self.rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                            self.record_dropped(oldest.len());
+                        }
+                    }
+                }
+            },
+            LogOverflowPolicy::DropBodies => {
+                let strip_bodies = self.tx.len() >= capacity;
+                if strip_bodies {
+                    for entry in entries.iter_mut() {
+                        entry.remove_bodies();
+                    }
+                }
+
+                let count = entries.len();
+                match self.tx.try_send(entries) {
+                    Ok(()) if strip_bodies => {
+                        if let Some(metrics) = &self.metrics {
+                            metrics.record_dropped_log_bodies(count);
+                        }
+                    }
+                    Err(flume::TrySendError::Full(_)) => self.record_dropped(count),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn redact(&self, entries: &mut LogMessage) {
+        for entry in entries.iter_mut() {
+            let metadata_only = matches!(
+                entry,
+                ProxyLogEntry::Proxied(event) if event.options.log == Some(LogMode::MetadataOnly)
+            );
+
+            if metadata_only {
+                entry.remove_bodies();
+            } else if let Some(redactor) = &self.redactor {
+                redactor.redact(entry);
+            }
+        }
+    }
+
+    /// The number of messages waiting in the queue
+    pub fn len(&self) -> usize {
+        self.tx.len()
+    }
+
+    /// Whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.tx.is_empty()
+    }
+
+    fn record_dropped(&self, count: usize) {
+        tracing::warn!(count, "Log queue is full, dropping log events");
+        if let Some(metrics) = &self.metrics {
+            metrics.record_dropped_log_events("queue_full", count);
+        }
+    }
+}
+
+/// A destination for logged events
+#[async_trait::async_trait]
+pub trait LogSink: std::fmt::Debug + Send + Sync {
+    /// A name for the sink, used when reporting errors
+    fn name(&self) -> &str;
+
+    /// Write a batch of log entries. If this returns an error, the entries are counted as
+    /// dropped.
+    async fn write_batch(&self, items: Vec<ProxyLogEntry>) -> Result<(), Report<Error>>;
+
+    /// Called when the logger starts, and periodically while it is idle, so that sinks which
+    /// hold on to failed writes can try them again.
+    async fn retry_pending(&self) {}
+
+    /// Called once for each batch that could not be written, after any retries.
+    fn record_write_failure(&self) {}
+
+    /// Whether a write that failed with `error` would fail the same way if it were retried, such as
+    /// when the destination rejects the data itself.
+    fn is_permanent_error(&self, _error: &Report<Error>) -> bool {
+        false
+    }
+}
+
+/// A [LogSink] along with how to batch the entries sent to it
+#[derive(Debug, Clone)]
+pub struct LogDestination {
+    /// The sink to write to
+    pub sink: Arc<dyn LogSink>,
+    /// Write a batch once it has this many entries
+    pub batch_size: usize,
+    /// Write a partial batch once no new entries have arrived for this long
+    pub debounce_time: Duration,
+}
+
+/// Start the logger task, which sends each log entry to every destination. Each destination is
+/// batched and written independently, but when the queue is bounded, a sink that falls behind
+/// by more than the queue capacity will hold up the others. If `redactor` is provided, entries
+/// are redacted before they are queued. If `metrics` is provided, dropped entries are counted
+/// there.
+pub fn start_logger(
+    destinations: Vec<LogDestination>,
+    queue: LogQueueOptions,
+    redactor: Option<Arc<Redactor>>,
+    metrics: Option<Arc<ProxyMetrics>>,
+) -> (LogSender, tokio::task::JoinHandle<()>) {
+    let (log_tx, log_rx) = LogSender::new(&queue, redactor, metrics.clone());
+    let task = tokio::task::spawn(logger_task(destinations, log_rx, queue, metrics));
+
+    (log_tx, task)
+}
+
+/// Start a logger task that only writes to the database. If `metrics` is provided, failed
+/// database writes will be recorded there. If `live_tail` is provided, each logged entry is also
+/// sent to live tail listeners.
 pub fn start_database_logger(
     db: Database,
     batch_size: usize,
     debounce_time: Duration,
+    metrics: Option<Arc<ProxyMetrics>>,
+    live_tail: Option<LiveTail>,
 ) -> (LogSender, tokio::task::JoinHandle<()>) {
-    let (log_tx, log_rx) = flume::unbounded();
+    start_logger(
+        vec![LogDestination {
+            sink: Arc::new(DatabaseSink::new(db, metrics.clone(), live_tail)),
+            batch_size,
+            debounce_time,
+        }],
+        LogQueueOptions::default(),
+        None,
+        metrics,
+    )
+}
 
-    let task = tokio::task::spawn(database_logger_task(db, log_rx, batch_size, debounce_time));
+async fn logger_task(
+    mut destinations: Vec<LogDestination>,
+    rx: flume::Receiver<LogMessage>,
+    queue: LogQueueOptions,
+    metrics: Option<Arc<ProxyMetrics>>,
+) {
+    if destinations.len() == 1 {
+        // Nothing to fan out to, so the sink can read the channel directly.
+        let destination = destinations.pop().unwrap();
+        sink_task(destination, rx, metrics).await;
+        return;
+    }
 
-    (log_tx, task)
+    let (senders, tasks): (Vec<_>, Vec<_>) = destinations
+        .into_iter()
+        .map(|destination| {
+            let (tx, rx) = queue.channel();
+            (
+                tx,
+                tokio::task::spawn(sink_task(destination, rx, metrics.clone())),
+            )
+        })
+        .unzip();
+
+    while let Ok(item) = rx.recv_async().await {
+        let Some((last, rest)) = senders.split_last() else {
+            // No destinations, so just drain the channel.
+            continue;
+        };
+
+        for tx in rest {
+            tx.send_async(item.clone()).await.ok();
+        }
+        last.send_async(item).await.ok();
+    }
+
+    // Close the sink channels and wait for them to write their final batches.
+    drop(senders);
+    for task in tasks {
+        task.await.ok();
+    }
 }
 
-async fn database_logger_task(
-    db: Database,
-    rx: flume::Receiver<SmallVec<[ProxyLogEntry; 1]>>,
-    batch_size: usize,
-    debounce_time: Duration,
+async fn sink_task(
+    destination: LogDestination,
+    rx: flume::Receiver<LogMessage>,
+    metrics: Option<Arc<ProxyMetrics>>,
 ) {
+    let LogDestination {
+        sink,
+        batch_size,
+        debounce_time,
+    } = destination;
+    let metrics = metrics.as_deref();
     let mut batch = Vec::with_capacity(batch_size);
 
+    sink.retry_pending().await;
+
     loop {
         tokio::select! {
             item = rx.recv_async() => {
@@ -125,42 +492,322 @@ async fn database_logger_task(
                     break;
                 };
 
-                tracing::debug!(num_items=item.len(), "Received items");
+                tracing::debug!(sink = sink.name(), num_items=item.len(), "Received items");
                 batch.extend(item);
 
                 if batch.len() >= batch_size {
                     let send_batch = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
-                    write_batch(db.as_ref(), send_batch).await;
+                    write_batch(sink.as_ref(), send_batch, metrics).await;
                 }
 
             }
             _ = tokio::time::sleep(debounce_time), if !batch.is_empty() => {
                 let send_batch = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
-                write_batch(db.as_ref(), send_batch).await;
+                write_batch(sink.as_ref(), send_batch, metrics).await;
+            }
+            _ = tokio::time::sleep(RETRY_PENDING_INTERVAL), if batch.is_empty() => {
+                sink.retry_pending().await;
             }
         }
     }
-    tracing::debug!("Closing database logger");
+    tracing::debug!(sink = sink.name(), "Closing logger");
 
     if !batch.is_empty() {
-        write_batch(db.as_ref(), batch).await;
+        write_batch(sink.as_ref(), batch, metrics).await;
+    }
+}
+
+async fn write_batch(
+    sink: &dyn LogSink,
+    items: Vec<ProxyLogEntry>,
+    metrics: Option<&ProxyMetrics>,
+) {
+    let count = items.len();
+    if let Err(e) = sink.write_batch(items).await {
+        tracing::error!(sink = sink.name(), error = ?e, count, "Failed to write logs");
+        sink.record_write_failure();
+        if let Some(metrics) = metrics {
+            metrics.record_dropped_log_events("write_failed", count);
+        }
     }
 }
 
-pub(super) const EVENT_INSERT_PREFIX: &str =
-        "INSERT INTO chronicle_events
+/// Writes log entries to a [ProxyDatabase](super::ProxyDatabase)
+#[derive(Debug)]
+pub struct DatabaseSink {
+    db: Database,
+    metrics: Option<Arc<ProxyMetrics>>,
+    live_tail: Option<LiveTail>,
+}
+
+impl DatabaseSink {
+    /// Create a sink for the database. If `metrics` is provided, failed database writes will be
+    /// recorded there. If `live_tail` is provided, each entry is also sent to live tail listeners
+    /// once it has been written.
+    pub fn new(
+        db: Database,
+        metrics: Option<Arc<ProxyMetrics>>,
+        live_tail: Option<LiveTail>,
+    ) -> Self {
+        Self {
+            db,
+            metrics,
+            live_tail,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LogSink for DatabaseSink {
+    fn name(&self) -> &str {
+        "database"
+    }
+
+    fn record_write_failure(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_db_write_failure();
+        }
+    }
+
+    fn is_permanent_error(&self, error: &Report<Error>) -> bool {
+        match error.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::Encode(_)) => true,
+            Some(sqlx::Error::Database(e)) => {
+                if e.kind() != sqlx::error::ErrorKind::Other {
+                    // A constraint violation
+                    return true;
+                }
+
+                // Invalid data, such as a NUL character in a JSON string, or a statement that is
+                // too large.
+                e.try_downcast_ref::<sqlx::postgres::PgDatabaseError>()
+                    .is_some_and(|e| e.code().starts_with("22") || e.code().starts_with("54"))
+            }
+            _ => false,
+        }
+    }
+
+    #[instrument(level = "trace", parent=None, skip(self, items), fields(chronicle.db_batch.num_items = items.len()))]
+    async fn write_batch(&self, items: Vec<ProxyLogEntry>) -> Result<(), Report<Error>> {
+        let live_events = self.live_tail.as_ref().map(|_| {
+            items
+                .iter()
+                .map(LiveEvent::from_log_entry)
+                .collect::<Vec<_>>()
+        });
+
+        self.db
+            .write_log_batch(items)
+            .await
+            .change_context(Error::LogSink)?;
+
+        // Only show events that were actually saved.
+        if let Some((live_tail, live_events)) = self.live_tail.as_ref().zip(live_events) {
+            live_tail.publish(self.db.as_ref(), live_events).await;
+        }
+
+        Ok(())
+    }
+}
+
+/// The start of a multi-row `INSERT` into the events table
+pub(super) fn event_insert_prefix(events_table: &str) -> String {
+    format!(
+        "INSERT INTO {events_table}
         (id, event_type, organization_id, project_id, user_id, chat_request, chat_response,
          error, provider, model, application, environment, request_organization_id, request_project_id,
          request_user_id, workflow_id, workflow_name, run_id, step_id, step_index,
-         prompt_id, prompt_version,
+         prompt_id, prompt_version, replay_of,
          meta, response_meta, retries, rate_limited, request_latency_ms,
-         total_latency_ms, created_at) VALUES\n";
+         total_latency_ms, created_at) VALUES\n"
+    )
+}
+
+/// In the upsert of a run or step `t` from a workflow event, whether the event is at least as new
+/// as the latest event already applied to it. Fields set by the newer event take precedence.
+/// A `pending` row only holds the usage of LLM calls, so any workflow event replaces it.
+pub(super) const NEWER_EVENT: &str =
+    "(EXCLUDED.updated_at >= t.updated_at OR t.status = 'pending')";
 
-#[instrument(level = "trace", parent=None, skip(db, items), fields(chronicle.db_batch.num_items = items.len()))]
-async fn write_batch(db: &dyn ProxyDatabase, items: Vec<ProxyLogEntry>) {
-    let result = db.write_log_batch(items).await;
+/// Like [NEWER_EVENT], for start events. A start event only replaces the status when it is
+/// strictly newer, so that a step which ends in the same second as it starts stays finished.
+pub(super) const NEWER_START: &str = "(EXCLUDED.updated_at > t.updated_at OR t.status = 'pending')";
 
-    if let Err(e) = result {
-        tracing::error!(error = ?e, "Failed to write logs to database");
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use smallvec::smallvec;
+    use uuid::Uuid;
+
+    use super::{LogOverflowPolicy, LogQueueOptions, LogSender, ProxyLogEntry, ProxyLogEvent};
+    use crate::{
+        format::{ChatMessage, ChatRequest},
+        metrics::ProxyMetrics,
+        redact::{LogMode, RedactionConfig, RedactionPolicy, RedactionRule, Redactor},
+        workflow_events::{EventPayload, StepEndData, StepEventData, WorkflowEvent},
+    };
+
+    fn entry(output: &str) -> ProxyLogEntry {
+        ProxyLogEntry::Workflow(WorkflowEvent::StepEnd(StepEventData {
+            run_id: Uuid::nil(),
+            step_id: Uuid::nil(),
+            data: StepEndData {
+                output: json!(output),
+                info: None,
+            },
+            time: None,
+            event_id: None,
+        }))
+    }
+
+    fn output(entry: &ProxyLogEntry) -> serde_json::Value {
+        match entry {
+            ProxyLogEntry::Workflow(WorkflowEvent::StepEnd(event)) => event.data.output.clone(),
+            _ => panic!("unexpected entry type"),
+        }
+    }
+
+    fn sender(
+        overflow: LogOverflowPolicy,
+    ) -> (
+        LogSender,
+        flume::Receiver<super::LogMessage>,
+        Arc<ProxyMetrics>,
+    ) {
+        let metrics = Arc::new(ProxyMetrics::new().unwrap());
+        let (tx, rx) = LogSender::new(
+            &LogQueueOptions {
+                capacity: Some(2),
+                overflow,
+            },
+            None,
+            Some(metrics.clone()),
+        );
+        (tx, rx, metrics)
+    }
+
+    fn received(rx: &flume::Receiver<super::LogMessage>) -> Vec<serde_json::Value> {
+        rx.drain().flatten().map(|e| output(&e)).collect()
+    }
+
+    #[tokio::test]
+    async fn drop_newest() {
+        let (tx, rx, metrics) = sender(LogOverflowPolicy::DropNewest);
+        for value in ["a", "b", "c"] {
+            tx.send(smallvec![entry(value)]).await;
+        }
+
+        assert_eq!(tx.len(), 2);
+        assert_eq!(received(&rx), vec![json!("a"), json!("b")]);
+        assert_eq!(metrics.dropped_log_events(), 1);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        let (tx, rx, metrics) = sender(LogOverflowPolicy::DropOldest);
+        for value in ["a", "b", "c", "d"] {
+            tx.send(smallvec![entry(value)]).await;
+        }
+
+        assert_eq!(received(&rx), vec![json!("c"), json!("d")]);
+        assert_eq!(metrics.dropped_log_events(), 2);
+    }
+
+    #[tokio::test]
+    async fn drop_bodies() {
+        let (tx, rx, metrics) = sender(LogOverflowPolicy::DropBodies);
+        for value in ["a", "b", "c", "d", "e"] {
+            tx.send(smallvec![entry(value)]).await;
+        }
+
+        // The first two fit in the queue as-is, the next two fit without their bodies, and the
+        // last is dropped.
+        assert_eq!(
+            received(&rx),
+            vec![json!("a"), json!("b"), json!(null), json!(null)]
+        );
+        assert_eq!(metrics.dropped_log_bodies(), 2);
+        assert_eq!(metrics.dropped_log_events(), 1);
+    }
+
+    #[tokio::test]
+    async fn block() {
+        let (tx, rx, metrics) = sender(LogOverflowPolicy::Block);
+        tx.send(smallvec![entry("a")]).await;
+        tx.send(smallvec![entry("b")]).await;
+
+        let send = tokio::spawn({
+            let tx = tx.clone();
+            async move { tx.send(smallvec![entry("c")]).await }
+        });
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!send.is_finished());
+
+        assert_eq!(output(&rx.recv_async().await.unwrap()[0]), json!("a"));
+        send.await.unwrap();
+
+        assert_eq!(received(&rx), vec![json!("b"), json!("c")]);
+        assert_eq!(metrics.dropped_log_events(), 0);
+    }
+
+    #[tokio::test]
+    async fn redaction() {
+        let redactor = Redactor::new(&RedactionConfig {
+            default: RedactionPolicy {
+                rules: vec![RedactionRule {
+                    pattern: "secret".to_string(),
+                    replacement: None,
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        let (tx, rx) = LogSender::new(&LogQueueOptions::default(), Some(Arc::new(redactor)), None);
+
+        let request = ChatRequest {
+            messages: vec![ChatMessage {
+                content: Some("a secret".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let payload = EventPayload {
+            typ: "test".to_string(),
+            data: None,
+            error: None,
+            run_id: Uuid::nil(),
+            step_id: Uuid::nil(),
+            time: None,
+            event_id: None,
+            internal_metadata: None,
+        };
+        let mut full = ProxyLogEvent::from_payload(Uuid::nil(), payload);
+        full.request = Some(request.clone());
+        let mut metadata_only = full.clone();
+        metadata_only.options.log = Some(LogMode::MetadataOnly);
+
+        tx.send(smallvec![
+            entry("the secret"),
+            ProxyLogEntry::Proxied(Box::new(full)),
+            ProxyLogEntry::Proxied(Box::new(metadata_only)),
+        ])
+        .await;
+
+        let received = rx.try_recv().unwrap();
+        assert_eq!(output(&received[0]), json!("the [REDACTED]"));
+
+        let messages = |entry: &ProxyLogEntry| match entry {
+            ProxyLogEntry::Proxied(event) => event.request.as_ref().unwrap().messages.clone(),
+            _ => panic!("unexpected entry type"),
+        };
+        assert_eq!(
+            messages(&received[1])[0].content.as_deref(),
+            Some("a [REDACTED]")
+        );
+        assert!(messages(&received[2]).is_empty());
     }
 }
@@ -0,0 +1,242 @@
+//! Versioned migrations for the database schema
+//!
+//! Each backend has a list of [Migration]s, such as
+//! [postgres::MIGRATIONS](super::postgres::MIGRATIONS). The backend's `run_migrations` function
+//! records each migration in the migrations table as it runs, along with a checksum of its SQL,
+//! so that it knows which migrations are left to run and can tell when the database was migrated
+//! by a newer version of Chronicle.
+//!
+//! Applications with their own migration system can run the SQL from [Migration::sql] there
+//! instead, and then call the backend's `record_migrations` function so that Chronicle knows the
+//! migrations were applied. The first migration writes to the meta table, so call the backend's
+//! `applied_migrations` function beforehand to create it.
+use std::borrow::Cow;
+
+use sha2::{Digest, Sha256};
+
+use super::tables::TableNames;
+
+/// A change to the database schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    /// A unique ID for the migration. IDs start with the date the migration was written, so they
+    /// sort in the order that the migrations run.
+    pub id: &'static str,
+    /// The SQL which applies the migration, written with the default table names
+    pub up: &'static str,
+    /// The SQL which reverts the migration, if it can be reverted
+    pub down: Option<&'static str>,
+}
+
+impl Migration {
+    /// A hex-encoded SHA-256 checksum of the migration's SQL, used to detect migrations that
+    /// changed after they were applied. This does not depend on the table names.
+    pub fn checksum(&self) -> String {
+        format!("{:x}", Sha256::digest(self.up))
+    }
+
+    /// The SQL which applies the migration, using the names in `tables`. On PostgreSQL, the
+    /// script expects the schema, if any, to be at the front of the search path.
+    pub fn sql(&self, tables: &TableNames) -> Cow<'static, str> {
+        tables.migration(self.up)
+    }
+
+    /// The SQL which reverts the migration, using the names in `tables`
+    pub fn down_sql(&self, tables: &TableNames) -> Option<Cow<'static, str>> {
+        self.down.map(|down| tables.migration(down))
+    }
+}
+
+/// A migration that has been recorded in the database
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct AppliedMigration {
+    /// The ID of the migration
+    pub id: String,
+    /// The checksum of the migration when it was applied
+    pub checksum: String,
+}
+
+/// A problem with the migrations recorded in the database
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum MigrationError {
+    /// The database has a migration that this version of Chronicle does not know about, which
+    /// means that it was migrated by a newer version.
+    #[error("The database has migration {0}, which is newer than this version of Chronicle")]
+    SchemaTooNew(String),
+    /// A migration's SQL changed after it was applied
+    #[error("Migration {0} has changed since it was applied to the database")]
+    ChecksumMismatch(String),
+    /// A migration can not be reverted because it has no down migration
+    #[error("Migration {0} can not be reverted")]
+    Irreversible(String),
+    /// A migration ID did not match any migration
+    #[error("Unknown migration {0}")]
+    UnknownMigration(String),
+}
+
+impl From<MigrationError> for sqlx::Error {
+    fn from(value: MigrationError) -> Self {
+        sqlx::Error::Configuration(Box::new(value))
+    }
+}
+
+/// Compare the migrations recorded in the database with the known migrations, and return the
+/// ones which still need to run, in order.
+pub fn pending_migrations<'a>(
+    migrations: &'a [Migration],
+    applied: &[AppliedMigration],
+) -> Result<Vec<&'a Migration>, MigrationError> {
+    for applied in applied {
+        let Some(migration) = migrations.iter().find(|m| m.id == applied.id) else {
+            return Err(MigrationError::SchemaTooNew(applied.id.clone()));
+        };
+
+        if migration.checksum() != applied.checksum {
+            return Err(MigrationError::ChecksumMismatch(applied.id.clone()));
+        }
+    }
+
+    Ok(migrations
+        .iter()
+        .filter(|m| !applied.iter().any(|a| a.id == m.id))
+        .collect())
+}
+
+/// Return the applied migrations which must be reverted to go back to the migration `target`,
+/// newest first. If `target` is `None`, every migration is reverted. Fails without reverting
+/// anything if one of the migrations has no down migration.
+pub fn migrations_to_revert<'a>(
+    migrations: &'a [Migration],
+    applied: &[AppliedMigration],
+    target: Option<&str>,
+) -> Result<Vec<&'a Migration>, MigrationError> {
+    let target_index = match target {
+        Some(target) => Some(
+            migrations
+                .iter()
+                .position(|m| m.id == target)
+                .ok_or_else(|| MigrationError::UnknownMigration(target.to_string()))?,
+        ),
+        None => None,
+    };
+
+    // Make sure that the database is not newer than this version before reverting anything.
+    pending_migrations(migrations, applied)?;
+
+    let revert = migrations
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(i, _)| target_index.map(|t| *i > t).unwrap_or(true))
+        .map(|(_, m)| m)
+        .filter(|m| applied.iter().any(|a| a.id == m.id))
+        .collect::<Vec<_>>();
+
+    if let Some(m) = revert.iter().find(|m| m.down.is_none()) {
+        return Err(MigrationError::Irreversible(m.id.to_string()));
+    }
+
+    Ok(revert)
+}
+
+/// The migrations to record for a database that was migrated before migrations were tracked,
+/// when only the number of applied migrations was stored.
+pub(crate) fn legacy_applied(migrations: &[Migration], version: usize) -> Vec<AppliedMigration> {
+    migrations
+        .iter()
+        .take(version)
+        .map(|m| AppliedMigration {
+            id: m.id.to_string(),
+            checksum: m.checksum(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            id: "20240101_one",
+            up: "CREATE TABLE chronicle_one (id int);",
+            down: Some("DROP TABLE chronicle_one;"),
+        },
+        Migration {
+            id: "20240201_two",
+            up: "CREATE TABLE chronicle_two (id int);",
+            down: None,
+        },
+        Migration {
+            id: "20240301_three",
+            up: "CREATE INDEX chronicle_two_id_idx ON chronicle_two (id);",
+            down: Some("DROP INDEX chronicle_two_id_idx;"),
+        },
+    ];
+
+    fn applied(n: usize) -> Vec<AppliedMigration> {
+        legacy_applied(MIGRATIONS, n)
+    }
+
+    #[test]
+    fn pending() {
+        let ids = |pending: Vec<&Migration>| pending.iter().map(|m| m.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(pending_migrations(MIGRATIONS, &[]).unwrap()),
+            vec!["20240101_one", "20240201_two", "20240301_three"]
+        );
+        assert_eq!(
+            ids(pending_migrations(MIGRATIONS, &applied(2)).unwrap()),
+            vec!["20240301_three"]
+        );
+        assert!(pending_migrations(MIGRATIONS, &applied(3))
+            .unwrap()
+            .is_empty());
+
+        let mut newer = applied(3);
+        newer.push(AppliedMigration {
+            id: "20240401_four".to_string(),
+            checksum: "abc".to_string(),
+        });
+        assert_eq!(
+            pending_migrations(MIGRATIONS, &newer),
+            Err(MigrationError::SchemaTooNew("20240401_four".to_string()))
+        );
+
+        let mut changed = applied(2);
+        changed[1].checksum = "abc".to_string();
+        assert_eq!(
+            pending_migrations(MIGRATIONS, &changed),
+            Err(MigrationError::ChecksumMismatch("20240201_two".to_string()))
+        );
+    }
+
+    #[test]
+    fn revert() {
+        let reverted = migrations_to_revert(MIGRATIONS, &applied(3), Some("20240201_two")).unwrap();
+        assert_eq!(reverted, vec![&MIGRATIONS[2]]);
+
+        assert_eq!(
+            migrations_to_revert(MIGRATIONS, &applied(3), None),
+            Err(MigrationError::Irreversible("20240201_two".to_string()))
+        );
+        assert_eq!(
+            migrations_to_revert(MIGRATIONS, &applied(1), None).unwrap(),
+            vec![&MIGRATIONS[0]]
+        );
+        assert_eq!(
+            migrations_to_revert(MIGRATIONS, &applied(3), Some("20240501_five")),
+            Err(MigrationError::UnknownMigration(
+                "20240501_five".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn checksum_ignores_table_names() {
+        let tables = TableNames::new("llm_").unwrap();
+        assert_eq!(MIGRATIONS[0].sql(&tables), "CREATE TABLE llm_one (id int);");
+        assert_eq!(MIGRATIONS[0].checksum().len(), 64);
+        assert_ne!(MIGRATIONS[0].checksum(), MIGRATIONS[1].checksum());
+    }
+}
@@ -0,0 +1,235 @@
+//! Time-based partitioning of the events table
+//!
+//! The primary key of the partitioned table is `(id, created_at)`, because PostgreSQL requires
+//! the partition key in every unique index. Event IDs are then only unique per `created_at`, so
+//! code that skips events which were already written must look them up by ID rather than rely on
+//! `ON CONFLICT`.
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Database;
+
+/// The number of partitions to create ahead of the current one
+pub const PARTITIONS_AHEAD: u32 = 3;
+/// How often the background task checks for partitions to create
+pub const PARTITION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How much time each partition of the events table covers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionInterval {
+    /// One partition per day
+    Day,
+    /// One partition per week, starting on Monday
+    Week,
+    /// One partition per calendar month
+    #[default]
+    Month,
+}
+
+/// An unrecognized [PartitionInterval]
+#[derive(thiserror::Error, Debug)]
+#[error("Unknown partition interval {0}")]
+pub struct UnknownPartitionInterval(String);
+
+impl FromStr for PartitionInterval {
+    type Err = UnknownPartitionInterval;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(PartitionInterval::Day),
+            "week" => Ok(PartitionInterval::Week),
+            "month" => Ok(PartitionInterval::Month),
+            _ => Err(UnknownPartitionInterval(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for PartitionInterval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            PartitionInterval::Day => "day",
+            PartitionInterval::Week => "week",
+            PartitionInterval::Month => "month",
+        };
+        f.write_str(s)
+    }
+}
+
+impl PartitionInterval {
+    /// The start of the partition that contains `time`
+    pub fn start_of(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let date = time.date_naive();
+        let start = match self {
+            PartitionInterval::Day => date,
+            PartitionInterval::Week => {
+                date - chrono::Duration::days(date.weekday().num_days_from_monday() as i64)
+            }
+            PartitionInterval::Month => date.with_day(1).unwrap_or(date),
+        };
+
+        start.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+    }
+
+    /// The start of the partition after the one that starts at `start`
+    pub fn next(&self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            PartitionInterval::Day => start + chrono::Duration::days(1),
+            PartitionInterval::Week => start + chrono::Duration::days(7),
+            PartitionInterval::Month => start
+                .checked_add_months(Months::new(1))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        }
+    }
+}
+
+/// A partition of the events table covering a range of time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventPartition {
+    /// The name of the partition's table
+    pub name: String,
+    /// The earliest time in the partition
+    pub start: DateTime<Utc>,
+    /// The end of the partition, exclusive
+    pub end: DateTime<Utc>,
+}
+
+impl EventPartition {
+    /// The partition of `events_table` that starts at `start`. The table name should not include
+    /// the schema.
+    pub fn new(events_table: &str, interval: PartitionInterval, start: DateTime<Utc>) -> Self {
+        Self {
+            name: format!("{events_table}_p{}", start.format("%Y%m%d")),
+            start,
+            end: interval.next(start),
+        }
+    }
+
+    /// Find the range of a partition from its table name. Returns `None` for tables that were not
+    /// created by Chronicle, such as the default partition.
+    pub fn from_name(events_table: &str, interval: PartitionInterval, name: &str) -> Option<Self> {
+        let date = name.strip_prefix(events_table)?.strip_prefix("_p")?;
+        let start = NaiveDate::parse_from_str(date, "%Y%m%d")
+            .ok()?
+            .and_hms_opt(0, 0, 0)?
+            .and_utc();
+        Some(Self::new(events_table, interval, start))
+    }
+
+    /// The partitions needed to hold data from `from` until [PARTITIONS_AHEAD] partitions after
+    /// the one containing `now`.
+    pub fn covering(
+        events_table: &str,
+        interval: PartitionInterval,
+        from: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Vec<Self> {
+        let mut last = interval.start_of(now);
+        for _ in 0..PARTITIONS_AHEAD {
+            last = interval.next(last);
+        }
+
+        let mut partitions = Vec::new();
+        let mut start = interval.start_of(from.min(now));
+        while start <= last {
+            let partition = Self::new(events_table, interval, start);
+            start = partition.end;
+            partitions.push(partition);
+        }
+
+        partitions
+    }
+}
+
+/// Start a task that periodically creates upcoming partitions, for databases that use them.
+pub fn start_partition_task(db: Database) -> tokio::task::JoinHandle<()> {
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(PARTITION_CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = db.create_partitions(Utc::now()).await {
+                tracing::error!(error = ?e, "Failed to create partitions");
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn interval_bounds() {
+        // A Wednesday
+        let time = Utc.with_ymd_and_hms(2024, 7, 31, 15, 30, 0).unwrap();
+        let day = |y, m, d| Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap();
+
+        assert_eq!(PartitionInterval::Day.start_of(time), day(2024, 7, 31));
+        assert_eq!(
+            PartitionInterval::Day.next(day(2024, 7, 31)),
+            day(2024, 8, 1)
+        );
+        assert_eq!(PartitionInterval::Week.start_of(time), day(2024, 7, 29));
+        assert_eq!(
+            PartitionInterval::Week.next(day(2024, 7, 29)),
+            day(2024, 8, 5)
+        );
+        assert_eq!(PartitionInterval::Month.start_of(time), day(2024, 7, 1));
+        assert_eq!(
+            PartitionInterval::Month.next(day(2024, 12, 1)),
+            day(2025, 1, 1)
+        );
+    }
+
+    #[test]
+    fn covering_partitions() {
+        let from = Utc.with_ymd_and_hms(2024, 6, 20, 0, 0, 0).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 7, 31, 15, 30, 0).unwrap();
+        let names =
+            EventPartition::covering("chronicle_events", PartitionInterval::Month, from, now)
+                .into_iter()
+                .map(|p| p.name)
+                .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "chronicle_events_p20240601",
+                "chronicle_events_p20240701",
+                "chronicle_events_p20240801",
+                "chronicle_events_p20240901",
+                "chronicle_events_p20241001",
+            ]
+        );
+
+        let partition = EventPartition::from_name(
+            "chronicle_events",
+            PartitionInterval::Month,
+            "chronicle_events_p20240901",
+        )
+        .unwrap();
+        assert_eq!(
+            partition.end,
+            Utc.with_ymd_and_hms(2024, 10, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            EventPartition::from_name(
+                "chronicle_events",
+                PartitionInterval::Month,
+                "chronicle_events_default"
+            ),
+            None
+        );
+        // Tables without a prefix
+        assert_eq!(
+            EventPartition::from_name("events", PartitionInterval::Month, "events_p20240901")
+                .map(|p| p.start),
+            Some(Utc.with_ymd_and_hms(2024, 9, 1, 0, 0, 0).unwrap())
+        );
+    }
+}
@@ -1,39 +1,673 @@
 //! PostgreSQL database logging
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use chrono::{DateTime, Utc};
 use error_stack::{Report, ResultExt};
-use sqlx::{PgExecutor, PgPool, QueryBuilder};
+use itertools::Itertools;
+use sqlx::{
+    postgres::PgListener, PgConnection, PgExecutor, PgPool, Postgres, QueryBuilder, Transaction,
+};
 use uuid::Uuid;
 
+use self::copy::CopyEncoder;
 use super::{
-    logging::{ProxyLogEntry, ProxyLogEvent},
+    blobs::StoredBody,
+    datasets::{
+        select_datasets, Dataset, DatasetItem, DatasetItemSource, NewDataset, DATASET_ITEM_COLUMNS,
+        DEFAULT_DATASET_QUERY_LIMIT,
+    },
+    export::{ExportEventQuery, ExportPage},
+    live_tail::{LiveEvent, LiveEventSender, LIVE_EVENT_CHANNEL},
+    logging::{ProxyLogEntry, ProxyLogEvent, NEWER_EVENT, NEWER_START},
+    migrations::{
+        legacy_applied, migrations_to_revert, pending_migrations, AppliedMigration, Migration,
+    },
+    partitions::{EventPartition, PartitionInterval},
+    retention::{ArchivedRow, PurgeCounts, PurgeTable, RetentionCutoffs},
+    runs::{
+        RunDetail, RunEvent, RunQuery, RunSummary, StepDetail, DEFAULT_RUN_LIST_LIMIT,
+        EVENT_COLUMNS, RUN_COLUMNS, STEP_COLUMNS,
+    },
+    scores::{Score, ScoreTarget, SCORE_COLUMNS},
+    search::{EventSearchQuery, EventSearchResult, DEFAULT_SEARCH_LIMIT},
+    stale::{AbandonedStep, MarkedStaleRuns, StaleCutoffs, StaleRun, STALE_TIMEOUT_INFO_KEY},
+    tables::TableNames,
+    usage::{LlmUsage, PendingUsage, UsageRollup, ADD_USAGE, USAGE_COLUMNS},
     DbProvider, ProxyDatabase,
 };
 use crate::{
     config::{AliasConfig, ApiKeyConfig},
+    format::SingleChatResponse,
+    prompts::{attach_labels, NewPrompt, Prompt, PromptTemplate, PromptVersion, PROMPT_COLUMNS},
     workflow_events::{
-        RunStartEvent, RunUpdateEvent, StepEventData, StepStartData, StepStateData, WorkflowEvent,
+        RunStartEvent, RunUpdateEvent, ScoreEvent, ScoreValue, StepEventData, StepStartData,
+        StepStateData, WorkflowEvent,
     },
     Error,
 };
 
-const POSTGRESQL_MIGRATIONS: &[&'static str] = &[
-    include_str!("../../migrations/20240419_chronicle_proxy_init_postgresql.sql"),
-    include_str!("../../migrations/20240424_chronicle_proxy_data_tables_postgresql.sql"),
-    include_str!("../../migrations/20240625_chronicle_proxy_steps_postgresql.sql"),
+mod copy;
+
+/// Chronicle's PostgreSQL migrations, in the order that they run
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        id: "20240419_chronicle_proxy_init",
+        up: include_str!("../../migrations/20240419_chronicle_proxy_init_postgresql.sql"),
+        down: None,
+    },
+    Migration {
+        id: "20240424_chronicle_proxy_data_tables",
+        up: include_str!("../../migrations/20240424_chronicle_proxy_data_tables_postgresql.sql"),
+        down: None,
+    },
+    Migration {
+        id: "20240625_chronicle_proxy_steps",
+        up: include_str!("../../migrations/20240625_chronicle_proxy_steps_postgresql.sql"),
+        down: None,
+    },
+    Migration {
+        id: "20240801_chronicle_proxy_retention",
+        up: include_str!("../../migrations/20240801_chronicle_proxy_retention_postgresql.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240801_chronicle_proxy_retention_postgresql.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240815_chronicle_proxy_scores",
+        up: include_str!("../../migrations/20240815_chronicle_proxy_scores_postgresql.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240815_chronicle_proxy_scores_postgresql.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240822_chronicle_proxy_datasets",
+        up: include_str!("../../migrations/20240822_chronicle_proxy_datasets_postgresql.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240822_chronicle_proxy_datasets_postgresql.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240829_chronicle_proxy_replays",
+        up: include_str!("../../migrations/20240829_chronicle_proxy_replays_postgresql.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240829_chronicle_proxy_replays_postgresql.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240905_chronicle_proxy_prompts",
+        up: include_str!("../../migrations/20240905_chronicle_proxy_prompts_postgresql.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240905_chronicle_proxy_prompts_postgresql.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240912_chronicle_proxy_usage_rollups",
+        up: include_str!("../../migrations/20240912_chronicle_proxy_usage_rollups_postgresql.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240912_chronicle_proxy_usage_rollups_postgresql.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240919_chronicle_proxy_step_updates",
+        up: include_str!("../../migrations/20240919_chronicle_proxy_step_updates_postgresql.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240919_chronicle_proxy_step_updates_postgresql.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240926_chronicle_proxy_stale_runs",
+        up: include_str!("../../migrations/20240926_chronicle_proxy_stale_runs_postgresql.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240926_chronicle_proxy_stale_runs_postgresql.down.sql"
+        )),
+    },
 ];
 
+/// Converts `chronicle_events` to a partitioned table. This is optional, so it is not one of the
+/// default migrations.
+const PARTITION_EVENTS_MIGRATION: &str =
+    include_str!("../../migrations/partition_events_postgresql.sql");
+
+const SEARCH_INDEX_MIGRATION: &str = include_str!("../../migrations/search_index_postgresql.sql");
+const DROP_SEARCH_INDEX_MIGRATION: &str =
+    include_str!("../../migrations/drop_search_index_postgresql.sql");
+
+const EVENT_COPY_COLUMNS: &str =
+    "(id, event_type, organization_id, project_id, user_id, chat_request, chat_response,
+     error, provider, model, application, environment, request_organization_id, request_project_id,
+     request_user_id, workflow_id, workflow_name, run_id, step_id, step_index,
+     prompt_id, prompt_version, replay_of,
+     meta, response_meta, retries, rate_limited, request_latency_ms,
+     total_latency_ms, created_at)";
+
+const STEP_COPY_COLUMNS: &str = "(id, run_id, type, parent_step, name, input, status, tags, info, \
+     span_id, start_time, updated_at)";
+
+/// The `chronicle_meta` key which records how `chronicle_events` is partitioned
+const PARTITION_INTERVAL_KEY: &str = "events_partition_interval";
+
+#[derive(sqlx::FromRow)]
+struct RunRow {
+    id: Uuid,
+    name: Option<String>,
+    description: Option<String>,
+    application: Option<String>,
+    environment: Option<String>,
+    input: Option<serde_json::Value>,
+    output: Option<serde_json::Value>,
+    status: String,
+    trace_id: Option<String>,
+    span_id: Option<String>,
+    tags: Option<Vec<String>>,
+    info: Option<serde_json::Value>,
+    #[sqlx(flatten)]
+    usage: LlmUsage,
+    updated_at: DateTime<Utc>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<RunRow> for RunSummary {
+    fn from(row: RunRow) -> Self {
+        RunSummary {
+            id: row.id,
+            name: row.name.unwrap_or_default(),
+            description: row.description,
+            application: row.application,
+            environment: row.environment,
+            input: row.input,
+            output: row.output,
+            status: row.status,
+            trace_id: row.trace_id,
+            span_id: row.span_id,
+            tags: row.tags.unwrap_or_default(),
+            info: row.info,
+            updated_at: row.updated_at,
+            created_at: row.created_at,
+            usage: row.usage,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct StepRow {
+    id: Uuid,
+    run_id: Uuid,
+    #[sqlx(rename = "type")]
+    typ: String,
+    parent_step: Option<Uuid>,
+    name: Option<String>,
+    input: Option<serde_json::Value>,
+    output: Option<serde_json::Value>,
+    status: String,
+    tags: Option<Vec<String>>,
+    info: Option<serde_json::Value>,
+    span_id: Option<String>,
+    #[sqlx(flatten)]
+    usage: LlmUsage,
+    start_time: DateTime<Utc>,
+    end_time: Option<DateTime<Utc>>,
+}
+
+impl From<StepRow> for StepDetail {
+    fn from(row: StepRow) -> Self {
+        StepDetail {
+            id: row.id,
+            run_id: row.run_id,
+            typ: row.typ,
+            parent_step: row.parent_step,
+            name: row.name,
+            input: row.input,
+            output: row.output,
+            status: row.status,
+            tags: row.tags.unwrap_or_default(),
+            info: row.info,
+            span_id: row.span_id,
+            start_time: row.start_time,
+            end_time: row.end_time,
+            duration_ms: None,
+            usage: row.usage,
+            events: Vec::new(),
+            steps: Vec::new(),
+        }
+    }
+}
+
+/// The usage that a step collected before its parent was known
+#[derive(sqlx::FromRow)]
+struct UnparentedUsage {
+    id: Uuid,
+    run_id: Uuid,
+    updated_at: DateTime<Utc>,
+    #[sqlx(flatten)]
+    usage: LlmUsage,
+}
+
+#[derive(sqlx::FromRow)]
+struct EventRow {
+    id: Uuid,
+    event_type: Option<String>,
+    step_id: Option<Uuid>,
+    provider: Option<String>,
+    model: Option<String>,
+    chat_request: Option<serde_json::Value>,
+    chat_response: Option<serde_json::Value>,
+    error: Option<serde_json::Value>,
+    meta: Option<serde_json::Value>,
+    retries: Option<i32>,
+    rate_limited: Option<bool>,
+    request_latency_ms: Option<i32>,
+    total_latency_ms: Option<i32>,
+    replay_of: Option<Uuid>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<EventRow> for RunEvent {
+    fn from(row: EventRow) -> Self {
+        RunEvent {
+            id: row.id,
+            event_type: row.event_type,
+            step_id: row.step_id,
+            provider: row.provider,
+            model: row.model,
+            chat_request: row.chat_request,
+            chat_response: row.chat_response,
+            error: row.error,
+            meta: row.meta,
+            retries: row.retries,
+            rate_limited: row.rate_limited,
+            request_latency_ms: row.request_latency_ms.map(i64::from),
+            total_latency_ms: row.total_latency_ms.map(i64::from),
+            replay_of: row.replay_of,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    run_id: Option<Uuid>,
+    #[sqlx(flatten)]
+    event: EventRow,
+}
+
+impl From<SearchRow> for EventSearchResult {
+    fn from(row: SearchRow) -> Self {
+        EventSearchResult {
+            run_id: row.run_id,
+            event: RunEvent::from(row.event),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ScoreRow {
+    id: Uuid,
+    run_id: Option<Uuid>,
+    step_id: Option<Uuid>,
+    event_id: Option<Uuid>,
+    name: String,
+    value: sqlx::types::Json<ScoreValue>,
+    source: String,
+    comment: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<ScoreRow> for Score {
+    type Error = Error;
+
+    fn try_from(row: ScoreRow) -> Result<Self, Self::Error> {
+        Ok(Score {
+            id: row.id,
+            run_id: row.run_id,
+            step_id: row.step_id,
+            event_id: row.event_id,
+            name: row.name,
+            value: row.value.0,
+            source: row.source.parse()?,
+            comment: row.comment,
+            created_at: row.created_at,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct DatasetRow {
+    id: Uuid,
+    name: String,
+    version: i32,
+    description: Option<String>,
+    item_count: i64,
+    created_at: DateTime<Utc>,
+}
+
+impl From<DatasetRow> for Dataset {
+    fn from(row: DatasetRow) -> Self {
+        Dataset {
+            id: row.id,
+            name: row.name,
+            version: row.version,
+            description: row.description,
+            item_count: row.item_count,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct DatasetItemRow {
+    id: Uuid,
+    dataset_id: Uuid,
+    event_id: Option<Uuid>,
+    request: serde_json::Value,
+    expected_response: Option<serde_json::Value>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<DatasetItemRow> for DatasetItem {
+    fn from(row: DatasetItemRow) -> Self {
+        DatasetItem {
+            id: row.id,
+            dataset_id: row.dataset_id,
+            event_id: row.event_id,
+            request: row.request,
+            expected_response: row.expected_response,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct PromptRow {
+    prompt_id: String,
+    version: i32,
+    description: Option<String>,
+    template: sqlx::types::Json<PromptTemplate>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<PromptRow> for Prompt {
+    fn from(row: PromptRow) -> Self {
+        Prompt {
+            id: row.prompt_id,
+            version: row.version as u32,
+            labels: Vec::new(),
+            description: row.description,
+            template: row.template.0,
+            created_at: Some(row.created_at),
+        }
+    }
+}
+
+/// How [PostgresDatabase] writes new events and steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PostgresWriteMode {
+    /// Stream new rows with `COPY ... FROM STDIN (FORMAT binary)`. This uses less CPU than
+    /// `INSERT` and has no limit on the number of rows. Batches with a single row, and updates
+    /// to existing rows, still use regular statements.
+    #[default]
+    Copy,
+    /// Write new rows with multi-row `INSERT` statements
+    Insert,
+}
+
+/// The columns of an event that come from its response
+struct ResponseColumns {
+    model: String,
+    provider: Option<String>,
+    body: Option<SingleChatResponse>,
+    meta: Option<serde_json::Value>,
+}
+
+impl ResponseColumns {
+    fn take(item: &mut ProxyLogEvent) -> Self {
+        let (rmodel, provider, body, meta) = match item
+            .response
+            .take()
+            .map(|r| (r.body.model.clone(), r.provider, r.body, r.info.meta))
+        {
+            Some((rmodel, rprovider, rbody, rmeta)) => {
+                (rmodel, Some(rprovider), Some(rbody), rmeta)
+            }
+            None => (None, None, None, None),
+        };
+
+        let model = rmodel
+            .or_else(|| item.request.as_ref().and_then(|r| r.model.clone()))
+            .unwrap_or_default();
+
+        Self {
+            model,
+            provider,
+            body,
+            meta,
+        }
+    }
+}
+
+/// Options for a [PostgresDatabase]
+#[derive(Debug, Clone, Default)]
+pub struct PostgresOptions {
+    /// How new rows are written
+    pub write_mode: PostgresWriteMode,
+    /// The schema and names of the tables
+    pub tables: TableNames,
+}
+
 /// PostgreSQL database support for logging
 #[derive(Debug)]
 pub struct PostgresDatabase {
     pool: PgPool,
+    write_mode: PostgresWriteMode,
+    tables: TableNames,
 }
 
 impl PostgresDatabase {
     /// Create a new [PostgresDatabase]
     pub fn new(pool: PgPool) -> Arc<dyn ProxyDatabase> {
-        Arc::new(Self { pool })
+        Self::with_options(pool, PostgresOptions::default())
+    }
+
+    /// Create a new [PostgresDatabase] which writes new rows using `write_mode`
+    pub fn with_write_mode(pool: PgPool, write_mode: PostgresWriteMode) -> Arc<dyn ProxyDatabase> {
+        Self::with_options(
+            pool,
+            PostgresOptions {
+                write_mode,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Create a new [PostgresDatabase] with custom options
+    pub fn with_options(pool: PgPool, options: PostgresOptions) -> Arc<dyn ProxyDatabase> {
+        Arc::new(Self {
+            pool,
+            write_mode: options.write_mode,
+            tables: options.tables,
+        })
+    }
+
+    fn use_copy(&self, rows: usize) -> bool {
+        self.write_mode == PostgresWriteMode::Copy && rows > 1
+    }
+
+    /// Insert events. With `skip_existing`, events whose IDs are already in the table are skipped
+    /// instead of failing the batch.
+    async fn write_events(
+        &self,
+        tx: &mut PgConnection,
+        mut events: Vec<ProxyLogEvent>,
+        skip_existing: bool,
+    ) -> Result<(), sqlx::Error> {
+        if skip_existing && !events.is_empty() {
+            // On a partitioned events table the primary key also includes created_at, so
+            // ON CONFLICT only catches a repeated event if it has the same time. Look for the IDs
+            // instead.
+            let ids = events.iter().map(|event| event.id).collect::<Vec<_>>();
+            let existing: Vec<Uuid> = sqlx::query_scalar(&format!(
+                "SELECT id FROM {} WHERE id = ANY($1)",
+                self.tables.events()
+            ))
+            .bind(&ids)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let mut seen = existing.into_iter().collect::<ahash::AHashSet<_>>();
+            events.retain(|event| seen.insert(event.id));
+        }
+
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        if self.use_copy(events.len()) && !skip_existing {
+            let mut encoder = CopyEncoder::new();
+            for event in events {
+                Self::encode_event(&mut encoder, event)
+                    .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+            }
+
+            let statement = format!(
+                "COPY {} {EVENT_COPY_COLUMNS} FROM STDIN (FORMAT binary)",
+                self.tables.events()
+            );
+            let mut copy = tx.copy_in_raw(&statement).await?;
+            copy.send(encoder.finish()).await?;
+            copy.finish().await?;
+        } else {
+            let mut builder =
+                QueryBuilder::new(super::logging::event_insert_prefix(&self.tables.events()));
+            for (i, event) in events.into_iter().enumerate() {
+                if i > 0 {
+                    builder.push(",");
+                }
+                Self::add_event_values(&mut builder, event);
+            }
+            if skip_existing {
+                builder.push(" ON CONFLICT DO NOTHING");
+            }
+            builder.build().execute(&mut *tx).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_step_starts(
+        &self,
+        tx: &mut PgConnection,
+        steps: Vec<StepEventData<StepStartData>>,
+    ) -> Result<(), sqlx::Error> {
+        // Steps that already have usage from a placeholder, and are about to learn their parent.
+        // Their usage hasn't been added to any ancestors yet.
+        let with_parent = steps
+            .iter()
+            .filter(|step| step.data.parent_step.is_some())
+            .map(|step| step.step_id)
+            .collect::<Vec<_>>();
+        let unparented: Vec<UnparentedUsage> = if with_parent.is_empty() {
+            Vec::new()
+        } else {
+            sqlx::query_as(&format!(
+                "SELECT id, run_id, updated_at, {USAGE_COLUMNS} FROM {}
+                WHERE id = ANY($1) AND parent_step IS NULL AND llm_calls > 0",
+                self.tables.steps()
+            ))
+            .bind(&with_parent)
+            .fetch_all(&mut *tx)
+            .await?
+        };
+
+        if self.use_copy(steps.len()) {
+            self.copy_step_starts(&mut *tx, steps).await?;
+        } else {
+            for step in steps {
+                self.write_step_start(&mut *tx, step).await?;
+            }
+        }
+
+        for step in unparented {
+            let parent: Option<Uuid> = sqlx::query_scalar(&format!(
+                "SELECT parent_step FROM {} WHERE id = $1",
+                self.tables.steps()
+            ))
+            .bind(step.id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            if let Some(parent) = parent {
+                let usage = PendingUsage {
+                    usage: step.usage,
+                    run_id: Some(step.run_id),
+                    last_call: step.updated_at,
+                };
+                self.add_step_usage(&mut *tx, parent, usage).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn copy_step_starts(
+        &self,
+        tx: &mut PgConnection,
+        steps: Vec<StepEventData<StepStartData>>,
+    ) -> Result<(), sqlx::Error> {
+        let mut encoder = CopyEncoder::new();
+        for step in steps {
+            Self::encode_step_start(&mut encoder, step)
+                .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+        }
+
+        // COPY can't skip steps that already exist, so copy into a temporary table first.
+        let steps_table = self.tables.steps();
+        let copy_table = self.tables.unqualified("steps_copy");
+        sqlx::query(&format!(
+            "CREATE TEMPORARY TABLE IF NOT EXISTS {copy_table}
+            (LIKE {steps_table} INCLUDING DEFAULTS) ON COMMIT DELETE ROWS",
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        let statement = format!("COPY {copy_table} {STEP_COPY_COLUMNS} FROM STDIN (FORMAT binary)");
+        let mut copy = tx.copy_in_raw(&statement).await?;
+        copy.send(encoder.finish()).await?;
+        copy.finish().await?;
+
+        // A step can only be upserted once per statement, so drop steps started more than once.
+        sqlx::query(&format!(
+            "WITH copied AS (DELETE FROM {copy_table} RETURNING *)
+            INSERT INTO {steps_table} AS t
+            SELECT DISTINCT ON (id) * FROM copied ORDER BY id, start_time
+            {}",
+            Self::step_start_conflict()
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The conflict clause for inserting started steps into the steps table as `t`. The existing
+    /// row is either a placeholder written by an event that arrived before the start, or the same
+    /// step started again.
+    fn step_start_conflict() -> String {
+        format!(
+            "ON CONFLICT (id) DO UPDATE SET
+                type = COALESCE(NULLIF(t.type, ''), EXCLUDED.type),
+                parent_step = COALESCE(t.parent_step, EXCLUDED.parent_step),
+                name = COALESCE(t.name, EXCLUDED.name),
+                input = COALESCE(NULLIF(t.input, 'null'::jsonb), EXCLUDED.input),
+                tags = COALESCE(t.tags, EXCLUDED.tags),
+                span_id = COALESCE(t.span_id, EXCLUDED.span_id),
+                info = {},
+                status = CASE WHEN {NEWER_START} THEN EXCLUDED.status ELSE t.status END,
+                start_time = LEAST(t.start_time, EXCLUDED.start_time),
+                updated_at = GREATEST(t.updated_at, EXCLUDED.updated_at)
+            WHERE t.run_id = EXCLUDED.run_id",
+            merge_info(NEWER_START)
+        )
     }
 
     async fn write_step_start(
@@ -41,23 +675,27 @@ impl PostgresDatabase {
         tx: impl PgExecutor<'_>,
         event: StepEventData<StepStartData>,
     ) -> Result<(), sqlx::Error> {
+        let time = event.event_time();
         let tags = if event.data.tags.is_empty() {
             None
         } else {
             Some(event.data.tags)
         };
 
-        sqlx::query(
+        sqlx::query(&format!(
             r##"
-            INSERT INTO chronicle_steps (
-                id, run_id, type, parent_step, name, input, status, tags, info, span_id, start_time
+            INSERT INTO {} AS t (
+                id, run_id, type, parent_step, name, input, status, tags, info, span_id, start_time,
+                updated_at
             )
             VALUES (
-                $1, $2, $3, $4, $5, $6, 'started', $7, $8, $9, $10
+                $1, $2, $3, $4, $5, $6, 'started', $7, $8, $9, $10, $10
             )
-            ON CONFLICT DO NOTHING;
+            {}
             "##,
-        )
+            self.tables.steps(),
+            Self::step_start_conflict()
+        ))
         .bind(event.step_id)
         .bind(event.run_id)
         .bind(event.data.typ)
@@ -67,7 +705,7 @@ impl PostgresDatabase {
         .bind(tags)
         .bind(event.data.info)
         .bind(event.data.span_id)
-        .bind(event.time.unwrap_or_else(|| Utc::now()))
+        .bind(time)
         .execute(tx)
         .await?;
         Ok(())
@@ -81,28 +719,151 @@ impl PostgresDatabase {
         status: &str,
         output: serde_json::Value,
         info: Option<serde_json::Value>,
-        timestamp: Option<DateTime<Utc>>,
+        timestamp: DateTime<Utc>,
     ) -> Result<(), sqlx::Error> {
-        sqlx::query(
+        // If the step hasn't started yet, this creates a placeholder for the start to fill in.
+        sqlx::query(&format!(
             r##"
-            UPDATE chronicle_steps
-            SET status = $1,
-                output = $2,
-                info = CASE
-                    WHEN NULLIF(info, 'null'::jsonb) IS NULL THEN $3
-                    WHEN NULLIF($3, 'null'::jsonb) IS NULL THEN info
-                    ELSE info || $3
+            INSERT INTO {} AS t (
+                id, run_id, type, status, output, info, start_time, end_time, updated_at
+            )
+            VALUES (
+                $1, $2, '', $3, $4, $5, $6, $6, $6
+            )
+            ON CONFLICT (id) DO UPDATE SET
+                status = CASE WHEN {NEWER_EVENT} THEN EXCLUDED.status ELSE t.status END,
+                output = CASE
+                    WHEN {NEWER_EVENT} OR t.end_time IS NULL THEN EXCLUDED.output
+                    ELSE t.output
+                    END,
+                info = {},
+                end_time = CASE
+                    WHEN {NEWER_EVENT} OR t.end_time IS NULL THEN EXCLUDED.end_time
+                    ELSE t.end_time
                     END,
-                end_time = $4
-            WHERE run_id = $5 AND id = $6
+                updated_at = GREATEST(t.updated_at, EXCLUDED.updated_at)
+            WHERE t.run_id = EXCLUDED.run_id
         "##,
-        )
+            self.tables.steps(),
+            merge_info(NEWER_EVENT)
+        ))
+        .bind(step_id)
+        .bind(run_id)
         .bind(status)
         .bind(output)
         .bind(info)
-        .bind(timestamp.unwrap_or_else(|| chrono::Utc::now()))
-        .bind(run_id)
+        .bind(timestamp)
+        .execute(tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Add the usage of a batch of LLM calls to their runs, their steps, and the ancestors of
+    /// those steps. Steps and runs that don't exist yet are created with a `pending` status, which
+    /// keeps them out of run listings and stale run checks until a workflow event arrives for them.
+    async fn write_usage(
+        &self,
+        tx: &mut PgConnection,
+        usage: UsageRollup,
+    ) -> Result<(), sqlx::Error> {
+        for (step_id, usage) in usage.steps {
+            self.add_step_usage(&mut *tx, step_id, usage).await?;
+        }
+
+        for (run_id, usage) in usage.runs {
+            sqlx::query(&format!(
+                "INSERT INTO {} AS t (
+                    id, name, status, updated_at, created_at, {USAGE_COLUMNS}
+                )
+                VALUES ($1, '', 'pending', $2, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (id) DO UPDATE SET {ADD_USAGE}",
+                self.tables.runs()
+            ))
+            .bind(run_id)
+            .bind(usage.last_call)
+            .bind(usage.usage.llm_calls)
+            .bind(usage.usage.prompt_tokens)
+            .bind(usage.usage.completion_tokens)
+            .bind(usage.usage.llm_errors)
+            .bind(usage.usage.llm_latency_ms)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Add usage to a step and its ancestors. A step that doesn't exist yet is created as a
+    /// `pending` placeholder, as long as its run is known.
+    async fn add_step_usage(
+        &self,
+        tx: impl PgExecutor<'_>,
+        step_id: Uuid,
+        usage: PendingUsage,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(
+            "WITH RECURSIVE ancestors(id) AS (
+                SELECT $1::uuid
+                UNION
+                SELECT s.parent_step FROM {steps} s JOIN ancestors a ON s.id = a.id
+                WHERE s.parent_step IS NOT NULL
+            )
+            INSERT INTO {steps} AS t (
+                id, run_id, type, status, start_time, updated_at, {USAGE_COLUMNS}
+            )
+            SELECT a.id, COALESCE(s.run_id, $2), '', 'pending', $3, $3, $4, $5, $6, $7, $8
+            FROM ancestors a
+            LEFT JOIN {steps} s ON s.id = a.id
+            WHERE COALESCE(s.run_id, $2) IS NOT NULL
+            ON CONFLICT (id) DO UPDATE SET {ADD_USAGE}",
+            steps = self.tables.steps()
+        ))
         .bind(step_id)
+        .bind(usage.run_id)
+        .bind(usage.last_call)
+        .bind(usage.usage.llm_calls)
+        .bind(usage.usage.prompt_tokens)
+        .bind(usage.usage.completion_tokens)
+        .bind(usage.usage.llm_errors)
+        .bind(usage.usage.llm_latency_ms)
+        .execute(tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Write a score. If the score has no run ID, it is taken from the score's step or LLM call.
+    async fn write_score(
+        &self,
+        tx: impl PgExecutor<'_>,
+        score: ScoreEvent,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (
+                id, run_id, step_id, event_id, name, value, source, comment, created_at
+            )
+            VALUES (
+                $1,
+                COALESCE(
+                    $2,
+                    (SELECT run_id FROM {} WHERE id = $3),
+                    (SELECT run_id FROM {} WHERE id = $4)
+                ),
+                $3, $4, $5, $6, $7, $8, $9
+            )
+            ON CONFLICT(id) DO NOTHING",
+            self.tables.scores(),
+            self.tables.steps(),
+            self.tables.events()
+        ))
+        .bind(score.id.unwrap_or_else(Uuid::now_v7))
+        .bind(score.run_id)
+        .bind(score.step_id)
+        .bind(score.event_id)
+        .bind(score.name)
+        .bind(sqlx::types::Json(score.value))
+        .bind(score.source.as_str())
+        .bind(score.comment)
+        .bind(score.time.unwrap_or_else(|| Utc::now()))
         .execute(tx)
         .await?;
         Ok(())
@@ -113,14 +874,20 @@ impl PostgresDatabase {
         tx: impl PgExecutor<'_>,
         event: StepEventData<StepStateData>,
     ) -> Result<(), sqlx::Error> {
-        sqlx::query(
-            "UPDATE chronicle_steps
-                    SET status=$1
-                    WHERE run_id=$2 AND id=$3",
-        )
-        .bind(event.data.state)
-        .bind(event.run_id)
+        let time = event.event_time();
+        sqlx::query(&format!(
+            "INSERT INTO {} AS t (id, run_id, type, status, start_time, updated_at)
+            VALUES ($1, $2, '', $3, $4, $4)
+            ON CONFLICT (id) DO UPDATE SET
+                status = CASE WHEN {NEWER_EVENT} THEN EXCLUDED.status ELSE t.status END,
+                updated_at = GREATEST(t.updated_at, EXCLUDED.updated_at)
+            WHERE t.run_id = EXCLUDED.run_id",
+            self.tables.steps()
+        ))
         .bind(event.step_id)
+        .bind(event.run_id)
+        .bind(event.data.state)
+        .bind(time)
         .execute(tx)
         .await?;
 
@@ -132,15 +899,18 @@ impl PostgresDatabase {
         tx: impl PgExecutor<'_>,
         event: RunStartEvent,
     ) -> Result<(), sqlx::Error> {
+        let time = event.event_time();
         let tags = if event.tags.is_empty() {
             None
         } else {
             Some(event.tags)
         };
 
-        sqlx::query(
+        // The run may already exist as a placeholder from an update that arrived first, or from
+        // an earlier start of the same run.
+        sqlx::query(&format!(
             r##"
-            INSERT INTO chronicle_runs (
+            INSERT INTO {} AS t (
                 id, name, description, application, environment, input, status,
                     trace_id, span_id, tags, info, updated_at, created_at
             )
@@ -148,10 +918,22 @@ impl PostgresDatabase {
                 $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $12
             )
             ON CONFLICT(id) DO UPDATE SET
-                status = EXCLUDED.status,
-                updated_at = EXCLUDED.updated_at;
+                name = COALESCE(NULLIF(t.name, ''), EXCLUDED.name),
+                description = COALESCE(t.description, EXCLUDED.description),
+                application = COALESCE(t.application, EXCLUDED.application),
+                environment = COALESCE(t.environment, EXCLUDED.environment),
+                input = COALESCE(NULLIF(t.input, 'null'::jsonb), EXCLUDED.input),
+                trace_id = COALESCE(t.trace_id, EXCLUDED.trace_id),
+                span_id = COALESCE(t.span_id, EXCLUDED.span_id),
+                tags = COALESCE(t.tags, EXCLUDED.tags),
+                info = {},
+                status = CASE WHEN {NEWER_START} THEN EXCLUDED.status ELSE t.status END,
+                created_at = LEAST(t.created_at, EXCLUDED.created_at),
+                updated_at = GREATEST(t.updated_at, EXCLUDED.updated_at);
             "##,
-        )
+            self.tables.runs(),
+            merge_info(NEWER_START)
+        ))
         .bind(event.id)
         .bind(event.name)
         .bind(event.description)
@@ -163,7 +945,7 @@ impl PostgresDatabase {
         .bind(event.span_id)
         .bind(tags)
         .bind(event.info)
-        .bind(event.time.unwrap_or_else(|| Utc::now()))
+        .bind(time)
         .execute(tx)
         .await?;
         Ok(())
@@ -174,42 +956,41 @@ impl PostgresDatabase {
         tx: impl PgExecutor<'_>,
         event: RunUpdateEvent,
     ) -> Result<(), sqlx::Error> {
-        sqlx::query(
-            "UPDATE chronicle_runs
-            SET status = $1,
-                output = $2,
-                info = CASE
-                    WHEN NULLIF(info, 'null'::jsonb) IS NULL THEN $3
-                    WHEN NULLIF($3, 'null'::jsonb) IS NULL THEN info
-                    ELSE info || $3
+        let time = event.event_time();
+        // If the run hasn't started yet, this creates a placeholder for the start to fill in.
+        sqlx::query(&format!(
+            "INSERT INTO {} AS t (id, name, status, output, info, updated_at, created_at)
+            VALUES ($1, '', $2, $3, $4, $5, $5)
+            ON CONFLICT (id) DO UPDATE SET
+                status = CASE WHEN {NEWER_EVENT} THEN EXCLUDED.status ELSE t.status END,
+                output = CASE
+                    WHEN NULLIF(EXCLUDED.output, 'null'::jsonb) IS NULL THEN t.output
+                    WHEN {NEWER_EVENT} OR NULLIF(t.output, 'null'::jsonb) IS NULL
+                        THEN EXCLUDED.output
+                    ELSE t.output
                     END,
-                updated_at = $4
-            WHERE id = $5",
-        )
+                info = {},
+                updated_at = GREATEST(t.updated_at, EXCLUDED.updated_at)",
+            self.tables.runs(),
+            merge_info(NEWER_EVENT)
+        ))
+        .bind(event.id)
         .bind(event.status.as_deref().unwrap_or("finished"))
         .bind(event.output)
         .bind(event.info)
-        .bind(event.time.unwrap_or_else(|| Utc::now()))
-        .bind(event.id)
+        .bind(time)
         .execute(tx)
         .await?;
         Ok(())
     }
 
-    fn add_event_values(builder: &mut QueryBuilder<'_, sqlx::Postgres>, item: ProxyLogEvent) {
-        let (rmodel, rprovider, rbody, rmeta) = match item
-            .response
-            .map(|r| (r.body.model.clone(), r.provider, r.body, r.info.meta))
-        {
-            Some((rmodel, rprovider, rbody, rmeta)) => {
-                (rmodel, Some(rprovider), Some(rbody), rmeta)
-            }
-            None => (None, None, None, None),
-        };
-
-        let model = rmodel
-            .or_else(|| item.request.as_ref().and_then(|r| r.model.clone()))
-            .unwrap_or_default();
+    fn add_event_values(builder: &mut QueryBuilder<'_, sqlx::Postgres>, mut item: ProxyLogEvent) {
+        let ResponseColumns {
+            model,
+            provider: rprovider,
+            body: rbody,
+            meta: rmeta,
+        } = ResponseColumns::take(&mut item);
 
         let extra = item.options.metadata.extra.filter(|m| !m.is_empty());
 
@@ -221,8 +1002,14 @@ impl PostgresDatabase {
             .push_bind(item.options.internal_metadata.organization_id)
             .push_bind(item.options.internal_metadata.project_id)
             .push_bind(item.options.internal_metadata.user_id)
-            .push_bind(sqlx::types::Json(item.request))
-            .push_bind(sqlx::types::Json(rbody))
+            .push_bind(sqlx::types::Json(StoredBody::new(
+                item.request,
+                item.offloaded.request,
+            )))
+            .push_bind(sqlx::types::Json(StoredBody::new(
+                rbody,
+                item.offloaded.response,
+            )))
             .push_bind(sqlx::types::Json(item.error))
             .push_bind(rprovider)
             .push_bind(model)
@@ -238,6 +1025,7 @@ impl PostgresDatabase {
             .push_bind(item.options.metadata.step_index.map(|i| i as i32))
             .push_bind(item.options.metadata.prompt_id)
             .push_bind(item.options.metadata.prompt_version.map(|i| i as i32))
+            .push_bind(item.options.metadata.replay_of)
             .push_bind(sqlx::types::Json(extra))
             .push_bind(rmeta)
             .push_bind(item.num_retries.map(|n| n as i32))
@@ -247,10 +1035,122 @@ impl PostgresDatabase {
             .push_bind(item.timestamp)
             .push_unseparated(")");
     }
+
+    /// Encode an event for [EVENT_COPY_COLUMNS], with the same values as [Self::add_event_values]
+    fn encode_event(
+        encoder: &mut CopyEncoder,
+        mut item: ProxyLogEvent,
+    ) -> Result<(), serde_json::Error> {
+        let response = ResponseColumns::take(&mut item);
+        let metadata = item.options.metadata;
+        let internal = item.options.internal_metadata;
+        let extra = metadata.extra.filter(|m| !m.is_empty());
+
+        encoder
+            .row(30)
+            .uuid(Some(item.id))
+            .text(Some(item.event_type.as_ref()))
+            .text(internal.organization_id.as_deref())
+            .text(internal.project_id.as_deref())
+            .text(internal.user_id.as_deref())
+            .jsonb(&StoredBody::new(&item.request, item.offloaded.request))?
+            .jsonb(&StoredBody::new(&response.body, item.offloaded.response))?
+            .jsonb(&item.error)?
+            .text(response.provider.as_deref())
+            .text(Some(response.model.as_str()))
+            .text(metadata.application.as_deref())
+            .text(metadata.environment.as_deref())
+            .text(metadata.organization_id.as_deref())
+            .text(metadata.project_id.as_deref())
+            .text(metadata.user_id.as_deref())
+            .text(metadata.workflow_id.as_deref())
+            .text(metadata.workflow_name.as_deref())
+            .uuid(metadata.run_id)
+            .uuid(metadata.step_id)
+            .int4(metadata.step_index.map(|i| i as i32))
+            .text(metadata.prompt_id.as_deref())
+            .int4(metadata.prompt_version.map(|i| i as i32))
+            .uuid(metadata.replay_of)
+            .jsonb(&extra)?
+            .nullable_jsonb(response.meta.as_ref())?
+            .int4(item.num_retries.map(|n| n as i32))
+            .bool(item.was_rate_limited)
+            .int4(item.latency.map(|d| d.as_millis() as i32))
+            .int4(item.total_latency.map(|d| d.as_millis() as i32))
+            .timestamptz(item.timestamp);
+        Ok(())
+    }
+
+    /// Encode a step for [STEP_COPY_COLUMNS], with the same values as [Self::write_step_start]
+    fn encode_step_start(
+        encoder: &mut CopyEncoder,
+        event: StepEventData<StepStartData>,
+    ) -> Result<(), serde_json::Error> {
+        let time = event.event_time();
+        let tags = Some(event.data.tags).filter(|t| !t.is_empty());
+
+        encoder
+            .row(12)
+            .uuid(Some(event.step_id))
+            .uuid(Some(event.run_id))
+            .text(Some(event.data.typ.as_str()))
+            .uuid(event.data.parent_step)
+            .text(event.data.name.as_deref())
+            .jsonb(&event.data.input)?
+            .text(Some("started"))
+            .text_array(tags.as_deref())
+            .nullable_jsonb(event.data.info.as_ref())?
+            .text(event.data.span_id.as_deref())
+            .timestamptz(time)
+            .timestamptz(time);
+        Ok(())
+    }
+
+    /// Convert prompt rows and add their labels. If `id` is set, only labels for that prompt are
+    /// loaded.
+    async fn with_prompt_labels(
+        &self,
+        rows: Vec<PromptRow>,
+        id: Option<&str>,
+    ) -> Result<Vec<Prompt>, Report<Error>> {
+        let mut prompts = rows.into_iter().map(Prompt::from).collect::<Vec<_>>();
+        if prompts.is_empty() {
+            return Ok(prompts);
+        }
+
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT prompt_id, label, version FROM {}",
+            self.tables.prompt_labels()
+        ));
+        if let Some(id) = id {
+            builder.push(" WHERE prompt_id = ").push_bind(id);
+        }
+        builder.push(" ORDER BY label");
+
+        let labels: Vec<(String, String, i32)> = builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::LoadingDatabase)
+            .attach_printable("Failed to load prompt labels")?;
+
+        attach_labels(
+            &mut prompts,
+            labels
+                .into_iter()
+                .map(|(id, label, version)| (id, label, version as u32))
+                .collect(),
+        );
+        Ok(prompts)
+    }
 }
 
 #[async_trait::async_trait]
 impl ProxyDatabase for PostgresDatabase {
+    fn tables(&self) -> TableNames {
+        self.tables.clone()
+    }
+
     async fn load_providers_from_database(
         &self,
         providers_table: &str,
@@ -306,46 +1206,55 @@ impl ProxyDatabase for PostgresDatabase {
     }
 
     async fn write_log_batch(&self, entries: Vec<ProxyLogEntry>) -> Result<(), sqlx::Error> {
-        let mut event_builder = sqlx::QueryBuilder::new(super::logging::EVENT_INSERT_PREFIX);
         let mut tx = self.pool.begin().await?;
-        let mut first_event = true;
+        let mut events = Vec::new();
+        // Events with an ID from the client, which may have been sent before.
+        let mut keyed_events = Vec::new();
+        let mut step_starts = Vec::new();
+        // Written last, so that they can find the run ID of steps and events in the same batch.
+        let mut scores = Vec::new();
+        let mut usage = UsageRollup::default();
 
         let mut run_ids = ahash::AHashSet::new();
 
         for entry in entries.into_iter() {
+            let is_insert = matches!(
+                entry,
+                ProxyLogEntry::Proxied(_)
+                    | ProxyLogEntry::Workflow(WorkflowEvent::Event(_))
+                    | ProxyLogEntry::Workflow(WorkflowEvent::StepStart(_))
+                    | ProxyLogEntry::Workflow(WorkflowEvent::Score(_))
+            );
+            if !is_insert && !step_starts.is_empty() {
+                // Updates may refer to steps that were started earlier in the batch.
+                self.write_step_starts(&mut tx, std::mem::take(&mut step_starts))
+                    .await?;
+            }
+
             match entry {
                 ProxyLogEntry::Proxied(item) => {
-                    if first_event {
-                        first_event = false;
-                    } else {
-                        event_builder.push(",");
-                    }
-
                     if let Some(run_id) = item.options.metadata.run_id {
                         run_ids.insert(run_id);
                     }
 
-                    Self::add_event_values(&mut event_builder, *item);
+                    usage.add_event(&item);
+                    events.push(*item);
                 }
                 ProxyLogEntry::Workflow(WorkflowEvent::Event(event)) => {
-                    if first_event {
-                        first_event = false;
-                    } else {
-                        event_builder.push(",");
-                    }
-
                     run_ids.insert(event.run_id);
-
-                    let item = ProxyLogEvent::from_payload(Uuid::now_v7(), event);
-                    Self::add_event_values(&mut event_builder, item);
+                    match event.event_id {
+                        Some(id) => keyed_events.push(ProxyLogEvent::from_payload(id, event)),
+                        None => events.push(ProxyLogEvent::from_payload(Uuid::now_v7(), event)),
+                    }
                 }
                 ProxyLogEntry::Workflow(WorkflowEvent::StepStart(event)) => {
                     run_ids.insert(event.run_id);
-                    self.write_step_start(&mut *tx, event).await?;
+                    step_starts.push(event);
                 }
 
                 ProxyLogEntry::Workflow(WorkflowEvent::StepEnd(event)) => {
                     run_ids.insert(event.run_id);
+                    let time = event.event_time();
                     self.write_step_end(
                         &mut *tx,
                         event.step_id,
@@ -353,7 +1262,7 @@ impl ProxyDatabase for PostgresDatabase {
                         "finished",
                         event.data.output,
                         event.data.info,
-                        event.time,
+                        time,
                     )
                     .await?;
                 }
@@ -363,6 +1272,7 @@ impl ProxyDatabase for PostgresDatabase {
                 }
                 ProxyLogEntry::Workflow(WorkflowEvent::StepError(event)) => {
                     run_ids.insert(event.run_id);
+                    let time = event.event_time();
                     self.write_step_end(
                         &mut *tx,
                         event.step_id,
@@ -370,7 +1280,7 @@ impl ProxyDatabase for PostgresDatabase {
                         "error",
                         event.data.error,
                         None,
-                        event.time,
+                        time,
                     )
                     .await?;
                 }
@@ -382,12 +1292,21 @@ impl ProxyDatabase for PostgresDatabase {
                     run_ids.insert(event.id);
                     self.write_run_update(&mut *tx, event).await?;
                 }
+                ProxyLogEntry::Workflow(WorkflowEvent::Score(event)) => {
+                    if let Some(run_id) = event.run_id {
+                        run_ids.insert(run_id);
+                    }
+                    scores.push(event);
+                }
             }
         }
 
-        if !first_event {
-            let query = event_builder.build();
-            query.execute(&mut *tx).await?;
+        self.write_step_starts(&mut tx, step_starts).await?;
+        self.write_events(&mut tx, events, false).await?;
+        self.write_events(&mut tx, keyed_events, true).await?;
+        self.write_usage(&mut tx, usage).await?;
+        for score in scores {
+            self.write_score(&mut *tx, score).await?;
         }
 
         if !run_ids.is_empty() {
@@ -409,296 +1328,2350 @@ impl ProxyDatabase for PostgresDatabase {
         tx.commit().await?;
         Ok(())
     }
-}
 
-/// Run database migrations specific to the proxy. These migrations are designed for a simple setup with
-/// single-tenant use. You may want to add multi-tenant features or partitioning, and can integrate
-/// the files from the `migrations` directory into your project to accomplish that.
-pub async fn run_default_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
-    let mut tx = pool.begin().await?;
+    async fn list_runs(&self, query: &RunQuery) -> Result<Vec<RunSummary>, Report<Error>> {
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT {RUN_COLUMNS} FROM {} WHERE status <> 'pending'",
+            self.tables.runs()
+        ));
 
-    sqlx::raw_sql(
-        "CREATE TABLE IF NOT EXISTS chronicle_meta (
-          key text PRIMARY KEY,
-          value jsonb
-        );",
-    )
-    .execute(&mut *tx)
-    .await?;
+        if let Some(name) = &query.name {
+            builder.push(" AND name = ").push_bind(name);
+        }
+        if let Some(application) = &query.application {
+            builder.push(" AND application = ").push_bind(application);
+        }
+        if let Some(environment) = &query.environment {
+            builder.push(" AND environment = ").push_bind(environment);
+        }
+        if let Some(status) = &query.status {
+            builder.push(" AND status = ").push_bind(status);
+        }
+        if !query.tags.is_empty() {
+            builder.push(" AND tags @> ").push_bind(&query.tags);
+        }
+        if let Some(start_time) = query.start_time {
+            builder.push(" AND created_at >= ").push_bind(start_time);
+        }
+        if let Some(end_time) = query.end_time {
+            builder.push(" AND created_at < ").push_bind(end_time);
+        }
 
-    let migration_version = sqlx::query_scalar::<_, i32>(
-        "SELECT cast(value as int) FROM chronicle_meta WHERE key='migration_version'",
-    )
-    .fetch_optional(&mut *tx)
-    .await?
-    .unwrap_or(0) as usize;
+        builder
+            .push(" ORDER BY created_at DESC LIMIT ")
+            .push_bind(query.limit.unwrap_or(DEFAULT_RUN_LIST_LIMIT) as i64)
+            .push(" OFFSET ")
+            .push_bind(query.offset.unwrap_or(0) as i64);
 
-    tracing::info!("Migration version is {}", migration_version);
+        let rows: Vec<RunRow> = builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::LoadingDatabase)
+            .attach_printable("Failed to load runs")?;
 
-    let start_migration = migration_version.min(POSTGRESQL_MIGRATIONS.len());
-    for (i, migration) in POSTGRESQL_MIGRATIONS[start_migration..].iter().enumerate() {
-        tracing::info!("Running migration {}", start_migration + i);
-        sqlx::raw_sql(migration).execute(&mut *tx).await?;
+        Ok(rows.into_iter().map(RunSummary::from).collect())
     }
 
-    let new_version = POSTGRESQL_MIGRATIONS.len();
+    async fn get_run(&self, id: Uuid) -> Result<Option<RunDetail>, Report<Error>> {
+        let run: Option<RunRow> = sqlx::query_as(&format!(
+            "SELECT {RUN_COLUMNS} FROM {} WHERE id = $1",
+            self.tables.runs()
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load run")?;
 
-    sqlx::query("UPDATE chronicle_meta SET value=$1::jsonb WHERE key='migration_version'")
-        .bind(new_version.to_string())
-        .execute(&mut *tx)
-        .await?;
+        let Some(run) = run else {
+            return Ok(None);
+        };
 
-    tx.commit().await?;
-    Ok(())
-}
+        let steps: Vec<StepRow> = sqlx::query_as(&format!(
+            "SELECT {STEP_COLUMNS} FROM {} WHERE run_id = $1",
+            self.tables.steps()
+        ))
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load steps")?;
 
-#[cfg(test)]
-mod test {
-    use std::time::Duration;
+        let events: Vec<EventRow> = sqlx::query_as(&format!(
+            "SELECT {EVENT_COLUMNS} FROM {} WHERE run_id = $1 ORDER BY created_at",
+            self.tables.events()
+        ))
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load events")?;
 
-    use chrono::{DateTime, TimeZone, Utc};
-    use serde_json::json;
-    use sqlx::{postgres::PgListener, PgPool, Row};
-    use uuid::Uuid;
+        Ok(Some(RunDetail::from_parts(
+            run.into(),
+            steps.into_iter().map(StepDetail::from).collect(),
+            events.into_iter().map(RunEvent::from).collect(),
+        )))
+    }
 
-    use crate::database::{
-        postgres::run_default_migrations,
-        testing::{test_events, TEST_EVENT1_ID, TEST_RUN_ID, TEST_STEP1_ID, TEST_STEP2_ID},
-    };
+    async fn get_event(&self, id: Uuid) -> Result<Option<RunEvent>, Report<Error>> {
+        let row: Option<EventRow> = sqlx::query_as(&format!(
+            "SELECT {EVENT_COLUMNS} FROM {} WHERE id = $1",
+            self.tables.events()
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load event")?;
 
-    fn dt(secs: i64) -> DateTime<Utc> {
-        Utc.timestamp_opt(secs, 0).unwrap()
+        Ok(row.map(RunEvent::from))
     }
 
-    #[sqlx::test(migrations = false)]
-    async fn test_database_writes(pool: PgPool) {
-        filigree::tracing_config::test::init();
-        run_default_migrations(&pool).await.unwrap();
-
-        let mut listener = PgListener::connect_with(&pool).await.unwrap();
-        let listen_task = tokio::task::spawn(async move {
-            listener
-                .listen(&format!("chronicle_run:{TEST_RUN_ID}"))
-                .await
-                .unwrap();
-            listener.recv().await
-        });
+    async fn list_scores(&self, target: ScoreTarget) -> Result<Vec<Score>, Report<Error>> {
+        let rows: Vec<ScoreRow> = sqlx::query_as(&format!(
+            "SELECT {SCORE_COLUMNS} FROM {} WHERE {} = $1 ORDER BY created_at, id",
+            self.tables.scores(),
+            target.column()
+        ))
+        .bind(target.id())
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load scores")?;
 
-        let db = super::PostgresDatabase::new(pool.clone());
+        rows.into_iter()
+            .map(Score::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(Error::LoadingDatabase)
+    }
 
-        db.write_log_batch(test_events())
+    async fn create_dataset(&self, dataset: &NewDataset) -> Result<Dataset, Report<Error>> {
+        let datasets = self.tables.datasets();
+        let items = self.tables.dataset_items();
+        let mut tx = self
+            .pool
+            .begin()
             .await
-            .expect("Writing events");
+            .change_context(Error::WritingDatabase)?;
 
-        let runs = sqlx::query(
-            "SELECT id, name, description, application, environment,
-                input, output, status, trace_id, span_id,
-                tags, info, updated_at, created_at
-                FROM chronicle_runs",
-        )
-        .fetch_all(&pool)
+        let previous: Option<(Uuid, i32)> = sqlx::query_as(&format!(
+            "SELECT id, version FROM {datasets} WHERE name = $1 ORDER BY version DESC LIMIT 1"
+        ))
+        .bind(&dataset.name)
+        .fetch_optional(&mut *tx)
         .await
-        .expect("Fetching runs");
-        assert_eq!(runs.len(), 1);
-        let run = &runs[0];
-
-        assert_eq!(run.get::<Uuid, _>(0), TEST_RUN_ID, "run id");
-        assert_eq!(run.get::<String, _>(1), "test run", "name");
-        assert_eq!(
-            run.get::<Option<String>, _>(2),
-            Some("test description".to_string()),
-            "description"
-        );
-        assert_eq!(
-            run.get::<Option<String>, _>(3),
-            Some("test application".to_string()),
-            "application"
-        );
-        assert_eq!(
-            run.get::<Option<String>, _>(4),
-            Some("test environment".to_string()),
-            "environment"
-        );
-        assert_eq!(
-            run.get::<Option<serde_json::Value>, _>(5),
-            Some(json!({"query":"abc"})),
-            "input"
-        );
-        assert_eq!(
-            run.get::<Option<serde_json::Value>, _>(6),
-            Some(json!({"result":"success"})),
-            "output"
-        );
-        assert_eq!(run.get::<String, _>(7), "finished", "status");
-        assert_eq!(
-            run.get::<Option<String>, _>(8),
-            Some("0123456789abcdef".to_string()),
-            "trace_id"
-        );
-        assert_eq!(
-            run.get::<Option<String>, _>(9),
-            Some("12345678".to_string()),
-            "span_id"
-        );
-        assert_eq!(
-            run.get::<Vec<String>, _>(10),
-            vec!["tag1".to_string(), "tag2".to_string()],
-            "tags"
-        );
-        assert_eq!(
-            run.get::<Option<serde_json::Value>, _>(11),
-            Some(json!({"info1":"value1","info2":"new_value", "info3":"value3"})),
-            "info"
-        );
-        assert_eq!(run.get::<DateTime<Utc>, _>(12), dt(5), "updated_at");
-        assert_eq!(run.get::<DateTime<Utc>, _>(13), dt(1), "created_at");
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to load previous dataset version")?;
 
-        let steps = sqlx::query(
-            "SELECT id, run_id, type, parent_step, name,
-                input, output, status, span_id, tags, info, start_time, end_time
-                FROM chronicle_steps
-                ORDER BY start_time ASC",
-        )
-        .fetch_all(&pool)
+        let id = Uuid::now_v7();
+        let version = previous.map(|(_, version)| version + 1).unwrap_or(1);
+        let created_at = Utc::now();
+        sqlx::query(&format!(
+            "INSERT INTO {datasets} (id, name, version, description, created_at)
+            VALUES ($1, $2, $3, $4, $5)"
+        ))
+        .bind(id)
+        .bind(&dataset.name)
+        .bind(version)
+        .bind(&dataset.description)
+        .bind(created_at)
+        .execute(&mut *tx)
         .await
-        .expect("Fetching steps");
-        assert_eq!(steps.len(), 2);
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to create dataset")?;
 
-        let step1 = &steps[0];
-        assert_eq!(step1.get::<Uuid, _>(0), TEST_STEP1_ID, "id");
-        assert_eq!(step1.get::<Uuid, _>(1), TEST_RUN_ID, "run_id");
-        assert_eq!(step1.get::<String, _>(2), "step_type", "type");
-        assert_eq!(step1.get::<Option<String>, _>(3), None, "parent_step");
-        assert_eq!(step1.get::<String, _>(4), "source_node1", "name");
-        assert_eq!(
-            step1.get::<Option<serde_json::Value>, _>(5),
-            Some(json!({ "task_param": "value"})),
-            "input"
-        );
-        assert_eq!(
-            step1.get::<Option<serde_json::Value>, _>(6),
-            Some(json!({ "result": "success" })),
-            "output"
-        );
-        assert_eq!(step1.get::<String, _>(7), "finished", "status");
-        assert_eq!(
-            step1.get::<Option<String>, _>(8),
-            Some("11111111".to_string()),
-            "span_id"
-        );
-        assert_eq!(
-            step1.get::<Option<Vec<String>>, _>(9),
-            Some(vec!["dag".to_string(), "node".to_string()]),
-            "tags"
-        );
-        assert_eq!(
-            step1.get::<Option<serde_json::Value>, _>(10),
-            Some(json!({"model": "a_model", "info3": "value3"})),
-            "info"
-        );
-        assert_eq!(
-            step1.get::<DateTime<Utc>, _>(11),
-            Utc.timestamp_opt(2, 0).unwrap(),
-            "start_time"
-        );
-        assert_eq!(
-            step1.get::<DateTime<Utc>, _>(12),
-            Utc.timestamp_opt(5, 0).unwrap(),
-            "end_time"
-        );
+        let mut item_count = 0;
+        if let Some((previous_id, _)) = previous {
+            let old_ids: Vec<Uuid> = sqlx::query_scalar(&format!(
+                "SELECT id FROM {items} WHERE dataset_id = $1 ORDER BY created_at, id"
+            ))
+            .bind(previous_id)
+            .fetch_all(&mut *tx)
+            .await
+            .change_context(Error::WritingDatabase)
+            .attach_printable("Failed to load previous dataset items")?;
+            let new_ids = old_ids.iter().map(|_| Uuid::now_v7()).collect::<Vec<_>>();
 
-        let step2 = &steps[1];
-        assert_eq!(step2.get::<Uuid, _>(0), TEST_STEP2_ID, "id");
-        assert_eq!(step2.get::<Uuid, _>(1), TEST_RUN_ID, "run_id");
-        assert_eq!(step2.get::<String, _>(2), "llm", "type");
-        assert_eq!(
-            step2.get::<Option<Uuid>, _>(3),
-            Some(TEST_STEP1_ID),
-            "parent_step"
-        );
-        assert_eq!(step2.get::<String, _>(4), "source_node2", "name");
-        assert_eq!(
-            step2.get::<Option<serde_json::Value>, _>(5),
-            Some(json!({ "task_param2": "value"})),
-            "input"
-        );
-        assert_eq!(
-            step2.get::<Option<serde_json::Value>, _>(6),
-            Some(json!({ "message": "an error" })),
-            "output"
-        );
-        assert_eq!(step2.get::<String, _>(7), "error", "status");
-        assert_eq!(
-            step2.get::<Option<String>, _>(8),
-            Some("22222222".to_string()),
-            "span_id"
-        );
-        assert_eq!(step2.get::<Option<Vec<String>>, _>(9), None, "tags");
-        assert_eq!(
-            step2.get::<Option<serde_json::Value>, _>(10),
-            Some(json!({"model": "a_model"})),
-            "info"
-        );
-        assert_eq!(
-            step2.get::<DateTime<Utc>, _>(11),
-            Utc.timestamp_opt(3, 0).unwrap(),
-            "start_time"
-        );
-        assert_eq!(
-            step2.get::<DateTime<Utc>, _>(12),
-            Utc.timestamp_opt(5, 0).unwrap(),
-            "end_time"
-        );
+            item_count = sqlx::query(&format!(
+                "INSERT INTO {items}
+                    (id, dataset_id, event_id, request, expected_response, created_at, updated_at)
+                SELECT ids.new_id, $1, event_id, request, expected_response, created_at, updated_at
+                FROM {items}
+                JOIN UNNEST($2::uuid[], $3::uuid[]) ids(old_id, new_id) ON id = ids.old_id"
+            ))
+            .bind(id)
+            .bind(&old_ids)
+            .bind(&new_ids)
+            .execute(&mut *tx)
+            .await
+            .change_context(Error::WritingDatabase)
+            .attach_printable("Failed to copy dataset items")?
+            .rows_affected();
+        }
 
-        let events = sqlx::query(
-            "SELECT id, event_type, step_id, run_id, meta, error, created_at
-                FROM chronicle_events
-                ORDER BY created_at ASC",
-        )
-        .fetch_all(&pool)
+        tx.commit().await.change_context(Error::WritingDatabase)?;
+
+        Ok(Dataset {
+            id,
+            name: dataset.name.clone(),
+            version,
+            description: dataset.description.clone(),
+            item_count: item_count as i64,
+            created_at,
+        })
+    }
+
+    async fn list_datasets(&self) -> Result<Vec<Dataset>, Report<Error>> {
+        let rows: Vec<DatasetRow> = sqlx::query_as(&format!(
+            "{} ORDER BY d.name, d.version",
+            select_datasets(&self.tables)
+        ))
+        .fetch_all(&self.pool)
         .await
-        .expect("Fetching steps");
-        assert_eq!(events.len(), 2);
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load datasets")?;
 
-        let event = &events[0];
-        assert_eq!(event.get::<Uuid, _>(0), TEST_EVENT1_ID, "id");
-        assert_eq!(event.get::<String, _>(1), "query", "event_type");
-        assert_eq!(event.get::<Uuid, _>(2), TEST_STEP2_ID, "step_id");
-        assert_eq!(event.get::<Uuid, _>(3), TEST_RUN_ID, "run_id");
-        assert_eq!(
+        Ok(rows.into_iter().map(Dataset::from).collect())
+    }
+
+    async fn get_dataset(&self, id: Uuid) -> Result<Option<Dataset>, Report<Error>> {
+        let row: Option<DatasetRow> = sqlx::query_as(&format!(
+            "{} WHERE d.id = $1",
+            select_datasets(&self.tables)
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load dataset")?;
+
+        Ok(row.map(Dataset::from))
+    }
+
+    async fn add_dataset_items(
+        &self,
+        dataset_id: Uuid,
+        source: &DatasetItemSource,
+    ) -> Result<u64, Report<Error>> {
+        let event_ids = match source {
+            DatasetItemSource::Events(ids) => ids.iter().copied().unique().collect::<Vec<_>>(),
+            DatasetItemSource::Query(query) => {
+                let mut builder = QueryBuilder::new(format!(
+                    "SELECT id FROM {}
+                    WHERE NULLIF(chat_request, 'null'::jsonb) IS NOT NULL
+                        AND NULLIF(error, 'null'::jsonb) IS NULL",
+                    self.tables.events()
+                ));
+
+                if let Some(application) = &query.application {
+                    builder.push(" AND application = ").push_bind(application);
+                }
+                if let Some(environment) = &query.environment {
+                    builder.push(" AND environment = ").push_bind(environment);
+                }
+                if let Some(provider) = &query.provider {
+                    builder.push(" AND provider = ").push_bind(provider);
+                }
+                if let Some(model) = &query.model {
+                    builder.push(" AND model = ").push_bind(model);
+                }
+                if let Some(run_id) = query.run_id {
+                    builder.push(" AND run_id = ").push_bind(run_id);
+                }
+                if let Some(start_time) = query.start_time {
+                    builder.push(" AND created_at >= ").push_bind(start_time);
+                }
+                if let Some(end_time) = query.end_time {
+                    builder.push(" AND created_at < ").push_bind(end_time);
+                }
+
+                builder
+                    .push(" ORDER BY created_at DESC LIMIT ")
+                    .push_bind(query.limit.unwrap_or(DEFAULT_DATASET_QUERY_LIMIT) as i64);
+
+                builder
+                    .build_query_scalar()
+                    .fetch_all(&self.pool)
+                    .await
+                    .change_context(Error::LoadingDatabase)
+                    .attach_printable("Failed to find events for dataset")?
+            }
+        };
+
+        if event_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let item_ids = event_ids.iter().map(|_| Uuid::now_v7()).collect::<Vec<_>>();
+        let result = sqlx::query(&format!(
+            "INSERT INTO {}
+                (id, dataset_id, event_id, request, expected_response, created_at, updated_at)
+            SELECT ids.item_id, $1, e.id, e.chat_request, NULLIF(e.chat_response, 'null'::jsonb),
+                $2, $2
+            FROM UNNEST($3::uuid[], $4::uuid[]) ids(event_id, item_id)
+            JOIN {} e ON e.id = ids.event_id
+            WHERE NULLIF(e.chat_request, 'null'::jsonb) IS NOT NULL
+            ON CONFLICT (dataset_id, event_id) DO NOTHING",
+            self.tables.dataset_items(),
+            self.tables.events()
+        ))
+        .bind(dataset_id)
+        .bind(Utc::now())
+        .bind(&event_ids)
+        .bind(&item_ids)
+        .execute(&self.pool)
+        .await
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to add dataset items")?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn update_dataset_item(
+        &self,
+        dataset_id: Uuid,
+        item_id: Uuid,
+        expected_response: &serde_json::Value,
+    ) -> Result<bool, Report<Error>> {
+        let result = sqlx::query(&format!(
+            "UPDATE {} SET expected_response = $1, updated_at = now()
+            WHERE dataset_id = $2 AND id = $3",
+            self.tables.dataset_items()
+        ))
+        .bind(expected_response)
+        .bind(dataset_id)
+        .bind(item_id)
+        .execute(&self.pool)
+        .await
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to update dataset item")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_dataset_items(
+        &self,
+        dataset_id: Uuid,
+    ) -> Result<Vec<DatasetItem>, Report<Error>> {
+        let rows: Vec<DatasetItemRow> = sqlx::query_as(&format!(
+            "SELECT {DATASET_ITEM_COLUMNS} FROM {} WHERE dataset_id = $1 ORDER BY created_at, id",
+            self.tables.dataset_items()
+        ))
+        .bind(dataset_id)
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load dataset items")?;
+
+        Ok(rows.into_iter().map(DatasetItem::from).collect())
+    }
+
+    async fn list_events_for_export(
+        &self,
+        query: &ExportEventQuery,
+        page: ExportPage,
+    ) -> Result<Vec<RunEvent>, Report<Error>> {
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT {EVENT_COLUMNS} FROM {}
+            WHERE NULLIF(chat_request, 'null'::jsonb) IS NOT NULL
+                AND NULLIF(chat_response, 'null'::jsonb) IS NOT NULL",
+            self.tables.events()
+        ));
+
+        if !query.include_errors {
+            builder.push(" AND NULLIF(error, 'null'::jsonb) IS NULL");
+        }
+        if let Some(application) = &query.application {
+            builder.push(" AND application = ").push_bind(application);
+        }
+        if let Some(environment) = &query.environment {
+            builder.push(" AND environment = ").push_bind(environment);
+        }
+        if let Some(provider) = &query.provider {
+            builder.push(" AND provider = ").push_bind(provider);
+        }
+        if let Some(model) = &query.model {
+            builder.push(" AND model = ").push_bind(model);
+        }
+        if let Some(prompt_id) = &query.prompt_id {
+            builder.push(" AND prompt_id = ").push_bind(prompt_id);
+        }
+        if let Some(prompt_version) = query.prompt_version {
+            builder
+                .push(" AND prompt_version = ")
+                .push_bind(prompt_version as i32);
+        }
+        if let Some(run_id) = query.run_id {
+            builder.push(" AND run_id = ").push_bind(run_id);
+        }
+        for (key, value) in &query.metadata {
+            builder
+                .push(" AND meta->>")
+                .push_bind(key)
+                .push(" = ")
+                .push_bind(value);
+        }
+        if let Some(start_time) = query.start_time {
+            builder.push(" AND created_at >= ").push_bind(start_time);
+        }
+        if let Some(end_time) = query.end_time {
+            builder.push(" AND created_at < ").push_bind(end_time);
+        }
+        if let Some(after) = page.after {
+            builder.push(" AND id > ").push_bind(after);
+        }
+
+        builder
+            .push(" ORDER BY id LIMIT ")
+            .push_bind(page.limit as i64);
+
+        let rows: Vec<EventRow> = builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::LoadingDatabase)
+            .attach_printable("Failed to load events for export")?;
+
+        Ok(rows.into_iter().map(RunEvent::from).collect())
+    }
+
+    async fn create_prompt(&self, prompt: &NewPrompt) -> Result<Prompt, Report<Error>> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .change_context(Error::WritingDatabase)?;
+
+        let version: i32 = sqlx::query_scalar(&format!(
+            "SELECT COALESCE(MAX(version), 0) + 1 FROM {} WHERE prompt_id = $1",
+            self.tables.prompts()
+        ))
+        .bind(&prompt.id)
+        .fetch_one(&mut *tx)
+        .await
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to load previous prompt version")?;
+
+        let created_at = Utc::now();
+        sqlx::query(&format!(
+            "INSERT INTO {} (prompt_id, version, description, template, created_at)
+            VALUES ($1, $2, $3, $4, $5)",
+            self.tables.prompts()
+        ))
+        .bind(&prompt.id)
+        .bind(version)
+        .bind(&prompt.description)
+        .bind(sqlx::types::Json(&prompt.template))
+        .bind(created_at)
+        .execute(&mut *tx)
+        .await
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to create prompt")?;
+
+        let labels = prompt.labels.iter().unique().cloned().collect::<Vec<_>>();
+        for label in &labels {
+            upsert_prompt_label(&mut *tx, &self.tables, &prompt.id, label, version).await?;
+        }
+
+        tx.commit().await.change_context(Error::WritingDatabase)?;
+
+        Ok(Prompt {
+            id: prompt.id.clone(),
+            version: version as u32,
+            labels,
+            description: prompt.description.clone(),
+            template: prompt.template.clone(),
+            created_at: Some(created_at),
+        })
+    }
+
+    async fn list_prompts(&self) -> Result<Vec<Prompt>, Report<Error>> {
+        let prompts = self.tables.prompts();
+        let rows: Vec<PromptRow> = sqlx::query_as(&format!(
+            "SELECT {PROMPT_COLUMNS} FROM {prompts} p
+            WHERE version = (SELECT MAX(version) FROM {prompts} p2 WHERE p2.prompt_id = p.prompt_id)
+            ORDER BY prompt_id"
+        ))
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load prompts")?;
+
+        self.with_prompt_labels(rows, None).await
+    }
+
+    async fn list_prompt_versions(&self, id: &str) -> Result<Vec<Prompt>, Report<Error>> {
+        let rows: Vec<PromptRow> = sqlx::query_as(&format!(
+            "SELECT {PROMPT_COLUMNS} FROM {} WHERE prompt_id = $1 ORDER BY version",
+            self.tables.prompts()
+        ))
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load prompt versions")?;
+
+        self.with_prompt_labels(rows, Some(id)).await
+    }
+
+    async fn get_prompt(
+        &self,
+        id: &str,
+        version: &PromptVersion,
+    ) -> Result<Option<Prompt>, Report<Error>> {
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT {PROMPT_COLUMNS} FROM {} WHERE prompt_id = ",
+            self.tables.prompts()
+        ));
+        builder.push_bind(id);
+        match version {
+            PromptVersion::Latest => {
+                builder.push(" ORDER BY version DESC LIMIT 1");
+            }
+            PromptVersion::Version(version) => {
+                builder.push(" AND version = ").push_bind(*version as i32);
+            }
+            PromptVersion::Label(label) => {
+                builder
+                    .push(format!(
+                        " AND version = (SELECT version FROM {} WHERE prompt_id = ",
+                        self.tables.prompt_labels()
+                    ))
+                    .push_bind(id)
+                    .push(" AND label = ")
+                    .push_bind(label)
+                    .push(")");
+            }
+        }
+
+        let row: Option<PromptRow> = builder
+            .build_query_as()
+            .fetch_optional(&self.pool)
+            .await
+            .change_context(Error::LoadingDatabase)
+            .attach_printable("Failed to load prompt")?;
+
+        let prompts = self
+            .with_prompt_labels(row.into_iter().collect(), Some(id))
+            .await?;
+        Ok(prompts.into_iter().next())
+    }
+
+    async fn set_prompt_label(
+        &self,
+        id: &str,
+        label: &str,
+        version: u32,
+    ) -> Result<bool, Report<Error>> {
+        upsert_prompt_label(&self.pool, &self.tables, id, label, version as i32).await
+    }
+
+    async fn remove_prompt_label(&self, id: &str, label: &str) -> Result<bool, Report<Error>> {
+        let result = sqlx::query(&format!(
+            "DELETE FROM {} WHERE prompt_id = $1 AND label = $2",
+            self.tables.prompt_labels()
+        ))
+        .bind(id)
+        .bind(label)
+        .execute(&self.pool)
+        .await
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to remove prompt label")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_prompt(&self, id: &str, version: Option<u32>) -> Result<u64, Report<Error>> {
+        // Labels are removed along with their versions by the foreign key.
+        let mut builder = QueryBuilder::new(format!(
+            "DELETE FROM {} WHERE prompt_id = ",
+            self.tables.prompts()
+        ));
+        builder.push_bind(id);
+        if let Some(version) = version {
+            builder.push(" AND version = ").push_bind(version as i32);
+        }
+
+        let result = builder
+            .build()
+            .execute(&self.pool)
+            .await
+            .change_context(Error::WritingDatabase)
+            .attach_printable("Failed to delete prompt")?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn search_events(
+        &self,
+        query: &EventSearchQuery,
+    ) -> Result<Vec<EventSearchResult>, Report<Error>> {
+        let enabled = search_index_enabled(&self.pool, &self.tables)
+            .await
+            .change_context(Error::LoadingDatabase)?;
+        if !enabled {
+            return Err(Report::new(Error::SearchNotEnabled));
+        }
+
+        if query.query.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT run_id, {EVENT_COLUMNS}
+            FROM {}, websearch_to_tsquery('english', ",
+            self.tables.events()
+        ));
+        builder
+            .push_bind(&query.query)
+            .push(") q WHERE search_text @@ q");
+
+        if let Some(application) = &query.application {
+            builder.push(" AND application = ").push_bind(application);
+        }
+        if let Some(environment) = &query.environment {
+            builder.push(" AND environment = ").push_bind(environment);
+        }
+        if let Some(start_time) = query.start_time {
+            builder.push(" AND created_at >= ").push_bind(start_time);
+        }
+        if let Some(end_time) = query.end_time {
+            builder.push(" AND created_at < ").push_bind(end_time);
+        }
+
+        builder
+            .push(" ORDER BY ts_rank(search_text, q) DESC, created_at DESC LIMIT ")
+            .push_bind(query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT) as i64)
+            .push(" OFFSET ")
+            .push_bind(query.offset.unwrap_or(0) as i64);
+
+        let rows: Vec<SearchRow> = builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::LoadingDatabase)
+            .attach_printable("Failed to search events")?;
+
+        Ok(rows.into_iter().map(EventSearchResult::from).collect())
+    }
+
+    async fn find_expired(
+        &self,
+        table: PurgeTable,
+        cutoffs: &RetentionCutoffs,
+        limit: usize,
+    ) -> Result<Vec<Uuid>, Report<Error>> {
+        let Some(latest) = cutoffs.latest() else {
+            return Ok(Vec::new());
+        };
+
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT id FROM {} WHERE ",
+            table.table_in(&self.tables)
+        ));
+        push_retention_filter(&mut builder, table, cutoffs, latest);
+        builder.push(" LIMIT ").push_bind(limit as i64);
+
+        builder
+            .build_query_scalar()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::Purge)
+            .attach_printable("Failed to find expired rows")
+    }
+
+    async fn find_stale_runs(
+        &self,
+        cutoffs: &StaleCutoffs,
+        limit: usize,
+    ) -> Result<Vec<StaleRun>, Report<Error>> {
+        let last_update = "GREATEST(r.updated_at, s.updated_at, e.created_at)";
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT r.id, {last_update} AS last_update
+            FROM {runs} r
+            LEFT JOIN LATERAL (
+                SELECT MAX(updated_at) AS updated_at FROM {steps} WHERE run_id = r.id
+            ) s ON true
+            LEFT JOIN LATERAL (
+                SELECT MAX(created_at) AS created_at FROM {events} WHERE run_id = r.id
+            ) e ON true
+            WHERE r.status = 'started' AND {last_update} < ",
+            runs = self.tables.runs(),
+            steps = self.tables.steps(),
+            events = self.tables.events(),
+        ));
+        push_stale_cutoff(&mut builder, cutoffs);
+        builder
+            .push(" ORDER BY last_update LIMIT ")
+            .push_bind(limit as i64);
+
+        let rows: Vec<(Uuid, DateTime<Utc>)> = builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::StaleRuns)
+            .attach_printable("Failed to find stale runs")?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(id, last_update)| StaleRun { id, last_update })
+            .collect())
+    }
+
+    async fn mark_stale_runs(
+        &self,
+        ids: &[Uuid],
+        run_status: &str,
+        step_status: &str,
+    ) -> Result<MarkedStaleRuns, Report<Error>> {
+        let mut tx = self.pool.begin().await.change_context(Error::StaleRuns)?;
+        // Leave updated_at alone so that a later update from the run still takes precedence.
+        let query = format!(
+            "UPDATE {} SET status = $2 WHERE id = ANY($1) AND status = 'started' RETURNING id",
+            self.tables.runs()
+        );
+        let runs: Vec<Uuid> = sqlx::query_scalar(&query)
+            .bind(ids)
+            .bind(run_status)
+            .fetch_all(&mut *tx)
+            .await
+            .change_context(Error::StaleRuns)
+            .attach_printable("Failed to update runs")?;
+
+        let query = format!(
+            "UPDATE {} SET status = $2, end_time = updated_at
+            WHERE run_id = ANY($1) AND end_time IS NULL AND status <> 'pending'
+            RETURNING id, run_id",
+            self.tables.steps()
+        );
+        let steps: Vec<(Uuid, Uuid)> = sqlx::query_as(&query)
+            .bind(&runs)
+            .bind(step_status)
+            .fetch_all(&mut *tx)
+            .await
+            .change_context(Error::StaleRuns)
+            .attach_printable("Failed to update steps")?;
+        tx.commit().await.change_context(Error::StaleRuns)?;
+
+        Ok(MarkedStaleRuns {
+            runs: runs.len() as u64,
+            steps: steps
+                .into_iter()
+                .map(|(id, run_id)| AbandonedStep { id, run_id })
+                .collect(),
+        })
+    }
+
+    async fn create_partitions(&self, now: DateTime<Utc>) -> Result<(), Report<Error>> {
+        let mut conn = self.pool.acquire().await.change_context(Error::Partition)?;
+        let Some(interval) = events_partition_interval(&mut *conn, &self.tables)
+            .await
+            .change_context(Error::Partition)?
+        else {
+            return Ok(());
+        };
+
+        let partitions =
+            EventPartition::covering(&self.tables.unqualified("events"), interval, now, now);
+        create_event_partitions(&mut conn, &self.tables, &partitions)
+            .await
+            .change_context(Error::Partition)
+    }
+
+    async fn drop_expired_partitions(&self, before: DateTime<Utc>) -> Result<u64, Report<Error>> {
+        let Some(interval) = events_partition_interval(&self.pool, &self.tables)
+            .await
+            .change_context(Error::Partition)?
+        else {
+            return Ok(0);
+        };
+
+        let names: Vec<String> = sqlx::query_scalar(
+            "SELECT c.relname::text
+            FROM pg_inherits i
+            JOIN pg_class c ON c.oid = i.inhrelid
+            WHERE i.inhparent = $1::regclass",
+        )
+        .bind(self.tables.events())
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::Partition)
+        .attach_printable("Failed to list partitions")?;
+
+        let events_table = self.tables.unqualified("events");
+        let mut dropped = 0;
+        for name in names {
+            let Some(partition) = EventPartition::from_name(&events_table, interval, &name) else {
+                continue;
+            };
+
+            if partition.end <= before {
+                sqlx::raw_sql(&format!(
+                    "DROP TABLE {}",
+                    self.tables.qualify(&partition.name)
+                ))
+                .execute(&self.pool)
+                .await
+                .change_context(Error::Partition)
+                .attach_printable_lazy(|| format!("Failed to drop {}", partition.name))?;
+                dropped += 1;
+            }
+        }
+
+        Ok(dropped)
+    }
+
+    async fn count_expired(
+        &self,
+        cutoffs: &RetentionCutoffs,
+    ) -> Result<PurgeCounts, Report<Error>> {
+        let Some(latest) = cutoffs.latest() else {
+            return Ok(PurgeCounts::default());
+        };
+
+        let runs_table = self.tables.runs();
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT (SELECT count(*) FROM {} WHERE ",
+            self.tables.events()
+        ));
+        push_retention_filter(&mut builder, PurgeTable::Events, cutoffs, latest);
+        builder.push(format!("), (SELECT count(*) FROM {runs_table} WHERE "));
+        push_retention_filter(&mut builder, PurgeTable::Runs, cutoffs, latest);
+        builder.push(format!(
+            "), (SELECT count(*) FROM {} WHERE run_id IN ",
+            self.tables.steps()
+        ));
+        builder.push(format!("(SELECT id FROM {runs_table} WHERE "));
+        push_retention_filter(&mut builder, PurgeTable::Runs, cutoffs, latest);
+        builder.push("))");
+
+        let (events, runs, steps): (i64, i64, i64) = builder
+            .build_query_as()
+            .fetch_one(&self.pool)
+            .await
+            .change_context(Error::Purge)
+            .attach_printable("Failed to count expired rows")?;
+
+        Ok(PurgeCounts {
+            events: events as u64,
+            runs: runs as u64,
+            steps: steps as u64,
+            ..Default::default()
+        })
+    }
+
+    async fn load_rows_for_archive(
+        &self,
+        table: PurgeTable,
+        ids: &[Uuid],
+    ) -> Result<Vec<ArchivedRow>, Report<Error>> {
+        let mut rows = load_json_rows(
+            &self.pool,
+            table.table_name(),
+            &format!(
+                // Leave out the full-text search column, since it can be rebuilt from the row.
+                "SELECT to_jsonb(t) - 'search_text' FROM {} t WHERE id = ANY($1)",
+                table.table_in(&self.tables)
+            ),
+            ids,
+        )
+        .await?;
+
+        if table == PurgeTable::Runs {
+            rows.extend(
+                load_json_rows(
+                    &self.pool,
+                    "chronicle_steps",
+                    &format!(
+                        "SELECT to_jsonb(t) FROM {} t WHERE run_id = ANY($1)",
+                        self.tables.steps()
+                    ),
+                    ids,
+                )
+                .await?,
+            );
+            rows.extend(
+                load_json_rows(
+                    &self.pool,
+                    "chronicle_scores",
+                    &format!(
+                        "SELECT to_jsonb(t) FROM {} t WHERE run_id = ANY($1)",
+                        self.tables.scores()
+                    ),
+                    ids,
+                )
+                .await?,
+            );
+        }
+
+        Ok(rows)
+    }
+
+    async fn delete_rows(
+        &self,
+        table: PurgeTable,
+        ids: &[Uuid],
+    ) -> Result<PurgeCounts, Report<Error>> {
+        match table {
+            PurgeTable::Events => {
+                let query = format!("DELETE FROM {} WHERE id = ANY($1)", self.tables.events());
+                let result = sqlx::query(&query)
+                    .bind(ids)
+                    .execute(&self.pool)
+                    .await
+                    .change_context(Error::Purge)
+                    .attach_printable("Failed to delete events")?;
+
+                Ok(PurgeCounts {
+                    events: result.rows_affected(),
+                    ..Default::default()
+                })
+            }
+            PurgeTable::Runs => {
+                let mut tx = self.pool.begin().await.change_context(Error::Purge)?;
+                let query = format!(
+                    "DELETE FROM {} WHERE run_id = ANY($1)",
+                    self.tables.scores()
+                );
+                sqlx::query(&query)
+                    .bind(ids)
+                    .execute(&mut *tx)
+                    .await
+                    .change_context(Error::Purge)
+                    .attach_printable("Failed to delete scores")?;
+                let query = format!("DELETE FROM {} WHERE run_id = ANY($1)", self.tables.steps());
+                let steps = sqlx::query(&query)
+                    .bind(ids)
+                    .execute(&mut *tx)
+                    .await
+                    .change_context(Error::Purge)
+                    .attach_printable("Failed to delete steps")?;
+                let query = format!("DELETE FROM {} WHERE id = ANY($1)", self.tables.runs());
+                let runs = sqlx::query(&query)
+                    .bind(ids)
+                    .execute(&mut *tx)
+                    .await
+                    .change_context(Error::Purge)
+                    .attach_printable("Failed to delete runs")?;
+                tx.commit().await.change_context(Error::Purge)?;
+
+                Ok(PurgeCounts {
+                    runs: runs.rows_affected(),
+                    steps: steps.rows_affected(),
+                    ..Default::default()
+                })
+            }
+        }
+    }
+
+    async fn notify_live_events(&self, events: &[LiveEvent]) -> Result<bool, Report<Error>> {
+        let payloads = events
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(Error::LiveEvents)?;
+
+        sqlx::query("SELECT pg_notify($1, payload) FROM UNNEST($2::text[]) AS payload")
+            .bind(LIVE_EVENT_CHANNEL)
+            .bind(&payloads)
+            .execute(&self.pool)
+            .await
+            .change_context(Error::LiveEvents)?;
+
+        Ok(true)
+    }
+
+    async fn listen_live_events(
+        &self,
+        tx: LiveEventSender,
+    ) -> Result<Option<tokio::task::JoinHandle<()>>, Report<Error>> {
+        let mut listener = PgListener::connect_with(&self.pool)
+            .await
+            .change_context(Error::LiveEvents)?;
+        listener
+            .listen(LIVE_EVENT_CHANNEL)
+            .await
+            .change_context(Error::LiveEvents)?;
+
+        let task = tokio::task::spawn(async move {
+            loop {
+                // The listener reconnects on its own, so an error here means that it failed to
+                // reconnect. Wait a bit and then let it try again.
+                let notification = match listener.recv().await {
+                    Ok(n) => n,
+                    Err(e) => {
+                        tracing::error!(error = ?e, "Live event listener failed");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+
+                match serde_json::from_str::<LiveEvent>(notification.payload()) {
+                    Ok(event) => {
+                        // This only fails if there are no receivers, which is fine.
+                        tx.send(Arc::new(event)).ok();
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = ?e, "Received invalid live event");
+                    }
+                }
+            }
+        });
+
+        Ok(Some(task))
+    }
+}
+
+/// Merge the `info` of a run or step `t` with the `info` from an upsert, so that keys from the
+/// event matching `newer` take precedence.
+fn merge_info(newer: &str) -> String {
+    format!(
+        "CASE
+            WHEN NULLIF(t.info, 'null'::jsonb) IS NULL THEN EXCLUDED.info
+            WHEN NULLIF(EXCLUDED.info, 'null'::jsonb) IS NULL THEN t.info
+            WHEN {newer} THEN t.info || EXCLUDED.info
+            ELSE EXCLUDED.info || t.info
+            END"
+    )
+}
+
+/// Add a condition that matches rows older than the retention cutoffs
+fn push_retention_filter(
+    builder: &mut QueryBuilder<'_, sqlx::Postgres>,
+    table: PurgeTable,
+    cutoffs: &RetentionCutoffs,
+    latest: DateTime<Utc>,
+) {
+    let column = table.time_column();
+    // Comparing against the latest cutoff first lets the database use the index on the column.
+    builder.push(column).push(" < ").push_bind(latest);
+    builder.push(" AND ").push(column).push(" < ");
+    if cutoffs.rules.is_empty() {
+        builder.push_bind(cutoffs.default);
+        return;
+    }
+
+    builder.push("CASE");
+    for rule in &cutoffs.rules {
+        builder.push(" WHEN true");
+        if let Some(application) = &rule.application {
+            builder
+                .push(" AND application = ")
+                .push_bind(application.clone());
+        }
+        if let Some(environment) = &rule.environment {
+            builder
+                .push(" AND environment = ")
+                .push_bind(environment.clone());
+        }
+        builder.push(" THEN ").push_bind(rule.cutoff);
+    }
+    builder
+        .push(" ELSE ")
+        .push_bind(cutoffs.default)
+        .push(" END");
+}
+
+/// Add the time before which a run `r` is stale. A timeout in the run's info takes precedence
+/// over the cutoffs.
+fn push_stale_cutoff(builder: &mut QueryBuilder<'_, sqlx::Postgres>, cutoffs: &StaleCutoffs) {
+    builder
+        .push(format!(
+            "CASE WHEN jsonb_typeof(r.info -> '{STALE_TIMEOUT_INFO_KEY}') = 'number' THEN "
+        ))
+        .push_bind(cutoffs.now)
+        .push(format!(
+            " - make_interval(secs => (r.info ->> '{STALE_TIMEOUT_INFO_KEY}')::float8)"
+        ));
+    for rule in &cutoffs.rules {
+        builder.push(" WHEN true");
+        if let Some(name) = &rule.name {
+            builder.push(" AND r.name = ").push_bind(name.clone());
+        }
+        if let Some(application) = &rule.application {
+            builder
+                .push(" AND r.application = ")
+                .push_bind(application.clone());
+        }
+        builder.push(" THEN ").push_bind(rule.cutoff);
+    }
+    builder
+        .push(" ELSE ")
+        .push_bind(cutoffs.default)
+        .push(" END");
+}
+
+/// Point a label at a version of a prompt. Returns `false` if the version does not exist.
+async fn upsert_prompt_label(
+    executor: impl PgExecutor<'_>,
+    tables: &TableNames,
+    id: &str,
+    label: &str,
+    version: i32,
+) -> Result<bool, Report<Error>> {
+    let result = sqlx::query(&format!(
+        "INSERT INTO {} (prompt_id, label, version, updated_at)
+        SELECT prompt_id, $2, version, $4 FROM {} WHERE prompt_id = $1 AND version = $3
+        ON CONFLICT (prompt_id, label)
+        DO UPDATE SET version = EXCLUDED.version, updated_at = EXCLUDED.updated_at",
+        tables.prompt_labels(),
+        tables.prompts()
+    ))
+    .bind(id)
+    .bind(label)
+    .bind(version)
+    .bind(Utc::now())
+    .execute(executor)
+    .await
+    .change_context(Error::WritingDatabase)
+    .attach_printable("Failed to set prompt label")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn load_json_rows(
+    pool: &PgPool,
+    table: &str,
+    query: &str,
+    ids: &[Uuid],
+) -> Result<Vec<ArchivedRow>, Report<Error>> {
+    let rows: Vec<serde_json::Value> = sqlx::query_scalar(query)
+        .bind(ids)
+        .fetch_all(pool)
+        .await
+        .change_context(Error::Purge)
+        .attach_printable_lazy(|| format!("Failed to load rows from {table}"))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| ArchivedRow {
+            table: table.to_string(),
+            row,
+        })
+        .collect())
+}
+
+/// Return how the events table is partitioned, or `None` if it is a regular table.
+pub async fn events_partition_interval(
+    executor: impl PgExecutor<'_>,
+    tables: &TableNames,
+) -> Result<Option<PartitionInterval>, sqlx::Error> {
+    let interval = sqlx::query_scalar::<_, sqlx::types::Json<PartitionInterval>>(&format!(
+        "SELECT value FROM {} WHERE key = $1",
+        tables.meta()
+    ))
+    .bind(PARTITION_INTERVAL_KEY)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(interval.map(|i| i.0))
+}
+
+async fn create_event_partitions(
+    conn: &mut PgConnection,
+    tables: &TableNames,
+    partitions: &[EventPartition],
+) -> Result<(), sqlx::Error> {
+    for partition in partitions {
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {} PARTITION OF {}
+            FOR VALUES FROM ('{}') TO ('{}')",
+            tables.qualify(&partition.name),
+            tables.events(),
+            partition.start.to_rfc3339(),
+            partition.end.to_rfc3339()
+        );
+        sqlx::query(&query).execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Convert the events table into a table partitioned by `created_at`, so that old events can be
+/// removed by dropping whole partitions. Existing events are copied into the new partitions, and
+/// the table stays locked until the copy is done. The background task started by the proxy
+/// creates new partitions as they are needed.
+///
+/// Returns `false` if the table was already partitioned.
+pub async fn partition_events_table(
+    pool: &PgPool,
+    tables: &TableNames,
+    interval: PartitionInterval,
+    now: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if events_partition_interval(&mut *tx, tables).await?.is_some() {
+        return Ok(false);
+    }
+
+    // The generated search column is not copied to the new table, so drop it here and add it
+    // again once the table is partitioned.
+    let search_index = search_index_enabled(&mut *tx, tables).await?;
+    if search_index {
+        run_migration(&mut tx, tables, DROP_SEARCH_INDEX_MIGRATION).await?;
+    }
+
+    run_migration(&mut tx, tables, PARTITION_EVENTS_MIGRATION).await?;
+
+    let unpartitioned = tables.table("events_unpartitioned");
+    let oldest = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(&format!(
+        "SELECT min(created_at) FROM {unpartitioned}"
+    ))
+    .fetch_one(&mut *tx)
+    .await?;
+    let partitions = EventPartition::covering(
+        &tables.unqualified("events"),
+        interval,
+        oldest.unwrap_or(now),
+        now,
+    );
+    create_event_partitions(&mut tx, tables, &partitions).await?;
+
+    sqlx::raw_sql(&format!(
+        "INSERT INTO {} SELECT * FROM {unpartitioned};
+        DROP TABLE {unpartitioned};",
+        tables.events()
+    ))
+    .execute(&mut *tx)
+    .await?;
+
+    if search_index {
+        run_migration(&mut tx, tables, SEARCH_INDEX_MIGRATION).await?;
+    }
+
+    sqlx::query(&format!(
+        "INSERT INTO {} (key, value) VALUES ($1, $2)",
+        tables.meta()
+    ))
+    .bind(PARTITION_INTERVAL_KEY)
+    .bind(sqlx::types::Json(interval))
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Return true if full-text search has been enabled with [enable_search_index].
+pub async fn search_index_enabled(
+    executor: impl PgExecutor<'_>,
+    tables: &TableNames,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT EXISTS (
+            SELECT 1 FROM information_schema.columns
+            WHERE table_schema = COALESCE($1, current_schema())
+                AND table_name = $2
+                AND column_name = 'search_text'
+        )",
+    )
+    .bind(tables.schema())
+    .bind(tables.unqualified("events"))
+    .fetch_one(executor)
+    .await
+}
+
+/// Add a full-text index over the message contents of `chat_request` and `chat_response`,
+/// which is required for [ProxyDatabase::search_events]. This rewrites the events table, so it
+/// can take a while on a large table.
+///
+/// Returns false if the index already exists.
+pub async fn enable_search_index(pool: &PgPool, tables: &TableNames) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if search_index_enabled(&mut *tx, tables).await? {
+        return Ok(false);
+    }
+
+    run_migration(&mut tx, tables, SEARCH_INDEX_MIGRATION).await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Remove the full-text index added by [enable_search_index]. Returns false if there was no
+/// index.
+pub async fn disable_search_index(pool: &PgPool, tables: &TableNames) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if !search_index_enabled(&mut *tx, tables).await? {
+        return Ok(false);
+    }
+
+    run_migration(&mut tx, tables, DROP_SEARCH_INDEX_MIGRATION).await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Run a migration script from the `migrations` directory, using the configured table names.
+/// The schema stays at the front of the search path until the transaction ends.
+async fn run_migration(
+    tx: &mut PgConnection,
+    tables: &TableNames,
+    migration: &str,
+) -> Result<(), sqlx::Error> {
+    if let Some(schema) = tables.schema() {
+        sqlx::raw_sql(&format!("SET LOCAL search_path TO {schema}, public"))
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    sqlx::raw_sql(&tables.migration(migration))
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+/// Run database migrations specific to the proxy. These migrations are designed for a simple setup with
+/// single-tenant use. You may want to add multi-tenant features or partitioning, and can integrate
+/// the files from the `migrations` directory into your project to accomplish that.
+pub async fn run_default_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
+    run_migrations(pool, &TableNames::default()).await
+}
+
+/// Run the migrations in [MIGRATIONS] which have not been applied yet, creating the tables with
+/// the names in `tables`. The schema is created if it does not exist.
+///
+/// This fails with [MigrationError::SchemaTooNew](super::migrations::MigrationError::SchemaTooNew)
+/// if the database was migrated by a newer version of Chronicle, and does not run anything if an
+/// applied migration has changed.
+pub async fn run_migrations(pool: &PgPool, tables: &TableNames) -> Result<(), sqlx::Error> {
+    let mut tx = begin_migrations(pool, tables).await?;
+    let applied = load_applied_migrations(&mut tx, tables).await?;
+    let pending = pending_migrations(MIGRATIONS, &applied)?;
+    tracing::info!(
+        "{} migrations applied, {} to run",
+        applied.len(),
+        pending.len()
+    );
+
+    for migration in pending {
+        tracing::info!("Running migration {}", migration.id);
+        run_migration(&mut tx, tables, migration.up).await?;
+        insert_migration(&mut tx, tables, migration).await?;
+    }
+
+    finish_migrations(tx, tables).await
+}
+
+/// Record the migrations in [MIGRATIONS] as applied, without running them. Use this when the
+/// migrations run from another migration system, so that Chronicle knows they were applied.
+pub async fn record_migrations(pool: &PgPool, tables: &TableNames) -> Result<(), sqlx::Error> {
+    let mut tx = begin_migrations(pool, tables).await?;
+    let applied = load_applied_migrations(&mut tx, tables).await?;
+    for migration in pending_migrations(MIGRATIONS, &applied)? {
+        insert_migration(&mut tx, tables, migration).await?;
+    }
+
+    finish_migrations(tx, tables).await
+}
+
+/// Revert the applied migrations that come after the migration `target`, newest first, using
+/// their down migrations. If `target` is `None`, every migration is reverted. Nothing is reverted
+/// if one of the migrations has no down migration.
+///
+/// Returns the IDs of the reverted migrations.
+pub async fn revert_migrations(
+    pool: &PgPool,
+    tables: &TableNames,
+    target: Option<&str>,
+) -> Result<Vec<&'static str>, sqlx::Error> {
+    let mut tx = begin_migrations(pool, tables).await?;
+    let applied = load_applied_migrations(&mut tx, tables).await?;
+    let revert = migrations_to_revert(MIGRATIONS, &applied, target)?;
+
+    let migrations_table = tables.table("migrations");
+    for migration in &revert {
+        tracing::info!("Reverting migration {}", migration.id);
+        run_migration(&mut tx, tables, migration.down.unwrap_or_default()).await?;
+        sqlx::query(&format!("DELETE FROM {migrations_table} WHERE id = $1"))
+            .bind(migration.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    finish_migrations(tx, tables).await?;
+    Ok(revert.into_iter().map(|m| m.id).collect())
+}
+
+/// Return the migrations that have been applied to the database, in order.
+pub async fn applied_migrations(
+    pool: &PgPool,
+    tables: &TableNames,
+) -> Result<Vec<AppliedMigration>, sqlx::Error> {
+    let mut tx = begin_migrations(pool, tables).await?;
+    let applied = load_applied_migrations(&mut tx, tables).await?;
+    finish_migrations(tx, tables).await?;
+    Ok(applied)
+}
+
+/// Start a transaction that changes the migrations. This takes an advisory lock, so that servers
+/// starting at the same time don't both run the migrations, and creates the tables that track
+/// the migrations.
+async fn begin_migrations(
+    pool: &PgPool,
+    tables: &TableNames,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let migrations_table = tables.table("migrations");
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(&migrations_table)
+        .execute(&mut *tx)
+        .await?;
+
+    if let Some(schema) = tables.schema() {
+        sqlx::raw_sql(&format!("CREATE SCHEMA IF NOT EXISTS {schema}"))
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let meta_table = tables.meta();
+    sqlx::raw_sql(&format!(
+        "CREATE TABLE IF NOT EXISTS {meta_table} (
+          key text PRIMARY KEY,
+          value jsonb
+        );
+        CREATE TABLE IF NOT EXISTS {migrations_table} (
+          id text PRIMARY KEY,
+          checksum text NOT NULL,
+          applied_at timestamptz NOT NULL DEFAULT now()
+        );"
+    ))
+    .execute(&mut *tx)
+    .await?;
+
+    // Before migrations were tracked, only the number of applied migrations was stored.
+    let tracked: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {migrations_table}"))
+        .fetch_one(&mut *tx)
+        .await?;
+    if tracked == 0 {
+        let version = sqlx::query_scalar::<_, i32>(&format!(
+            "SELECT cast(value as int) FROM {meta_table} WHERE key='migration_version'"
+        ))
+        .fetch_optional(&mut *tx)
+        .await?
+        .unwrap_or(0) as usize;
+
+        for applied in legacy_applied(MIGRATIONS, version) {
+            sqlx::query(&format!(
+                "INSERT INTO {migrations_table} (id, checksum) VALUES ($1, $2)"
+            ))
+            .bind(applied.id)
+            .bind(applied.checksum)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    Ok(tx)
+}
+
+async fn load_applied_migrations(
+    tx: &mut PgConnection,
+    tables: &TableNames,
+) -> Result<Vec<AppliedMigration>, sqlx::Error> {
+    sqlx::query_as(&format!(
+        "SELECT id, checksum FROM {} ORDER BY id",
+        tables.table("migrations")
+    ))
+    .fetch_all(&mut *tx)
+    .await
+}
+
+async fn insert_migration(
+    tx: &mut PgConnection,
+    tables: &TableNames,
+    migration: &Migration,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "INSERT INTO {} (id, checksum) VALUES ($1, $2)",
+        tables.table("migrations")
+    ))
+    .bind(migration.id)
+    .bind(migration.checksum())
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// Commit the migration changes. This also updates the number of applied migrations in the meta
+/// table, which was used to track migrations before the migrations table existed.
+async fn finish_migrations(
+    mut tx: Transaction<'static, Postgres>,
+    tables: &TableNames,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "UPDATE {} SET value=to_jsonb((SELECT count(*) FROM {})) WHERE key='migration_version'",
+        tables.meta(),
+        tables.table("migrations")
+    ))
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use chrono::{DateTime, TimeZone, Utc};
+    use serde_json::json;
+    use sqlx::{postgres::PgListener, PgPool, Row};
+    use uuid::Uuid;
+
+    use crate::{
+        database::{
+            live_tail::LiveEvent,
+            logging::{DatabaseSink, LogSink, ProxyLogEntry},
+            migrations::{AppliedMigration, MigrationError},
+            partitions::PartitionInterval,
+            postgres::{
+                applied_migrations, enable_search_index, partition_events_table, record_migrations,
+                revert_migrations, run_default_migrations, run_migrations, PostgresDatabase,
+                PostgresOptions, PostgresWriteMode, MIGRATIONS,
+            },
+            search::EventSearchQuery,
+            tables::TableNames,
+            testing::{
+                bench_events, chat_event, test_events, TEST_EVENT1_ID, TEST_RUN_ID, TEST_STEP1_ID,
+                TEST_STEP2_ID,
+            },
+        },
+        workflow_events::{EventPayload, WorkflowEvent},
+        Error,
+    };
+
+    fn dt(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(secs, 0).unwrap()
+    }
+
+    async fn list_partitions(pool: &PgPool, table: &str) -> Vec<String> {
+        sqlx::query_scalar(
+            "SELECT c.relname::text
+            FROM pg_inherits i
+            JOIN pg_class c ON c.oid = i.inhrelid
+            WHERE i.inhparent = $1::regclass
+            ORDER BY c.relname",
+        )
+        .bind(table)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn count_rows(pool: &PgPool, table: &str) -> i64 {
+        sqlx::query_scalar(&format!("SELECT count(*) FROM {table}"))
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_database_writes(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let mut listener = PgListener::connect_with(&pool).await.unwrap();
+        let listen_task = tokio::task::spawn(async move {
+            listener
+                .listen(&format!("chronicle_run:{TEST_RUN_ID}"))
+                .await
+                .unwrap();
+            listener.recv().await
+        });
+
+        let db = super::PostgresDatabase::new(pool.clone());
+
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        let runs = sqlx::query(
+            "SELECT id, name, description, application, environment,
+                input, output, status, trace_id, span_id,
+                tags, info, updated_at, created_at
+                FROM chronicle_runs",
+        )
+        .fetch_all(&pool)
+        .await
+        .expect("Fetching runs");
+        assert_eq!(runs.len(), 1);
+        let run = &runs[0];
+
+        assert_eq!(run.get::<Uuid, _>(0), TEST_RUN_ID, "run id");
+        assert_eq!(run.get::<String, _>(1), "test run", "name");
+        assert_eq!(
+            run.get::<Option<String>, _>(2),
+            Some("test description".to_string()),
+            "description"
+        );
+        assert_eq!(
+            run.get::<Option<String>, _>(3),
+            Some("test application".to_string()),
+            "application"
+        );
+        assert_eq!(
+            run.get::<Option<String>, _>(4),
+            Some("test environment".to_string()),
+            "environment"
+        );
+        assert_eq!(
+            run.get::<Option<serde_json::Value>, _>(5),
+            Some(json!({"query":"abc"})),
+            "input"
+        );
+        assert_eq!(
+            run.get::<Option<serde_json::Value>, _>(6),
+            Some(json!({"result":"success"})),
+            "output"
+        );
+        assert_eq!(run.get::<String, _>(7), "finished", "status");
+        assert_eq!(
+            run.get::<Option<String>, _>(8),
+            Some("0123456789abcdef".to_string()),
+            "trace_id"
+        );
+        assert_eq!(
+            run.get::<Option<String>, _>(9),
+            Some("12345678".to_string()),
+            "span_id"
+        );
+        assert_eq!(
+            run.get::<Vec<String>, _>(10),
+            vec!["tag1".to_string(), "tag2".to_string()],
+            "tags"
+        );
+        assert_eq!(
+            run.get::<Option<serde_json::Value>, _>(11),
+            Some(json!({"info1":"value1","info2":"new_value", "info3":"value3"})),
+            "info"
+        );
+        assert_eq!(run.get::<DateTime<Utc>, _>(12), dt(5), "updated_at");
+        assert_eq!(run.get::<DateTime<Utc>, _>(13), dt(1), "created_at");
+
+        let steps = sqlx::query(
+            "SELECT id, run_id, type, parent_step, name,
+                input, output, status, span_id, tags, info, start_time, end_time
+                FROM chronicle_steps
+                ORDER BY start_time ASC",
+        )
+        .fetch_all(&pool)
+        .await
+        .expect("Fetching steps");
+        assert_eq!(steps.len(), 2);
+
+        let step1 = &steps[0];
+        assert_eq!(step1.get::<Uuid, _>(0), TEST_STEP1_ID, "id");
+        assert_eq!(step1.get::<Uuid, _>(1), TEST_RUN_ID, "run_id");
+        assert_eq!(step1.get::<String, _>(2), "step_type", "type");
+        assert_eq!(step1.get::<Option<String>, _>(3), None, "parent_step");
+        assert_eq!(step1.get::<String, _>(4), "source_node1", "name");
+        assert_eq!(
+            step1.get::<Option<serde_json::Value>, _>(5),
+            Some(json!({ "task_param": "value"})),
+            "input"
+        );
+        assert_eq!(
+            step1.get::<Option<serde_json::Value>, _>(6),
+            Some(json!({ "result": "success" })),
+            "output"
+        );
+        assert_eq!(step1.get::<String, _>(7), "finished", "status");
+        assert_eq!(
+            step1.get::<Option<String>, _>(8),
+            Some("11111111".to_string()),
+            "span_id"
+        );
+        assert_eq!(
+            step1.get::<Option<Vec<String>>, _>(9),
+            Some(vec!["dag".to_string(), "node".to_string()]),
+            "tags"
+        );
+        assert_eq!(
+            step1.get::<Option<serde_json::Value>, _>(10),
+            Some(json!({"model": "a_model", "info3": "value3"})),
+            "info"
+        );
+        assert_eq!(
+            step1.get::<DateTime<Utc>, _>(11),
+            Utc.timestamp_opt(2, 0).unwrap(),
+            "start_time"
+        );
+        assert_eq!(
+            step1.get::<DateTime<Utc>, _>(12),
+            Utc.timestamp_opt(5, 0).unwrap(),
+            "end_time"
+        );
+
+        let step2 = &steps[1];
+        assert_eq!(step2.get::<Uuid, _>(0), TEST_STEP2_ID, "id");
+        assert_eq!(step2.get::<Uuid, _>(1), TEST_RUN_ID, "run_id");
+        assert_eq!(step2.get::<String, _>(2), "llm", "type");
+        assert_eq!(
+            step2.get::<Option<Uuid>, _>(3),
+            Some(TEST_STEP1_ID),
+            "parent_step"
+        );
+        assert_eq!(step2.get::<String, _>(4), "source_node2", "name");
+        assert_eq!(
+            step2.get::<Option<serde_json::Value>, _>(5),
+            Some(json!({ "task_param2": "value"})),
+            "input"
+        );
+        assert_eq!(
+            step2.get::<Option<serde_json::Value>, _>(6),
+            Some(json!({ "message": "an error" })),
+            "output"
+        );
+        assert_eq!(step2.get::<String, _>(7), "error", "status");
+        assert_eq!(
+            step2.get::<Option<String>, _>(8),
+            Some("22222222".to_string()),
+            "span_id"
+        );
+        assert_eq!(step2.get::<Option<Vec<String>>, _>(9), None, "tags");
+        assert_eq!(
+            step2.get::<Option<serde_json::Value>, _>(10),
+            Some(json!({"model": "a_model"})),
+            "info"
+        );
+        assert_eq!(
+            step2.get::<DateTime<Utc>, _>(11),
+            Utc.timestamp_opt(3, 0).unwrap(),
+            "start_time"
+        );
+        assert_eq!(
+            step2.get::<DateTime<Utc>, _>(12),
+            Utc.timestamp_opt(5, 0).unwrap(),
+            "end_time"
+        );
+
+        let events = sqlx::query(
+            "SELECT id, event_type, step_id, run_id, meta, error, created_at
+                FROM chronicle_events
+                ORDER BY created_at ASC",
+        )
+        .fetch_all(&pool)
+        .await
+        .expect("Fetching steps");
+        assert_eq!(events.len(), 2);
+
+        let event = &events[0];
+        assert_eq!(event.get::<Uuid, _>(0), TEST_EVENT1_ID, "id");
+        assert_eq!(event.get::<String, _>(1), "query", "event_type");
+        assert_eq!(event.get::<Uuid, _>(2), TEST_STEP2_ID, "step_id");
+        assert_eq!(event.get::<Uuid, _>(3), TEST_RUN_ID, "run_id");
+        assert_eq!(
             event.get::<Option<serde_json::Value>, _>(4),
             Some(json!({"some_key": "some_value"})),
             "meta"
         );
         assert_eq!(
-            event.get::<Option<serde_json::Value>, _>(5),
-            Some(json!(null)),
-            "error"
+            event.get::<Option<serde_json::Value>, _>(5),
+            Some(json!(null)),
+            "error"
+        );
+        assert_eq!(event.get::<DateTime<Utc>, _>(6), dt(4), "created_at");
+
+        let event2 = &events[1];
+        assert_eq!(event2.get::<String, _>(1), "an_event", "event_type");
+        assert_eq!(event2.get::<Uuid, _>(2), TEST_STEP2_ID, "step_id");
+        assert_eq!(event2.get::<Uuid, _>(3), TEST_RUN_ID, "run_id");
+        assert_eq!(
+            event2.get::<Option<serde_json::Value>, _>(4),
+            Some(json!({"key": "value"})),
+            "meta"
+        );
+        assert_eq!(
+            event2.get::<Option<serde_json::Value>, _>(5),
+            Some(json!({ "message": "something went wrong"})),
+            "error"
+        );
+        assert_eq!(event2.get::<DateTime<Utc>, _>(6), dt(5), "created_at");
+
+        let listened = tokio::time::timeout(Duration::from_secs(1), listen_task)
+            .await
+            .expect("Listener not notified")
+            .expect("Listener panicked")
+            .expect("Listener encountered an error");
+        assert_eq!(listened.channel(), format!("chronicle_run:{TEST_RUN_ID}"));
+        assert_eq!(listened.payload(), "", "payload");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_run_queries(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_run_queries(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_usage_rollups(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_usage_rollups(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_usage_rollups_out_of_order(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        crate::database::testing::test_usage_rollups_out_of_order(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_usage_without_workflow_events(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        crate::database::testing::test_usage_without_workflow_events(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_out_of_order_events(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        crate::database::testing::test_out_of_order_events(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_stale_runs(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        crate::database::testing::test_stale_runs(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_insert_write_mode(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = PostgresDatabase::with_write_mode(pool.clone(), PostgresWriteMode::Insert);
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_run_queries(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_copy_existing_steps(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = PostgresDatabase::new(pool.clone());
+        let batch = bench_events(10);
+        db.write_log_batch(batch.clone())
+            .await
+            .expect("Writing events");
+        // The second copy of each step should be ignored, as with INSERT. The proxy only logs each
+        // LLM call once, so the events get new IDs.
+        let batch = batch
+            .into_iter()
+            .map(|mut entry| {
+                if let ProxyLogEntry::Proxied(event) = &mut entry {
+                    event.id = Uuid::now_v7();
+                }
+                entry
+            })
+            .collect();
+        db.write_log_batch(batch).await.expect("Writing again");
+
+        assert_eq!(count_rows(&pool, "chronicle_steps").await, 10);
+        assert_eq!(count_rows(&pool, "chronicle_events").await, 20);
+    }
+
+    /// Compare the write modes. Run with
+    /// `cargo test --release -p chronicle-proxy bench_write_modes -- --ignored --nocapture`
+    #[sqlx::test(migrations = false)]
+    #[ignore]
+    async fn bench_write_modes(pool: PgPool) {
+        const BATCHES: usize = 50;
+        const BATCH_SIZE: usize = 1000;
+
+        run_default_migrations(&pool).await.unwrap();
+
+        for mode in [PostgresWriteMode::Insert, PostgresWriteMode::Copy] {
+            let db = PostgresDatabase::with_write_mode(pool.clone(), mode);
+            let batches = (0..BATCHES)
+                .map(|_| bench_events(BATCH_SIZE))
+                .collect::<Vec<_>>();
+
+            let start = std::time::Instant::now();
+            for batch in batches {
+                db.write_log_batch(batch).await.expect("Writing events");
+            }
+            let elapsed = start.elapsed();
+
+            let rows = BATCHES * BATCH_SIZE * 2;
+            println!(
+                "{mode:?}: wrote {rows} rows in {elapsed:?}, {:.0} rows/sec",
+                rows as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_scores(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_scores(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_datasets(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_datasets(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_replay(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        crate::database::testing::test_replay(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_prompts(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        crate::database::testing::test_prompts(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_export(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        crate::database::testing::test_export(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_purge(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_purge(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_blob_offloading(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_blob_offloading(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_search(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        let err = db
+            .search_events(&EventSearchQuery {
+                query: "test".to_string(),
+                ..Default::default()
+            })
+            .await
+            .expect_err("Search should fail without the index");
+        assert!(matches!(err.current_context(), Error::SearchNotEnabled));
+
+        let tables = TableNames::default();
+        assert!(enable_search_index(&pool, &tables).await.unwrap());
+        assert!(!enable_search_index(&pool, &tables).await.unwrap());
+        crate::database::testing::test_search(db.as_ref()).await;
+
+        assert!(super::disable_search_index(&pool, &tables).await.unwrap());
+        assert!(!super::search_index_enabled(&pool, &tables).await.unwrap());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_partitioning(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        let aug = Utc.with_ymd_and_hms(2024, 8, 15, 0, 0, 0).unwrap();
+        sqlx::query("UPDATE chronicle_events SET created_at = $1")
+            .bind(aug)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let converted = partition_events_table(
+            &pool,
+            &TableNames::default(),
+            PartitionInterval::Month,
+            aug + chrono::Duration::days(5),
+        )
+        .await
+        .expect("Partitioning");
+        assert!(converted);
+        let converted =
+            partition_events_table(&pool, &TableNames::default(), PartitionInterval::Day, aug)
+                .await
+                .expect("Partitioning again");
+        assert!(!converted, "table is already partitioned");
+
+        assert_eq!(
+            list_partitions(&pool, "chronicle_events").await,
+            vec![
+                "chronicle_events_default",
+                "chronicle_events_p20240801",
+                "chronicle_events_p20240901",
+                "chronicle_events_p20241001",
+                "chronicle_events_p20241101",
+            ]
+        );
+        assert_eq!(count_rows(&pool, "chronicle_events_p20240801").await, 2);
+
+        let run = db.get_run(TEST_RUN_ID).await.unwrap().expect("Run exists");
+        let step2 = &run.steps[0].steps[0];
+        assert_eq!(step2.id, TEST_STEP2_ID);
+        assert_eq!(
+            step2.events.len(),
+            2,
+            "events are readable after partitioning"
         );
-        assert_eq!(event.get::<DateTime<Utc>, _>(6), dt(4), "created_at");
 
-        let event2 = &events[1];
-        assert_eq!(event2.get::<String, _>(1), "an_event", "event_type");
-        assert_eq!(event2.get::<Uuid, _>(2), TEST_STEP2_ID, "step_id");
-        assert_eq!(event2.get::<Uuid, _>(3), TEST_RUN_ID, "run_id");
+        let ProxyLogEntry::Proxied(mut event) = test_events().swap_remove(3) else {
+            panic!("expected a proxied event");
+        };
+        event.id = Uuid::now_v7();
+        event.timestamp = Utc.with_ymd_and_hms(2024, 9, 2, 0, 0, 0).unwrap();
+        db.write_log_batch(vec![ProxyLogEntry::Proxied(event)])
+            .await
+            .expect("Writing to a partition");
+        assert_eq!(count_rows(&pool, "chronicle_events_p20240901").await, 1);
+
+        // The primary key includes created_at, so resending an event with a different time must
+        // still be caught.
+        let keyed_event = |day| {
+            ProxyLogEntry::Workflow(WorkflowEvent::Event(EventPayload {
+                typ: "retried".to_string(),
+                data: None,
+                error: None,
+                run_id: TEST_RUN_ID,
+                step_id: TEST_STEP2_ID,
+                time: Some(Utc.with_ymd_and_hms(2024, 9, day, 0, 0, 0).unwrap()),
+                internal_metadata: None,
+                event_id: Some(Uuid::from_u128(80)),
+            }))
+        };
+        db.write_log_batch(vec![keyed_event(3), keyed_event(4)])
+            .await
+            .expect("Writing a keyed event");
+        db.write_log_batch(vec![keyed_event(5)])
+            .await
+            .expect("Writing a keyed event again");
+        assert_eq!(count_rows(&pool, "chronicle_events_p20240901").await, 2);
+
+        db.create_partitions(Utc.with_ymd_and_hms(2024, 12, 20, 0, 0, 0).unwrap())
+            .await
+            .expect("Creating partitions");
         assert_eq!(
-            event2.get::<Option<serde_json::Value>, _>(4),
-            Some(json!({"key": "value"})),
-            "meta"
+            list_partitions(&pool, "chronicle_events")
+                .await
+                .last()
+                .map(|s| s.as_str()),
+            Some("chronicle_events_p20250301")
         );
+
+        let dropped = db
+            .drop_expired_partitions(Utc.with_ymd_and_hms(2024, 10, 1, 0, 0, 0).unwrap())
+            .await
+            .expect("Dropping partitions");
+        assert_eq!(dropped, 2);
+        assert_eq!(count_rows(&pool, "chronicle_events").await, 0);
         assert_eq!(
-            event2.get::<Option<serde_json::Value>, _>(5),
-            Some(json!({ "message": "something went wrong"})),
-            "error"
+            list_partitions(&pool, "chronicle_events").await[1],
+            "chronicle_events_p20241001"
         );
-        assert_eq!(event2.get::<DateTime<Utc>, _>(6), dt(5), "created_at");
+    }
 
-        let listened = tokio::time::timeout(Duration::from_secs(1), listen_task)
+    #[sqlx::test(migrations = false)]
+    async fn test_migration_tracking(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        let tables = TableNames::default();
+        let migration_ids =
+            |applied: Vec<AppliedMigration>| applied.into_iter().map(|m| m.id).collect::<Vec<_>>();
+        let all_ids = MIGRATIONS.iter().map(|m| m.id).collect::<Vec<_>>();
+        let mut sorted_ids = all_ids.clone();
+        sorted_ids.sort();
+        assert_eq!(all_ids, sorted_ids, "Migration IDs should sort in order");
+
+        run_default_migrations(&pool).await.unwrap();
+
+        // A database migrated before the migrations table existed
+        sqlx::query("DELETE FROM chronicle_migrations")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let applied = applied_migrations(&pool, &tables).await.unwrap();
+        assert_eq!(migration_ids(applied), all_ids);
+
+        let reverted = revert_migrations(&pool, &tables, Some("20240625_chronicle_proxy_steps"))
+            .await
+            .expect("Reverting migrations");
+        assert_eq!(
+            reverted,
+            vec![
+                "20240926_chronicle_proxy_stale_runs",
+                "20240919_chronicle_proxy_step_updates",
+                "20240912_chronicle_proxy_usage_rollups",
+                "20240905_chronicle_proxy_prompts",
+                "20240829_chronicle_proxy_replays",
+                "20240822_chronicle_proxy_datasets",
+                "20240815_chronicle_proxy_scores",
+                "20240801_chronicle_proxy_retention"
+            ]
+        );
+        let index_count: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM pg_indexes WHERE indexname = 'chronicle_events_created_at_idx'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(index_count, 0);
+        let applied = applied_migrations(&pool, &tables).await.unwrap();
+        assert_eq!(migration_ids(applied), all_ids[..3]);
+
+        run_default_migrations(&pool)
+            .await
+            .expect("Migrating after revert");
+        let applied = applied_migrations(&pool, &tables).await.unwrap();
+        assert_eq!(migration_ids(applied), all_ids);
+
+        let err = revert_migrations(&pool, &tables, None)
+            .await
+            .expect_err("Reverting irreversible migrations");
+        let sqlx::Error::Configuration(err) = err else {
+            panic!("Unexpected error {err:?}");
+        };
+        assert_eq!(
+            err.downcast_ref::<MigrationError>(),
+            Some(&MigrationError::Irreversible(
+                "20240625_chronicle_proxy_steps".to_string()
+            ))
+        );
+
+        sqlx::query(
+            "INSERT INTO chronicle_migrations (id, checksum, applied_at)
+            VALUES ('29991231_from_the_future', 'abc', now())",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let err = run_default_migrations(&pool)
+            .await
+            .expect_err("Migrating a newer database");
+        let sqlx::Error::Configuration(err) = err else {
+            panic!("Unexpected error {err:?}");
+        };
+        assert_eq!(
+            err.downcast_ref::<MigrationError>(),
+            Some(&MigrationError::SchemaTooNew(
+                "29991231_from_the_future".to_string()
+            ))
+        );
+
+        // Migrations run by another migration system
+        let app_tables = TableNames::new("app_").unwrap();
+        let applied = applied_migrations(&pool, &app_tables).await.unwrap();
+        assert!(applied.is_empty());
+        for migration in MIGRATIONS {
+            sqlx::raw_sql(&migration.sql(&app_tables))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        record_migrations(&pool, &app_tables)
+            .await
+            .expect("Recording migrations");
+        run_migrations(&pool, &app_tables)
+            .await
+            .expect("Running recorded migrations");
+        let applied = applied_migrations(&pool, &app_tables).await.unwrap();
+        assert_eq!(migration_ids(applied), all_ids);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_custom_tables(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        let tables = TableNames::new("llm_")
+            .unwrap()
+            .with_schema("telemetry")
+            .unwrap();
+        run_migrations(&pool, &tables).await.unwrap();
+        run_migrations(&pool, &tables)
+            .await
+            .expect("Running migrations again");
+
+        let db = PostgresDatabase::with_options(
+            pool.clone(),
+            PostgresOptions {
+                tables: tables.clone(),
+                ..Default::default()
+            },
+        );
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+        crate::database::testing::test_run_queries(db.as_ref()).await;
+
+        let default_tables: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM information_schema.tables WHERE table_name LIKE 'chronicle%'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(default_tables, 0);
+
+        let aug = Utc.with_ymd_and_hms(2024, 8, 15, 0, 0, 0).unwrap();
+        sqlx::query("UPDATE telemetry.llm_events SET created_at = $1")
+            .bind(aug)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(enable_search_index(&pool, &tables).await.unwrap());
+        let converted = partition_events_table(
+            &pool,
+            &tables,
+            PartitionInterval::Month,
+            aug + chrono::Duration::days(5),
+        )
+        .await
+        .expect("Partitioning");
+        assert!(converted);
+        assert_eq!(
+            list_partitions(&pool, "telemetry.llm_events").await,
+            vec![
+                "llm_events_default",
+                "llm_events_p20240801",
+                "llm_events_p20240901",
+                "llm_events_p20241001",
+                "llm_events_p20241101",
+            ]
+        );
+        assert_eq!(count_rows(&pool, "telemetry.llm_events_p20240801").await, 2);
+
+        crate::database::testing::test_search(db.as_ref()).await;
+
+        let dropped = db
+            .drop_expired_partitions(Utc.with_ymd_and_hms(2024, 9, 1, 0, 0, 0).unwrap())
+            .await
+            .expect("Dropping partitions");
+        assert_eq!(dropped, 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_live_events(pool: PgPool) {
+        filigree::tracing_config::test::init();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        let (tx, mut rx) = tokio::sync::broadcast::channel(10);
+        let listen_task = db
+            .listen_live_events(tx)
+            .await
+            .unwrap()
+            .expect("Postgres supports listening");
+
+        let event = LiveEvent::from_log_entry(&test_events()[0]);
+        let notified = db.notify_live_events(&[event.clone()]).await.unwrap();
+        assert!(notified, "Postgres supports notify");
+
+        let received = tokio::time::timeout(Duration::from_secs(1), rx.recv())
             .await
             .expect("Listener not notified")
-            .expect("Listener panicked")
-            .expect("Listener encountered an error");
-        assert_eq!(listened.channel(), format!("chronicle_run:{TEST_RUN_ID}"));
-        assert_eq!(listened.payload(), "", "payload");
+            .unwrap();
+        assert_eq!(received.typ, event.typ);
+        assert_eq!(received.run_id, event.run_id);
+        assert_eq!(received.step_id, event.step_id);
+
+        listen_task.abort();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_permanent_write_errors(pool: PgPool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::PostgresDatabase::new(pool.clone());
+        let sink = DatabaseSink::new(db, None, None);
+
+        // JSONB can't store a NUL character, so this will never be written.
+        let err = sink
+            .write_batch(vec![chat_event(Uuid::now_v7(), "a \u{0} b", Some("4"))])
+            .await
+            .expect_err("Writing a NUL character");
+        assert!(sink.is_permanent_error(&err), "{err:?}");
+
+        pool.close().await;
+        let err = sink
+            .write_batch(vec![chat_event(
+                Uuid::now_v7(),
+                "What is 2 + 2?",
+                Some("4"),
+            )])
+            .await
+            .expect_err("Writing to a closed pool");
+        assert!(!sink.is_permanent_error(&err), "{err:?}");
     }
 }
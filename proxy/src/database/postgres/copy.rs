@@ -0,0 +1,190 @@
+//! Encoding rows for `COPY ... FROM STDIN (FORMAT binary)`
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+const SIGNATURE: &[u8] = b"PGCOPY\n\xff\r\n\0";
+/// The OID of the `text` type, used in array headers
+const TEXT_OID: i32 = 25;
+/// The version byte that precedes `jsonb` values
+const JSONB_VERSION: u8 = 1;
+/// PostgreSQL timestamps count microseconds from 2000-01-01 instead of the Unix epoch.
+const POSTGRES_EPOCH_OFFSET_MICROS: i64 = 946_684_800_000_000;
+
+/// Builds the data for a binary COPY. Each row must write exactly the columns listed in the
+/// COPY statement, in the same order.
+pub(super) struct CopyEncoder {
+    buf: Vec<u8>,
+}
+
+impl CopyEncoder {
+    pub fn new() -> Self {
+        let mut buf = Vec::with_capacity(4096);
+        buf.extend_from_slice(SIGNATURE);
+        // Flags
+        buf.extend_from_slice(&0i32.to_be_bytes());
+        // Header extension length
+        buf.extend_from_slice(&0i32.to_be_bytes());
+        Self { buf }
+    }
+
+    /// Start a new row with `columns` fields
+    pub fn row(&mut self, columns: i16) -> &mut Self {
+        self.buf.extend_from_slice(&columns.to_be_bytes());
+        self
+    }
+
+    fn null(&mut self) -> &mut Self {
+        self.buf.extend_from_slice(&(-1i32).to_be_bytes());
+        self
+    }
+
+    fn field(&mut self, data: &[u8]) -> &mut Self {
+        self.buf
+            .extend_from_slice(&(data.len() as i32).to_be_bytes());
+        self.buf.extend_from_slice(data);
+        self
+    }
+
+    /// Write a field whose contents are appended to the buffer by `f`
+    fn write_field<E>(
+        &mut self,
+        f: impl FnOnce(&mut Vec<u8>) -> Result<(), E>,
+    ) -> Result<&mut Self, E> {
+        let len_pos = self.buf.len();
+        self.buf.extend_from_slice(&0i32.to_be_bytes());
+        f(&mut self.buf)?;
+        let len = (self.buf.len() - len_pos - 4) as i32;
+        self.buf[len_pos..len_pos + 4].copy_from_slice(&len.to_be_bytes());
+        Ok(self)
+    }
+
+    pub fn uuid(&mut self, value: Option<Uuid>) -> &mut Self {
+        match value {
+            Some(value) => self.field(value.as_bytes()),
+            None => self.null(),
+        }
+    }
+
+    pub fn text(&mut self, value: Option<&str>) -> &mut Self {
+        match value {
+            Some(value) => self.field(value.as_bytes()),
+            None => self.null(),
+        }
+    }
+
+    pub fn int4(&mut self, value: Option<i32>) -> &mut Self {
+        match value {
+            Some(value) => self.field(&value.to_be_bytes()),
+            None => self.null(),
+        }
+    }
+
+    pub fn bool(&mut self, value: Option<bool>) -> &mut Self {
+        match value {
+            Some(value) => self.field(&[value as u8]),
+            None => self.null(),
+        }
+    }
+
+    pub fn timestamptz(&mut self, value: DateTime<Utc>) -> &mut Self {
+        let micros = value.timestamp_micros() - POSTGRES_EPOCH_OFFSET_MICROS;
+        self.field(&micros.to_be_bytes())
+    }
+
+    /// Write a value as `jsonb`. Like binding [sqlx::types::Json], `None` is written as a JSON
+    /// `null` rather than an SQL `NULL`.
+    pub fn jsonb(&mut self, value: &impl Serialize) -> Result<&mut Self, serde_json::Error> {
+        self.write_field(|buf| {
+            buf.push(JSONB_VERSION);
+            serde_json::to_writer(buf, value)
+        })
+    }
+
+    /// Write a `jsonb` value, or an SQL `NULL` if there is no value.
+    pub fn nullable_jsonb(
+        &mut self,
+        value: Option<&serde_json::Value>,
+    ) -> Result<&mut Self, serde_json::Error> {
+        match value {
+            Some(value) => self.jsonb(value),
+            None => Ok(self.null()),
+        }
+    }
+
+    /// Write a one-dimensional `text[]`
+    pub fn text_array(&mut self, value: Option<&[String]>) -> &mut Self {
+        let Some(value) = value else {
+            return self.null();
+        };
+
+        let len = 20 + value.iter().map(|item| 4 + item.len()).sum::<usize>();
+        self.buf.extend_from_slice(&(len as i32).to_be_bytes());
+        // Dimensions, whether there are nulls, and the element type
+        self.buf.extend_from_slice(&1i32.to_be_bytes());
+        self.buf.extend_from_slice(&0i32.to_be_bytes());
+        self.buf.extend_from_slice(&TEXT_OID.to_be_bytes());
+        // The length and lower bound of the dimension
+        self.buf
+            .extend_from_slice(&(value.len() as i32).to_be_bytes());
+        self.buf.extend_from_slice(&1i32.to_be_bytes());
+        for item in value {
+            self.field(item.as_bytes());
+        }
+        self
+    }
+
+    /// Add the trailer and return the data to send
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buf.extend_from_slice(&(-1i16).to_be_bytes());
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn encode_row() {
+        let mut encoder = CopyEncoder::new();
+        encoder
+            .row(4)
+            .int4(Some(7))
+            .text(None)
+            .timestamptz(Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 1).unwrap())
+            .text_array(Some(&["a".to_string()]));
+        let data = encoder.finish();
+
+        let mut expected = SIGNATURE.to_vec();
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[0, 4]);
+        expected.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 7]);
+        expected.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        expected.extend_from_slice(&[0, 0, 0, 8]);
+        expected.extend_from_slice(&1_000_000i64.to_be_bytes());
+        expected.extend_from_slice(&[0, 0, 0, 25]);
+        expected.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 25]);
+        expected.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1]);
+        expected.extend_from_slice(&[0, 0, 0, 1, b'a']);
+        expected.extend_from_slice(&[0xff, 0xff]);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn encode_jsonb() {
+        let mut encoder = CopyEncoder::new();
+        encoder
+            .jsonb(&Option::<serde_json::Value>::None)
+            .unwrap()
+            .nullable_jsonb(None)
+            .unwrap();
+        let data = encoder.finish();
+        assert_eq!(
+            &data[19..],
+            &[0, 0, 0, 5, 1, b'n', b'u', b'l', b'l', 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+}
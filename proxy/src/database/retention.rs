@@ -0,0 +1,395 @@
+//! Deleting old data from the database
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use error_stack::{Report, ResultExt};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationSeconds};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use super::{tables::TableNames, Database, ProxyDatabase};
+use crate::Error;
+
+/// The default number of rows deleted by each query
+pub const DEFAULT_PURGE_BATCH_SIZE: usize = 1000;
+/// The default time between runs of the background purge job
+pub const DEFAULT_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long to keep data in the database
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RetentionConfig {
+    /// Delete events and runs older than this many days, unless a rule matches them. If omitted,
+    /// data that doesn't match a rule is kept forever.
+    pub max_age_days: Option<u32>,
+    /// Retention rules for specific applications and environments. When more than one rule
+    /// matches, a rule with both an application and an environment takes precedence over a
+    /// rule with just one of them.
+    #[serde(default)]
+    pub rules: Vec<RetentionRule>,
+    /// How many rows to delete in each query. Smaller batches hold locks for less time.
+    /// Defaults to 1000.
+    pub batch_size: Option<usize>,
+    /// How often the background job runs, in seconds. Defaults to one hour.
+    #[serde_as(as = "Option<DurationSeconds>")]
+    pub interval: Option<Duration>,
+    /// Set to false to disable the background job, so that data is only purged by running
+    /// `chronicle purge`.
+    pub background: Option<bool>,
+    /// Before deleting rows, write them as lines of JSON to a file in this directory.
+    pub archive_dir: Option<PathBuf>,
+}
+
+/// A retention policy for an application or environment
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RetentionRule {
+    /// Only apply to data from this application
+    pub application: Option<String>,
+    /// Only apply to data from this environment
+    pub environment: Option<String>,
+    /// Delete data older than this many days. If omitted, matching data is kept forever.
+    pub max_age_days: Option<u32>,
+}
+
+fn cutoff(now: DateTime<Utc>, max_age_days: Option<u32>) -> Option<DateTime<Utc>> {
+    max_age_days.map(|days| now - chrono::Duration::days(days as i64))
+}
+
+impl RetentionConfig {
+    /// Calculate the time before which data should be deleted, relative to `now`
+    pub fn cutoffs(&self, now: DateTime<Utc>) -> RetentionCutoffs {
+        let mut rules = self
+            .rules
+            .iter()
+            .map(|rule| RuleCutoff {
+                application: rule.application.clone(),
+                environment: rule.environment.clone(),
+                cutoff: cutoff(now, rule.max_age_days),
+            })
+            .collect::<Vec<_>>();
+        // The database checks the rules in order, so put the most specific ones first.
+        rules.sort_by_key(|rule| {
+            rule.application.is_none() as u8 + rule.environment.is_none() as u8
+        });
+
+        RetentionCutoffs {
+            rules,
+            default: cutoff(now, self.max_age_days),
+        }
+    }
+}
+
+/// The cutoff time for data matching a [RetentionRule]
+#[derive(Debug, Clone)]
+pub struct RuleCutoff {
+    /// Only apply to data from this application
+    pub application: Option<String>,
+    /// Only apply to data from this environment
+    pub environment: Option<String>,
+    /// Data older than this should be deleted. If `None`, the data is kept forever.
+    pub cutoff: Option<DateTime<Utc>>,
+}
+
+/// The times before which data should be deleted, calculated from a [RetentionConfig]
+#[derive(Debug, Clone)]
+pub struct RetentionCutoffs {
+    /// The cutoffs for each rule, with the most specific rules first
+    pub rules: Vec<RuleCutoff>,
+    /// The cutoff for data that doesn't match any rule
+    pub default: Option<DateTime<Utc>>,
+}
+
+impl RetentionCutoffs {
+    /// The latest cutoff time. No data newer than this will be deleted, and if this is `None`,
+    /// no data will be deleted at all.
+    pub fn latest(&self) -> Option<DateTime<Utc>> {
+        self.rules
+            .iter()
+            .map(|rule| rule.cutoff)
+            .chain(std::iter::once(self.default))
+            .flatten()
+            .max()
+    }
+
+    /// The earliest cutoff time, if every row has one. All data older than this should be
+    /// deleted, so this is `None` if any data is kept forever.
+    pub fn earliest(&self) -> Option<DateTime<Utc>> {
+        let cutoffs = self
+            .rules
+            .iter()
+            .map(|rule| rule.cutoff)
+            .chain(std::iter::once(self.default))
+            .collect::<Option<Vec<_>>>()?;
+        cutoffs.into_iter().min()
+    }
+}
+
+/// A table that can be purged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PurgeTable {
+    /// The `chronicle_events` table, purged by `created_at`
+    Events,
+    /// The `chronicle_runs` table, purged by `updated_at`. The steps of a run are deleted along
+    /// with it.
+    Runs,
+}
+
+impl PurgeTable {
+    /// The name of the table with the default prefix, which labels the table's rows in archives
+    pub fn table_name(&self) -> &'static str {
+        match self {
+            PurgeTable::Events => "chronicle_events",
+            PurgeTable::Runs => "chronicle_runs",
+        }
+    }
+
+    /// The name of the table in the database
+    pub fn table_in(&self, tables: &TableNames) -> String {
+        match self {
+            PurgeTable::Events => tables.events(),
+            PurgeTable::Runs => tables.runs(),
+        }
+    }
+
+    /// The column compared against the retention cutoff
+    pub fn time_column(&self) -> &'static str {
+        match self {
+            PurgeTable::Events => "created_at",
+            PurgeTable::Runs => "updated_at",
+        }
+    }
+}
+
+/// A row loaded from the database so that it can be archived
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchivedRow {
+    /// The table that the row came from
+    pub table: String,
+    /// The row's columns
+    pub row: serde_json::Value,
+}
+
+/// The number of rows purged from each table
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PurgeCounts {
+    pub events: u64,
+    pub runs: u64,
+    pub steps: u64,
+    /// Partitions of the events table that were dropped. The events in these partitions are not
+    /// included in `events`.
+    pub partitions: u64,
+}
+
+impl std::ops::AddAssign for PurgeCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.events += other.events;
+        self.runs += other.runs;
+        self.steps += other.steps;
+        self.partitions += other.partitions;
+    }
+}
+
+/// Writes purged rows to a JSON lines file. The file is only created once there is something to
+/// write.
+struct Archive {
+    path: PathBuf,
+    file: Option<tokio::fs::File>,
+}
+
+impl Archive {
+    fn new(dir: &Path, now: DateTime<Utc>) -> Self {
+        Self {
+            path: dir.join(format!(
+                "chronicle-archive-{}.jsonl",
+                now.format("%Y%m%dT%H%M%SZ")
+            )),
+            file: None,
+        }
+    }
+
+    async fn write(&mut self, rows: &[ArchivedRow]) -> Result<(), Report<Error>> {
+        let mut buf = Vec::new();
+        for row in rows {
+            serde_json::to_writer(&mut buf, row).change_context(Error::Purge)?;
+            buf.push(b'\n');
+        }
+
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => {
+                if let Some(dir) = self.path.parent() {
+                    tokio::fs::create_dir_all(dir)
+                        .await
+                        .change_context(Error::Purge)?;
+                }
+
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .await
+                    .change_context(Error::Purge)
+                    .attach_printable_lazy(|| format!("Failed to open {}", self.path.display()))?
+            }
+        };
+
+        file.write_all(&buf).await.change_context(Error::Purge)?;
+        // Make sure the rows are on disk before they are deleted from the database.
+        file.sync_data().await.change_context(Error::Purge)?;
+        self.file = Some(file);
+        Ok(())
+    }
+}
+
+/// Delete data older than the retention policy allows, relative to `now`. If `dry_run` is true,
+/// nothing is deleted, and the returned counts are the number of rows that would be deleted.
+///
+/// When the events table is partitioned and the policy doesn't keep any data forever, partitions
+/// older than every cutoff are dropped whole instead of deleting their rows. This is skipped when
+/// archiving, so that every row is written to the archive.
+pub async fn purge(
+    db: &dyn ProxyDatabase,
+    config: &RetentionConfig,
+    now: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<PurgeCounts, Report<Error>> {
+    let cutoffs = config.cutoffs(now);
+    if cutoffs.latest().is_none() {
+        return Ok(PurgeCounts::default());
+    }
+
+    if dry_run {
+        return db.count_expired(&cutoffs).await;
+    }
+
+    let batch_size = config.batch_size.unwrap_or(DEFAULT_PURGE_BATCH_SIZE).max(1);
+    let mut archive = config
+        .archive_dir
+        .as_deref()
+        .map(|dir| Archive::new(dir, now));
+    let mut total = PurgeCounts::default();
+
+    if archive.is_none() {
+        if let Some(earliest) = cutoffs.earliest() {
+            total.partitions = db.drop_expired_partitions(earliest).await?;
+        }
+    }
+
+    for table in [PurgeTable::Events, PurgeTable::Runs] {
+        loop {
+            let ids = db.find_expired(table, &cutoffs, batch_size).await?;
+            if ids.is_empty() {
+                break;
+            }
+
+            if let Some(archive) = &mut archive {
+                let rows = db.load_rows_for_archive(table, &ids).await?;
+                archive.write(&rows).await?;
+            }
+
+            total += db.delete_rows(table, &ids).await?;
+
+            if ids.len() < batch_size {
+                break;
+            }
+        }
+    }
+
+    Ok(total)
+}
+
+/// Start a task that periodically purges old data.
+pub fn start_retention_task(db: Database, config: RetentionConfig) -> tokio::task::JoinHandle<()> {
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval.unwrap_or(DEFAULT_PURGE_INTERVAL));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            match purge(db.as_ref(), &config, Utc::now(), false).await {
+                Ok(counts) => {
+                    if counts != PurgeCounts::default() {
+                        tracing::info!(
+                            events = counts.events,
+                            runs = counts.runs,
+                            steps = counts.steps,
+                            "Purged old data"
+                        );
+                    }
+                }
+                Err(e) => tracing::error!(error = ?e, "Failed to purge old data"),
+            }
+        }
+    })
+}
+
+/// Convert a list of IDs returned from the database into UUIDs
+pub(super) fn parse_ids(ids: Vec<String>) -> Result<Vec<Uuid>, Report<Error>> {
+    ids.iter()
+        .map(|id| Uuid::parse_str(id))
+        .collect::<Result<Vec<_>, _>>()
+        .change_context(Error::Purge)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn cutoffs() {
+        let now = Utc.timestamp_opt(100 * 86400, 0).unwrap();
+        let config = RetentionConfig {
+            max_age_days: Some(30),
+            rules: vec![
+                RetentionRule {
+                    environment: Some("dev".to_string()),
+                    max_age_days: Some(1),
+                    ..Default::default()
+                },
+                RetentionRule {
+                    application: Some("audit".to_string()),
+                    environment: Some("prod".to_string()),
+                    max_age_days: None,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let cutoffs = config.cutoffs(now);
+        assert_eq!(cutoffs.rules[0].application.as_deref(), Some("audit"));
+        assert_eq!(cutoffs.rules[0].cutoff, None);
+        assert_eq!(cutoffs.rules[1].environment.as_deref(), Some("dev"));
+        assert_eq!(
+            cutoffs.rules[1].cutoff,
+            Some(now - chrono::Duration::days(1))
+        );
+        assert_eq!(cutoffs.default, Some(now - chrono::Duration::days(30)));
+        assert_eq!(cutoffs.latest(), Some(now - chrono::Duration::days(1)));
+
+        assert_eq!(cutoffs.earliest(), None, "one rule keeps data forever");
+
+        let keep_everything = RetentionConfig::default().cutoffs(now);
+        assert_eq!(keep_everything.latest(), None);
+        assert_eq!(keep_everything.earliest(), None);
+
+        let all_expire = RetentionConfig {
+            max_age_days: Some(30),
+            rules: vec![RetentionRule {
+                environment: Some("dev".to_string()),
+                max_age_days: Some(1),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            all_expire.cutoffs(now).earliest(),
+            Some(now - chrono::Duration::days(30))
+        );
+    }
+}
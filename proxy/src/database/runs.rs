@@ -0,0 +1,286 @@
+//! Reading runs and steps back out of the database
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::usage::LlmUsage;
+
+pub(super) const RUN_COLUMNS: &str = "id, name, description, application, environment, input,
+    output, status, trace_id, span_id, tags, info, updated_at, created_at,
+    llm_calls, prompt_tokens, completion_tokens, llm_errors, llm_latency_ms";
+
+pub(super) const STEP_COLUMNS: &str = "id, run_id, type, parent_step, name, input, output, status,
+    tags, info, span_id, start_time, end_time,
+    llm_calls, prompt_tokens, completion_tokens, llm_errors, llm_latency_ms";
+
+pub(super) const EVENT_COLUMNS: &str = "id, event_type, step_id, provider, model, chat_request,
+    chat_response, error, meta, retries, rate_limited, request_latency_ms, total_latency_ms,
+    replay_of, created_at";
+
+/// The default number of runs returned by [ProxyDatabase::list_runs](super::ProxyDatabase::list_runs)
+pub const DEFAULT_RUN_LIST_LIMIT: u32 = 50;
+
+/// Filters for listing runs
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RunQuery {
+    /// Only return runs with this name
+    pub name: Option<String>,
+    /// Only return runs from this application
+    pub application: Option<String>,
+    /// Only return runs from this environment
+    pub environment: Option<String>,
+    /// Only return runs with this status
+    pub status: Option<String>,
+    /// Only return runs which have all of these tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Only return runs created at or after this time
+    pub start_time: Option<DateTime<Utc>>,
+    /// Only return runs created before this time
+    pub end_time: Option<DateTime<Utc>>,
+    /// The maximum number of runs to return. Defaults to [DEFAULT_RUN_LIST_LIMIT].
+    pub limit: Option<u32>,
+    /// Skip this many runs, for pagination
+    pub offset: Option<u32>,
+}
+
+/// A run, without its steps
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub id: Uuid,
+    /// The name of the run. This is empty when an update for the run was recorded before its
+    /// start event.
+    pub name: String,
+    pub description: Option<String>,
+    pub application: Option<String>,
+    pub environment: Option<String>,
+    pub input: Option<serde_json::Value>,
+    pub output: Option<serde_json::Value>,
+    pub status: String,
+    pub trace_id: Option<String>,
+    pub span_id: Option<String>,
+    pub tags: Vec<String>,
+    pub info: Option<serde_json::Value>,
+    pub updated_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    /// The LLM calls made in the run
+    pub usage: LlmUsage,
+}
+
+/// A logged event that belongs to a run, usually an LLM call.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunEvent {
+    pub id: Uuid,
+    pub event_type: Option<String>,
+    pub step_id: Option<Uuid>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub chat_request: Option<serde_json::Value>,
+    pub chat_response: Option<serde_json::Value>,
+    pub error: Option<serde_json::Value>,
+    pub meta: Option<serde_json::Value>,
+    pub retries: Option<i32>,
+    pub rate_limited: Option<bool>,
+    pub request_latency_ms: Option<i64>,
+    pub total_latency_ms: Option<i64>,
+    /// The event that this event replays, if it was sent by [Proxy::replay](crate::Proxy::replay)
+    pub replay_of: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A step in a run, along with its events and child steps
+#[derive(Debug, Clone, Serialize)]
+pub struct StepDetail {
+    pub id: Uuid,
+    pub run_id: Uuid,
+    /// The type of the step. This is empty when another event for the step was recorded before
+    /// its start event.
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub parent_step: Option<Uuid>,
+    pub name: Option<String>,
+    pub input: Option<serde_json::Value>,
+    pub output: Option<serde_json::Value>,
+    pub status: String,
+    pub tags: Vec<String>,
+    pub info: Option<serde_json::Value>,
+    pub span_id: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: Option<DateTime<Utc>>,
+    /// The time between `start_time` and `end_time`, if the step has ended.
+    pub duration_ms: Option<i64>,
+    /// The LLM calls made in this step and its child steps
+    pub usage: LlmUsage,
+    /// Events logged with this step's ID
+    pub events: Vec<RunEvent>,
+    /// Steps whose `parent_step` is this step
+    pub steps: Vec<StepDetail>,
+}
+
+/// A run with its steps arranged into a tree
+#[derive(Debug, Clone, Serialize)]
+pub struct RunDetail {
+    #[serde(flatten)]
+    pub run: RunSummary,
+    /// The time between the creation of the run and its latest update
+    pub duration_ms: i64,
+    /// The top-level steps of the run. Steps with a `parent_step` that does not exist in the run
+    /// are also placed here.
+    pub steps: Vec<StepDetail>,
+    /// Events which belong to the run but not to any known step
+    pub events: Vec<RunEvent>,
+}
+
+impl RunDetail {
+    /// Assemble a [RunDetail] from a flat list of steps and events, nesting the steps by
+    /// `parent_step` and attaching each event to its step.
+    pub fn from_parts(run: RunSummary, steps: Vec<StepDetail>, events: Vec<RunEvent>) -> Self {
+        let step_ids = steps.iter().map(|s| s.id).collect::<ahash::AHashSet<_>>();
+
+        let mut events_by_step: HashMap<Option<Uuid>, Vec<RunEvent>> = HashMap::new();
+        for event in events {
+            let key = event.step_id.filter(|id| step_ids.contains(id));
+            events_by_step.entry(key).or_default().push(event);
+        }
+
+        let mut children: HashMap<Option<Uuid>, Vec<StepDetail>> = HashMap::new();
+        for mut step in steps {
+            step.duration_ms = step
+                .end_time
+                .map(|end| (end - step.start_time).num_milliseconds());
+            step.events = events_by_step.remove(&Some(step.id)).unwrap_or_default();
+
+            let parent = step.parent_step.filter(|id| step_ids.contains(id));
+            children.entry(parent).or_default().push(step);
+        }
+
+        fn attach_children(
+            mut steps: Vec<StepDetail>,
+            children: &mut HashMap<Option<Uuid>, Vec<StepDetail>>,
+        ) -> Vec<StepDetail> {
+            steps.sort_by_key(|s| s.start_time);
+            for step in steps.iter_mut() {
+                let step_children = children.remove(&Some(step.id)).unwrap_or_default();
+                step.steps = attach_children(step_children, children);
+            }
+            steps
+        }
+
+        let roots = children.remove(&None).unwrap_or_default();
+        let mut steps = attach_children(roots, &mut children);
+        // Anything left over is part of a parent_step cycle. Rather than drop those steps, just
+        // put them at the top level.
+        steps.extend(children.into_values().flatten());
+
+        let duration_ms = (run.updated_at - run.created_at).num_milliseconds();
+        RunDetail {
+            run,
+            duration_ms,
+            steps,
+            events: events_by_step.remove(&None).unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::{RunDetail, RunEvent, RunSummary, StepDetail};
+
+    fn step(id: u128, parent: Option<u128>, start: i64, end: Option<i64>) -> StepDetail {
+        StepDetail {
+            id: Uuid::from_u128(id),
+            run_id: Uuid::from_u128(100),
+            typ: "step".to_string(),
+            parent_step: parent.map(Uuid::from_u128),
+            name: None,
+            input: None,
+            output: None,
+            status: "started".to_string(),
+            tags: vec![],
+            info: None,
+            span_id: None,
+            start_time: Utc.timestamp_opt(start, 0).unwrap(),
+            end_time: end.map(|e| Utc.timestamp_opt(e, 0).unwrap()),
+            duration_ms: None,
+            usage: Default::default(),
+            events: vec![],
+            steps: vec![],
+        }
+    }
+
+    fn event(id: u128, step: Option<u128>) -> RunEvent {
+        RunEvent {
+            id: Uuid::from_u128(id),
+            event_type: Some("chronicle_llm_request".to_string()),
+            step_id: step.map(Uuid::from_u128),
+            provider: None,
+            model: None,
+            chat_request: None,
+            chat_response: None,
+            error: None,
+            meta: None,
+            retries: None,
+            rate_limited: None,
+            request_latency_ms: None,
+            total_latency_ms: None,
+            replay_of: None,
+            created_at: Utc.timestamp_opt(1, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn builds_step_tree() {
+        let run = RunSummary {
+            id: Uuid::from_u128(100),
+            name: "run".to_string(),
+            description: None,
+            application: None,
+            environment: None,
+            input: None,
+            output: None,
+            status: "finished".to_string(),
+            trace_id: None,
+            span_id: None,
+            tags: vec![],
+            info: None,
+            updated_at: Utc.timestamp_opt(10, 0).unwrap(),
+            created_at: Utc.timestamp_opt(1, 0).unwrap(),
+            usage: Default::default(),
+        };
+
+        let steps = vec![
+            step(3, Some(1), 3, None),
+            step(1, None, 1, Some(9)),
+            step(2, Some(1), 2, Some(4)),
+            // Parent doesn't exist, so this should end up at the top level.
+            step(4, Some(50), 5, Some(6)),
+        ];
+
+        let events = vec![event(10, Some(2)), event(11, None), event(12, Some(60))];
+
+        let detail = RunDetail::from_parts(run, steps, events);
+
+        assert_eq!(detail.duration_ms, 9000);
+        assert_eq!(detail.steps.len(), 2);
+        assert_eq!(detail.steps[0].id, Uuid::from_u128(1));
+        assert_eq!(detail.steps[0].duration_ms, Some(8000));
+        assert_eq!(detail.steps[1].id, Uuid::from_u128(4));
+
+        let children = &detail.steps[0].steps;
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].id, Uuid::from_u128(2));
+        assert_eq!(children[0].duration_ms, Some(2000));
+        assert_eq!(children[0].events.len(), 1);
+        assert_eq!(children[0].events[0].id, Uuid::from_u128(10));
+        assert_eq!(children[1].id, Uuid::from_u128(3));
+        assert_eq!(children[1].duration_ms, None);
+
+        let run_events = detail.events.iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(run_events, vec![Uuid::from_u128(11), Uuid::from_u128(12)]);
+    }
+}
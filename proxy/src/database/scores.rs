@@ -0,0 +1,192 @@
+//! Reading scores back out of the database
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::workflow_events::{ScoreSource, ScoreValue};
+
+pub(super) const SCORE_COLUMNS: &str =
+    "id, run_id, step_id, event_id, name, value, source, comment, created_at";
+
+/// The run, step, or LLM call whose scores should be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScoreTarget {
+    /// Every score in the run, including the scores for its steps and LLM calls
+    Run(Uuid),
+    /// The scores for a step
+    Step(Uuid),
+    /// The scores for an LLM call
+    Event(Uuid),
+}
+
+impl ScoreTarget {
+    /// The ID of the run, step, or event
+    pub fn id(&self) -> Uuid {
+        match self {
+            ScoreTarget::Run(id) | ScoreTarget::Step(id) | ScoreTarget::Event(id) => *id,
+        }
+    }
+
+    /// The column of the scores table which holds the target's ID
+    pub(super) fn column(&self) -> &'static str {
+        match self {
+            ScoreTarget::Run(_) => "run_id",
+            ScoreTarget::Step(_) => "step_id",
+            ScoreTarget::Event(_) => "event_id",
+        }
+    }
+}
+
+/// A score stored in the database
+#[derive(Debug, Clone, Serialize)]
+pub struct Score {
+    pub id: Uuid,
+    pub run_id: Option<Uuid>,
+    pub step_id: Option<Uuid>,
+    pub event_id: Option<Uuid>,
+    pub name: String,
+    pub value: ScoreValue,
+    pub source: ScoreSource,
+    pub comment: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Statistics for the scores with the same name and source
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScoreSummary {
+    pub name: String,
+    pub source: ScoreSource,
+    /// The number of scores
+    pub count: u64,
+    /// The average of the numeric and boolean scores, with booleans counting as 1 or 0. For
+    /// boolean scores, this is the fraction that were true.
+    pub mean: Option<f64>,
+    /// The lowest numeric or boolean score
+    pub min: Option<f64>,
+    /// The highest numeric or boolean score
+    pub max: Option<f64>,
+    /// How many times each categorical value was given
+    pub categories: BTreeMap<String, u64>,
+}
+
+/// Summarize scores by name and source, sorted by name.
+pub fn summarize_scores(scores: &[Score]) -> Vec<ScoreSummary> {
+    let mut groups: BTreeMap<(&str, ScoreSource), Vec<&Score>> = BTreeMap::new();
+    for score in scores {
+        groups
+            .entry((score.name.as_str(), score.source))
+            .or_default()
+            .push(score);
+    }
+
+    groups
+        .into_iter()
+        .map(|((name, source), scores)| {
+            let numbers = scores
+                .iter()
+                .filter_map(|s| s.value.as_f64())
+                .collect::<Vec<_>>();
+
+            let mut categories = BTreeMap::new();
+            for score in &scores {
+                if let ScoreValue::Categorical(category) = &score.value {
+                    *categories.entry(category.clone()).or_insert(0) += 1;
+                }
+            }
+
+            ScoreSummary {
+                name: name.to_string(),
+                source,
+                count: scores.len() as u64,
+                mean: (!numbers.is_empty())
+                    .then(|| numbers.iter().sum::<f64>() / numbers.len() as f64),
+                min: numbers.iter().copied().reduce(f64::min),
+                max: numbers.iter().copied().reduce(f64::max),
+                categories,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn score(name: &str, value: ScoreValue, source: ScoreSource) -> Score {
+        Score {
+            id: Uuid::now_v7(),
+            run_id: None,
+            step_id: None,
+            event_id: None,
+            name: name.to_string(),
+            value,
+            source,
+            comment: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn summarizes_by_name_and_source() {
+        let scores = vec![
+            score("thumbs_up", ScoreValue::Boolean(true), ScoreSource::Human),
+            score("thumbs_up", ScoreValue::Boolean(false), ScoreSource::Human),
+            score("thumbs_up", ScoreValue::Boolean(true), ScoreSource::Human),
+            score(
+                "thumbs_up",
+                ScoreValue::Boolean(true),
+                ScoreSource::Heuristic,
+            ),
+            score("quality", ScoreValue::Numeric(2.0), ScoreSource::Model),
+            score("quality", ScoreValue::Numeric(4.0), ScoreSource::Model),
+            score(
+                "label",
+                ScoreValue::Categorical("correct".to_string()),
+                ScoreSource::Model,
+            ),
+            score(
+                "label",
+                ScoreValue::Categorical("correct".to_string()),
+                ScoreSource::Model,
+            ),
+            score(
+                "label",
+                ScoreValue::Categorical("off_topic".to_string()),
+                ScoreSource::Model,
+            ),
+        ];
+
+        let summary = summarize_scores(&scores);
+        let names = summary
+            .iter()
+            .map(|s| (s.name.as_str(), s.source))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                ("label", ScoreSource::Model),
+                ("quality", ScoreSource::Model),
+                ("thumbs_up", ScoreSource::Human),
+                ("thumbs_up", ScoreSource::Heuristic),
+            ]
+        );
+
+        assert_eq!(summary[0].count, 3);
+        assert_eq!(summary[0].mean, None);
+        assert_eq!(
+            summary[0].categories,
+            BTreeMap::from([("correct".to_string(), 2), ("off_topic".to_string(), 1)])
+        );
+
+        assert_eq!(summary[1].mean, Some(3.0));
+        assert_eq!(summary[1].min, Some(2.0));
+        assert_eq!(summary[1].max, Some(4.0));
+        assert!(summary[1].categories.is_empty());
+
+        assert_eq!(summary[2].count, 3);
+        assert_eq!(summary[2].mean, Some(2.0 / 3.0));
+        assert_eq!(summary[3].count, 1);
+    }
+}
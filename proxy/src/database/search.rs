@@ -0,0 +1,39 @@
+//! Full-text search over logged requests and responses
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::runs::RunEvent;
+
+/// The default number of events returned by
+/// [ProxyDatabase::search_events](super::ProxyDatabase::search_events)
+pub const DEFAULT_SEARCH_LIMIT: u32 = 50;
+
+/// A full-text search for events
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EventSearchQuery {
+    /// The words to search for. Events must contain all of the words, in the system prompt,
+    /// the message contents of the request, or the choices of the response.
+    pub query: String,
+    /// Only return events from this application
+    pub application: Option<String>,
+    /// Only return events from this environment
+    pub environment: Option<String>,
+    /// Only return events created at or after this time
+    pub start_time: Option<DateTime<Utc>>,
+    /// Only return events created before this time
+    pub end_time: Option<DateTime<Utc>>,
+    /// The maximum number of events to return. Defaults to [DEFAULT_SEARCH_LIMIT].
+    pub limit: Option<u32>,
+    /// Skip this many events, for pagination
+    pub offset: Option<u32>,
+}
+
+/// An event that matched a search, with the best matches first
+#[derive(Debug, Clone, Serialize)]
+pub struct EventSearchResult {
+    /// The run that the event belongs to, if any
+    pub run_id: Option<Uuid>,
+    #[serde(flatten)]
+    pub event: RunEvent,
+}
@@ -0,0 +1,352 @@
+//! Built-in log sinks that write events somewhere other than the database
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use error_stack::{Report, ResultExt};
+use reqwest::header::HeaderMap;
+use tokio::io::AsyncWriteExt;
+
+use super::logging::{LogSink, ProxyLogEntry};
+use crate::Error;
+
+/// Serialize each entry as a line of JSON
+fn to_json_lines(items: &[ProxyLogEntry]) -> Result<Vec<u8>, Report<Error>> {
+    let mut buf = Vec::new();
+    for item in items {
+        serde_json::to_writer(&mut buf, item).change_context(Error::LogSink)?;
+        buf.push(b'\n');
+    }
+
+    Ok(buf)
+}
+
+/// Writes each log entry as a line of JSON to stdout
+#[derive(Debug, Default)]
+pub struct StdoutSink;
+
+#[async_trait::async_trait]
+impl LogSink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    async fn write_batch(&self, items: Vec<ProxyLogEntry>) -> Result<(), Report<Error>> {
+        let buf = to_json_lines(&items)?;
+        let mut stdout = std::io::stdout().lock();
+        stdout
+            .write_all(&buf)
+            .and_then(|_| stdout.flush())
+            .change_context(Error::LogSink)
+    }
+}
+
+#[derive(Debug)]
+struct OpenFile {
+    file: tokio::fs::File,
+    size: u64,
+}
+
+/// Writes log entries as lines of JSON to a file. When the file grows past `max_bytes`, it is
+/// renamed to `<path>.1`, any existing `<path>.1` is renamed to `<path>.2`, and so on, keeping
+/// at most `max_files` old files.
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: tokio::sync::Mutex<Option<OpenFile>>,
+}
+
+impl FileSink {
+    /// The default size at which the file is rotated
+    pub const DEFAULT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+    /// The default number of rotated files to keep
+    pub const DEFAULT_MAX_FILES: usize = 5;
+
+    /// Create a new file sink
+    pub fn new(path: impl Into<PathBuf>, max_bytes: u64, max_files: usize) -> Self {
+        Self {
+            path: path.into(),
+            max_bytes,
+            max_files,
+            file: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    async fn open(path: &Path) -> Result<OpenFile, Report<Error>> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .change_context(Error::LogSink)
+            .attach_printable_lazy(|| format!("Failed to open {}", path.display()))?;
+        let size = file.metadata().await.change_context(Error::LogSink)?.len();
+
+        Ok(OpenFile { file, size })
+    }
+
+    async fn rotate(&self) -> Result<(), Report<Error>> {
+        if self.max_files == 0 {
+            return remove_if_exists(&self.path).await;
+        }
+
+        remove_if_exists(&self.rotated_path(self.max_files)).await?;
+        for index in (1..self.max_files).rev() {
+            rename_if_exists(&self.rotated_path(index), &self.rotated_path(index + 1)).await?;
+        }
+        rename_if_exists(&self.path, &self.rotated_path(1)).await
+    }
+}
+
+async fn remove_if_exists(path: &Path) -> Result<(), Report<Error>> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)
+            .change_context(Error::LogSink)
+            .attach_printable_lazy(|| format!("Failed to remove {}", path.display())),
+        _ => Ok(()),
+    }
+}
+
+async fn rename_if_exists(from: &Path, to: &Path) -> Result<(), Report<Error>> {
+    match tokio::fs::rename(from, to).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)
+            .change_context(Error::LogSink)
+            .attach_printable_lazy(|| format!("Failed to rename {}", from.display())),
+        _ => Ok(()),
+    }
+}
+
+#[async_trait::async_trait]
+impl LogSink for FileSink {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn write_batch(&self, items: Vec<ProxyLogEntry>) -> Result<(), Report<Error>> {
+        let buf = to_json_lines(&items)?;
+        let mut current = self.file.lock().await;
+
+        let mut file = match current.take() {
+            Some(file) => file,
+            None => Self::open(&self.path).await?,
+        };
+
+        if file.size > 0 && file.size + buf.len() as u64 > self.max_bytes {
+            drop(file);
+            self.rotate().await?;
+            file = Self::open(&self.path).await?;
+        }
+
+        file.file
+            .write_all(&buf)
+            .await
+            .change_context(Error::LogSink)?;
+        file.file.flush().await.change_context(Error::LogSink)?;
+        file.size += buf.len() as u64;
+
+        *current = Some(file);
+        Ok(())
+    }
+}
+
+/// Sends each batch of log entries to a URL as a JSON array
+#[derive(Debug)]
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    headers: HeaderMap,
+}
+
+impl WebhookSink {
+    /// Create a new webhook sink. `headers` are sent with every request.
+    pub fn new(client: reqwest::Client, url: String, headers: HeaderMap) -> Self {
+        Self {
+            client,
+            url,
+            headers,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LogSink for WebhookSink {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn write_batch(&self, items: Vec<ProxyLogEntry>) -> Result<(), Report<Error>> {
+        self.client
+            .post(&self.url)
+            .headers(self.headers.clone())
+            .json(&items)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .change_context(Error::LogSink)
+            .attach_printable_lazy(|| format!("Failed to send logs to {}", self.url))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use error_stack::Report;
+    use smallvec::smallvec;
+    use uuid::Uuid;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    use super::{FileSink, WebhookSink};
+    use crate::{
+        database::logging::{
+            start_logger, LogDestination, LogQueueOptions, LogSink, ProxyLogEntry,
+        },
+        workflow_events::{StepEventData, StepStateData, WorkflowEvent},
+        Error,
+    };
+
+    fn entry(state: &str) -> ProxyLogEntry {
+        ProxyLogEntry::Workflow(WorkflowEvent::StepState(StepEventData {
+            run_id: Uuid::nil(),
+            step_id: Uuid::nil(),
+            data: StepStateData {
+                state: state.to_string(),
+            },
+            time: None,
+            event_id: None,
+        }))
+    }
+
+    #[derive(Debug, Default)]
+    struct CollectingSink(Mutex<Vec<Vec<ProxyLogEntry>>>);
+
+    #[async_trait::async_trait]
+    impl LogSink for CollectingSink {
+        fn name(&self) -> &str {
+            "collecting"
+        }
+
+        async fn write_batch(&self, items: Vec<ProxyLogEntry>) -> Result<(), Report<Error>> {
+            self.0.lock().unwrap().push(items);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn multiple_sinks() {
+        let first = Arc::new(CollectingSink::default());
+        let second = Arc::new(CollectingSink::default());
+
+        let (tx, task) = start_logger(
+            vec![
+                LogDestination {
+                    sink: first.clone(),
+                    batch_size: 2,
+                    debounce_time: Duration::from_secs(60),
+                },
+                LogDestination {
+                    sink: second.clone(),
+                    batch_size: 10,
+                    debounce_time: Duration::from_secs(60),
+                },
+            ],
+            LogQueueOptions::default(),
+            None,
+            None,
+        );
+
+        for state in ["a", "b", "c"] {
+            tx.send(smallvec![entry(state)]).await;
+        }
+
+        // Closing the channel flushes the partial batches.
+        drop(tx);
+        task.await.unwrap();
+
+        let batch_sizes = |sink: &CollectingSink| {
+            sink.0
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|b| b.len())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(batch_sizes(&first), vec![2, 1]);
+        assert_eq!(batch_sizes(&second), vec![3]);
+    }
+
+    #[tokio::test]
+    async fn file_rotation() {
+        let dir = std::env::temp_dir().join(format!("chronicle-file-sink-{}", Uuid::now_v7()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("events.jsonl");
+
+        let line_len = serde_json::to_string(&entry("a")).unwrap().len() as u64 + 1;
+        // Fit two entries in each file, and keep two old files
+        let sink = FileSink::new(&path, line_len * 2, 2);
+        for state in ["a", "b", "c", "d", "e", "f", "g"] {
+            sink.write_batch(vec![entry(state)]).await.unwrap();
+        }
+
+        let read_states = |path: std::path::PathBuf| {
+            std::fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                    value["data"]["state"].as_str().unwrap().to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(read_states(path.clone()), vec!["g"]);
+        assert_eq!(read_states(sink.rotated_path(1)), vec!["e", "f"]);
+        assert_eq!(read_states(sink.rotated_path(2)), vec!["c", "d"]);
+        assert!(!sink.rotated_path(3).exists());
+
+        tokio::fs::remove_dir_all(&dir).await.ok();
+    }
+
+    #[tokio::test]
+    async fn webhook() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/events"))
+            .and(matchers::header("x-api-key", "the-key"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-api-key", "the-key".parse().unwrap());
+        let sink = WebhookSink::new(
+            reqwest::Client::new(),
+            format!("{}/events", server.uri()),
+            headers,
+        );
+
+        sink.write_batch(vec![entry("a"), entry("b")])
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body[0]["type"], "step:state");
+        assert_eq!(body[1]["data"]["state"], "b");
+    }
+}
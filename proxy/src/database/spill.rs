@@ -0,0 +1,952 @@
+//! Retrying failed log writes, and spilling them to disk while the destination is unavailable
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use error_stack::{Report, ResultExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use uuid::Uuid;
+
+use super::logging::{CollectedProxiedResult, LogSink, ProxyLogEntry, ProxyLogEvent};
+use crate::{
+    format::{ChatRequest, ResponseInfo, SingleChatResponse},
+    metrics::ProxyMetrics,
+    request::{BackoffValue, RetryOptions},
+    workflow_events::WorkflowEvent,
+    Error, ProxyRequestInternalMetadata, ProxyRequestMetadata, ProxyRequestOptions,
+};
+
+/// How many spilled entries to write in each batch when replaying the spill file
+const REPLAY_BATCH_SIZE: usize = 500;
+
+/// Once replaying the spill file has failed this many times in a row, the batch that fails is
+/// written one entry at a time, so that an entry the destination won't accept can't hold up the
+/// entries after it.
+const MAX_REPLAY_FAILURES: u32 = 5;
+
+/// Where to write log entries that could not be written to their destination
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpillOptions {
+    /// The file to write entries to
+    pub path: PathBuf,
+    /// Stop spilling once the file would grow larger than this. Any more failed entries are
+    /// dropped. Defaults to 1GiB.
+    #[serde(default = "default_spill_max_bytes")]
+    pub max_bytes: u64,
+}
+
+const fn default_spill_max_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+
+/// Wraps a [LogSink], retrying failed writes with backoff. Once the retries are exhausted, the
+/// batch is appended to a spill file. Before each new batch is written, the spill file is
+/// replayed, so that entries reach the destination in the order they were logged. The file is
+/// also replayed when the logger starts, so entries spilled before a restart are not lost.
+///
+/// If the spill file can't be replayed, the new batch is spilled right away instead of being
+/// retried, since the destination is probably still down.
+///
+/// Errors which [LogSink::is_permanent_error] says won't go away are not retried. Instead the
+/// batch is written one entry at a time, and the entries that the destination rejects are
+/// dropped.
+#[derive(Debug)]
+pub struct RetryingSink {
+    inner: Arc<dyn LogSink>,
+    retry: RetryOptions,
+    spill: Option<SpillOptions>,
+    metrics: Option<Arc<ProxyMetrics>>,
+    spill_state: tokio::sync::Mutex<SpillState>,
+}
+
+#[derive(Debug)]
+struct SpillState {
+    /// The current size of the spill file
+    size: u64,
+    /// How many times in a row replaying the spill file has failed
+    failed_replays: u32,
+}
+
+impl RetryingSink {
+    /// Wrap `inner` with retries. If `spill` is `None`, batches which still fail after retrying
+    /// are dropped.
+    pub fn new(
+        inner: Arc<dyn LogSink>,
+        retry: RetryOptions,
+        spill: Option<SpillOptions>,
+        metrics: Option<Arc<ProxyMetrics>>,
+    ) -> Self {
+        let spill_size = spill
+            .as_ref()
+            .and_then(|spill| std::fs::metadata(&spill.path).ok())
+            .map(|m| m.len())
+            .unwrap_or(0);
+
+        Self {
+            inner,
+            retry,
+            spill,
+            metrics,
+            spill_state: tokio::sync::Mutex::new(SpillState {
+                size: spill_size,
+                failed_replays: 0,
+            }),
+        }
+    }
+
+    fn record_dropped(&self, reason: &str, count: usize) {
+        tracing::error!(
+            sink = self.inner.name(),
+            reason,
+            count,
+            "Dropped log events"
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics.record_dropped_log_events(reason, count);
+        }
+    }
+
+    async fn write_with_retry(
+        &self,
+        items: &[ProxyLogEntry],
+        max_tries: u32,
+    ) -> Result<(), Report<Error>> {
+        let mut backoff = BackoffValue::new(&self.retry);
+        let mut current_try = 1;
+        loop {
+            let result = self.inner.write_batch(items.to_vec()).await;
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if current_try >= max_tries || self.inner.is_permanent_error(&e) => {
+                    return Err(e)
+                }
+                Err(e) => {
+                    let wait = backoff.next();
+                    tracing::warn!(
+                        sink = self.inner.name(),
+                        error = ?e,
+                        ?wait,
+                        "Failed to write logs, retrying"
+                    );
+                    tokio::time::sleep(wait).await;
+                    current_try += 1;
+                }
+            }
+        }
+    }
+
+    /// Write entries one at a time, dropping the ones that the destination rejects. An entry is
+    /// rejected when it fails with a permanent error, or when it fails while other entries are
+    /// written. Once two entries in a row fail before anything has been written, the destination
+    /// is probably down, so the error is returned along with the entries that haven't been
+    /// written.
+    async fn write_each(
+        &self,
+        items: Vec<ProxyLogEntry>,
+    ) -> Result<(), (Report<Error>, Vec<ProxyLogEntry>)> {
+        let mut written = false;
+        let mut rejected = 0;
+        // An entry which failed before anything was written, so it may not have been rejected.
+        let mut suspect: Option<(ProxyLogEntry, Report<Error>)> = None;
+        let mut result = Ok(());
+
+        let mut items = items.into_iter();
+        while let Some(item) = items.next() {
+            match self.inner.write_batch(vec![item.clone()]).await {
+                Ok(()) => {
+                    written = true;
+                    if let Some((_, e)) = suspect.take() {
+                        self.log_rejected(&e);
+                        rejected += 1;
+                    }
+                }
+                Err(e) if written || self.inner.is_permanent_error(&e) => {
+                    self.log_rejected(&e);
+                    rejected += 1;
+                }
+                Err(e) => match suspect.take() {
+                    None => suspect = Some((item, e)),
+                    Some((first, _)) => {
+                        let rest = [first, item].into_iter().chain(items).collect();
+                        result = Err((e, rest));
+                        break;
+                    }
+                },
+            }
+        }
+
+        // Nothing else was written, so the last entry may have failed for another reason.
+        if let Some((item, e)) = suspect {
+            result = Err((e, vec![item]));
+        }
+
+        if rejected > 0 {
+            self.record_dropped("rejected", rejected);
+        }
+        result
+    }
+
+    fn log_rejected(&self, error: &Report<Error>) {
+        tracing::error!(
+            sink = self.inner.name(),
+            error = ?error,
+            "Log entry was rejected"
+        );
+    }
+
+    /// Append entries to the spill file, or drop them if there is no room.
+    async fn spill_entries(
+        &self,
+        spill: &SpillOptions,
+        state: &mut SpillState,
+        items: Vec<ProxyLogEntry>,
+    ) {
+        let count = items.len();
+        let buf = match to_spill_lines(items) {
+            Ok(buf) => buf,
+            Err(e) => {
+                tracing::error!(error = ?e, "Failed to serialize log entries");
+                self.record_dropped("spill_failed", count);
+                return;
+            }
+        };
+
+        if state.size + buf.len() as u64 > spill.max_bytes {
+            self.record_dropped("spill_full", count);
+            return;
+        }
+
+        match append_file(&spill.path, &buf).await {
+            Ok(()) => {
+                state.size += buf.len() as u64;
+                tracing::warn!(
+                    sink = self.inner.name(),
+                    count,
+                    path = %spill.path.display(),
+                    "Spilled log events to disk"
+                );
+            }
+            Err(e) => {
+                tracing::error!(error = ?e, "Failed to write spill file");
+                self.record_dropped("spill_failed", count);
+            }
+        }
+    }
+
+    /// Write the entries in the spill file to the destination. If a write fails, the entries
+    /// which have not been written yet are left in the spill file.
+    async fn replay(
+        &self,
+        spill: &SpillOptions,
+        state: &mut SpillState,
+    ) -> Result<(), Report<Error>> {
+        let file = match tokio::fs::File::open(&spill.path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                state.size = 0;
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(e)
+                    .change_context(Error::LogSink)
+                    .attach_printable_lazy(|| format!("Failed to read {}", spill.path.display()))
+            }
+        };
+
+        let mut reader = BufReader::new(file);
+        let mut corrupt = 0;
+        let mut total = 0;
+        let result = loop {
+            let chunk = match read_spilled_entries(&mut reader, &mut corrupt).await {
+                Ok(chunk) => chunk,
+                Err(e) => break Err(e),
+            };
+            if chunk.is_empty() {
+                break Ok(());
+            }
+
+            let count = chunk.len();
+            if let Err((e, rest)) = self.replay_entries(state, chunk).await {
+                state.size = rewrite_file(&spill.path, to_spill_lines(rest)?, &mut reader).await?;
+                break Err(e);
+            }
+            total += count;
+        };
+
+        if corrupt > 0 {
+            self.record_dropped("spill_corrupt", corrupt);
+        }
+        result?;
+
+        remove_file(&spill.path).await?;
+        state.size = 0;
+        tracing::info!(
+            sink = self.inner.name(),
+            count = total,
+            "Replayed spilled log events"
+        );
+        Ok(())
+    }
+
+    /// Write a batch of spilled entries. If the batch fails with a permanent error, or has failed
+    /// too many times in a row, the entries are written one at a time so that the entries which
+    /// the destination rejects can be dropped.
+    async fn replay_entries(
+        &self,
+        state: &mut SpillState,
+        items: Vec<ProxyLogEntry>,
+    ) -> Result<(), (Report<Error>, Vec<ProxyLogEntry>)> {
+        let result = match self.inner.write_batch(items.clone()).await {
+            Ok(()) => Ok(()),
+            Err(e)
+                if self.inner.is_permanent_error(&e)
+                    || state.failed_replays + 1 >= MAX_REPLAY_FAILURES =>
+            {
+                self.write_each(items).await
+            }
+            Err(e) => Err((e, items)),
+        };
+
+        match result {
+            Ok(()) => state.failed_replays = 0,
+            Err(_) => state.failed_replays = state.failed_replays.saturating_add(1),
+        }
+        result
+    }
+}
+
+#[async_trait::async_trait]
+impl LogSink for RetryingSink {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn write_batch(&self, items: Vec<ProxyLogEntry>) -> Result<(), Report<Error>> {
+        let mut state = self.spill_state.lock().await;
+
+        // Write the older spilled entries first. If that fails, the destination is probably still
+        // down, so there's no point in retrying the new batch.
+        let replayed = match self.spill.as_ref().filter(|_| state.size > 0) {
+            Some(spill) => self.replay(spill, &mut state).await,
+            None => Ok(()),
+        };
+
+        let result = match replayed {
+            Ok(()) => match self.write_with_retry(&items, self.retry.max_tries()).await {
+                Ok(()) => Ok(()),
+                Err(e) if self.inner.is_permanent_error(&e) => self.write_each(items).await,
+                Err(e) => Err((e, items)),
+            },
+            Err(e) => Err((
+                e.attach_printable("Failed to replay spilled log events"),
+                items,
+            )),
+        };
+
+        if let Err((e, items)) = result {
+            tracing::error!(
+                sink = self.inner.name(),
+                error = ?e,
+                "Failed to write logs"
+            );
+            self.inner.record_write_failure();
+            match &self.spill {
+                Some(spill) => self.spill_entries(spill, &mut state, items).await,
+                None => self.record_dropped("write_failed", items.len()),
+            }
+        }
+
+        // Failures have already been handled above.
+        Ok(())
+    }
+
+    async fn retry_pending(&self) {
+        let mut state = self.spill_state.lock().await;
+        let Some(spill) = self.spill.as_ref().filter(|_| state.size > 0) else {
+            return;
+        };
+
+        if let Err(e) = self.replay(spill, &mut state).await {
+            tracing::warn!(
+                sink = self.inner.name(),
+                error = ?e,
+                "Failed to replay spilled log events"
+            );
+        }
+    }
+}
+
+async fn append_file(path: &Path, buf: &[u8]) -> Result<(), Report<Error>> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .change_context(Error::LogSink)?;
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .change_context(Error::LogSink)
+        .attach_printable_lazy(|| format!("Failed to open {}", path.display()))?;
+    file.write_all(buf).await.change_context(Error::LogSink)?;
+    file.sync_data().await.change_context(Error::LogSink)?;
+    Ok(())
+}
+
+/// Read up to [REPLAY_BATCH_SIZE] entries from the spill file. Lines that can't be parsed are
+/// skipped and counted in `corrupt`.
+async fn read_spilled_entries(
+    reader: &mut BufReader<tokio::fs::File>,
+    corrupt: &mut usize,
+) -> Result<Vec<ProxyLogEntry>, Report<Error>> {
+    let mut entries = Vec::new();
+    let mut line = Vec::new();
+    while entries.len() < REPLAY_BATCH_SIZE {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .await
+            .change_context(Error::LogSink)?;
+        if read == 0 {
+            break;
+        }
+        if line.iter().all(|b| b.is_ascii_whitespace()) {
+            continue;
+        }
+
+        match serde_json::from_slice::<SpilledEntry>(&line) {
+            Ok(entry) => entries.push(ProxyLogEntry::from(entry)),
+            Err(_) => *corrupt += 1,
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Replace the contents of the file with `buf` followed by the rest of `remaining`, returning the
+/// new size.
+async fn rewrite_file(
+    path: &Path,
+    buf: Vec<u8>,
+    remaining: &mut BufReader<tokio::fs::File>,
+) -> Result<u64, Report<Error>> {
+    let mut temp_path = path.to_path_buf().into_os_string();
+    temp_path.push(".tmp");
+
+    let mut file = tokio::fs::File::create(&temp_path)
+        .await
+        .change_context(Error::LogSink)?;
+    file.write_all(&buf).await.change_context(Error::LogSink)?;
+    let copied = tokio::io::copy_buf(remaining, &mut file)
+        .await
+        .change_context(Error::LogSink)?;
+    file.sync_data().await.change_context(Error::LogSink)?;
+    drop(file);
+
+    tokio::fs::rename(&temp_path, path)
+        .await
+        .change_context(Error::LogSink)?;
+    Ok(buf.len() as u64 + copied)
+}
+
+async fn remove_file(path: &Path) -> Result<(), Report<Error>> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)
+            .change_context(Error::LogSink)
+            .attach_printable_lazy(|| format!("Failed to remove {}", path.display())),
+        _ => Ok(()),
+    }
+}
+
+fn to_spill_lines(items: Vec<ProxyLogEntry>) -> Result<Vec<u8>, Report<Error>> {
+    let mut buf = Vec::new();
+    for item in items {
+        serde_json::to_writer(&mut buf, &SpilledEntry::from(item))
+            .change_context(Error::LogSink)?;
+        buf.push(b'\n');
+    }
+
+    Ok(buf)
+}
+
+/// The on-disk form of a [ProxyLogEntry]. Unlike the JSON written by the log sinks, this keeps
+/// everything needed to write the entry to the database later.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum SpilledEntry {
+    Proxied(Box<SpilledEvent>),
+    Workflow {
+        event: Box<WorkflowEvent>,
+        /// [EventPayload](crate::workflow_events::EventPayload) doesn't deserialize its internal
+        /// metadata, so it is stored separately.
+        internal_metadata: Option<ProxyRequestInternalMetadata>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct SpilledEvent {
+    id: Uuid,
+    event_type: String,
+    timestamp: DateTime<Utc>,
+    request: Option<ChatRequest>,
+    response: Option<SpilledResponse>,
+    latency: Option<Duration>,
+    total_latency: Option<Duration>,
+    was_rate_limited: Option<bool>,
+    num_retries: Option<u32>,
+    error: Option<serde_json::Value>,
+    metadata: ProxyRequestMetadata,
+    internal_metadata: ProxyRequestInternalMetadata,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SpilledResponse {
+    body: SingleChatResponse,
+    meta: Option<serde_json::Value>,
+    model: String,
+    provider: String,
+}
+
+impl From<ProxyLogEntry> for SpilledEntry {
+    fn from(entry: ProxyLogEntry) -> Self {
+        match entry {
+            ProxyLogEntry::Proxied(event) => {
+                let event = *event;
+                SpilledEntry::Proxied(Box::new(SpilledEvent {
+                    id: event.id,
+                    event_type: event.event_type.into_owned(),
+                    timestamp: event.timestamp,
+                    request: event.request,
+                    response: event.response.map(|r| SpilledResponse {
+                        body: r.body,
+                        meta: r.info.meta,
+                        model: r.info.model,
+                        provider: r.provider,
+                    }),
+                    latency: event.latency,
+                    total_latency: event.total_latency,
+                    was_rate_limited: event.was_rate_limited,
+                    num_retries: event.num_retries,
+                    error: event.error,
+                    // The rest of the options aren't logged, and may contain secrets.
+                    metadata: event.options.metadata,
+                    internal_metadata: event.options.internal_metadata,
+                }))
+            }
+            ProxyLogEntry::Workflow(event) => {
+                let internal_metadata = match &event {
+                    WorkflowEvent::Event(payload) => payload.internal_metadata.clone(),
+                    _ => None,
+                };
+
+                SpilledEntry::Workflow {
+                    event: Box::new(event),
+                    internal_metadata,
+                }
+            }
+        }
+    }
+}
+
+impl From<SpilledEntry> for ProxyLogEntry {
+    fn from(entry: SpilledEntry) -> Self {
+        match entry {
+            SpilledEntry::Proxied(event) => {
+                let event = *event;
+                ProxyLogEntry::Proxied(Box::new(ProxyLogEvent {
+                    id: event.id,
+                    event_type: event.event_type.into(),
+                    timestamp: event.timestamp,
+                    request: event.request,
+                    response: event.response.map(|r| CollectedProxiedResult {
+                        body: r.body,
+                        info: ResponseInfo {
+                            meta: r.meta,
+                            model: r.model,
+                        },
+                        provider: r.provider,
+                    }),
+                    latency: event.latency,
+                    total_latency: event.total_latency,
+                    was_rate_limited: event.was_rate_limited,
+                    num_retries: event.num_retries,
+                    error: event.error,
+                    options: ProxyRequestOptions {
+                        metadata: event.metadata,
+                        internal_metadata: event.internal_metadata,
+                        ..Default::default()
+                    },
+                    offloaded: Default::default(),
+                }))
+            }
+            SpilledEntry::Workflow {
+                mut event,
+                internal_metadata,
+            } => {
+                if let WorkflowEvent::Event(payload) = event.as_mut() {
+                    payload.internal_metadata = internal_metadata;
+                }
+
+                ProxyLogEntry::Workflow(*event)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    };
+
+    use error_stack::Report;
+    use uuid::Uuid;
+
+    use super::{RetryingSink, SpillOptions};
+    use crate::{
+        database::logging::{LogSink, ProxyLogEntry},
+        metrics::ProxyMetrics,
+        request::RetryOptions,
+        workflow_events::{StepEventData, StepStateData, WorkflowEvent},
+        Error,
+    };
+
+    fn entry(state: &str) -> ProxyLogEntry {
+        ProxyLogEntry::Workflow(WorkflowEvent::StepState(StepEventData {
+            run_id: Uuid::nil(),
+            step_id: Uuid::nil(),
+            data: StepStateData {
+                state: state.to_string(),
+            },
+            time: None,
+            event_id: None,
+        }))
+    }
+
+    fn state(entry: &ProxyLogEntry) -> &str {
+        match entry {
+            ProxyLogEntry::Workflow(WorkflowEvent::StepState(event)) => &event.data.state,
+            _ => panic!("Unexpected entry {entry:?}"),
+        }
+    }
+
+    /// Attached to the errors for entries that [FlakySink] will never accept
+    #[derive(Debug)]
+    struct Rejected;
+
+    /// A sink which fails while `down` is set. It always rejects batches with a `bad` entry, and
+    /// fails batches with a `stuck` entry without saying that the error is permanent.
+    #[derive(Debug, Default)]
+    struct FlakySink {
+        down: AtomicBool,
+        attempts: Mutex<usize>,
+        failures: Mutex<usize>,
+        written: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl LogSink for FlakySink {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        async fn write_batch(&self, items: Vec<ProxyLogEntry>) -> Result<(), Report<Error>> {
+            *self.attempts.lock().unwrap() += 1;
+            if self.down.load(Ordering::Relaxed) {
+                return Err(Report::new(Error::LogSink));
+            }
+            if items.iter().any(|e| state(e) == "bad") {
+                return Err(Report::new(Error::LogSink).attach(Rejected));
+            }
+            if items.iter().any(|e| state(e) == "stuck") {
+                return Err(Report::new(Error::LogSink));
+            }
+
+            self.written
+                .lock()
+                .unwrap()
+                .extend(items.iter().map(|e| state(e).to_string()));
+            Ok(())
+        }
+
+        fn record_write_failure(&self) {
+            *self.failures.lock().unwrap() += 1;
+        }
+
+        fn is_permanent_error(&self, error: &Report<Error>) -> bool {
+            error.contains::<Rejected>()
+        }
+    }
+
+    fn retry_options() -> RetryOptions {
+        serde_json::from_value(serde_json::json!({
+            "initial_backoff": 1,
+            "jitter": 0,
+            "max_tries": 3,
+        }))
+        .unwrap()
+    }
+
+    fn spill_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("chronicle-spill-{}.jsonl", Uuid::now_v7()))
+    }
+
+    #[tokio::test]
+    async fn retry_then_drop() {
+        let inner = Arc::new(FlakySink::default());
+        inner.down.store(true, Ordering::Relaxed);
+        let metrics = Arc::new(ProxyMetrics::new().unwrap());
+        let sink = RetryingSink::new(inner.clone(), retry_options(), None, Some(metrics.clone()));
+
+        sink.write_batch(vec![entry("a"), entry("b")])
+            .await
+            .unwrap();
+        assert_eq!(*inner.attempts.lock().unwrap(), 3);
+        assert_eq!(metrics.dropped_log_events(), 2);
+        assert_eq!(*inner.failures.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn spill_and_replay() {
+        let path = spill_path();
+        let inner = Arc::new(FlakySink::default());
+        inner.down.store(true, Ordering::Relaxed);
+        let metrics = Arc::new(ProxyMetrics::new().unwrap());
+        let spill = SpillOptions {
+            path: path.clone(),
+            max_bytes: 1024 * 1024,
+        };
+        let sink = RetryingSink::new(
+            inner.clone(),
+            retry_options(),
+            Some(spill.clone()),
+            Some(metrics.clone()),
+        );
+
+        sink.write_batch(vec![entry("a"), entry("b")])
+            .await
+            .unwrap();
+        assert_eq!(*inner.attempts.lock().unwrap(), 3);
+
+        // Once something is spilled, failures go straight to the spill file.
+        sink.write_batch(vec![entry("c")]).await.unwrap();
+        assert_eq!(*inner.attempts.lock().unwrap(), 4);
+        assert!(path.exists());
+        assert_eq!(*inner.failures.lock().unwrap(), 2);
+
+        // A new sink on the same file should replay it on startup
+        inner.down.store(false, Ordering::Relaxed);
+        let restarted = RetryingSink::new(inner.clone(), retry_options(), Some(spill), None);
+        restarted.retry_pending().await;
+
+        assert_eq!(*inner.written.lock().unwrap(), vec!["a", "b", "c"]);
+        assert!(!path.exists());
+        assert_eq!(metrics.dropped_log_events(), 0);
+
+        // With the destination back up, writes go through normally.
+        restarted.write_batch(vec![entry("d")]).await.unwrap();
+        assert_eq!(*inner.written.lock().unwrap(), vec!["a", "b", "c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn replay_after_successful_write() {
+        let path = spill_path();
+        let inner = Arc::new(FlakySink::default());
+        inner.down.store(true, Ordering::Relaxed);
+        let sink = RetryingSink::new(
+            inner.clone(),
+            retry_options(),
+            Some(SpillOptions {
+                path: path.clone(),
+                max_bytes: 1024 * 1024,
+            }),
+            None,
+        );
+
+        sink.write_batch(vec![entry("a")]).await.unwrap();
+        inner.down.store(false, Ordering::Relaxed);
+        sink.write_batch(vec![entry("b")]).await.unwrap();
+
+        assert_eq!(*inner.written.lock().unwrap(), vec!["a", "b"]);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn spill_limit() {
+        let path = spill_path();
+        let inner = Arc::new(FlakySink::default());
+        inner.down.store(true, Ordering::Relaxed);
+        let metrics = Arc::new(ProxyMetrics::new().unwrap());
+
+        let line_len = serde_json::to_vec(&super::SpilledEntry::from(entry("a")))
+            .unwrap()
+            .len() as u64
+            + 1;
+        let sink = RetryingSink::new(
+            inner.clone(),
+            retry_options(),
+            Some(SpillOptions {
+                path: path.clone(),
+                max_bytes: line_len * 2,
+            }),
+            Some(metrics.clone()),
+        );
+
+        sink.write_batch(vec![entry("a"), entry("b")])
+            .await
+            .unwrap();
+        sink.write_batch(vec![entry("c")]).await.unwrap();
+        assert_eq!(metrics.dropped_log_events(), 1);
+
+        inner.down.store(false, Ordering::Relaxed);
+        sink.retry_pending().await;
+        assert_eq!(*inner.written.lock().unwrap(), vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn drop_rejected_entries() {
+        let inner = Arc::new(FlakySink::default());
+        let metrics = Arc::new(ProxyMetrics::new().unwrap());
+        let sink = RetryingSink::new(inner.clone(), retry_options(), None, Some(metrics.clone()));
+
+        sink.write_batch(vec![entry("a"), entry("bad"), entry("b")])
+            .await
+            .unwrap();
+        assert_eq!(*inner.written.lock().unwrap(), vec!["a", "b"]);
+        assert_eq!(metrics.dropped_log_events(), 1);
+        // The batch isn't retried, only split up.
+        assert_eq!(*inner.attempts.lock().unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn replay_rejected_entries() {
+        let path = spill_path();
+        let inner = Arc::new(FlakySink::default());
+        inner.down.store(true, Ordering::Relaxed);
+        let metrics = Arc::new(ProxyMetrics::new().unwrap());
+        let sink = RetryingSink::new(
+            inner.clone(),
+            retry_options(),
+            Some(SpillOptions {
+                path: path.clone(),
+                max_bytes: 1024 * 1024,
+            }),
+            Some(metrics.clone()),
+        );
+
+        sink.write_batch(vec![entry("bad"), entry("a")])
+            .await
+            .unwrap();
+        inner.down.store(false, Ordering::Relaxed);
+
+        sink.retry_pending().await;
+        assert_eq!(*inner.written.lock().unwrap(), vec!["a"]);
+        assert_eq!(metrics.dropped_log_events(), 1);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn replay_failing_entries() {
+        let path = spill_path();
+        let inner = Arc::new(FlakySink::default());
+        inner.down.store(true, Ordering::Relaxed);
+        let metrics = Arc::new(ProxyMetrics::new().unwrap());
+        let sink = RetryingSink::new(
+            inner.clone(),
+            retry_options(),
+            Some(SpillOptions {
+                path: path.clone(),
+                max_bytes: 1024 * 1024,
+            }),
+            Some(metrics.clone()),
+        );
+
+        sink.write_batch(vec![entry("stuck"), entry("a")])
+            .await
+            .unwrap();
+        inner.down.store(false, Ordering::Relaxed);
+
+        // Without a permanent error, the entry is only dropped once replaying it has failed
+        // several times.
+        for _ in 1..super::MAX_REPLAY_FAILURES {
+            sink.retry_pending().await;
+            assert!(inner.written.lock().unwrap().is_empty());
+            assert!(path.exists());
+        }
+
+        sink.retry_pending().await;
+        assert_eq!(*inner.written.lock().unwrap(), vec!["a"]);
+        assert_eq!(metrics.dropped_log_events(), 1);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn keep_entries_while_down() {
+        let path = spill_path();
+        let inner = Arc::new(FlakySink::default());
+        inner.down.store(true, Ordering::Relaxed);
+        let metrics = Arc::new(ProxyMetrics::new().unwrap());
+        let sink = RetryingSink::new(
+            inner.clone(),
+            retry_options(),
+            Some(SpillOptions {
+                path: path.clone(),
+                max_bytes: 1024 * 1024,
+            }),
+            Some(metrics.clone()),
+        );
+
+        sink.write_batch(vec![entry("a"), entry("b"), entry("c")])
+            .await
+            .unwrap();
+        for _ in 0..super::MAX_REPLAY_FAILURES * 2 {
+            sink.retry_pending().await;
+        }
+        assert_eq!(metrics.dropped_log_events(), 0);
+
+        inner.down.store(false, Ordering::Relaxed);
+        sink.retry_pending().await;
+        assert_eq!(*inner.written.lock().unwrap(), vec!["a", "b", "c"]);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn keep_unwritten_entries_of_large_spill() {
+        let path = spill_path();
+        let inner = Arc::new(FlakySink::default());
+        inner.down.store(true, Ordering::Relaxed);
+        let sink = RetryingSink::new(
+            inner.clone(),
+            retry_options(),
+            Some(SpillOptions {
+                path: path.clone(),
+                max_bytes: 1024 * 1024,
+            }),
+            None,
+        );
+
+        let mut entries = vec![entry("a"); super::REPLAY_BATCH_SIZE];
+        entries.push(entry("stuck"));
+        entries.extend(vec![entry("b"); super::REPLAY_BATCH_SIZE]);
+        sink.write_batch(entries).await.unwrap();
+        inner.down.store(false, Ordering::Relaxed);
+
+        // The first batch is written, and the rest stay in the file.
+        sink.retry_pending().await;
+        assert_eq!(
+            inner.written.lock().unwrap().len(),
+            super::REPLAY_BATCH_SIZE
+        );
+        let remaining = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(remaining.lines().count(), super::REPLAY_BATCH_SIZE + 1);
+        assert_eq!(sink.spill_state.lock().await.size, remaining.len() as u64);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,73 +4,522 @@ use std::sync::Arc;
 use chrono::{DateTime, Utc};
 use error_stack::{Report, ResultExt};
 use itertools::Itertools;
-use sqlx::{QueryBuilder, SqliteExecutor, SqlitePool};
+use sqlx::{
+    sqlite::SqliteRow, Column, QueryBuilder, Row, Sqlite, SqliteConnection, SqliteExecutor,
+    SqlitePool, Transaction,
+};
 use uuid::Uuid;
 
 use super::{
-    logging::{ProxyLogEntry, ProxyLogEvent},
+    blobs::StoredBody,
+    datasets::{
+        select_datasets, Dataset, DatasetItem, DatasetItemSource, NewDataset, DATASET_ITEM_COLUMNS,
+        DEFAULT_DATASET_QUERY_LIMIT,
+    },
+    export::{ExportEventQuery, ExportPage},
+    logging::{ProxyLogEntry, ProxyLogEvent, NEWER_EVENT, NEWER_START},
+    migrations::{
+        legacy_applied, migrations_to_revert, pending_migrations, AppliedMigration, Migration,
+    },
+    retention::{parse_ids, ArchivedRow, PurgeCounts, PurgeTable, RetentionCutoffs},
+    runs::{
+        RunDetail, RunEvent, RunQuery, RunSummary, StepDetail, DEFAULT_RUN_LIST_LIMIT,
+        EVENT_COLUMNS, RUN_COLUMNS, STEP_COLUMNS,
+    },
+    scores::{Score, ScoreTarget, SCORE_COLUMNS},
+    search::{EventSearchQuery, EventSearchResult, DEFAULT_SEARCH_LIMIT},
+    stale::{AbandonedStep, MarkedStaleRuns, StaleCutoffs, StaleRun, STALE_TIMEOUT_INFO_KEY},
+    tables::TableNames,
+    usage::{LlmCall, LlmUsage, PendingUsage, UsageRollup, ADD_USAGE, USAGE_COLUMNS},
     DbProvider, ProxyDatabase,
 };
 use crate::{
     config::{AliasConfig, AliasConfigProvider, ApiKeyConfig},
+    prompts::{attach_labels, NewPrompt, Prompt, PromptTemplate, PromptVersion, PROMPT_COLUMNS},
     workflow_events::{
-        RunStartEvent, RunUpdateEvent, StepEventData, StepStartData, StepStateData, WorkflowEvent,
+        RunStartEvent, RunUpdateEvent, ScoreEvent, ScoreValue, StepEventData, StepStartData,
+        StepStateData, WorkflowEvent,
     },
     Error,
 };
 
-const SQLITE_MIGRATIONS: &[&'static str] = &[
-    include_str!("../../migrations/20240419_chronicle_proxy_init_sqlite.sql"),
-    include_str!("../../migrations/20240424_chronicle_proxy_data_tables_sqlite.sql"),
-    include_str!("../../migrations/20240625_chronicle_proxy_steps_sqlite.sql"),
+/// Chronicle's SQLite migrations, in the order that they run
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        id: "20240419_chronicle_proxy_init",
+        up: include_str!("../../migrations/20240419_chronicle_proxy_init_sqlite.sql"),
+        down: None,
+    },
+    Migration {
+        id: "20240424_chronicle_proxy_data_tables",
+        up: include_str!("../../migrations/20240424_chronicle_proxy_data_tables_sqlite.sql"),
+        down: None,
+    },
+    Migration {
+        id: "20240625_chronicle_proxy_steps",
+        up: include_str!("../../migrations/20240625_chronicle_proxy_steps_sqlite.sql"),
+        down: None,
+    },
+    Migration {
+        id: "20240801_chronicle_proxy_retention",
+        up: include_str!("../../migrations/20240801_chronicle_proxy_retention_sqlite.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240801_chronicle_proxy_retention_sqlite.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240815_chronicle_proxy_scores",
+        up: include_str!("../../migrations/20240815_chronicle_proxy_scores_sqlite.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240815_chronicle_proxy_scores_sqlite.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240822_chronicle_proxy_datasets",
+        up: include_str!("../../migrations/20240822_chronicle_proxy_datasets_sqlite.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240822_chronicle_proxy_datasets_sqlite.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240829_chronicle_proxy_replays",
+        up: include_str!("../../migrations/20240829_chronicle_proxy_replays_sqlite.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240829_chronicle_proxy_replays_sqlite.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240905_chronicle_proxy_prompts",
+        up: include_str!("../../migrations/20240905_chronicle_proxy_prompts_sqlite.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240905_chronicle_proxy_prompts_sqlite.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240912_chronicle_proxy_usage_rollups",
+        up: include_str!("../../migrations/20240912_chronicle_proxy_usage_rollups_sqlite.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240912_chronicle_proxy_usage_rollups_sqlite.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240919_chronicle_proxy_step_updates",
+        up: include_str!("../../migrations/20240919_chronicle_proxy_step_updates_sqlite.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240919_chronicle_proxy_step_updates_sqlite.down.sql"
+        )),
+    },
+    Migration {
+        id: "20240926_chronicle_proxy_stale_runs",
+        up: include_str!("../../migrations/20240926_chronicle_proxy_stale_runs_sqlite.sql"),
+        down: Some(include_str!(
+            "../../migrations/20240926_chronicle_proxy_stale_runs_sqlite.down.sql"
+        )),
+    },
 ];
 
+const SEARCH_INDEX_MIGRATION: &str = include_str!("../../migrations/search_index_sqlite.sql");
+const DROP_SEARCH_INDEX_MIGRATION: &str =
+    include_str!("../../migrations/drop_search_index_sqlite.sql");
+
+fn from_timestamp(secs: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs, 0).unwrap_or_default()
+}
+
+fn split_tags(tags: Option<String>) -> Vec<String> {
+    tags.filter(|t| !t.is_empty())
+        .map(|t| t.split('|').map(|s| s.to_string()).collect())
+        .unwrap_or_default()
+}
+
+#[derive(sqlx::FromRow)]
+struct RunRow {
+    id: String,
+    name: String,
+    description: Option<String>,
+    application: Option<String>,
+    environment: Option<String>,
+    input: Option<serde_json::Value>,
+    output: Option<serde_json::Value>,
+    status: String,
+    trace_id: Option<String>,
+    span_id: Option<String>,
+    tags: Option<String>,
+    info: Option<serde_json::Value>,
+    #[sqlx(flatten)]
+    usage: LlmUsage,
+    updated_at: i64,
+    created_at: i64,
+}
+
+impl TryFrom<RunRow> for RunSummary {
+    type Error = uuid::Error;
+
+    fn try_from(row: RunRow) -> Result<Self, Self::Error> {
+        Ok(RunSummary {
+            id: Uuid::parse_str(&row.id)?,
+            name: row.name,
+            description: row.description,
+            application: row.application,
+            environment: row.environment,
+            input: row.input,
+            output: row.output,
+            status: row.status,
+            trace_id: row.trace_id,
+            span_id: row.span_id,
+            tags: split_tags(row.tags),
+            info: row.info,
+            updated_at: from_timestamp(row.updated_at),
+            created_at: from_timestamp(row.created_at),
+            usage: row.usage,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct StepRow {
+    id: String,
+    run_id: String,
+    #[sqlx(rename = "type")]
+    typ: String,
+    parent_step: Option<String>,
+    name: Option<String>,
+    input: Option<serde_json::Value>,
+    output: Option<serde_json::Value>,
+    status: String,
+    tags: Option<String>,
+    info: Option<serde_json::Value>,
+    span_id: Option<String>,
+    #[sqlx(flatten)]
+    usage: LlmUsage,
+    start_time: i64,
+    end_time: Option<i64>,
+}
+
+impl TryFrom<StepRow> for StepDetail {
+    type Error = uuid::Error;
+
+    fn try_from(row: StepRow) -> Result<Self, Self::Error> {
+        Ok(StepDetail {
+            id: Uuid::parse_str(&row.id)?,
+            run_id: Uuid::parse_str(&row.run_id)?,
+            typ: row.typ,
+            parent_step: row
+                .parent_step
+                .as_deref()
+                .map(Uuid::parse_str)
+                .transpose()?,
+            name: row.name,
+            input: row.input,
+            output: row.output,
+            status: row.status,
+            tags: split_tags(row.tags),
+            info: row.info,
+            span_id: row.span_id,
+            start_time: from_timestamp(row.start_time),
+            end_time: row.end_time.map(from_timestamp),
+            duration_ms: None,
+            usage: row.usage,
+            events: Vec::new(),
+            steps: Vec::new(),
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct EventRow {
+    id: String,
+    event_type: Option<String>,
+    step_id: Option<String>,
+    provider: Option<String>,
+    model: Option<String>,
+    chat_request: Option<serde_json::Value>,
+    chat_response: Option<serde_json::Value>,
+    error: Option<serde_json::Value>,
+    meta: Option<serde_json::Value>,
+    retries: Option<i32>,
+    rate_limited: Option<bool>,
+    request_latency_ms: Option<i64>,
+    total_latency_ms: Option<i64>,
+    replay_of: Option<String>,
+    created_at: i64,
+}
+
+impl TryFrom<EventRow> for RunEvent {
+    type Error = uuid::Error;
+
+    fn try_from(row: EventRow) -> Result<Self, Self::Error> {
+        Ok(RunEvent {
+            id: Uuid::parse_str(&row.id)?,
+            event_type: row.event_type,
+            step_id: row.step_id.as_deref().map(Uuid::parse_str).transpose()?,
+            provider: row.provider,
+            model: row.model,
+            chat_request: row.chat_request,
+            chat_response: row.chat_response,
+            error: row.error,
+            meta: row.meta,
+            retries: row.retries,
+            rate_limited: row.rate_limited,
+            request_latency_ms: row.request_latency_ms,
+            total_latency_ms: row.total_latency_ms,
+            replay_of: row.replay_of.as_deref().map(Uuid::parse_str).transpose()?,
+            created_at: from_timestamp(row.created_at),
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    run_id: Option<String>,
+    #[sqlx(flatten)]
+    event: EventRow,
+}
+
+impl TryFrom<SearchRow> for EventSearchResult {
+    type Error = uuid::Error;
+
+    fn try_from(row: SearchRow) -> Result<Self, Self::Error> {
+        Ok(EventSearchResult {
+            run_id: row.run_id.as_deref().map(Uuid::parse_str).transpose()?,
+            event: RunEvent::try_from(row.event)?,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ScoreRow {
+    id: String,
+    run_id: Option<String>,
+    step_id: Option<String>,
+    event_id: Option<String>,
+    name: String,
+    value: sqlx::types::Json<ScoreValue>,
+    source: String,
+    comment: Option<String>,
+    created_at: i64,
+}
+
+impl TryFrom<ScoreRow> for Score {
+    type Error = Report<Error>;
+
+    fn try_from(row: ScoreRow) -> Result<Self, Self::Error> {
+        let parse_id = |id: Option<String>| {
+            id.as_deref()
+                .map(Uuid::parse_str)
+                .transpose()
+                .change_context(Error::LoadingDatabase)
+        };
+
+        Ok(Score {
+            id: Uuid::parse_str(&row.id).change_context(Error::LoadingDatabase)?,
+            run_id: parse_id(row.run_id)?,
+            step_id: parse_id(row.step_id)?,
+            event_id: parse_id(row.event_id)?,
+            name: row.name,
+            value: row.value.0,
+            source: row.source.parse()?,
+            comment: row.comment,
+            created_at: from_timestamp(row.created_at),
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct DatasetRow {
+    id: String,
+    name: String,
+    version: i32,
+    description: Option<String>,
+    item_count: i64,
+    created_at: i64,
+}
+
+impl TryFrom<DatasetRow> for Dataset {
+    type Error = uuid::Error;
+
+    fn try_from(row: DatasetRow) -> Result<Self, Self::Error> {
+        Ok(Dataset {
+            id: Uuid::parse_str(&row.id)?,
+            name: row.name,
+            version: row.version,
+            description: row.description,
+            item_count: row.item_count,
+            created_at: from_timestamp(row.created_at),
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct DatasetItemRow {
+    id: String,
+    dataset_id: String,
+    event_id: Option<String>,
+    request: serde_json::Value,
+    expected_response: Option<serde_json::Value>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl TryFrom<DatasetItemRow> for DatasetItem {
+    type Error = uuid::Error;
+
+    fn try_from(row: DatasetItemRow) -> Result<Self, Self::Error> {
+        Ok(DatasetItem {
+            id: Uuid::parse_str(&row.id)?,
+            dataset_id: Uuid::parse_str(&row.dataset_id)?,
+            event_id: row.event_id.as_deref().map(Uuid::parse_str).transpose()?,
+            request: row.request,
+            expected_response: row.expected_response,
+            created_at: from_timestamp(row.created_at),
+            updated_at: from_timestamp(row.updated_at),
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct PromptRow {
+    prompt_id: String,
+    version: i32,
+    description: Option<String>,
+    template: sqlx::types::Json<PromptTemplate>,
+    created_at: i64,
+}
+
+impl From<PromptRow> for Prompt {
+    fn from(row: PromptRow) -> Self {
+        Prompt {
+            id: row.prompt_id,
+            version: row.version as u32,
+            labels: Vec::new(),
+            description: row.description,
+            template: row.template.0,
+            created_at: Some(from_timestamp(row.created_at)),
+        }
+    }
+}
+
+/// Convert a search into an FTS5 query that matches all of the words. Each word is quoted so that
+/// punctuation in the search is not read as FTS5 syntax.
+fn fts5_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .join(" ")
+}
+
 /// Log events to an SQLite database
 #[derive(Debug)]
 pub struct SqliteDatabase {
     pool: SqlitePool,
+    tables: TableNames,
 }
 
 impl SqliteDatabase {
     /// Create a new [SqliteDatabase]
     pub fn new(pool: SqlitePool) -> Arc<dyn ProxyDatabase> {
-        Arc::new(Self { pool })
+        Arc::new(Self {
+            pool,
+            tables: TableNames::default(),
+        })
+    }
+
+    /// Create a new [SqliteDatabase] which uses tables with a custom prefix. SQLite has no
+    /// schemas, so this fails if `tables` has one.
+    pub fn with_tables(
+        pool: SqlitePool,
+        tables: TableNames,
+    ) -> Result<Arc<dyn ProxyDatabase>, Report<Error>> {
+        if let Some(schema) = tables.schema() {
+            return Err(Report::new(Error::InvalidTableName(schema.to_string()))
+                .attach_printable("SQLite databases do not support schemas"));
+        }
+
+        Ok(Arc::new(Self { pool, tables }))
     }
 
     async fn write_step_start(
         &self,
-        tx: impl SqliteExecutor<'_>,
+        tx: &mut SqliteConnection,
         event: StepEventData<StepStartData>,
     ) -> Result<(), sqlx::Error> {
+        let time = event.event_time();
+        let step_id = event.step_id;
+        let run_id = event.run_id;
+        let parent_step = event.data.parent_step;
+
+        // If a placeholder for the step already has usage, that usage hasn't been added to any
+        // ancestors yet, since the parent wasn't known.
+        let unparented: Option<LlmUsage> = match parent_step {
+            Some(_) => {
+                sqlx::query_as(&format!(
+                    "SELECT {USAGE_COLUMNS} FROM {}
+                    WHERE id = $1 AND run_id = $2 AND parent_step IS NULL AND llm_calls > 0",
+                    self.tables.steps()
+                ))
+                .bind(step_id.to_string())
+                .bind(run_id.to_string())
+                .fetch_optional(&mut *tx)
+                .await?
+            }
+            None => None,
+        };
+
         let tags = if event.data.tags.is_empty() {
             None
         } else {
             Some(event.data.tags.join("|"))
         };
 
-        sqlx::query(
+        // The step may already exist as a placeholder from an event that arrived before the
+        // start, or from an earlier start of the same step.
+        sqlx::query(&format!(
             r##"
-            INSERT INTO chronicle_steps (
-                id, run_id, type, parent_step, name, input, status, tags, info, span_id, start_time
+            INSERT INTO {} AS t (
+                id, run_id, type, parent_step, name, input, status, tags, info, span_id, start_time,
+                updated_at
             )
             VALUES (
-                $1, $2, $3, $4, $5, $6, 'started', $7, $8, $9, $10
+                $1, $2, $3, $4, $5, $6, 'started', $7, $8, $9, $10, $10
             )
-            ON CONFLICT DO NOTHING;
+            ON CONFLICT (id) DO UPDATE SET
+                type = COALESCE(NULLIF(t.type, ''), EXCLUDED.type),
+                parent_step = COALESCE(t.parent_step, EXCLUDED.parent_step),
+                name = COALESCE(t.name, EXCLUDED.name),
+                input = COALESCE(NULLIF(t.input, 'null'), EXCLUDED.input),
+                tags = COALESCE(t.tags, EXCLUDED.tags),
+                span_id = COALESCE(t.span_id, EXCLUDED.span_id),
+                info = {},
+                status = CASE WHEN {NEWER_START} THEN EXCLUDED.status ELSE t.status END,
+                start_time = MIN(t.start_time, EXCLUDED.start_time),
+                updated_at = MAX(t.updated_at, EXCLUDED.updated_at)
+            WHERE t.run_id = EXCLUDED.run_id;
             "##,
-        )
-        .bind(event.step_id.to_string())
-        .bind(event.run_id.to_string())
+            self.tables.steps(),
+            merge_info(NEWER_START)
+        ))
+        .bind(step_id.to_string())
+        .bind(run_id.to_string())
         .bind(event.data.typ)
-        .bind(event.data.parent_step.map(|s| s.to_string()))
+        .bind(parent_step.map(|s| s.to_string()))
         .bind(event.data.name)
         .bind(event.data.input)
         .bind(tags)
         .bind(event.data.info)
         .bind(event.data.span_id)
-        .bind(event.time.unwrap_or_else(|| Utc::now()).timestamp())
-        .execute(tx)
+        .bind(time.timestamp())
+        .execute(&mut *tx)
         .await?;
+
+        if let Some((parent, usage)) = parent_step.zip(unparented) {
+            let usage = PendingUsage {
+                usage,
+                run_id: Some(run_id),
+                last_call: time,
+            };
+            self.add_step_usage(&mut *tx, parent, usage).await?;
+        }
+
         Ok(())
     }
 
@@ -82,28 +531,151 @@ impl SqliteDatabase {
         status: &str,
         output: serde_json::Value,
         info: Option<serde_json::Value>,
-        timestamp: Option<DateTime<Utc>>,
+        timestamp: DateTime<Utc>,
     ) -> Result<(), sqlx::Error> {
-        sqlx::query(
+        // If the step hasn't started yet, this creates a placeholder for the start to fill in.
+        sqlx::query(&format!(
             r##"
-            UPDATE chronicle_steps
-            SET status = $1,
-                output = $2,
-                info = CASE
-                    WHEN NULLIF(info, 'null') IS NULL THEN $3
-                    WHEN NULLIF($3, 'null') IS NULL THEN info
-                    ELSE json_patch(info, $3)
+            INSERT INTO {} AS t (
+                id, run_id, type, status, output, info, start_time, end_time, updated_at
+            )
+            VALUES (
+                $1, $2, '', $3, $4, $5, $6, $6, $6
+            )
+            ON CONFLICT (id) DO UPDATE SET
+                status = CASE WHEN {NEWER_EVENT} THEN EXCLUDED.status ELSE t.status END,
+                output = CASE
+                    WHEN {NEWER_EVENT} OR t.end_time IS NULL THEN EXCLUDED.output
+                    ELSE t.output
                     END,
-                end_time = $4
-            WHERE run_id = $5 AND id = $6
+                info = {},
+                end_time = CASE
+                    WHEN {NEWER_EVENT} OR t.end_time IS NULL THEN EXCLUDED.end_time
+                    ELSE t.end_time
+                    END,
+                updated_at = MAX(t.updated_at, EXCLUDED.updated_at)
+            WHERE t.run_id = EXCLUDED.run_id
         "##,
-        )
+            self.tables.steps(),
+            merge_info(NEWER_EVENT)
+        ))
+        .bind(step_id.to_string())
+        .bind(run_id.to_string())
         .bind(status)
         .bind(output)
         .bind(info)
-        .bind(timestamp.unwrap_or_else(|| chrono::Utc::now()).timestamp())
-        .bind(run_id.to_string())
+        .bind(timestamp.timestamp())
+        .execute(tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Add the usage of a batch of LLM calls to their runs, their steps, and the ancestors of
+    /// those steps. Steps and runs that don't exist yet are created with a `pending` status, which
+    /// keeps them out of run listings and stale run checks until a workflow event arrives for them.
+    async fn write_usage(
+        &self,
+        tx: &mut SqliteConnection,
+        usage: UsageRollup,
+    ) -> Result<(), sqlx::Error> {
+        for (step_id, usage) in usage.steps {
+            self.add_step_usage(&mut *tx, step_id, usage).await?;
+        }
+
+        for (run_id, usage) in usage.runs {
+            sqlx::query(&format!(
+                "INSERT INTO {} AS t (
+                    id, name, status, updated_at, created_at, {USAGE_COLUMNS}
+                )
+                VALUES ($1, '', 'pending', $2, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (id) DO UPDATE SET {ADD_USAGE}",
+                self.tables.runs()
+            ))
+            .bind(run_id.to_string())
+            .bind(usage.last_call.timestamp())
+            .bind(usage.usage.llm_calls)
+            .bind(usage.usage.prompt_tokens)
+            .bind(usage.usage.completion_tokens)
+            .bind(usage.usage.llm_errors)
+            .bind(usage.usage.llm_latency_ms)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Add usage to a step and its ancestors. A step that doesn't exist yet is created as a
+    /// `pending` placeholder, as long as its run is known.
+    async fn add_step_usage(
+        &self,
+        tx: impl SqliteExecutor<'_>,
+        step_id: Uuid,
+        usage: PendingUsage,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(
+            "WITH RECURSIVE ancestors(id) AS (
+                SELECT $1
+                UNION
+                SELECT s.parent_step FROM {steps} s JOIN ancestors a ON s.id = a.id
+                WHERE s.parent_step IS NOT NULL
+            )
+            INSERT INTO {steps} AS t (
+                id, run_id, type, status, start_time, updated_at, {USAGE_COLUMNS}
+            )
+            SELECT a.id, COALESCE(s.run_id, $2), '', 'pending', $3, $3, $4, $5, $6, $7, $8
+            FROM ancestors a
+            LEFT JOIN {steps} s ON s.id = a.id
+            WHERE COALESCE(s.run_id, $2) IS NOT NULL
+            ON CONFLICT (id) DO UPDATE SET {ADD_USAGE}",
+            steps = self.tables.steps()
+        ))
         .bind(step_id.to_string())
+        .bind(usage.run_id.map(|id| id.to_string()))
+        .bind(usage.last_call.timestamp())
+        .bind(usage.usage.llm_calls)
+        .bind(usage.usage.prompt_tokens)
+        .bind(usage.usage.completion_tokens)
+        .bind(usage.usage.llm_errors)
+        .bind(usage.usage.llm_latency_ms)
+        .execute(tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Write a score. If the score has no run ID, it is taken from the score's step or LLM call.
+    async fn write_score(
+        &self,
+        tx: impl SqliteExecutor<'_>,
+        score: ScoreEvent,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (
+                id, run_id, step_id, event_id, name, value, source, comment, created_at
+            )
+            VALUES (
+                $1,
+                COALESCE(
+                    $2,
+                    (SELECT run_id FROM {} WHERE id = $3),
+                    (SELECT run_id FROM {} WHERE id = $4)
+                ),
+                $3, $4, $5, $6, $7, $8, $9
+            )
+            ON CONFLICT(id) DO NOTHING",
+            self.tables.scores(),
+            self.tables.steps(),
+            self.tables.events()
+        ))
+        .bind(score.id.unwrap_or_else(Uuid::now_v7).to_string())
+        .bind(score.run_id.map(|id| id.to_string()))
+        .bind(score.step_id.map(|id| id.to_string()))
+        .bind(score.event_id.map(|id| id.to_string()))
+        .bind(score.name)
+        .bind(sqlx::types::Json(score.value))
+        .bind(score.source.as_str())
+        .bind(score.comment)
+        .bind(score.time.unwrap_or_else(|| Utc::now()).timestamp())
         .execute(tx)
         .await?;
         Ok(())
@@ -114,14 +686,20 @@ impl SqliteDatabase {
         tx: impl SqliteExecutor<'_>,
         event: StepEventData<StepStateData>,
     ) -> Result<(), sqlx::Error> {
-        sqlx::query(
-            "UPDATE chronicle_steps
-                    SET status=$1
-                    WHERE run_id=$2 AND id=$3",
-        )
-        .bind(event.data.state)
-        .bind(event.run_id.to_string())
+        let time = event.event_time();
+        sqlx::query(&format!(
+            "INSERT INTO {} AS t (id, run_id, type, status, start_time, updated_at)
+            VALUES ($1, $2, '', $3, $4, $4)
+            ON CONFLICT (id) DO UPDATE SET
+                status = CASE WHEN {NEWER_EVENT} THEN EXCLUDED.status ELSE t.status END,
+                updated_at = MAX(t.updated_at, EXCLUDED.updated_at)
+            WHERE t.run_id = EXCLUDED.run_id",
+            self.tables.steps()
+        ))
         .bind(event.step_id.to_string())
+        .bind(event.run_id.to_string())
+        .bind(event.data.state)
+        .bind(time.timestamp())
         .execute(tx)
         .await?;
 
@@ -133,15 +711,18 @@ impl SqliteDatabase {
         tx: impl SqliteExecutor<'_>,
         event: RunStartEvent,
     ) -> Result<(), sqlx::Error> {
+        let time = event.event_time();
         let tags = if event.tags.is_empty() {
             None
         } else {
             Some(event.tags.join("|"))
         };
 
-        sqlx::query(
+        // The run may already exist as a placeholder from an update that arrived first, or from
+        // an earlier start of the same run.
+        sqlx::query(&format!(
             r##"
-            INSERT INTO chronicle_runs (
+            INSERT INTO {} AS t (
                 id, name, description, application, environment, input, status,
                     trace_id, span_id, tags, info, updated_at, created_at
             )
@@ -149,10 +730,22 @@ impl SqliteDatabase {
                 $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $12
             )
             ON CONFLICT(id) DO UPDATE SET
-                status = EXCLUDED.status,
-                updated_at = EXCLUDED.updated_at;
+                name = COALESCE(NULLIF(t.name, ''), EXCLUDED.name),
+                description = COALESCE(t.description, EXCLUDED.description),
+                application = COALESCE(t.application, EXCLUDED.application),
+                environment = COALESCE(t.environment, EXCLUDED.environment),
+                input = COALESCE(NULLIF(t.input, 'null'), EXCLUDED.input),
+                trace_id = COALESCE(t.trace_id, EXCLUDED.trace_id),
+                span_id = COALESCE(t.span_id, EXCLUDED.span_id),
+                tags = COALESCE(t.tags, EXCLUDED.tags),
+                info = {},
+                status = CASE WHEN {NEWER_START} THEN EXCLUDED.status ELSE t.status END,
+                created_at = MIN(t.created_at, EXCLUDED.created_at),
+                updated_at = MAX(t.updated_at, EXCLUDED.updated_at);
             "##,
-        )
+            self.tables.runs(),
+            merge_info(NEWER_START)
+        ))
         .bind(event.id.to_string())
         .bind(event.name)
         .bind(event.description)
@@ -164,7 +757,7 @@ impl SqliteDatabase {
         .bind(event.span_id)
         .bind(tags)
         .bind(event.info)
-        .bind(event.time.unwrap_or_else(|| Utc::now()).timestamp())
+        .bind(time.timestamp())
         .execute(tx)
         .await?;
         Ok(())
@@ -175,23 +768,28 @@ impl SqliteDatabase {
         tx: impl SqliteExecutor<'_>,
         event: RunUpdateEvent,
     ) -> Result<(), sqlx::Error> {
-        sqlx::query(
-            "UPDATE chronicle_runs
-            SET status = $1,
-                output = $2,
-                info = CASE
-                    WHEN NULLIF(info, 'null') IS NULL THEN $3
-                    WHEN NULLIF($3, 'null') IS NULL THEN info
-                    ELSE json_patch(info, $3)
+        let time = event.event_time();
+        // If the run hasn't started yet, this creates a placeholder for the start to fill in.
+        sqlx::query(&format!(
+            "INSERT INTO {} AS t (id, name, status, output, info, updated_at, created_at)
+            VALUES ($1, '', $2, $3, $4, $5, $5)
+            ON CONFLICT (id) DO UPDATE SET
+                status = CASE WHEN {NEWER_EVENT} THEN EXCLUDED.status ELSE t.status END,
+                output = CASE
+                    WHEN NULLIF(EXCLUDED.output, 'null') IS NULL THEN t.output
+                    WHEN {NEWER_EVENT} OR NULLIF(t.output, 'null') IS NULL THEN EXCLUDED.output
+                    ELSE t.output
                     END,
-                updated_at = $4
-            WHERE id = $5",
-        )
+                info = {},
+                updated_at = MAX(t.updated_at, EXCLUDED.updated_at)",
+            self.tables.runs(),
+            merge_info(NEWER_EVENT)
+        ))
+        .bind(event.id.to_string())
         .bind(event.status.as_deref().unwrap_or("finished"))
         .bind(event.output)
         .bind(event.info)
-        .bind(event.time.unwrap_or_else(|| Utc::now()).timestamp())
-        .bind(event.id.to_string())
+        .bind(time.timestamp())
         .execute(tx)
         .await?;
         Ok(())
@@ -224,8 +822,14 @@ impl SqliteDatabase {
             .push_bind(item.options.internal_metadata.organization_id)
             .push_bind(item.options.internal_metadata.project_id)
             .push_bind(item.options.internal_metadata.user_id)
-            .push_bind(sqlx::types::Json(item.request))
-            .push_bind(sqlx::types::Json(rbody))
+            .push_bind(sqlx::types::Json(StoredBody::new(
+                item.request,
+                item.offloaded.request,
+            )))
+            .push_bind(sqlx::types::Json(StoredBody::new(
+                rbody,
+                item.offloaded.response,
+            )))
             .push_bind(sqlx::types::Json(item.error))
             .push_bind(rprovider)
             .push_bind(model)
@@ -241,6 +845,7 @@ impl SqliteDatabase {
             .push_bind(item.options.metadata.step_index.map(|i| i as i32))
             .push_bind(item.options.metadata.prompt_id)
             .push_bind(item.options.metadata.prompt_version.map(|i| i as i32))
+            .push_bind(item.options.metadata.replay_of.map(|u| u.to_string()))
             .push_bind(sqlx::types::Json(extra))
             .push_bind(rmeta)
             .push_bind(item.num_retries.map(|n| n as i32))
@@ -250,10 +855,52 @@ impl SqliteDatabase {
             .push_bind(item.timestamp.timestamp())
             .push_unseparated(")");
     }
+
+    /// Convert prompt rows and add their labels. If `id` is set, only labels for that prompt are
+    /// loaded.
+    async fn with_prompt_labels(
+        &self,
+        rows: Vec<PromptRow>,
+        id: Option<&str>,
+    ) -> Result<Vec<Prompt>, Report<Error>> {
+        let mut prompts = rows.into_iter().map(Prompt::from).collect::<Vec<_>>();
+        if prompts.is_empty() {
+            return Ok(prompts);
+        }
+
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT prompt_id, label, version FROM {}",
+            self.tables.prompt_labels()
+        ));
+        if let Some(id) = id {
+            builder.push(" WHERE prompt_id = ").push_bind(id);
+        }
+        builder.push(" ORDER BY label");
+
+        let labels: Vec<(String, String, i32)> = builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::LoadingDatabase)
+            .attach_printable("Failed to load prompt labels")?;
+
+        attach_labels(
+            &mut prompts,
+            labels
+                .into_iter()
+                .map(|(id, label, version)| (id, label, version as u32))
+                .collect(),
+        );
+        Ok(prompts)
+    }
 }
 
 #[async_trait::async_trait]
 impl ProxyDatabase for SqliteDatabase {
+    fn tables(&self) -> TableNames {
+        self.tables.clone()
+    }
+
     async fn load_providers_from_database(
         &self,
         providers_table: &str,
@@ -350,9 +997,14 @@ impl ProxyDatabase for SqliteDatabase {
     }
 
     async fn write_log_batch(&self, entries: Vec<ProxyLogEntry>) -> Result<(), sqlx::Error> {
-        let mut event_builder = sqlx::QueryBuilder::new(super::logging::EVENT_INSERT_PREFIX);
+        let mut event_builder =
+            sqlx::QueryBuilder::new(super::logging::event_insert_prefix(&self.tables.events()));
         let mut tx = self.pool.begin().await?;
         let mut first_event = true;
+        // Written last, so that they can find the run ID of steps and events in the same batch.
+        let mut scores = Vec::new();
+        // The usage of each LLM call, which is only counted if the call wasn't already recorded.
+        let mut calls = ahash::AHashMap::new();
 
         for entry in entries.into_iter() {
             tracing::debug!(?entry, "Processing event");
@@ -364,6 +1016,9 @@ impl ProxyDatabase for SqliteDatabase {
                         event_builder.push(",");
                     }
 
+                    if let Some(call) = LlmCall::from_event(&item) {
+                        calls.insert(item.id.to_string(), call);
+                    }
                     Self::add_event_values(&mut event_builder, *item);
                 }
                 ProxyLogEntry::Workflow(WorkflowEvent::Event(event)) => {
@@ -373,7 +1028,8 @@ impl ProxyDatabase for SqliteDatabase {
                         event_builder.push(",");
                     }
 
-                    let item = ProxyLogEvent::from_payload(Uuid::now_v7(), event);
+                    let id = event.event_id.unwrap_or_else(Uuid::now_v7);
+                    let item = ProxyLogEvent::from_payload(id, event);
                     Self::add_event_values(&mut event_builder, item);
                 }
                 ProxyLogEntry::Workflow(WorkflowEvent::StepStart(event)) => {
@@ -381,6 +1037,7 @@ impl ProxyDatabase for SqliteDatabase {
                 }
 
                 ProxyLogEntry::Workflow(WorkflowEvent::StepEnd(event)) => {
+                    let time = event.event_time();
                     self.write_step_end(
                         &mut *tx,
                         event.step_id,
@@ -388,7 +1045,7 @@ impl ProxyDatabase for SqliteDatabase {
                         "finished",
                         event.data.output,
                         event.data.info,
-                        event.time,
+                        time,
                     )
                     .await?;
                 }
@@ -396,6 +1053,7 @@ impl ProxyDatabase for SqliteDatabase {
                     self.write_step_status(&mut *tx, event).await?;
                 }
                 ProxyLogEntry::Workflow(WorkflowEvent::StepError(event)) => {
+                    let time = event.event_time();
                     self.write_step_end(
                         &mut *tx,
                         event.step_id,
@@ -403,7 +1061,7 @@ impl ProxyDatabase for SqliteDatabase {
                         "error",
                         event.data.error,
                         None,
-                        event.time,
+                        time,
                     )
                     .await?;
                 }
@@ -413,105 +1071,1469 @@ impl ProxyDatabase for SqliteDatabase {
                 ProxyLogEntry::Workflow(WorkflowEvent::RunUpdate(event)) => {
                     self.write_run_update(&mut *tx, event).await?;
                 }
+                ProxyLogEntry::Workflow(WorkflowEvent::Score(event)) => {
+                    scores.push(event);
+                }
             }
         }
 
+        let mut usage = UsageRollup::default();
         if !first_event {
-            let query = event_builder.build();
-            query.execute(&mut *tx).await?;
+            // Skip events that were already recorded, such as events with IDs from the client
+            // which were sent again.
+            event_builder.push(" ON CONFLICT DO NOTHING RETURNING id");
+            let inserted: Vec<String> = event_builder
+                .build_query_scalar()
+                .fetch_all(&mut *tx)
+                .await?;
+            for id in inserted {
+                if let Some(call) = calls.get(&id) {
+                    usage.add_call(call);
+                }
+            }
+        }
+
+        self.write_usage(&mut tx, usage).await?;
+        for score in scores {
+            self.write_score(&mut *tx, score).await?;
         }
 
         tx.commit().await?;
 
         Ok(())
     }
-}
 
-/// Run database migrations specific to the proxy. These migrations are designed for a simple setup with
-/// single-tenant use. You may want to add multi-tenant features or partitioning, and can integrate
-/// the files from the `migrations` directory into your project to accomplish that.
-pub async fn run_default_migrations(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    let mut tx = pool.begin().await?;
-    sqlx::raw_sql(
-        "CREATE TABLE IF NOT EXISTS chronicle_meta (
-          key text PRIMARY KEY,
-          value text
-        );",
-    )
-    .execute(&mut *tx)
-    .await?;
+    async fn list_runs(&self, query: &RunQuery) -> Result<Vec<RunSummary>, Report<Error>> {
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT {RUN_COLUMNS} FROM {} WHERE status <> 'pending'",
+            self.tables.runs()
+        ));
 
-    let migration_version = sqlx::query_scalar::<_, i32>(
-        "SELECT cast(value as int) FROM chronicle_meta WHERE key='migration_version'",
-    )
-    .fetch_optional(&mut *tx)
-    .await?
-    .unwrap_or(0) as usize;
+        if let Some(name) = &query.name {
+            builder.push(" AND name = ").push_bind(name);
+        }
+        if let Some(application) = &query.application {
+            builder.push(" AND application = ").push_bind(application);
+        }
+        if let Some(environment) = &query.environment {
+            builder.push(" AND environment = ").push_bind(environment);
+        }
+        if let Some(status) = &query.status {
+            builder.push(" AND status = ").push_bind(status);
+        }
+        // Tags are stored as a pipe-separated string
+        for tag in &query.tags {
+            builder
+                .push(" AND ('|' || tags || '|') LIKE ")
+                .push_bind(format!("%|{tag}|%"));
+        }
+        if let Some(start_time) = query.start_time {
+            builder
+                .push(" AND created_at >= ")
+                .push_bind(start_time.timestamp());
+        }
+        if let Some(end_time) = query.end_time {
+            builder
+                .push(" AND created_at < ")
+                .push_bind(end_time.timestamp());
+        }
+
+        builder
+            .push(" ORDER BY created_at DESC LIMIT ")
+            .push_bind(query.limit.unwrap_or(DEFAULT_RUN_LIST_LIMIT) as i64)
+            .push(" OFFSET ")
+            .push_bind(query.offset.unwrap_or(0) as i64);
 
-    tracing::info!("Migration version is {}", migration_version);
+        let rows: Vec<RunRow> = builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::LoadingDatabase)
+            .attach_printable("Failed to load runs")?;
 
-    let start_migration = migration_version.min(SQLITE_MIGRATIONS.len());
-    for (i, migration) in SQLITE_MIGRATIONS[start_migration..].iter().enumerate() {
-        tracing::info!("Running migration {}", start_migration + i);
-        sqlx::raw_sql(migration).execute(&mut *tx).await?;
+        rows.into_iter()
+            .map(RunSummary::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(Error::LoadingDatabase)
     }
 
-    let new_version = SQLITE_MIGRATIONS.len();
+    async fn get_run(&self, id: Uuid) -> Result<Option<RunDetail>, Report<Error>> {
+        let id = id.to_string();
+        let run: Option<RunRow> = sqlx::query_as(&format!(
+            "SELECT {RUN_COLUMNS} FROM {} WHERE id = $1",
+            self.tables.runs()
+        ))
+        .bind(&id)
+        .fetch_optional(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load run")?;
+
+        let Some(run) = run else {
+            return Ok(None);
+        };
 
-    sqlx::query("UPDATE chronicle_meta SET value=$1 WHERE key='migration_version'")
-        .bind(new_version.to_string())
-        .execute(&mut *tx)
-        .await?;
+        let steps: Vec<StepRow> = sqlx::query_as(&format!(
+            "SELECT {STEP_COLUMNS} FROM {} WHERE run_id = $1",
+            self.tables.steps()
+        ))
+        .bind(&id)
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load steps")?;
 
-    tx.commit().await?;
-    Ok(())
-}
+        let events: Vec<EventRow> = sqlx::query_as(&format!(
+            "SELECT {EVENT_COLUMNS} FROM {} WHERE run_id = $1 ORDER BY created_at",
+            self.tables.events()
+        ))
+        .bind(&id)
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load events")?;
 
-#[cfg(test)]
-mod test {
-    use serde_json::json;
-    use sqlx::Row;
+        let run = RunSummary::try_from(run).change_context(Error::LoadingDatabase)?;
+        let steps = steps
+            .into_iter()
+            .map(StepDetail::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(Error::LoadingDatabase)?;
+        let events = events
+            .into_iter()
+            .map(RunEvent::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(Error::LoadingDatabase)?;
 
-    use crate::database::{
-        sqlite::run_default_migrations,
-        testing::{test_events, TEST_EVENT1_ID, TEST_RUN_ID, TEST_STEP1_ID, TEST_STEP2_ID},
-    };
+        Ok(Some(RunDetail::from_parts(run, steps, events)))
+    }
 
-    #[sqlx::test(migrations = false)]
-    async fn test_database_writes(pool: sqlx::SqlitePool) {
-        filigree::tracing_config::test::init();
-        run_default_migrations(&pool).await.unwrap();
+    async fn get_event(&self, id: Uuid) -> Result<Option<RunEvent>, Report<Error>> {
+        let row: Option<EventRow> = sqlx::query_as(&format!(
+            "SELECT {EVENT_COLUMNS} FROM {} WHERE id = $1",
+            self.tables.events()
+        ))
+        .bind(id.to_string())
+        .fetch_optional(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load event")?;
 
-        let db = super::SqliteDatabase::new(pool.clone());
+        row.map(RunEvent::try_from)
+            .transpose()
+            .change_context(Error::LoadingDatabase)
+    }
 
-        db.write_log_batch(test_events())
+    async fn list_scores(&self, target: ScoreTarget) -> Result<Vec<Score>, Report<Error>> {
+        let rows: Vec<ScoreRow> = sqlx::query_as(&format!(
+            "SELECT {SCORE_COLUMNS} FROM {} WHERE {} = $1 ORDER BY created_at, id",
+            self.tables.scores(),
+            target.column()
+        ))
+        .bind(target.id().to_string())
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load scores")?;
+
+        rows.into_iter().map(Score::try_from).collect()
+    }
+
+    async fn create_dataset(&self, dataset: &NewDataset) -> Result<Dataset, Report<Error>> {
+        let datasets = self.tables.datasets();
+        let items = self.tables.dataset_items();
+        let mut tx = self
+            .pool
+            .begin()
             .await
-            .expect("Writing events");
+            .change_context(Error::WritingDatabase)?;
 
-        let runs = sqlx::query(
-            "SELECT id, name, description, application, environment,
-                input, output, status, trace_id, span_id,
-                tags, info, updated_at, created_at
-                FROM chronicle_runs",
-        )
-        .fetch_all(&pool)
+        let previous: Option<(String, i32)> = sqlx::query_as(&format!(
+            "SELECT id, version FROM {datasets} WHERE name = $1 ORDER BY version DESC LIMIT 1"
+        ))
+        .bind(&dataset.name)
+        .fetch_optional(&mut *tx)
         .await
-        .expect("Fetching runs");
-        assert_eq!(runs.len(), 1);
-        let run = &runs[0];
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to load previous dataset version")?;
 
-        assert_eq!(run.get::<String, _>(0), TEST_RUN_ID.to_string(), "run id");
-        assert_eq!(run.get::<String, _>(1), "test run", "name");
-        assert_eq!(
-            run.get::<Option<String>, _>(2),
-            Some("test description".to_string()),
-            "description"
-        );
-        assert_eq!(
-            run.get::<Option<String>, _>(3),
-            Some("test application".to_string()),
-            "application"
-        );
+        let id = Uuid::now_v7();
+        let version = previous
+            .as_ref()
+            .map(|(_, version)| version + 1)
+            .unwrap_or(1);
+        let created_at = Utc::now();
+        sqlx::query(&format!(
+            "INSERT INTO {datasets} (id, name, version, description, created_at)
+            VALUES ($1, $2, $3, $4, $5)"
+        ))
+        .bind(id.to_string())
+        .bind(&dataset.name)
+        .bind(version)
+        .bind(&dataset.description)
+        .bind(created_at.timestamp())
+        .execute(&mut *tx)
+        .await
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to create dataset")?;
+
+        let mut item_count = 0;
+        if let Some((previous_id, _)) = previous {
+            let old_ids: Vec<String> = sqlx::query_scalar(&format!(
+                "SELECT id FROM {items} WHERE dataset_id = $1 ORDER BY created_at, id"
+            ))
+            .bind(previous_id)
+            .fetch_all(&mut *tx)
+            .await
+            .change_context(Error::WritingDatabase)
+            .attach_printable("Failed to load previous dataset items")?;
+
+            let query = format!(
+                "INSERT INTO {items}
+                    (id, dataset_id, event_id, request, expected_response, created_at, updated_at)
+                SELECT $1, $2, event_id, request, expected_response, created_at, updated_at
+                FROM {items}
+                WHERE id = $3"
+            );
+            for old_id in old_ids {
+                item_count += sqlx::query(&query)
+                    .bind(Uuid::now_v7().to_string())
+                    .bind(id.to_string())
+                    .bind(old_id)
+                    .execute(&mut *tx)
+                    .await
+                    .change_context(Error::WritingDatabase)
+                    .attach_printable("Failed to copy dataset items")?
+                    .rows_affected();
+            }
+        }
+
+        tx.commit().await.change_context(Error::WritingDatabase)?;
+
+        Ok(Dataset {
+            id,
+            name: dataset.name.clone(),
+            version,
+            description: dataset.description.clone(),
+            item_count: item_count as i64,
+            created_at: from_timestamp(created_at.timestamp()),
+        })
+    }
+
+    async fn list_datasets(&self) -> Result<Vec<Dataset>, Report<Error>> {
+        let rows: Vec<DatasetRow> = sqlx::query_as(&format!(
+            "{} ORDER BY d.name, d.version",
+            select_datasets(&self.tables)
+        ))
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load datasets")?;
+
+        rows.into_iter()
+            .map(Dataset::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(Error::LoadingDatabase)
+    }
+
+    async fn get_dataset(&self, id: Uuid) -> Result<Option<Dataset>, Report<Error>> {
+        let row: Option<DatasetRow> = sqlx::query_as(&format!(
+            "{} WHERE d.id = $1",
+            select_datasets(&self.tables)
+        ))
+        .bind(id.to_string())
+        .fetch_optional(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load dataset")?;
+
+        row.map(Dataset::try_from)
+            .transpose()
+            .change_context(Error::LoadingDatabase)
+    }
+
+    async fn add_dataset_items(
+        &self,
+        dataset_id: Uuid,
+        source: &DatasetItemSource,
+    ) -> Result<u64, Report<Error>> {
+        let event_ids = match source {
+            DatasetItemSource::Events(ids) => ids
+                .iter()
+                .unique()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>(),
+            DatasetItemSource::Query(query) => {
+                let mut builder = QueryBuilder::new(format!(
+                    "SELECT id FROM {}
+                    WHERE NULLIF(chat_request, 'null') IS NOT NULL
+                        AND NULLIF(error, 'null') IS NULL",
+                    self.tables.events()
+                ));
+
+                if let Some(application) = &query.application {
+                    builder.push(" AND application = ").push_bind(application);
+                }
+                if let Some(environment) = &query.environment {
+                    builder.push(" AND environment = ").push_bind(environment);
+                }
+                if let Some(provider) = &query.provider {
+                    builder.push(" AND provider = ").push_bind(provider);
+                }
+                if let Some(model) = &query.model {
+                    builder.push(" AND model = ").push_bind(model);
+                }
+                if let Some(run_id) = query.run_id {
+                    builder.push(" AND run_id = ").push_bind(run_id.to_string());
+                }
+                if let Some(start_time) = query.start_time {
+                    builder
+                        .push(" AND created_at >= ")
+                        .push_bind(start_time.timestamp());
+                }
+                if let Some(end_time) = query.end_time {
+                    builder
+                        .push(" AND created_at < ")
+                        .push_bind(end_time.timestamp());
+                }
+
+                builder
+                    .push(" ORDER BY created_at DESC LIMIT ")
+                    .push_bind(query.limit.unwrap_or(DEFAULT_DATASET_QUERY_LIMIT) as i64);
+
+                builder
+                    .build_query_scalar()
+                    .fetch_all(&self.pool)
+                    .await
+                    .change_context(Error::LoadingDatabase)
+                    .attach_printable("Failed to find events for dataset")?
+            }
+        };
+
+        let query = format!(
+            "INSERT INTO {}
+                (id, dataset_id, event_id, request, expected_response, created_at, updated_at)
+            SELECT $1, $2, id, chat_request, NULLIF(chat_response, 'null'), $3, $3
+            FROM {}
+            WHERE id = $4 AND NULLIF(chat_request, 'null') IS NOT NULL
+            ON CONFLICT (dataset_id, event_id) DO NOTHING",
+            self.tables.dataset_items(),
+            self.tables.events()
+        );
+
+        let now = Utc::now().timestamp();
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .change_context(Error::WritingDatabase)?;
+        let mut added = 0;
+        for event_id in event_ids {
+            added += sqlx::query(&query)
+                .bind(Uuid::now_v7().to_string())
+                .bind(dataset_id.to_string())
+                .bind(now)
+                .bind(event_id)
+                .execute(&mut *tx)
+                .await
+                .change_context(Error::WritingDatabase)
+                .attach_printable("Failed to add dataset items")?
+                .rows_affected();
+        }
+        tx.commit().await.change_context(Error::WritingDatabase)?;
+
+        Ok(added)
+    }
+
+    async fn update_dataset_item(
+        &self,
+        dataset_id: Uuid,
+        item_id: Uuid,
+        expected_response: &serde_json::Value,
+    ) -> Result<bool, Report<Error>> {
+        let result = sqlx::query(&format!(
+            "UPDATE {} SET expected_response = $1, updated_at = $2
+            WHERE dataset_id = $3 AND id = $4",
+            self.tables.dataset_items()
+        ))
+        .bind(sqlx::types::Json(expected_response))
+        .bind(Utc::now().timestamp())
+        .bind(dataset_id.to_string())
+        .bind(item_id.to_string())
+        .execute(&self.pool)
+        .await
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to update dataset item")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_dataset_items(
+        &self,
+        dataset_id: Uuid,
+    ) -> Result<Vec<DatasetItem>, Report<Error>> {
+        let rows: Vec<DatasetItemRow> = sqlx::query_as(&format!(
+            "SELECT {DATASET_ITEM_COLUMNS} FROM {} WHERE dataset_id = $1 ORDER BY created_at, id",
+            self.tables.dataset_items()
+        ))
+        .bind(dataset_id.to_string())
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load dataset items")?;
+
+        rows.into_iter()
+            .map(DatasetItem::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(Error::LoadingDatabase)
+    }
+
+    async fn list_events_for_export(
+        &self,
+        query: &ExportEventQuery,
+        page: ExportPage,
+    ) -> Result<Vec<RunEvent>, Report<Error>> {
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT {EVENT_COLUMNS} FROM {}
+            WHERE NULLIF(chat_request, 'null') IS NOT NULL
+                AND NULLIF(chat_response, 'null') IS NOT NULL",
+            self.tables.events()
+        ));
+
+        if !query.include_errors {
+            builder.push(" AND NULLIF(error, 'null') IS NULL");
+        }
+        if let Some(application) = &query.application {
+            builder.push(" AND application = ").push_bind(application);
+        }
+        if let Some(environment) = &query.environment {
+            builder.push(" AND environment = ").push_bind(environment);
+        }
+        if let Some(provider) = &query.provider {
+            builder.push(" AND provider = ").push_bind(provider);
+        }
+        if let Some(model) = &query.model {
+            builder.push(" AND model = ").push_bind(model);
+        }
+        if let Some(prompt_id) = &query.prompt_id {
+            builder.push(" AND prompt_id = ").push_bind(prompt_id);
+        }
+        if let Some(prompt_version) = query.prompt_version {
+            builder
+                .push(" AND prompt_version = ")
+                .push_bind(prompt_version as i32);
+        }
+        if let Some(run_id) = query.run_id {
+            builder.push(" AND run_id = ").push_bind(run_id.to_string());
+        }
+        for (key, value) in &query.metadata {
+            builder
+                .push(" AND CAST(json_extract(meta, ")
+                .push_bind(format!("$.\"{key}\""))
+                .push(") AS TEXT) = ")
+                .push_bind(value);
+        }
+        if let Some(start_time) = query.start_time {
+            builder
+                .push(" AND created_at >= ")
+                .push_bind(start_time.timestamp());
+        }
+        if let Some(end_time) = query.end_time {
+            builder
+                .push(" AND created_at < ")
+                .push_bind(end_time.timestamp());
+        }
+        if let Some(after) = page.after {
+            builder.push(" AND id > ").push_bind(after.to_string());
+        }
+
+        builder
+            .push(" ORDER BY id LIMIT ")
+            .push_bind(page.limit as i64);
+
+        let rows: Vec<EventRow> = builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::LoadingDatabase)
+            .attach_printable("Failed to load events for export")?;
+
+        rows.into_iter()
+            .map(RunEvent::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(Error::LoadingDatabase)
+    }
+
+    async fn create_prompt(&self, prompt: &NewPrompt) -> Result<Prompt, Report<Error>> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .change_context(Error::WritingDatabase)?;
+
+        let version: i32 = sqlx::query_scalar(&format!(
+            "SELECT COALESCE(MAX(version), 0) + 1 FROM {} WHERE prompt_id = $1",
+            self.tables.prompts()
+        ))
+        .bind(&prompt.id)
+        .fetch_one(&mut *tx)
+        .await
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to load previous prompt version")?;
+
+        let created_at = Utc::now();
+        sqlx::query(&format!(
+            "INSERT INTO {} (prompt_id, version, description, template, created_at)
+            VALUES ($1, $2, $3, $4, $5)",
+            self.tables.prompts()
+        ))
+        .bind(&prompt.id)
+        .bind(version)
+        .bind(&prompt.description)
+        .bind(sqlx::types::Json(&prompt.template))
+        .bind(created_at.timestamp())
+        .execute(&mut *tx)
+        .await
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to create prompt")?;
+
+        let labels = prompt.labels.iter().unique().cloned().collect::<Vec<_>>();
+        for label in &labels {
+            upsert_prompt_label(&mut *tx, &self.tables, &prompt.id, label, version).await?;
+        }
+
+        tx.commit().await.change_context(Error::WritingDatabase)?;
+
+        Ok(Prompt {
+            id: prompt.id.clone(),
+            version: version as u32,
+            labels,
+            description: prompt.description.clone(),
+            template: prompt.template.clone(),
+            created_at: Some(from_timestamp(created_at.timestamp())),
+        })
+    }
+
+    async fn list_prompts(&self) -> Result<Vec<Prompt>, Report<Error>> {
+        let prompts = self.tables.prompts();
+        let rows: Vec<PromptRow> = sqlx::query_as(&format!(
+            "SELECT {PROMPT_COLUMNS} FROM {prompts} p
+            WHERE version = (SELECT MAX(version) FROM {prompts} p2 WHERE p2.prompt_id = p.prompt_id)
+            ORDER BY prompt_id"
+        ))
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load prompts")?;
+
+        self.with_prompt_labels(rows, None).await
+    }
+
+    async fn list_prompt_versions(&self, id: &str) -> Result<Vec<Prompt>, Report<Error>> {
+        let rows: Vec<PromptRow> = sqlx::query_as(&format!(
+            "SELECT {PROMPT_COLUMNS} FROM {} WHERE prompt_id = $1 ORDER BY version",
+            self.tables.prompts()
+        ))
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .change_context(Error::LoadingDatabase)
+        .attach_printable("Failed to load prompt versions")?;
+
+        self.with_prompt_labels(rows, Some(id)).await
+    }
+
+    async fn get_prompt(
+        &self,
+        id: &str,
+        version: &PromptVersion,
+    ) -> Result<Option<Prompt>, Report<Error>> {
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT {PROMPT_COLUMNS} FROM {} WHERE prompt_id = ",
+            self.tables.prompts()
+        ));
+        builder.push_bind(id);
+        match version {
+            PromptVersion::Latest => {
+                builder.push(" ORDER BY version DESC LIMIT 1");
+            }
+            PromptVersion::Version(version) => {
+                builder.push(" AND version = ").push_bind(*version as i32);
+            }
+            PromptVersion::Label(label) => {
+                builder
+                    .push(format!(
+                        " AND version = (SELECT version FROM {} WHERE prompt_id = ",
+                        self.tables.prompt_labels()
+                    ))
+                    .push_bind(id)
+                    .push(" AND label = ")
+                    .push_bind(label)
+                    .push(")");
+            }
+        }
+
+        let row: Option<PromptRow> = builder
+            .build_query_as()
+            .fetch_optional(&self.pool)
+            .await
+            .change_context(Error::LoadingDatabase)
+            .attach_printable("Failed to load prompt")?;
+
+        let prompts = self
+            .with_prompt_labels(row.into_iter().collect(), Some(id))
+            .await?;
+        Ok(prompts.into_iter().next())
+    }
+
+    async fn set_prompt_label(
+        &self,
+        id: &str,
+        label: &str,
+        version: u32,
+    ) -> Result<bool, Report<Error>> {
+        upsert_prompt_label(&self.pool, &self.tables, id, label, version as i32).await
+    }
+
+    async fn remove_prompt_label(&self, id: &str, label: &str) -> Result<bool, Report<Error>> {
+        let result = sqlx::query(&format!(
+            "DELETE FROM {} WHERE prompt_id = $1 AND label = $2",
+            self.tables.prompt_labels()
+        ))
+        .bind(id)
+        .bind(label)
+        .execute(&self.pool)
+        .await
+        .change_context(Error::WritingDatabase)
+        .attach_printable("Failed to remove prompt label")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_prompt(&self, id: &str, version: Option<u32>) -> Result<u64, Report<Error>> {
+        // Labels are removed along with their versions by the foreign key.
+        let mut builder = QueryBuilder::new(format!(
+            "DELETE FROM {} WHERE prompt_id = ",
+            self.tables.prompts()
+        ));
+        builder.push_bind(id);
+        if let Some(version) = version {
+            builder.push(" AND version = ").push_bind(version as i32);
+        }
+
+        let result = builder
+            .build()
+            .execute(&self.pool)
+            .await
+            .change_context(Error::WritingDatabase)
+            .attach_printable("Failed to delete prompt")?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn search_events(
+        &self,
+        query: &EventSearchQuery,
+    ) -> Result<Vec<EventSearchResult>, Report<Error>> {
+        let enabled = search_index_enabled(&self.pool, &self.tables)
+            .await
+            .change_context(Error::LoadingDatabase)?;
+        if !enabled {
+            return Err(Report::new(Error::SearchNotEnabled));
+        }
+
+        let search = fts5_query(&query.query);
+        if search.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let fts_table = self.tables.table("events_fts");
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT run_id, {EVENT_COLUMNS}
+            FROM {fts_table}
+            JOIN {} s ON s.rowid = {fts_table}.rowid
+            JOIN {} e ON e.id = s.event_id
+            WHERE {fts_table} MATCH ",
+            self.tables.table("events_search"),
+            self.tables.events()
+        ));
+        builder.push_bind(search);
+
+        if let Some(application) = &query.application {
+            builder.push(" AND application = ").push_bind(application);
+        }
+        if let Some(environment) = &query.environment {
+            builder.push(" AND environment = ").push_bind(environment);
+        }
+        if let Some(start_time) = query.start_time {
+            builder
+                .push(" AND created_at >= ")
+                .push_bind(start_time.timestamp());
+        }
+        if let Some(end_time) = query.end_time {
+            builder
+                .push(" AND created_at < ")
+                .push_bind(end_time.timestamp());
+        }
+
+        builder
+            .push(format!(
+                " ORDER BY {fts_table}.rank, created_at DESC LIMIT "
+            ))
+            .push_bind(query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT) as i64)
+            .push(" OFFSET ")
+            .push_bind(query.offset.unwrap_or(0) as i64);
+
+        let rows: Vec<SearchRow> = builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::LoadingDatabase)
+            .attach_printable("Failed to search events")?;
+
+        rows.into_iter()
+            .map(EventSearchResult::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(Error::LoadingDatabase)
+    }
+
+    async fn find_expired(
+        &self,
+        table: PurgeTable,
+        cutoffs: &RetentionCutoffs,
+        limit: usize,
+    ) -> Result<Vec<Uuid>, Report<Error>> {
+        let Some(latest) = cutoffs.latest() else {
+            return Ok(Vec::new());
+        };
+
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT id FROM {} WHERE ",
+            table.table_in(&self.tables)
+        ));
+        push_retention_filter(&mut builder, table, cutoffs, latest);
+        builder.push(" LIMIT ").push_bind(limit as i64);
+
+        let ids: Vec<String> = builder
+            .build_query_scalar()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::Purge)
+            .attach_printable("Failed to find expired rows")?;
+
+        parse_ids(ids)
+    }
+
+    async fn find_stale_runs(
+        &self,
+        cutoffs: &StaleCutoffs,
+        limit: usize,
+    ) -> Result<Vec<StaleRun>, Report<Error>> {
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT id, last_update FROM (
+                SELECT r.id, r.name, r.application, r.info, MAX(
+                    r.updated_at,
+                    COALESCE((SELECT MAX(updated_at) FROM {steps} WHERE run_id = r.id), 0),
+                    COALESCE((SELECT MAX(created_at) FROM {events} WHERE run_id = r.id), 0)
+                ) AS last_update
+                FROM {runs} r
+                WHERE r.status = 'started'
+            ) r
+            WHERE last_update < ",
+            runs = self.tables.runs(),
+            steps = self.tables.steps(),
+            events = self.tables.events(),
+        ));
+        push_stale_cutoff(&mut builder, cutoffs);
+        builder
+            .push(" ORDER BY last_update LIMIT ")
+            .push_bind(limit as i64);
+
+        let rows: Vec<(String, i64)> = builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .change_context(Error::StaleRuns)
+            .attach_printable("Failed to find stale runs")?;
+
+        rows.into_iter()
+            .map(|(id, last_update)| {
+                Ok(StaleRun {
+                    id: Uuid::parse_str(&id)?,
+                    last_update: from_timestamp(last_update),
+                })
+            })
+            .collect::<Result<Vec<_>, uuid::Error>>()
+            .change_context(Error::StaleRuns)
+    }
+
+    async fn mark_stale_runs(
+        &self,
+        ids: &[Uuid],
+        run_status: &str,
+        step_status: &str,
+    ) -> Result<MarkedStaleRuns, Report<Error>> {
+        let mut tx = self.pool.begin().await.change_context(Error::StaleRuns)?;
+        // Leave updated_at alone so that a later update from the run still takes precedence.
+        let mut builder = QueryBuilder::new(format!("UPDATE {} SET status = ", self.tables.runs()));
+        builder
+            .push_bind(run_status)
+            .push(" WHERE status = 'started' AND id");
+        push_id_list(&mut builder, ids);
+        builder.push(" RETURNING id");
+        let runs: Vec<String> = builder
+            .build_query_scalar()
+            .fetch_all(&mut *tx)
+            .await
+            .change_context(Error::StaleRuns)
+            .attach_printable("Failed to update runs")?;
+        let runs = parse_ids(runs).change_context(Error::StaleRuns)?;
+
+        let mut builder =
+            QueryBuilder::new(format!("UPDATE {} SET status = ", self.tables.steps()));
+        builder.push_bind(step_status).push(
+            ", end_time = updated_at WHERE end_time IS NULL AND status <> 'pending' AND run_id",
+        );
+        push_id_list(&mut builder, &runs);
+        builder.push(" RETURNING id, run_id");
+        let steps: Vec<(String, String)> = builder
+            .build_query_as()
+            .fetch_all(&mut *tx)
+            .await
+            .change_context(Error::StaleRuns)
+            .attach_printable("Failed to update steps")?;
+        tx.commit().await.change_context(Error::StaleRuns)?;
+
+        let steps = steps
+            .into_iter()
+            .map(|(id, run_id)| {
+                Ok(AbandonedStep {
+                    id: Uuid::parse_str(&id)?,
+                    run_id: Uuid::parse_str(&run_id)?,
+                })
+            })
+            .collect::<Result<Vec<_>, uuid::Error>>()
+            .change_context(Error::StaleRuns)?;
+
+        Ok(MarkedStaleRuns {
+            runs: runs.len() as u64,
+            steps,
+        })
+    }
+
+    async fn count_expired(
+        &self,
+        cutoffs: &RetentionCutoffs,
+    ) -> Result<PurgeCounts, Report<Error>> {
+        let Some(latest) = cutoffs.latest() else {
+            return Ok(PurgeCounts::default());
+        };
+
+        let runs_table = self.tables.runs();
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT (SELECT count(*) FROM {} WHERE ",
+            self.tables.events()
+        ));
+        push_retention_filter(&mut builder, PurgeTable::Events, cutoffs, latest);
+        builder.push(format!("), (SELECT count(*) FROM {runs_table} WHERE "));
+        push_retention_filter(&mut builder, PurgeTable::Runs, cutoffs, latest);
+        builder.push(format!(
+            "), (SELECT count(*) FROM {} WHERE run_id IN ",
+            self.tables.steps()
+        ));
+        builder.push(format!("(SELECT id FROM {runs_table} WHERE "));
+        push_retention_filter(&mut builder, PurgeTable::Runs, cutoffs, latest);
+        builder.push("))");
+
+        let (events, runs, steps): (i64, i64, i64) = builder
+            .build_query_as()
+            .fetch_one(&self.pool)
+            .await
+            .change_context(Error::Purge)
+            .attach_printable("Failed to count expired rows")?;
+
+        Ok(PurgeCounts {
+            events: events as u64,
+            runs: runs as u64,
+            steps: steps as u64,
+            ..Default::default()
+        })
+    }
+
+    async fn load_rows_for_archive(
+        &self,
+        table: PurgeTable,
+        ids: &[Uuid],
+    ) -> Result<Vec<ArchivedRow>, Report<Error>> {
+        let mut rows = load_json_rows(
+            &self.pool,
+            table.table_name(),
+            &table.table_in(&self.tables),
+            "id",
+            ids,
+        )
+        .await?;
+        if table == PurgeTable::Runs {
+            rows.extend(
+                load_json_rows(
+                    &self.pool,
+                    "chronicle_steps",
+                    &self.tables.steps(),
+                    "run_id",
+                    ids,
+                )
+                .await?,
+            );
+            rows.extend(
+                load_json_rows(
+                    &self.pool,
+                    "chronicle_scores",
+                    &self.tables.scores(),
+                    "run_id",
+                    ids,
+                )
+                .await?,
+            );
+        }
+
+        Ok(rows)
+    }
+
+    async fn delete_rows(
+        &self,
+        table: PurgeTable,
+        ids: &[Uuid],
+    ) -> Result<PurgeCounts, Report<Error>> {
+        match table {
+            PurgeTable::Events => {
+                let events = delete_by_id(&self.pool, &self.tables.events(), "id", ids)
+                    .await
+                    .change_context(Error::Purge)
+                    .attach_printable("Failed to delete events")?;
+
+                Ok(PurgeCounts {
+                    events,
+                    ..Default::default()
+                })
+            }
+            PurgeTable::Runs => {
+                let mut tx = self.pool.begin().await.change_context(Error::Purge)?;
+                delete_by_id(&mut *tx, &self.tables.scores(), "run_id", ids)
+                    .await
+                    .change_context(Error::Purge)
+                    .attach_printable("Failed to delete scores")?;
+                let steps = delete_by_id(&mut *tx, &self.tables.steps(), "run_id", ids)
+                    .await
+                    .change_context(Error::Purge)
+                    .attach_printable("Failed to delete steps")?;
+                let runs = delete_by_id(&mut *tx, &self.tables.runs(), "id", ids)
+                    .await
+                    .change_context(Error::Purge)
+                    .attach_printable("Failed to delete runs")?;
+                tx.commit().await.change_context(Error::Purge)?;
+
+                Ok(PurgeCounts {
+                    runs,
+                    steps,
+                    ..Default::default()
+                })
+            }
+        }
+    }
+}
+
+/// Merge the `info` of a run or step `t` with the `info` from an upsert, so that keys from the
+/// event matching `newer` take precedence.
+fn merge_info(newer: &str) -> String {
+    format!(
+        "CASE
+            WHEN NULLIF(t.info, 'null') IS NULL THEN EXCLUDED.info
+            WHEN NULLIF(EXCLUDED.info, 'null') IS NULL THEN t.info
+            WHEN {newer} THEN json_patch(t.info, EXCLUDED.info)
+            ELSE json_patch(EXCLUDED.info, t.info)
+            END"
+    )
+}
+
+/// Add a condition that matches rows older than the retention cutoffs
+fn push_retention_filter(
+    builder: &mut QueryBuilder<'_, sqlx::Sqlite>,
+    table: PurgeTable,
+    cutoffs: &RetentionCutoffs,
+    latest: DateTime<Utc>,
+) {
+    let column = table.time_column();
+    // Comparing against the latest cutoff first lets the database use the index on the column.
+    builder
+        .push(column)
+        .push(" < ")
+        .push_bind(latest.timestamp());
+    builder.push(" AND ").push(column).push(" < ");
+    if cutoffs.rules.is_empty() {
+        builder.push_bind(cutoffs.default.map(|d| d.timestamp()));
+        return;
+    }
+
+    builder.push("CASE");
+    for rule in &cutoffs.rules {
+        builder.push(" WHEN true");
+        if let Some(application) = &rule.application {
+            builder
+                .push(" AND application = ")
+                .push_bind(application.clone());
+        }
+        if let Some(environment) = &rule.environment {
+            builder
+                .push(" AND environment = ")
+                .push_bind(environment.clone());
+        }
+        builder
+            .push(" THEN ")
+            .push_bind(rule.cutoff.map(|d| d.timestamp()));
+    }
+    builder
+        .push(" ELSE ")
+        .push_bind(cutoffs.default.map(|d| d.timestamp()))
+        .push(" END");
+}
+
+/// Add the time before which a run `r` is stale. A timeout in the run's info takes precedence
+/// over the cutoffs.
+fn push_stale_cutoff(builder: &mut QueryBuilder<'_, sqlx::Sqlite>, cutoffs: &StaleCutoffs) {
+    builder
+        .push(format!(
+            "CASE WHEN json_type(r.info, '$.{STALE_TIMEOUT_INFO_KEY}') IN ('integer', 'real') THEN "
+        ))
+        .push_bind(cutoffs.now.timestamp())
+        .push(format!(
+            " - json_extract(r.info, '$.{STALE_TIMEOUT_INFO_KEY}')"
+        ));
+    for rule in &cutoffs.rules {
+        builder.push(" WHEN true");
+        if let Some(name) = &rule.name {
+            builder.push(" AND r.name = ").push_bind(name.clone());
+        }
+        if let Some(application) = &rule.application {
+            builder
+                .push(" AND r.application = ")
+                .push_bind(application.clone());
+        }
+        builder
+            .push(" THEN ")
+            .push_bind(rule.cutoff.map(|d| d.timestamp()));
+    }
+    builder
+        .push(" ELSE ")
+        .push_bind(cutoffs.default.map(|d| d.timestamp()))
+        .push(" END");
+}
+
+fn push_id_list(builder: &mut QueryBuilder<'_, sqlx::Sqlite>, ids: &[Uuid]) {
+    builder.push(" IN (");
+    let mut separated = builder.separated(", ");
+    for id in ids {
+        separated.push_bind(id.to_string());
+    }
+    separated.push_unseparated(")");
+}
+
+async fn delete_by_id(
+    executor: impl SqliteExecutor<'_>,
+    table: &str,
+    id_column: &str,
+    ids: &[Uuid],
+) -> Result<u64, sqlx::Error> {
+    let mut builder = QueryBuilder::new(format!("DELETE FROM {table} WHERE {id_column}"));
+    push_id_list(&mut builder, ids);
+    let result = builder.build().execute(executor).await?;
+    Ok(result.rows_affected())
+}
+
+/// Convert a row to JSON. The values are converted based on how SQLite stored them, so JSON
+/// columns come out as strings.
+fn row_to_json(row: &SqliteRow) -> serde_json::Value {
+    let columns = row.columns().iter().enumerate().map(|(i, column)| {
+        let value = row
+            .try_get::<Option<String>, _>(i)
+            .map(serde_json::Value::from)
+            .or_else(|_| {
+                row.try_get::<Option<i64>, _>(i)
+                    .map(serde_json::Value::from)
+            })
+            .or_else(|_| {
+                row.try_get::<Option<f64>, _>(i)
+                    .map(serde_json::Value::from)
+            })
+            .or_else(|_| {
+                row.try_get::<Option<Vec<u8>>, _>(i)
+                    .map(serde_json::Value::from)
+            })
+            .unwrap_or_default();
+
+        (column.name().to_string(), value)
+    });
+
+    serde_json::Value::Object(columns.collect())
+}
+
+/// Point a label at a version of a prompt. Returns `false` if the version does not exist.
+async fn upsert_prompt_label(
+    executor: impl SqliteExecutor<'_>,
+    tables: &TableNames,
+    id: &str,
+    label: &str,
+    version: i32,
+) -> Result<bool, Report<Error>> {
+    let result = sqlx::query(&format!(
+        "INSERT INTO {} (prompt_id, label, version, updated_at)
+        SELECT prompt_id, $2, version, $4 FROM {} WHERE prompt_id = $1 AND version = $3
+        ON CONFLICT (prompt_id, label)
+        DO UPDATE SET version = EXCLUDED.version, updated_at = EXCLUDED.updated_at",
+        tables.prompt_labels(),
+        tables.prompts()
+    ))
+    .bind(id)
+    .bind(label)
+    .bind(version)
+    .bind(Utc::now().timestamp())
+    .execute(executor)
+    .await
+    .change_context(Error::WritingDatabase)
+    .attach_printable("Failed to set prompt label")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Load rows from `table`, labeling them with the table's default name `label`
+async fn load_json_rows(
+    pool: &SqlitePool,
+    label: &str,
+    table: &str,
+    id_column: &str,
+    ids: &[Uuid],
+) -> Result<Vec<ArchivedRow>, Report<Error>> {
+    let mut builder = QueryBuilder::new(format!("SELECT * FROM {table} WHERE {id_column}"));
+    push_id_list(&mut builder, ids);
+    let rows = builder
+        .build()
+        .fetch_all(pool)
+        .await
+        .change_context(Error::Purge)
+        .attach_printable_lazy(|| format!("Failed to load rows from {table}"))?;
+
+    Ok(rows
+        .iter()
+        .map(|row| ArchivedRow {
+            table: label.to_string(),
+            row: row_to_json(row),
+        })
+        .collect())
+}
+
+/// Return true if full-text search has been enabled with [enable_search_index].
+pub async fn search_index_enabled(
+    executor: impl SqliteExecutor<'_>,
+    tables: &TableNames,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT EXISTS (
+            SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = $1
+        )",
+    )
+    .bind(tables.unqualified("events_fts"))
+    .fetch_one(executor)
+    .await
+}
+
+/// Add an FTS5 index over the message contents of `chat_request` and `chat_response`, which is
+/// required for [ProxyDatabase::search_events]. Existing events are indexed, and triggers keep
+/// the index up to date.
+///
+/// Returns false if the index already exists.
+pub async fn enable_search_index(
+    pool: &SqlitePool,
+    tables: &TableNames,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if search_index_enabled(&mut *tx, tables).await? {
+        return Ok(false);
+    }
+
+    sqlx::raw_sql(&tables.migration(SEARCH_INDEX_MIGRATION))
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Remove the index added by [enable_search_index]. Returns false if there was no index.
+pub async fn disable_search_index(
+    pool: &SqlitePool,
+    tables: &TableNames,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if !search_index_enabled(&mut *tx, tables).await? {
+        return Ok(false);
+    }
+
+    sqlx::raw_sql(&tables.migration(DROP_SEARCH_INDEX_MIGRATION))
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Run database migrations specific to the proxy. These migrations are designed for a simple setup with
+/// single-tenant use. You may want to add multi-tenant features or partitioning, and can integrate
+/// the files from the `migrations` directory into your project to accomplish that.
+pub async fn run_default_migrations(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    run_migrations(pool, &TableNames::default()).await
+}
+
+/// Run the migrations in [MIGRATIONS] which have not been applied yet, creating the tables with
+/// the names in `tables`. SQLite has no schemas, so this fails if `tables` has one.
+///
+/// This fails with [MigrationError::SchemaTooNew](super::migrations::MigrationError::SchemaTooNew)
+/// if the database was migrated by a newer version of Chronicle, and does not run anything if an
+/// applied migration has changed.
+pub async fn run_migrations(pool: &SqlitePool, tables: &TableNames) -> Result<(), sqlx::Error> {
+    let mut tx = begin_migrations(pool, tables).await?;
+    let applied = load_applied_migrations(&mut tx, tables).await?;
+    let pending = pending_migrations(MIGRATIONS, &applied)?;
+    tracing::info!(
+        "{} migrations applied, {} to run",
+        applied.len(),
+        pending.len()
+    );
+
+    for migration in pending {
+        tracing::info!("Running migration {}", migration.id);
+        sqlx::raw_sql(&migration.sql(tables))
+            .execute(&mut *tx)
+            .await?;
+        insert_migration(&mut tx, tables, migration).await?;
+    }
+
+    finish_migrations(tx, tables).await
+}
+
+/// Record the migrations in [MIGRATIONS] as applied, without running them. Use this when the
+/// migrations run from another migration system, so that Chronicle knows they were applied.
+pub async fn record_migrations(pool: &SqlitePool, tables: &TableNames) -> Result<(), sqlx::Error> {
+    let mut tx = begin_migrations(pool, tables).await?;
+    let applied = load_applied_migrations(&mut tx, tables).await?;
+    for migration in pending_migrations(MIGRATIONS, &applied)? {
+        insert_migration(&mut tx, tables, migration).await?;
+    }
+
+    finish_migrations(tx, tables).await
+}
+
+/// Revert the applied migrations that come after the migration `target`, newest first, using
+/// their down migrations. If `target` is `None`, every migration is reverted. Nothing is reverted
+/// if one of the migrations has no down migration.
+///
+/// Returns the IDs of the reverted migrations.
+pub async fn revert_migrations(
+    pool: &SqlitePool,
+    tables: &TableNames,
+    target: Option<&str>,
+) -> Result<Vec<&'static str>, sqlx::Error> {
+    let mut tx = begin_migrations(pool, tables).await?;
+    let applied = load_applied_migrations(&mut tx, tables).await?;
+    let revert = migrations_to_revert(MIGRATIONS, &applied, target)?;
+
+    let migrations_table = tables.table("migrations");
+    for migration in &revert {
+        tracing::info!("Reverting migration {}", migration.id);
+        sqlx::raw_sql(&migration.down_sql(tables).unwrap_or_default())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!("DELETE FROM {migrations_table} WHERE id = $1"))
+            .bind(migration.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    finish_migrations(tx, tables).await?;
+    Ok(revert.into_iter().map(|m| m.id).collect())
+}
+
+/// Return the migrations that have been applied to the database, in order.
+pub async fn applied_migrations(
+    pool: &SqlitePool,
+    tables: &TableNames,
+) -> Result<Vec<AppliedMigration>, sqlx::Error> {
+    let mut tx = begin_migrations(pool, tables).await?;
+    let applied = load_applied_migrations(&mut tx, tables).await?;
+    finish_migrations(tx, tables).await?;
+    Ok(applied)
+}
+
+/// Create the tables that track the migrations, and start a transaction that changes them.
+///
+/// SQLite has no advisory locks, so the transaction starts with a write to take the database's
+/// write lock. Any other process running the migrations waits for it, instead of failing when it
+/// tries to write after reading a snapshot that is now out of date.
+async fn begin_migrations(
+    pool: &SqlitePool,
+    tables: &TableNames,
+) -> Result<Transaction<'static, Sqlite>, sqlx::Error> {
+    if tables.schema().is_some() {
+        return Err(sqlx::Error::Configuration(
+            "SQLite databases do not support schemas".into(),
+        ));
+    }
+
+    let meta_table = tables.meta();
+    let migrations_table = tables.table("migrations");
+    sqlx::raw_sql(&format!(
+        "CREATE TABLE IF NOT EXISTS {meta_table} (
+          key text PRIMARY KEY,
+          value text
+        );
+        CREATE TABLE IF NOT EXISTS {migrations_table} (
+          id text PRIMARY KEY,
+          checksum text NOT NULL,
+          applied_at int NOT NULL
+        );"
+    ))
+    .execute(pool)
+    .await?;
+
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!("DELETE FROM {migrations_table} WHERE false"))
+        .execute(&mut *tx)
+        .await?;
+
+    // Before migrations were tracked, only the number of applied migrations was stored.
+    let tracked: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {migrations_table}"))
+        .fetch_one(&mut *tx)
+        .await?;
+    if tracked == 0 {
+        let version = sqlx::query_scalar::<_, i32>(&format!(
+            "SELECT cast(value as int) FROM {meta_table} WHERE key='migration_version'"
+        ))
+        .fetch_optional(&mut *tx)
+        .await?
+        .unwrap_or(0) as usize;
+
+        for applied in legacy_applied(MIGRATIONS, version) {
+            sqlx::query(&format!(
+                "INSERT INTO {migrations_table} (id, checksum, applied_at) VALUES ($1, $2, $3)"
+            ))
+            .bind(applied.id)
+            .bind(applied.checksum)
+            .bind(Utc::now().timestamp())
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    Ok(tx)
+}
+
+async fn load_applied_migrations(
+    tx: &mut SqliteConnection,
+    tables: &TableNames,
+) -> Result<Vec<AppliedMigration>, sqlx::Error> {
+    sqlx::query_as(&format!(
+        "SELECT id, checksum FROM {} ORDER BY id",
+        tables.table("migrations")
+    ))
+    .fetch_all(&mut *tx)
+    .await
+}
+
+async fn insert_migration(
+    tx: &mut SqliteConnection,
+    tables: &TableNames,
+    migration: &Migration,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "INSERT INTO {} (id, checksum, applied_at) VALUES ($1, $2, $3)",
+        tables.table("migrations")
+    ))
+    .bind(migration.id)
+    .bind(migration.checksum())
+    .bind(Utc::now().timestamp())
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// Commit the migration changes. This also updates the number of applied migrations in the meta
+/// table, which was used to track migrations before the migrations table existed.
+async fn finish_migrations(
+    mut tx: Transaction<'static, Sqlite>,
+    tables: &TableNames,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "UPDATE {} SET value=cast((SELECT count(*) FROM {}) as text) WHERE key='migration_version'",
+        tables.meta(),
+        tables.table("migrations")
+    ))
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use sqlx::Row;
+
+    use crate::{
+        database::{
+            logging::ProxyLogEntry,
+            migrations::{AppliedMigration, MigrationError},
+            search::EventSearchQuery,
+            sqlite::{
+                applied_migrations, record_migrations, revert_migrations, run_default_migrations,
+                run_migrations, SqliteDatabase, MIGRATIONS,
+            },
+            tables::TableNames,
+            testing::{test_events, TEST_EVENT1_ID, TEST_RUN_ID, TEST_STEP1_ID, TEST_STEP2_ID},
+        },
+        Error,
+    };
+
+    #[sqlx::test(migrations = false)]
+    async fn test_database_writes(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        let runs = sqlx::query(
+            "SELECT id, name, description, application, environment,
+                input, output, status, trace_id, span_id,
+                tags, info, updated_at, created_at
+                FROM chronicle_runs",
+        )
+        .fetch_all(&pool)
+        .await
+        .expect("Fetching runs");
+        assert_eq!(runs.len(), 1);
+        let run = &runs[0];
+
+        assert_eq!(run.get::<String, _>(0), TEST_RUN_ID.to_string(), "run id");
+        assert_eq!(run.get::<String, _>(1), "test run", "name");
+        assert_eq!(
+            run.get::<Option<String>, _>(2),
+            Some("test description".to_string()),
+            "description"
+        );
+        assert_eq!(
+            run.get::<Option<String>, _>(3),
+            Some("test application".to_string()),
+            "application"
+        );
         assert_eq!(
             run.get::<Option<String>, _>(4),
             Some("test environment".to_string()),
@@ -686,4 +2708,343 @@ mod test {
         );
         assert_eq!(event2.get::<i64, _>(6), 5, "created_at");
     }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_run_queries(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_run_queries(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_usage_rollups(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_usage_rollups(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_usage_rollups_out_of_order(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        crate::database::testing::test_usage_rollups_out_of_order(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_usage_without_workflow_events(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        crate::database::testing::test_usage_without_workflow_events(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_usage_of_repeated_calls(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        // A batch that is written again, such as when it is replayed from a spill file, should
+        // not count its calls twice.
+        let calls = test_events()
+            .into_iter()
+            .filter(|entry| matches!(entry, ProxyLogEntry::Proxied(_)))
+            .collect::<Vec<_>>();
+        db.write_log_batch(calls)
+            .await
+            .expect("Writing calls again");
+
+        let run = db
+            .get_run(TEST_RUN_ID)
+            .await
+            .expect("Fetching run")
+            .expect("Run should exist");
+        assert_eq!(run.run.usage.llm_calls, 1);
+        assert_eq!(run.steps[0].usage.llm_calls, 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_out_of_order_events(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        crate::database::testing::test_out_of_order_events(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_stale_runs(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        crate::database::testing::test_stale_runs(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_scores(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_scores(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_datasets(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_datasets(db.as_ref()).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_replay(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        crate::database::testing::test_replay(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_prompts(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        crate::database::testing::test_prompts(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_export(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        crate::database::testing::test_export(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_purge(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_purge(db.as_ref()).await;
+    }
+    #[sqlx::test(migrations = false)]
+    async fn test_blob_offloading(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        crate::database::testing::test_blob_offloading(db).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_search(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        run_default_migrations(&pool).await.unwrap();
+
+        let db = super::SqliteDatabase::new(pool.clone());
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+
+        let err = db
+            .search_events(&EventSearchQuery {
+                query: "test".to_string(),
+                ..Default::default()
+            })
+            .await
+            .expect_err("Search should fail without the index");
+        assert!(matches!(err.current_context(), Error::SearchNotEnabled));
+
+        let tables = TableNames::default();
+        assert!(super::enable_search_index(&pool, &tables).await.unwrap());
+        assert!(!super::enable_search_index(&pool, &tables).await.unwrap());
+        crate::database::testing::test_search(db.as_ref()).await;
+
+        assert!(super::disable_search_index(&pool, &tables).await.unwrap());
+        assert!(!super::search_index_enabled(&pool, &tables).await.unwrap());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_migration_tracking(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        let tables = TableNames::default();
+        let migration_ids =
+            |applied: Vec<AppliedMigration>| applied.into_iter().map(|m| m.id).collect::<Vec<_>>();
+        let all_ids = MIGRATIONS.iter().map(|m| m.id).collect::<Vec<_>>();
+        let mut sorted_ids = all_ids.clone();
+        sorted_ids.sort();
+        assert_eq!(all_ids, sorted_ids, "Migration IDs should sort in order");
+
+        run_default_migrations(&pool).await.unwrap();
+
+        // A database migrated before the migrations table existed
+        sqlx::query("DELETE FROM chronicle_migrations")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let applied = applied_migrations(&pool, &tables).await.unwrap();
+        assert_eq!(migration_ids(applied), all_ids);
+
+        let reverted = revert_migrations(&pool, &tables, Some("20240625_chronicle_proxy_steps"))
+            .await
+            .expect("Reverting migrations");
+        assert_eq!(
+            reverted,
+            vec![
+                "20240926_chronicle_proxy_stale_runs",
+                "20240919_chronicle_proxy_step_updates",
+                "20240912_chronicle_proxy_usage_rollups",
+                "20240905_chronicle_proxy_prompts",
+                "20240829_chronicle_proxy_replays",
+                "20240822_chronicle_proxy_datasets",
+                "20240815_chronicle_proxy_scores",
+                "20240801_chronicle_proxy_retention"
+            ]
+        );
+        let index_count: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM sqlite_master WHERE name = 'chronicle_events_created_at_idx'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(index_count, 0);
+        let applied = applied_migrations(&pool, &tables).await.unwrap();
+        assert_eq!(migration_ids(applied), all_ids[..3]);
+
+        run_default_migrations(&pool)
+            .await
+            .expect("Migrating after revert");
+        let applied = applied_migrations(&pool, &tables).await.unwrap();
+        assert_eq!(migration_ids(applied), all_ids);
+
+        let err = revert_migrations(&pool, &tables, None)
+            .await
+            .expect_err("Reverting irreversible migrations");
+        let sqlx::Error::Configuration(err) = err else {
+            panic!("Unexpected error {err:?}");
+        };
+        assert_eq!(
+            err.downcast_ref::<MigrationError>(),
+            Some(&MigrationError::Irreversible(
+                "20240625_chronicle_proxy_steps".to_string()
+            ))
+        );
+
+        sqlx::query(
+            "INSERT INTO chronicle_migrations (id, checksum, applied_at)
+            VALUES ('29991231_from_the_future', 'abc', 0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let err = run_default_migrations(&pool)
+            .await
+            .expect_err("Migrating a newer database");
+        let sqlx::Error::Configuration(err) = err else {
+            panic!("Unexpected error {err:?}");
+        };
+        assert_eq!(
+            err.downcast_ref::<MigrationError>(),
+            Some(&MigrationError::SchemaTooNew(
+                "29991231_from_the_future".to_string()
+            ))
+        );
+
+        // Migrations run by another migration system
+        let app_tables = TableNames::new("app_").unwrap();
+        let applied = applied_migrations(&pool, &app_tables).await.unwrap();
+        assert!(applied.is_empty());
+        for migration in MIGRATIONS {
+            sqlx::raw_sql(&migration.sql(&app_tables))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        record_migrations(&pool, &app_tables)
+            .await
+            .expect("Recording migrations");
+        run_migrations(&pool, &app_tables)
+            .await
+            .expect("Running recorded migrations");
+        let applied = applied_migrations(&pool, &app_tables).await.unwrap();
+        assert_eq!(migration_ids(applied), all_ids);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_custom_tables(pool: sqlx::SqlitePool) {
+        filigree::tracing_config::test::init();
+        let tables = TableNames::new("llm_").unwrap();
+        run_migrations(&pool, &tables).await.unwrap();
+        run_migrations(&pool, &tables)
+            .await
+            .expect("Running migrations again");
+
+        let db = SqliteDatabase::with_tables(pool.clone(), tables.clone()).unwrap();
+        db.write_log_batch(test_events())
+            .await
+            .expect("Writing events");
+        crate::database::testing::test_run_queries(db.as_ref()).await;
+
+        assert!(super::enable_search_index(&pool, &tables).await.unwrap());
+        crate::database::testing::test_search(db.as_ref()).await;
+
+        let default_tables: i64 =
+            sqlx::query_scalar("SELECT count(*) FROM sqlite_master WHERE name LIKE 'chronicle%'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(default_tables, 0);
+
+        let with_schema = tables.with_schema("telemetry").unwrap();
+        assert!(
+            run_migrations(&pool, &with_schema).await.is_err(),
+            "SQLite migrations with a schema"
+        );
+        assert!(
+            SqliteDatabase::with_tables(pool.clone(), with_schema).is_err(),
+            "SQLite database with a schema"
+        );
+    }
 }
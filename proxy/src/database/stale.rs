@@ -0,0 +1,330 @@
+//! Marking runs that stopped sending updates as timed out
+use std::time::Duration;
+
+use ahash::AHashMap;
+use chrono::{DateTime, Utc};
+use error_stack::{Report, ResultExt};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationSeconds};
+use uuid::Uuid;
+
+use super::{
+    logging::{LogSender, ProxyLogEntry},
+    Database, ProxyDatabase,
+};
+use crate::{
+    workflow_events::{EventPayload, WorkflowEvent},
+    Error,
+};
+
+/// The default status for runs that time out
+pub const DEFAULT_STALE_RUN_STATUS: &str = "timed_out";
+/// The default status for the unfinished steps of runs that time out
+pub const DEFAULT_STALE_STEP_STATUS: &str = "abandoned";
+/// The default number of runs marked at a time
+pub const DEFAULT_STALE_BATCH_SIZE: usize = 100;
+/// The default time between runs of the background sweep
+pub const DEFAULT_STALE_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// The type of the event recorded for each abandoned step when
+/// [record_events](StaleRunConfig::record_events) is set
+pub const STALE_STEP_EVENT_TYPE: &str = "step:timeout";
+/// The key in a run's `info` that sets how many seconds the run can go without updates before it
+/// times out. This takes precedence over the configuration.
+pub const STALE_TIMEOUT_INFO_KEY: &str = "stale_timeout";
+
+/// How to find runs that have stopped sending updates, such as runs whose process crashed. Only
+/// runs with the `started` status are checked. A run's latest update is the latest of its own
+/// updates, the events for its steps, and its logged events.
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StaleRunConfig {
+    /// Time out runs that have had no updates for this many seconds, unless a rule matches them.
+    /// If omitted, only runs that match a rule or set a timeout in their info time out.
+    #[serde_as(as = "Option<DurationSeconds>")]
+    pub timeout: Option<Duration>,
+    /// Timeouts for runs with specific names and applications. When more than one rule matches, a
+    /// rule with both a name and an application takes precedence, followed by a rule with just a
+    /// name.
+    #[serde(default)]
+    pub rules: Vec<StaleRunRule>,
+    /// The status for runs that time out. Defaults to `timed_out`.
+    pub run_status: Option<String>,
+    /// The status for the unfinished steps of runs that time out. Defaults to `abandoned`.
+    pub step_status: Option<String>,
+    /// Record a `step:timeout` event with the reason for each step that is abandoned. This is a
+    /// generic event rather than a `step:error`, so that the step keeps its abandoned status.
+    #[serde(default)]
+    pub record_events: bool,
+    /// How many runs to mark in each query. Defaults to 100.
+    pub batch_size: Option<usize>,
+    /// How often the background job runs, in seconds. Defaults to one minute.
+    #[serde_as(as = "Option<DurationSeconds>")]
+    pub interval: Option<Duration>,
+    /// Set to false to disable the background job.
+    pub background: Option<bool>,
+}
+
+/// A timeout for runs with a particular name or application
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StaleRunRule {
+    /// Only apply to runs with this name
+    pub name: Option<String>,
+    /// Only apply to runs from this application
+    pub application: Option<String>,
+    /// Time out matching runs after this many seconds without updates. If omitted, matching runs
+    /// never time out.
+    #[serde_as(as = "Option<DurationSeconds>")]
+    pub timeout: Option<Duration>,
+}
+
+fn cutoff(now: DateTime<Utc>, timeout: Option<Duration>) -> Option<DateTime<Utc>> {
+    timeout.map(|timeout| now - chrono::Duration::seconds(timeout.as_secs() as i64))
+}
+
+impl StaleRunConfig {
+    /// Calculate the time before which a run's latest update makes it stale, relative to `now`
+    pub fn cutoffs(&self, now: DateTime<Utc>) -> StaleCutoffs {
+        let mut rules = self
+            .rules
+            .iter()
+            .map(|rule| StaleRuleCutoff {
+                name: rule.name.clone(),
+                application: rule.application.clone(),
+                cutoff: cutoff(now, rule.timeout),
+            })
+            .collect::<Vec<_>>();
+        // The database checks the rules in order, so put the most specific ones first.
+        rules.sort_by_key(|rule| (rule.name.is_none(), rule.application.is_none()));
+
+        StaleCutoffs {
+            now,
+            rules,
+            default: cutoff(now, self.timeout),
+        }
+    }
+}
+
+/// The cutoff time for runs matching a [StaleRunRule]
+#[derive(Debug, Clone)]
+pub struct StaleRuleCutoff {
+    /// Only apply to runs with this name
+    pub name: Option<String>,
+    /// Only apply to runs from this application
+    pub application: Option<String>,
+    /// Runs last updated before this time are stale. If `None`, the runs never time out.
+    pub cutoff: Option<DateTime<Utc>>,
+}
+
+/// The times before which runs are stale, calculated from a [StaleRunConfig]
+#[derive(Debug, Clone)]
+pub struct StaleCutoffs {
+    /// The current time, which timeouts from a run's info are relative to
+    pub now: DateTime<Utc>,
+    /// The cutoffs for each rule, with the most specific rules first
+    pub rules: Vec<StaleRuleCutoff>,
+    /// The cutoff for runs that don't match any rule
+    pub default: Option<DateTime<Utc>>,
+}
+
+/// A run that has gone without updates for longer than its timeout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleRun {
+    pub id: Uuid,
+    /// The time of the latest update to the run, its steps, or its events
+    pub last_update: DateTime<Utc>,
+}
+
+/// A step that was abandoned because its run timed out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbandonedStep {
+    pub id: Uuid,
+    pub run_id: Uuid,
+}
+
+/// The runs and steps changed by [ProxyDatabase::mark_stale_runs]
+#[derive(Debug, Clone, Default)]
+pub struct MarkedStaleRuns {
+    /// The number of runs that were marked as timed out
+    pub runs: u64,
+    /// The unfinished steps of those runs
+    pub steps: Vec<AbandonedStep>,
+}
+
+/// The number of runs and steps marked by [sweep_stale_runs]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StaleCounts {
+    pub runs: u64,
+    pub steps: u64,
+}
+
+/// Mark runs that have had no updates for longer than their timeout, and their unfinished steps.
+///
+/// A run's `updated_at` is left alone, so an update sent later by a run that was only slow still
+/// takes precedence over the timeout.
+///
+/// If `log_tx` is provided, the events for abandoned steps are sent through it like any other
+/// logged event. Otherwise they are written to the database directly.
+pub async fn sweep_stale_runs(
+    db: &dyn ProxyDatabase,
+    config: &StaleRunConfig,
+    now: DateTime<Utc>,
+    log_tx: Option<&LogSender>,
+) -> Result<StaleCounts, Report<Error>> {
+    let cutoffs = config.cutoffs(now);
+    let batch_size = config.batch_size.unwrap_or(DEFAULT_STALE_BATCH_SIZE).max(1);
+    let run_status = config
+        .run_status
+        .as_deref()
+        .unwrap_or(DEFAULT_STALE_RUN_STATUS);
+    let step_status = config
+        .step_status
+        .as_deref()
+        .unwrap_or(DEFAULT_STALE_STEP_STATUS);
+    let mut total = StaleCounts::default();
+
+    loop {
+        let runs = db.find_stale_runs(&cutoffs, batch_size).await?;
+        if runs.is_empty() {
+            break;
+        }
+
+        let ids = runs.iter().map(|run| run.id).collect::<Vec<_>>();
+        let marked = db.mark_stale_runs(&ids, run_status, step_status).await?;
+        total.runs += marked.runs;
+        total.steps += marked.steps.len() as u64;
+
+        if config.record_events && !marked.steps.is_empty() {
+            let last_updates = runs
+                .iter()
+                .map(|run| (run.id, run.last_update))
+                .collect::<AHashMap<_, _>>();
+            let events = marked
+                .steps
+                .iter()
+                .map(|step| {
+                    let last_update = last_updates.get(&step.run_id).copied().unwrap_or(now);
+                    stale_step_event(step, last_update, step_status, now)
+                })
+                .collect::<Vec<_>>();
+
+            match log_tx {
+                Some(log_tx) => log_tx.send(events.into()).await,
+                None => db
+                    .write_log_batch(events)
+                    .await
+                    .change_context(Error::StaleRuns)
+                    .attach_printable("Failed to record events for abandoned steps")?,
+            }
+        }
+
+        // Stop if another update finished the runs first, so that the same runs aren't found again.
+        if runs.len() < batch_size || marked.runs == 0 {
+            break;
+        }
+    }
+
+    Ok(total)
+}
+
+/// A `step:timeout` event explaining why a step was abandoned. This is a generic event rather than
+/// a `step:error`, so that it doesn't change the step's status.
+fn stale_step_event(
+    step: &AbandonedStep,
+    last_update: DateTime<Utc>,
+    step_status: &str,
+    now: DateTime<Utc>,
+) -> ProxyLogEntry {
+    let idle = (now - last_update).num_seconds();
+    ProxyLogEntry::Workflow(WorkflowEvent::Event(EventPayload {
+        typ: STALE_STEP_EVENT_TYPE.to_string(),
+        data: Some(serde_json::json!({ "status": step_status })),
+        error: Some(serde_json::json!({
+            "message": format!("The run timed out after {idle} seconds without updates"),
+            "last_update": last_update,
+        })),
+        run_id: step.run_id,
+        step_id: step.id,
+        time: Some(now),
+        internal_metadata: None,
+        event_id: None,
+    }))
+}
+
+/// Start a task that periodically marks stale runs. Events for abandoned steps are sent through
+/// `log_tx` when it is provided.
+pub fn start_stale_run_task(
+    db: Database,
+    config: StaleRunConfig,
+    log_tx: Option<LogSender>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::task::spawn(async move {
+        let mut interval =
+            tokio::time::interval(config.interval.unwrap_or(DEFAULT_STALE_SWEEP_INTERVAL));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            match sweep_stale_runs(db.as_ref(), &config, Utc::now(), log_tx.as_ref()).await {
+                Ok(counts) => {
+                    if counts != StaleCounts::default() {
+                        tracing::info!(
+                            runs = counts.runs,
+                            steps = counts.steps,
+                            "Marked stale runs"
+                        );
+                    }
+                }
+                Err(e) => tracing::error!(error = ?e, "Failed to mark stale runs"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn cutoffs() {
+        let now = Utc.timestamp_opt(100_000, 0).unwrap();
+        let config: StaleRunConfig = serde_json::from_value(serde_json::json!({
+            "timeout": 3600,
+            "rules": [
+                { "application": "chat", "timeout": 60 },
+                { "name": "nightly", "application": "batch" },
+                { "name": "nightly", "timeout": 86400 },
+            ],
+        }))
+        .unwrap();
+
+        let cutoffs = config.cutoffs(now);
+        let names = cutoffs
+            .rules
+            .iter()
+            .map(|rule| (rule.name.as_deref(), rule.application.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                (Some("nightly"), Some("batch")),
+                (Some("nightly"), None),
+                (None, Some("chat")),
+            ]
+        );
+        assert_eq!(cutoffs.rules[0].cutoff, None);
+        assert_eq!(
+            cutoffs.rules[1].cutoff,
+            Some(now - chrono::Duration::days(1))
+        );
+        assert_eq!(
+            cutoffs.rules[2].cutoff,
+            Some(now - chrono::Duration::minutes(1))
+        );
+        assert_eq!(cutoffs.default, Some(now - chrono::Duration::hours(1)));
+
+        assert_eq!(StaleRunConfig::default().cutoffs(now).default, None);
+    }
+}
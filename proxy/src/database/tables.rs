@@ -0,0 +1,218 @@
+//! Naming the tables that Chronicle uses
+use error_stack::Report;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// The prefix on the names of Chronicle's tables, unless configured otherwise
+pub const DEFAULT_TABLE_PREFIX: &str = "chronicle_";
+
+/// Configuration for where Chronicle's tables are in the database
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableConfig {
+    /// The PostgreSQL schema that holds the tables. Defaults to the first schema in the
+    /// connection's search path.
+    pub schema: Option<String>,
+    /// The prefix for each table name. Defaults to `chronicle_`.
+    pub prefix: Option<String>,
+}
+
+/// The names of Chronicle's tables. Every table name is the prefix followed by the name of the
+/// table, such as `events` or `runs`, and is qualified by the schema if there is one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableNames {
+    schema: Option<String>,
+    prefix: String,
+}
+
+impl Default for TableNames {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            prefix: DEFAULT_TABLE_PREFIX.to_string(),
+        }
+    }
+}
+
+/// Names are inserted into SQL without quoting, so only allow plain identifiers.
+fn validate_identifier(name: &str, allow_empty: bool) -> Result<(), Report<Error>> {
+    let valid = (allow_empty || !name.is_empty())
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(Report::new(Error::InvalidTableName(name.to_string())))
+    }
+}
+
+impl TableNames {
+    /// Use tables whose names start with `prefix`. The prefix may only contain lowercase ASCII
+    /// letters, digits, and underscores.
+    pub fn new(prefix: impl Into<String>) -> Result<Self, Report<Error>> {
+        let prefix = prefix.into();
+        validate_identifier(&prefix, true)?;
+        Ok(Self {
+            schema: None,
+            prefix,
+        })
+    }
+
+    /// Use tables in this PostgreSQL schema. The name has the same restrictions as the prefix.
+    pub fn with_schema(mut self, schema: impl Into<String>) -> Result<Self, Report<Error>> {
+        let schema = schema.into();
+        validate_identifier(&schema, false)?;
+        self.schema = Some(schema);
+        Ok(self)
+    }
+
+    /// Create the names from the configuration
+    pub fn from_config(config: &TableConfig) -> Result<Self, Report<Error>> {
+        let names = Self::new(config.prefix.as_deref().unwrap_or(DEFAULT_TABLE_PREFIX))?;
+        match &config.schema {
+            Some(schema) => names.with_schema(schema),
+            None => Ok(names),
+        }
+    }
+
+    /// The schema that holds the tables, if one was set
+    pub fn schema(&self) -> Option<&str> {
+        self.schema.as_deref()
+    }
+
+    /// The prefix on each table name
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The name of a table or index without the schema, such as `chronicle_events`. Use this
+    /// where the schema is not allowed, such as when naming a new index.
+    pub fn unqualified(&self, name: &str) -> String {
+        format!("{}{name}", self.prefix)
+    }
+
+    /// Qualify a full table name, such as one returned by [TableNames::unqualified], with the
+    /// schema if there is one
+    pub fn qualify(&self, name: &str) -> String {
+        match &self.schema {
+            Some(schema) => format!("{schema}.{name}"),
+            None => name.to_string(),
+        }
+    }
+
+    /// The name of a table, qualified by the schema if there is one
+    pub fn table(&self, name: &str) -> String {
+        self.qualify(&self.unqualified(name))
+    }
+
+    /// The events table
+    pub fn events(&self) -> String {
+        self.table("events")
+    }
+
+    /// The runs table
+    pub fn runs(&self) -> String {
+        self.table("runs")
+    }
+
+    /// The steps table
+    pub fn steps(&self) -> String {
+        self.table("steps")
+    }
+
+    /// The scores table
+    pub fn scores(&self) -> String {
+        self.table("scores")
+    }
+
+    /// The datasets table
+    pub fn datasets(&self) -> String {
+        self.table("datasets")
+    }
+
+    /// The table of items in each dataset
+    pub fn dataset_items(&self) -> String {
+        self.table("dataset_items")
+    }
+
+    /// The table of prompt template versions
+    pub fn prompts(&self) -> String {
+        self.table("prompts")
+    }
+
+    /// The table of labels that point to prompt versions
+    pub fn prompt_labels(&self) -> String {
+        self.table("prompt_labels")
+    }
+
+    /// The table which records the migration version and other settings
+    pub fn meta(&self) -> String {
+        self.table("meta")
+    }
+
+    /// Rename the tables in a migration script, which is written using the default prefix. The
+    /// script is expected to run with the schema, if any, at the front of the search path.
+    pub(crate) fn migration<'a>(&self, sql: &'a str) -> std::borrow::Cow<'a, str> {
+        if self.prefix == DEFAULT_TABLE_PREFIX {
+            std::borrow::Cow::Borrowed(sql)
+        } else {
+            std::borrow::Cow::Owned(sql.replace(DEFAULT_TABLE_PREFIX, &self.prefix))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn table_names() {
+        let names = TableNames::default();
+        assert_eq!(names.events(), "chronicle_events");
+        assert_eq!(
+            names.migration("DROP TABLE chronicle_runs"),
+            "DROP TABLE chronicle_runs"
+        );
+
+        let names = TableNames::new("app_llm_")
+            .unwrap()
+            .with_schema("telemetry")
+            .unwrap();
+        assert_eq!(names.events(), "telemetry.app_llm_events");
+        assert_eq!(
+            names.unqualified("events_p20240801"),
+            "app_llm_events_p20240801"
+        );
+        assert_eq!(
+            names.qualify("app_llm_events_p20240801"),
+            "telemetry.app_llm_events_p20240801"
+        );
+        assert_eq!(
+            names.migration("CREATE INDEX chronicle_runs_tags_idx ON chronicle_runs (tags)"),
+            "CREATE INDEX app_llm_runs_tags_idx ON app_llm_runs (tags)"
+        );
+
+        assert_eq!(TableNames::new("").unwrap().runs(), "runs");
+    }
+
+    #[test]
+    fn invalid_names() {
+        assert!(
+            TableNames::new("chronicle; DROP TABLE users; --").is_err(),
+            "punctuation"
+        );
+        assert!(TableNames::new("Chronicle_").is_err(), "uppercase");
+        assert!(TableNames::new("1_").is_err(), "leading digit");
+        assert!(
+            TableNames::default().with_schema("").is_err(),
+            "empty schema"
+        );
+        let from_config = TableNames::from_config(&TableConfig {
+            schema: Some("bad-schema".to_string()),
+            prefix: None,
+        });
+        assert!(from_config.is_err(), "schema from config");
+    }
+}
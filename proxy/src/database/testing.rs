@@ -1,13 +1,38 @@
+use std::{sync::Arc, time::Duration};
+
 use chrono::{TimeZone, Utc};
+use futures::TryStreamExt;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::{
-    database::logging::{ProxyLogEntry, ProxyLogEvent},
+    database::{
+        blobs::{BlobOffloadingDatabase, BlobRef, FilesystemBlobStore},
+        datasets::{DatasetEventQuery, DatasetItem, DatasetItemSource, NewDataset},
+        export::{ExportEventQuery, ExportPage},
+        logging::{start_database_logger, CollectedProxiedResult, ProxyLogEntry, ProxyLogEvent},
+        retention::{purge, PurgeCounts, PurgeTable, RetentionConfig, RetentionRule},
+        runs::{RunQuery, RunSummary},
+        scores::{summarize_scores, ScoreTarget},
+        search::{EventSearchQuery, EventSearchResult},
+        stale::{
+            sweep_stale_runs, StaleCounts, StaleRunConfig, StaleRunRule, STALE_STEP_EVENT_TYPE,
+        },
+        usage::LlmUsage,
+        Database, ProxyDatabase,
+    },
+    fine_tuning::{export_fine_tuning, FineTuningOptions, FineTuningSplit},
+    format::{
+        ChatChoice, ChatMessage, ChatRequest, ResponseInfo, SingleChatResponse, UsageResponse,
+    },
+    prompts::{NewPrompt, Prompt, PromptMessage, PromptReference, PromptTemplate, PromptVersion},
+    replay::{ChoiceComparison, ReplayOverrides},
+    testing::TestProvider,
     workflow_events::{
-        ErrorData, EventPayload, RunStartEvent, RunUpdateEvent, StepEndData, StepEventData,
-        StepStartData, WorkflowEvent,
+        ErrorData, EventPayload, RunStartEvent, RunUpdateEvent, ScoreEvent, ScoreSource,
+        ScoreValue, StepEndData, StepEventData, StepStartData, StepStateData, WorkflowEvent,
     },
+    Error,
 };
 
 pub const TEST_STEP1_ID: Uuid = Uuid::from_u128(1);
@@ -33,11 +58,13 @@ pub fn test_events() -> Vec<ProxyLogEntry> {
                 "info2": "value2"
             })),
             time: Some(Utc.timestamp_opt(1, 0).unwrap()),
+            event_id: None,
         })),
         ProxyLogEntry::Workflow(WorkflowEvent::StepStart(StepEventData {
             step_id: TEST_STEP1_ID,
             run_id: TEST_RUN_ID,
             time: Some(Utc.timestamp_opt(2, 0).unwrap()),
+            event_id: None,
             data: StepStartData {
                 name: Some("source_node1".to_string()),
                 typ: "step_type".to_string(),
@@ -52,6 +79,7 @@ pub fn test_events() -> Vec<ProxyLogEntry> {
             step_id: TEST_STEP2_ID,
             run_id: TEST_RUN_ID,
             time: Some(Utc.timestamp_opt(3, 0).unwrap()),
+            event_id: None,
             data: StepStartData {
                 name: Some("source_node2".to_string()),
                 typ: "llm".to_string(),
@@ -89,6 +117,7 @@ pub fn test_events() -> Vec<ProxyLogEntry> {
                 },
                 ..Default::default()
             },
+            offloaded: Default::default(),
         })),
         ProxyLogEntry::Workflow(WorkflowEvent::Event(EventPayload {
             typ: "an_event".to_string(),
@@ -101,12 +130,14 @@ pub fn test_events() -> Vec<ProxyLogEntry> {
             step_id: TEST_STEP2_ID,
             run_id: TEST_RUN_ID,
             time: Some(Utc.timestamp_opt(5, 0).unwrap()),
+            event_id: None,
             internal_metadata: None,
         })),
         ProxyLogEntry::Workflow(WorkflowEvent::StepError(StepEventData {
             step_id: TEST_STEP2_ID,
             run_id: TEST_RUN_ID,
             time: Some(Utc.timestamp_opt(5, 0).unwrap()),
+            event_id: None,
             data: ErrorData {
                 error: json!({"message": "an error"}),
             },
@@ -115,6 +146,7 @@ pub fn test_events() -> Vec<ProxyLogEntry> {
             step_id: TEST_STEP1_ID,
             run_id: TEST_RUN_ID,
             time: Some(Utc.timestamp_opt(5, 0).unwrap()),
+            event_id: None,
             data: StepEndData {
                 output: json!({ "result": "success" }),
                 info: Some(json!({ "info3": "value3" })),
@@ -126,6 +158,1419 @@ pub fn test_events() -> Vec<ProxyLogEntry> {
             output: Some(json!({ "result": "success" })),
             info: Some(json!({ "info2": "new_value", "info3": "value3"})),
             time: Some(Utc.timestamp_opt(5, 0).unwrap()),
+            event_id: None,
         })),
     ]
 }
+
+/// An LLM request event in [TEST_STEP2_ID], with an optional response
+pub fn chat_event(id: Uuid, request: &str, response: Option<&str>) -> ProxyLogEntry {
+    let message = |role: &str, content: &str| ChatMessage {
+        role: Some(role.to_string()),
+        content: Some(content.to_string()),
+        ..Default::default()
+    };
+
+    ProxyLogEntry::Proxied(Box::new(ProxyLogEvent {
+        id,
+        event_type: std::borrow::Cow::Borrowed("chronicle_llm_request"),
+        timestamp: Utc.timestamp_opt(4, 0).unwrap(),
+        request: Some(ChatRequest {
+            messages: vec![message("user", request)],
+            ..Default::default()
+        }),
+        response: response.map(|content| CollectedProxiedResult {
+            body: SingleChatResponse {
+                choices: vec![ChatChoice {
+                    message: message("assistant", content),
+                    ..Default::default()
+                }],
+                ..SingleChatResponse::new_for_collection(1)
+            },
+            info: ResponseInfo {
+                meta: None,
+                model: "a_model".to_string(),
+            },
+            provider: "a_provider".to_string(),
+        }),
+        latency: None,
+        total_latency: None,
+        was_rate_limited: None,
+        num_retries: None,
+        error: None,
+        options: crate::ProxyRequestOptions {
+            metadata: crate::ProxyRequestMetadata {
+                step_id: Some(TEST_STEP2_ID),
+                run_id: Some(TEST_RUN_ID),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        offloaded: Default::default(),
+    }))
+}
+
+/// Generate `n` step starts and `n` events for a new run, for benchmarking writes
+pub fn bench_events(n: usize) -> Vec<ProxyLogEntry> {
+    let mut template = test_events();
+    let ProxyLogEntry::Proxied(event) = template.swap_remove(3) else {
+        panic!("expected a proxied event");
+    };
+    let ProxyLogEntry::Workflow(WorkflowEvent::StepStart(step)) = template.swap_remove(1) else {
+        panic!("expected a step start");
+    };
+
+    let run_id = Uuid::now_v7();
+    (0..n)
+        .flat_map(|_| {
+            let step_id = Uuid::now_v7();
+            let mut step = step.clone();
+            step.step_id = step_id;
+            step.run_id = run_id;
+
+            let mut event = event.clone();
+            event.id = Uuid::now_v7();
+            event.options.metadata.run_id = Some(run_id);
+            event.options.metadata.step_id = Some(step_id);
+
+            [
+                ProxyLogEntry::Workflow(WorkflowEvent::StepStart(step)),
+                ProxyLogEntry::Proxied(event),
+            ]
+        })
+        .collect()
+}
+
+/// Check that the runs and steps written by [test_events] can be read back
+pub async fn test_run_queries(db: &dyn ProxyDatabase) {
+    let runs = db
+        .list_runs(&RunQuery {
+            application: Some("test application".to_string()),
+            tags: vec!["tag1".to_string()],
+            ..Default::default()
+        })
+        .await
+        .expect("Listing runs");
+    assert_eq!(
+        runs.iter().map(|r: &RunSummary| r.id).collect::<Vec<_>>(),
+        vec![TEST_RUN_ID]
+    );
+    assert_eq!(runs[0].tags, vec!["tag1".to_string(), "tag2".to_string()]);
+
+    let runs = db
+        .list_runs(&RunQuery {
+            tags: vec!["tag3".to_string()],
+            ..Default::default()
+        })
+        .await
+        .expect("Listing runs");
+    assert!(runs.is_empty(), "tag filter should exclude the run");
+
+    let runs = db
+        .list_runs(&RunQuery {
+            start_time: Some(Utc.timestamp_opt(2, 0).unwrap()),
+            ..Default::default()
+        })
+        .await
+        .expect("Listing runs");
+    assert!(runs.is_empty(), "time filter should exclude the run");
+
+    let run = db
+        .get_run(TEST_RUN_ID)
+        .await
+        .expect("Fetching run")
+        .expect("Run should exist");
+    assert_eq!(run.run.name, "test run");
+    assert_eq!(run.run.status, "finished");
+    assert_eq!(run.duration_ms, 4000);
+    assert!(
+        run.events.is_empty(),
+        "all events should be attached to steps"
+    );
+
+    assert_eq!(run.steps.len(), 1);
+    let step1 = &run.steps[0];
+    assert_eq!(step1.id, TEST_STEP1_ID);
+    assert_eq!(step1.duration_ms, Some(3000));
+    assert!(step1.events.is_empty());
+
+    assert_eq!(step1.steps.len(), 1);
+    let step2 = &step1.steps[0];
+    assert_eq!(step2.id, TEST_STEP2_ID);
+    assert_eq!(step2.status, "error");
+    assert_eq!(step2.duration_ms, Some(2000));
+    assert_eq!(step2.events.len(), 2);
+    assert_eq!(step2.events[0].id, TEST_EVENT1_ID);
+
+    let missing = db
+        .get_run(Uuid::from_u128(12345))
+        .await
+        .expect("Fetching missing run");
+    assert!(missing.is_none());
+}
+
+/// Check that the usage of LLM calls is added to their steps, the ancestors of those steps, and
+/// their runs. The caller should write [test_events] first.
+pub async fn test_usage_rollups(db: &dyn ProxyDatabase) {
+    let mut with_usage = chat_event(Uuid::from_u128(60), "What is 2 + 2?", Some("4"));
+    if let ProxyLogEntry::Proxied(event) = &mut with_usage {
+        event.latency = Some(Duration::from_millis(100));
+        event.response.as_mut().unwrap().body.usage = Some(UsageResponse {
+            prompt_tokens: Some(10),
+            completion_tokens: Some(5),
+            total_tokens: Some(15),
+        });
+    }
+    db.write_log_batch(vec![with_usage])
+        .await
+        .expect("Writing events");
+
+    let mut failed = chat_event(Uuid::from_u128(61), "What is 3 + 3?", None);
+    if let ProxyLogEntry::Proxied(event) = &mut failed {
+        event.options.metadata.step_id = Some(TEST_STEP1_ID);
+        event.latency = Some(Duration::from_millis(20));
+        event.total_latency = Some(Duration::from_millis(50));
+        event.error = Some(json!({ "message": "an error" }));
+    }
+    db.write_log_batch(vec![failed])
+        .await
+        .expect("Writing events");
+
+    let run = db
+        .get_run(TEST_RUN_ID)
+        .await
+        .expect("Fetching run")
+        .expect("Run should exist");
+    let step1 = &run.steps[0];
+    let step2 = &step1.steps[0];
+
+    // Step 2 has TEST_EVENT1_ID, which had no response, and event 60.
+    assert_eq!(
+        step2.usage,
+        LlmUsage {
+            llm_calls: 2,
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            llm_errors: 0,
+            llm_latency_ms: 100,
+        }
+    );
+    let total = LlmUsage {
+        llm_calls: 3,
+        prompt_tokens: 10,
+        completion_tokens: 5,
+        llm_errors: 1,
+        llm_latency_ms: 150,
+    };
+    assert_eq!(step1.usage, total, "step 1 includes its child step");
+    assert_eq!(run.run.usage, total);
+}
+
+/// Check that usage is rolled up when the LLM calls arrive before their steps and run start, and
+/// when a step's parent only becomes known after it has usage.
+pub async fn test_usage_rollups_out_of_order(db: &dyn ProxyDatabase) {
+    let run_id = Uuid::from_u128(300);
+    let grandparent = Uuid::from_u128(301);
+    let parent = Uuid::from_u128(302);
+    let child = Uuid::from_u128(303);
+
+    let call = |id, step_id| {
+        let mut event = chat_event(Uuid::from_u128(id), "What is 2 + 2?", Some("4"));
+        if let ProxyLogEntry::Proxied(event) = &mut event {
+            event.options.metadata.step_id = Some(step_id);
+            event.options.metadata.run_id = Some(run_id);
+            event.latency = Some(Duration::from_millis(100));
+            event.response.as_mut().unwrap().body.usage = Some(UsageResponse {
+                prompt_tokens: Some(10),
+                completion_tokens: Some(5),
+                total_tokens: Some(15),
+            });
+        }
+        event
+    };
+    let step_start = |step_id, parent_step, secs| {
+        ProxyLogEntry::Workflow(WorkflowEvent::StepStart(StepEventData {
+            step_id,
+            run_id,
+            time: Some(Utc.timestamp_opt(secs, 0).unwrap()),
+            event_id: None,
+            data: StepStartData {
+                name: None,
+                typ: "step_type".to_string(),
+                parent_step,
+                span_id: None,
+                info: None,
+                tags: vec![],
+                input: json!({}),
+            },
+        }))
+    };
+
+    // Nothing has started yet, so the calls create placeholders.
+    db.write_log_batch(vec![call(310, child), call(311, parent)])
+        .await
+        .expect("Writing calls");
+    // The child learns its parent after collecting usage.
+    db.write_log_batch(vec![step_start(child, Some(parent), 3)])
+        .await
+        .expect("Writing child start");
+    // The parent learns its own parent, which hasn't started yet.
+    db.write_log_batch(vec![
+        step_start(parent, Some(grandparent), 2),
+        call(312, child),
+    ])
+    .await
+    .expect("Writing parent start");
+    db.write_log_batch(vec![
+        ProxyLogEntry::Workflow(WorkflowEvent::RunStart(RunStartEvent {
+            id: run_id,
+            name: "usage run".to_string(),
+            description: None,
+            application: None,
+            environment: None,
+            status: None,
+            input: None,
+            trace_id: None,
+            span_id: None,
+            tags: vec![],
+            info: None,
+            time: Some(Utc.timestamp_opt(1, 0).unwrap()),
+            event_id: None,
+        })),
+        step_start(grandparent, None, 1),
+    ])
+    .await
+    .expect("Writing run start");
+
+    let run = db
+        .get_run(run_id)
+        .await
+        .expect("Fetching run")
+        .expect("Run should exist");
+    let usage = |calls| LlmUsage {
+        llm_calls: calls,
+        prompt_tokens: 10 * calls,
+        completion_tokens: 5 * calls,
+        llm_errors: 0,
+        llm_latency_ms: 100 * calls,
+    };
+
+    assert_eq!(run.run.name, "usage run");
+    assert_eq!(run.run.status, "started");
+    assert_eq!(run.run.usage, usage(3));
+
+    assert_eq!(run.steps.len(), 1);
+    let grandparent_step = &run.steps[0];
+    assert_eq!(grandparent_step.id, grandparent);
+    assert_eq!(grandparent_step.typ, "step_type");
+    assert_eq!(grandparent_step.usage, usage(3));
+
+    let parent_step = &grandparent_step.steps[0];
+    assert_eq!(parent_step.id, parent);
+    assert_eq!(parent_step.usage, usage(3));
+
+    let child_step = &parent_step.steps[0];
+    assert_eq!(child_step.id, child);
+    assert_eq!(child_step.status, "started");
+    assert_eq!(child_step.usage, usage(2));
+}
+
+/// LLM calls whose run or step never sends a workflow event should not show up as runs of their
+/// own, or be marked as stale.
+pub async fn test_usage_without_workflow_events(db: Database) {
+    let metadata_run = Uuid::from_u128(320);
+    let started_run = Uuid::from_u128(322);
+    let unstarted_step = Uuid::from_u128(323);
+
+    let call = |id, run_id, step_id| {
+        let mut event = chat_event(Uuid::from_u128(id), "What is 2 + 2?", Some("4"));
+        if let ProxyLogEntry::Proxied(event) = &mut event {
+            event.options.metadata.run_id = Some(run_id);
+            event.options.metadata.step_id = Some(step_id);
+        }
+        event
+    };
+
+    db.write_log_batch(vec![
+        call(330, metadata_run, Uuid::from_u128(321)),
+        ProxyLogEntry::Workflow(WorkflowEvent::RunStart(RunStartEvent {
+            id: started_run,
+            name: "started run".to_string(),
+            description: None,
+            application: None,
+            environment: None,
+            status: None,
+            input: None,
+            trace_id: None,
+            span_id: None,
+            tags: vec![],
+            info: None,
+            time: Some(Utc.timestamp_opt(1, 0).unwrap()),
+            event_id: None,
+        })),
+        call(331, started_run, unstarted_step),
+    ])
+    .await
+    .expect("Writing calls");
+
+    let runs = db
+        .list_runs(&RunQuery::default())
+        .await
+        .expect("Listing runs");
+    assert_eq!(
+        runs.iter().map(|run| run.id).collect::<Vec<_>>(),
+        vec![started_run],
+        "runs only known from call metadata are not listed"
+    );
+
+    let run = db
+        .get_run(metadata_run)
+        .await
+        .expect("Fetching run")
+        .expect("Run should exist");
+    assert_eq!(run.run.status, "pending");
+    assert_eq!(run.run.usage.llm_calls, 1);
+
+    let config = StaleRunConfig {
+        timeout: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+    let counts = sweep_stale_runs(
+        db.as_ref(),
+        &config,
+        Utc::now() + Duration::from_secs(3600),
+        None,
+    )
+    .await
+    .expect("Sweeping");
+    assert_eq!(counts, StaleCounts { runs: 1, steps: 0 });
+
+    let run = db
+        .get_run(started_run)
+        .await
+        .expect("Fetching run")
+        .expect("Run should exist");
+    assert_eq!(run.run.status, "timed_out");
+    assert_eq!(run.steps[0].id, unstarted_step);
+    assert_eq!(run.steps[0].status, "pending");
+    assert_eq!(run.steps[0].usage.llm_calls, 1);
+
+    // An update for the run replaces the pending status, even though it is older than the call.
+    db.write_log_batch(vec![ProxyLogEntry::Workflow(WorkflowEvent::RunUpdate(
+        RunUpdateEvent {
+            id: metadata_run,
+            status: Some("finished".to_string()),
+            output: None,
+            info: None,
+            time: Some(Utc.timestamp_opt(5, 0).unwrap()),
+            event_id: None,
+        },
+    ))])
+    .await
+    .expect("Writing run update");
+
+    let run = db
+        .get_run(metadata_run)
+        .await
+        .expect("Fetching run")
+        .expect("Run should exist");
+    assert_eq!(run.run.status, "finished");
+    assert_eq!(run.run.usage.llm_calls, 1);
+    let runs = db
+        .list_runs(&RunQuery::default())
+        .await
+        .expect("Listing runs");
+    assert_eq!(runs.len(), 2);
+}
+
+/// Write the events from [test_events] out of order and more than once, and check that the run
+/// ends up the same as when they are written in order.
+pub async fn test_out_of_order_events(db: &dyn ProxyDatabase) {
+    let mut events = test_events();
+    let event_id = Uuid::from_u128(70);
+    for entry in &mut events {
+        if let ProxyLogEntry::Workflow(WorkflowEvent::Event(event)) = entry {
+            event.event_id = Some(event_id);
+        }
+    }
+    let run_update = events.pop().unwrap();
+    let step1_end = events.pop().unwrap();
+    let step2_error = events.pop().unwrap();
+    let step2_state = ProxyLogEntry::Workflow(WorkflowEvent::StepState(StepEventData {
+        step_id: TEST_STEP2_ID,
+        run_id: TEST_RUN_ID,
+        time: Some(Utc.timestamp_opt(4, 0).unwrap()),
+        event_id: None,
+        data: StepStateData {
+            state: "running".to_string(),
+        },
+    }));
+
+    // The ends arrive before the starts, and the step state arrives after the newer error.
+    db.write_log_batch(vec![run_update.clone(), step1_end.clone(), step2_error])
+        .await
+        .expect("Writing ends");
+
+    let run = db
+        .get_run(TEST_RUN_ID)
+        .await
+        .expect("Fetching run")
+        .expect("Placeholder run should exist");
+    assert_eq!(run.run.name, "");
+    assert_eq!(run.run.status, "finished");
+    assert_eq!(run.steps.len(), 2);
+    assert!(run.steps.iter().all(|step| step.typ.is_empty()));
+
+    let mut starts = events.clone();
+    starts.push(step2_state);
+    db.write_log_batch(starts).await.expect("Writing starts");
+    // Sending everything again shouldn't change anything. The proxy only logs each LLM call once,
+    // so that isn't sent again.
+    events.retain(|entry| !matches!(entry, ProxyLogEntry::Proxied(_)));
+    events.extend([run_update, step1_end]);
+    db.write_log_batch(events).await.expect("Writing again");
+
+    let run = db
+        .get_run(TEST_RUN_ID)
+        .await
+        .expect("Fetching run")
+        .expect("Run should exist");
+    assert_eq!(run.run.name, "test run");
+    assert_eq!(run.run.application.as_deref(), Some("test application"));
+    assert_eq!(run.run.input, Some(json!({ "query": "abc" })));
+    assert_eq!(run.run.status, "finished");
+    assert_eq!(run.run.output, Some(json!({ "result": "success" })));
+    assert_eq!(
+        run.run.info,
+        Some(json!({ "info1": "value1", "info2": "new_value", "info3": "value3" }))
+    );
+    assert_eq!(run.run.created_at, Utc.timestamp_opt(1, 0).unwrap());
+    assert_eq!(run.run.updated_at, Utc.timestamp_opt(5, 0).unwrap());
+
+    assert_eq!(run.steps.len(), 1);
+    let step1 = &run.steps[0];
+    assert_eq!(step1.id, TEST_STEP1_ID);
+    assert_eq!(step1.typ, "step_type");
+    assert_eq!(step1.name.as_deref(), Some("source_node1"));
+    assert_eq!(step1.status, "finished");
+    assert_eq!(step1.output, Some(json!({ "result": "success" })));
+    assert_eq!(
+        step1.info,
+        Some(json!({ "model": "a_model", "info3": "value3" }))
+    );
+    assert_eq!(step1.start_time, Utc.timestamp_opt(2, 0).unwrap());
+    assert_eq!(step1.end_time, Some(Utc.timestamp_opt(5, 0).unwrap()));
+
+    let step2 = &step1.steps[0];
+    assert_eq!(step2.typ, "llm");
+    assert_eq!(step2.status, "error", "the older step state is ignored");
+    assert_eq!(step2.output, Some(json!({ "message": "an error" })));
+    assert_eq!(step2.start_time, Utc.timestamp_opt(3, 0).unwrap());
+
+    let event_ids = step2.events.iter().map(|e| e.id).collect::<Vec<_>>();
+    assert_eq!(
+        event_ids,
+        vec![TEST_EVENT1_ID, event_id],
+        "the event with an ID is only recorded once"
+    );
+}
+
+/// Check that stale runs are marked, and that the events for their abandoned steps are sent
+/// through the logger.
+pub async fn test_stale_runs(db: Database) {
+    let stale_run = Uuid::from_u128(200);
+    let stale_step = Uuid::from_u128(201);
+    let own_timeout_run = Uuid::from_u128(202);
+    let nightly_run = Uuid::from_u128(203);
+    let active_run = Uuid::from_u128(204);
+    let finished_run = Uuid::from_u128(205);
+    let time = |secs| Some(Utc.timestamp_opt(secs, 0).unwrap());
+
+    let run_start = |id, name: &str, info| {
+        ProxyLogEntry::Workflow(WorkflowEvent::RunStart(RunStartEvent {
+            id,
+            name: name.to_string(),
+            description: None,
+            application: Some("test application".to_string()),
+            environment: None,
+            input: None,
+            trace_id: None,
+            span_id: None,
+            status: None,
+            tags: vec![],
+            info,
+            time: time(1),
+            event_id: None,
+        }))
+    };
+
+    db.write_log_batch(vec![
+        run_start(stale_run, "stale run", None),
+        ProxyLogEntry::Workflow(WorkflowEvent::StepStart(StepEventData {
+            step_id: stale_step,
+            run_id: stale_run,
+            time: time(2),
+            event_id: None,
+            data: StepStartData {
+                typ: "step_type".to_string(),
+                name: Some("stale step".to_string()),
+                parent_step: None,
+                span_id: None,
+                tags: vec![],
+                info: None,
+                input: json!({}),
+            },
+        })),
+        run_start(
+            own_timeout_run,
+            "slow run",
+            Some(json!({ "stale_timeout": 5000 })),
+        ),
+        run_start(nightly_run, "nightly", None),
+        run_start(active_run, "active run", None),
+        ProxyLogEntry::Workflow(WorkflowEvent::Event(EventPayload {
+            typ: "progress".to_string(),
+            data: None,
+            error: None,
+            run_id: active_run,
+            step_id: Uuid::from_u128(206),
+            time: time(990),
+            internal_metadata: None,
+            event_id: None,
+        })),
+        run_start(finished_run, "finished run", None),
+        ProxyLogEntry::Workflow(WorkflowEvent::RunUpdate(RunUpdateEvent {
+            id: finished_run,
+            status: Some("finished".to_string()),
+            output: None,
+            info: None,
+            time: time(2),
+            event_id: None,
+        })),
+    ])
+    .await
+    .expect("Writing events");
+
+    let config = StaleRunConfig {
+        timeout: Some(Duration::from_secs(60)),
+        rules: vec![StaleRunRule {
+            name: Some("nightly".to_string()),
+            ..Default::default()
+        }],
+        record_events: true,
+        batch_size: Some(1),
+        ..Default::default()
+    };
+    let now = time(1000).unwrap();
+    let (log_tx, log_task) =
+        start_database_logger(db.clone(), 10, Duration::from_millis(10), None, None);
+    let counts = sweep_stale_runs(db.as_ref(), &config, now, Some(&log_tx))
+        .await
+        .expect("Sweeping");
+    assert_eq!(counts, StaleCounts { runs: 1, steps: 1 });
+    drop(log_tx);
+    log_task.await.expect("Logger task");
+
+    let counts = sweep_stale_runs(db.as_ref(), &config, now, None)
+        .await
+        .expect("Sweeping again");
+    assert_eq!(counts, StaleCounts::default(), "runs are only marked once");
+
+    let run = db
+        .get_run(stale_run)
+        .await
+        .expect("Fetching run")
+        .expect("Run should exist");
+    assert_eq!(run.run.status, "timed_out");
+    assert_eq!(run.run.updated_at, time(1).unwrap());
+    let step = &run.steps[0];
+    assert_eq!(step.status, "abandoned");
+    assert_eq!(step.end_time, time(2), "the step ends at its last update");
+    let errors = step
+        .events
+        .iter()
+        .filter(|e| e.event_type.as_deref() == Some(STALE_STEP_EVENT_TYPE))
+        .collect::<Vec<_>>();
+    assert_eq!(errors.len(), 1, "the abandoned step has a timeout event");
+
+    for (id, status) in [
+        (own_timeout_run, "started"),
+        (nightly_run, "started"),
+        (active_run, "started"),
+        (finished_run, "finished"),
+    ] {
+        let run = db
+            .get_run(id)
+            .await
+            .expect("Fetching run")
+            .expect("Run should exist");
+        assert_eq!(run.run.status, status, "run {}", run.run.name);
+    }
+
+    // The step was only slow, so its real end takes precedence.
+    db.write_log_batch(vec![ProxyLogEntry::Workflow(WorkflowEvent::StepEnd(
+        StepEventData {
+            step_id: stale_step,
+            run_id: stale_run,
+            time: time(1010),
+            event_id: None,
+            data: StepEndData {
+                output: json!({ "result": "late" }),
+                info: None,
+            },
+        },
+    ))])
+    .await
+    .expect("Writing late step end");
+
+    let run = db
+        .get_run(stale_run)
+        .await
+        .expect("Fetching run")
+        .expect("Run should exist");
+    assert_eq!(run.steps[0].status, "finished");
+    assert_eq!(run.steps[0].end_time, time(1010));
+}
+
+/// Check that the data written by [test_events] is purged according to the retention policy
+pub async fn test_purge(db: &dyn ProxyDatabase) {
+    let now = Utc.timestamp_opt(5, 0).unwrap() + chrono::Duration::days(2);
+
+    let app_rule = RetentionConfig {
+        rules: vec![RetentionRule {
+            application: Some("test application".to_string()),
+            max_age_days: Some(1),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let counts = purge(db, &app_rule, now, true).await.expect("Dry run");
+    assert_eq!(
+        counts,
+        PurgeCounts {
+            events: 0,
+            runs: 1,
+            steps: 2,
+            ..Default::default()
+        },
+        "only the run matches the application rule"
+    );
+
+    let keep_newer = RetentionConfig {
+        max_age_days: Some(3),
+        ..Default::default()
+    };
+    let counts = purge(db, &keep_newer, now, false).await.expect("Purging");
+    assert_eq!(counts, PurgeCounts::default(), "nothing is old enough");
+
+    let archive_dir = std::env::temp_dir().join(format!("chronicle-archive-{}", Uuid::now_v7()));
+    let config = RetentionConfig {
+        max_age_days: Some(1),
+        batch_size: Some(1),
+        archive_dir: Some(archive_dir.clone()),
+        ..Default::default()
+    };
+    let counts = purge(db, &config, now, false).await.expect("Purging");
+    assert_eq!(
+        counts,
+        PurgeCounts {
+            events: 2,
+            runs: 1,
+            steps: 2,
+            ..Default::default()
+        }
+    );
+
+    let run = db.get_run(TEST_RUN_ID).await.expect("Getting run");
+    assert!(run.is_none(), "run should be deleted");
+
+    let counts = purge(db, &config, now, true).await.expect("Dry run");
+    assert_eq!(counts, PurgeCounts::default(), "everything was deleted");
+
+    let mut archived = Vec::new();
+    for file in std::fs::read_dir(&archive_dir).expect("Reading archive dir") {
+        let contents = std::fs::read_to_string(file.unwrap().path()).unwrap();
+        archived.extend(
+            contents
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()),
+        );
+    }
+    std::fs::remove_dir_all(&archive_dir).ok();
+
+    let mut tables = archived
+        .iter()
+        .map(|row| row["table"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    tables.sort();
+    assert_eq!(
+        tables,
+        vec![
+            "chronicle_events",
+            "chronicle_events",
+            "chronicle_runs",
+            "chronicle_steps",
+            "chronicle_steps"
+        ]
+    );
+}
+
+/// Check that large bodies are moved to the blob store, and loaded again when reading runs.
+/// [test_events] should already have been written to `inner`.
+pub async fn test_blob_offloading(inner: Database) {
+    let blob_dir = std::env::temp_dir().join(format!("chronicle-blobs-{}", Uuid::now_v7()));
+    let store = std::sync::Arc::new(FilesystemBlobStore::new(&blob_dir));
+    let db = BlobOffloadingDatabase::new(inner.clone(), store, 1000);
+
+    let small_id = Uuid::from_u128(6);
+    let large_id = Uuid::from_u128(7);
+    let large_content = "x".repeat(2000);
+    db.write_log_batch(vec![
+        chat_event(small_id, "hello", None),
+        chat_event(large_id, &large_content, None),
+    ])
+    .await
+    .expect("Writing events");
+
+    let find_request = |run: &crate::database::runs::RunDetail, id: Uuid| {
+        run.steps[0].steps[0]
+            .events
+            .iter()
+            .find(|e| e.id == id)
+            .and_then(|e| e.chat_request.clone())
+            .expect("Event should have a request")
+    };
+
+    let stored = inner
+        .get_run(TEST_RUN_ID)
+        .await
+        .expect("Fetching run")
+        .expect("Run should exist");
+    let reference = BlobRef::from_value(&find_request(&stored, large_id))
+        .expect("Large request should be stored as a reference");
+    assert!(reference.size > 2000);
+    assert!(
+        BlobRef::from_value(&find_request(&stored, small_id)).is_none(),
+        "Small request should be stored inline"
+    );
+
+    let run = db
+        .get_run(TEST_RUN_ID)
+        .await
+        .expect("Fetching run")
+        .expect("Run should exist");
+    assert_eq!(
+        find_request(&run, large_id)["messages"][0]["content"],
+        json!(large_content)
+    );
+    assert_eq!(
+        find_request(&run, small_id)["messages"][0]["content"],
+        json!("hello")
+    );
+
+    let archived = db
+        .load_rows_for_archive(PurgeTable::Events, &[large_id])
+        .await
+        .expect("Loading rows");
+    let request = &archived[0].row["chat_request"];
+    // SQLite returns JSON columns as strings.
+    let request = match request.as_str() {
+        Some(s) => serde_json::from_str(s).unwrap(),
+        None => request.clone(),
+    };
+    assert_eq!(request["messages"][0]["content"], json!(large_content));
+
+    std::fs::remove_dir_all(&blob_dir).ok();
+}
+
+/// Check full-text search over requests and responses. [test_events] should already have been
+/// written to `db`, and the search index should be enabled.
+pub async fn test_search(db: &dyn ProxyDatabase) {
+    db.write_log_batch(vec![
+        chat_event(
+            Uuid::from_u128(6),
+            "What color is the sky?",
+            Some("The sky is blue"),
+        ),
+        chat_event(
+            Uuid::from_u128(7),
+            "Name a fruit",
+            Some("A banana is a yellow fruit"),
+        ),
+        chat_event(Uuid::from_u128(8), "Describe a banana", None),
+    ])
+    .await
+    .expect("Writing events");
+
+    let search = |query: &str| EventSearchQuery {
+        query: query.to_string(),
+        ..Default::default()
+    };
+    let ids = |results: Vec<EventSearchResult>| {
+        let mut ids = results.iter().map(|r| r.event.id).collect::<Vec<_>>();
+        ids.sort();
+        ids
+    };
+
+    let results = db.search_events(&search("blue")).await.expect("Searching");
+    assert_eq!(
+        ids(results.clone()),
+        vec![Uuid::from_u128(6)],
+        "matches responses"
+    );
+    assert_eq!(results[0].run_id, Some(TEST_RUN_ID));
+    assert_eq!(results[0].event.step_id, Some(TEST_STEP2_ID));
+
+    let results = db
+        .search_events(&search("banana"))
+        .await
+        .expect("Searching");
+    assert_eq!(
+        ids(results),
+        vec![Uuid::from_u128(7), Uuid::from_u128(8)],
+        "matches requests and responses"
+    );
+
+    let results = db
+        .search_events(&search("yellow banana"))
+        .await
+        .expect("Searching");
+    assert_eq!(ids(results), vec![Uuid::from_u128(7)], "matches all words");
+
+    let results = db
+        .search_events(&search("\"unbalanced"))
+        .await
+        .expect("Searching with punctuation");
+    assert!(results.is_empty());
+
+    let results = db.search_events(&search("  ")).await.expect("Empty search");
+    assert!(results.is_empty());
+
+    let results = db
+        .search_events(&EventSearchQuery {
+            start_time: Some(Utc.timestamp_opt(10, 0).unwrap()),
+            ..search("banana")
+        })
+        .await
+        .expect("Searching");
+    assert!(results.is_empty(), "time filter should exclude the events");
+}
+
+/// Check that scores are written and read back. [test_events] should already have been written
+/// to `db`.
+pub async fn test_scores(db: &dyn ProxyDatabase) {
+    let score = |name: &str, value: ScoreValue, source: ScoreSource| ScoreEvent {
+        id: None,
+        run_id: None,
+        step_id: None,
+        event_id: None,
+        name: name.to_string(),
+        value,
+        source,
+        comment: None,
+        time: Some(Utc.timestamp_opt(6, 0).unwrap()),
+    };
+    let entry = |score: ScoreEvent| ProxyLogEntry::Workflow(WorkflowEvent::Score(score));
+
+    let duplicate = ScoreEvent {
+        id: Some(Uuid::from_u128(300)),
+        step_id: Some(TEST_STEP1_ID),
+        ..score("quality", ScoreValue::Numeric(0.5), ScoreSource::Model)
+    };
+    db.write_log_batch(vec![
+        // Scores are written after the events in the same batch, so the run ID can be filled
+        // in from this event.
+        chat_event(Uuid::from_u128(9), "Tell me a joke", Some("No")),
+        entry(ScoreEvent {
+            event_id: Some(Uuid::from_u128(9)),
+            comment: Some("Not funny".to_string()),
+            ..score("thumbs_up", ScoreValue::Boolean(false), ScoreSource::Human)
+        }),
+        entry(ScoreEvent {
+            event_id: Some(TEST_EVENT1_ID),
+            ..score("thumbs_up", ScoreValue::Boolean(true), ScoreSource::Human)
+        }),
+        entry(duplicate.clone()),
+        entry(duplicate),
+        entry(ScoreEvent {
+            run_id: Some(TEST_RUN_ID),
+            ..score(
+                "label",
+                ScoreValue::Categorical("correct".to_string()),
+                ScoreSource::Heuristic,
+            )
+        }),
+    ])
+    .await
+    .expect("Writing scores");
+
+    let scores = db
+        .list_scores(ScoreTarget::Run(TEST_RUN_ID))
+        .await
+        .expect("Listing run scores");
+    assert_eq!(scores.len(), 4, "duplicate score IDs are only written once");
+    assert!(scores.iter().all(|s| s.run_id == Some(TEST_RUN_ID)));
+
+    let summary = summarize_scores(&scores);
+    assert_eq!(
+        summary
+            .iter()
+            .map(|s| (s.name.as_str(), s.count, s.mean))
+            .collect::<Vec<_>>(),
+        vec![
+            ("label", 1, None),
+            ("quality", 1, Some(0.5)),
+            ("thumbs_up", 2, Some(0.5))
+        ]
+    );
+
+    let scores = db
+        .list_scores(ScoreTarget::Event(Uuid::from_u128(9)))
+        .await
+        .expect("Listing event scores");
+    assert_eq!(scores.len(), 1);
+    assert_eq!(scores[0].value, ScoreValue::Boolean(false));
+    assert_eq!(scores[0].source, ScoreSource::Human);
+    assert_eq!(scores[0].comment.as_deref(), Some("Not funny"));
+    assert_eq!(scores[0].created_at, Utc.timestamp_opt(6, 0).unwrap());
+
+    let scores = db
+        .list_scores(ScoreTarget::Step(TEST_STEP1_ID))
+        .await
+        .expect("Listing step scores");
+    assert_eq!(scores.len(), 1);
+    assert_eq!(scores[0].id, Uuid::from_u128(300));
+
+    let scores = db
+        .list_scores(ScoreTarget::Run(Uuid::from_u128(12345)))
+        .await
+        .expect("Listing scores for a missing run");
+    assert!(scores.is_empty());
+}
+
+pub async fn test_datasets(db: &dyn ProxyDatabase) {
+    db.write_log_batch(vec![
+        chat_event(Uuid::from_u128(20), "What is 2 + 2?", Some("4")),
+        chat_event(Uuid::from_u128(21), "What is 3 + 3?", Some("6")),
+        chat_event(Uuid::from_u128(22), "What is 4 + 4?", None),
+    ])
+    .await
+    .expect("Writing events");
+
+    let v1 = db
+        .create_dataset(&NewDataset {
+            name: "math".to_string(),
+            description: Some("Arithmetic".to_string()),
+        })
+        .await
+        .expect("Creating dataset");
+    assert_eq!(v1.version, 1);
+    assert_eq!(v1.item_count, 0);
+
+    let added = db
+        .add_dataset_items(
+            v1.id,
+            &DatasetItemSource::Events(vec![
+                Uuid::from_u128(20),
+                Uuid::from_u128(20),
+                TEST_EVENT1_ID,
+                Uuid::from_u128(12345),
+            ]),
+        )
+        .await
+        .expect("Adding events by ID");
+    assert_eq!(
+        added, 1,
+        "duplicate, missing, and request-less events are skipped"
+    );
+
+    let added = db
+        .add_dataset_items(
+            v1.id,
+            &DatasetItemSource::Query(DatasetEventQuery {
+                run_id: Some(TEST_RUN_ID),
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect("Adding events by query");
+    assert_eq!(added, 2, "event 20 is already in the dataset");
+
+    let items = db
+        .list_dataset_items(v1.id)
+        .await
+        .expect("Listing dataset items");
+    assert_eq!(items.len(), 3);
+    let item_for = |items: &[DatasetItem], event: u128| {
+        items
+            .iter()
+            .find(|item| item.event_id == Some(Uuid::from_u128(event)))
+            .cloned()
+            .expect("item for event")
+    };
+    let item = item_for(&items, 20);
+    assert_eq!(item.dataset_id, v1.id);
+    assert_eq!(
+        item.request["messages"][0]["content"],
+        json!("What is 2 + 2?")
+    );
+    assert_eq!(
+        item.expected_response.unwrap()["choices"][0]["message"]["content"],
+        json!("4")
+    );
+    let item = item_for(&items, 22);
+    assert_eq!(item.expected_response, None);
+
+    let expected = json!({ "choices": [{ "message": { "role": "assistant", "content": "8" } }] });
+    let updated = db
+        .update_dataset_item(v1.id, item.id, &expected)
+        .await
+        .expect("Updating item");
+    assert!(updated);
+    let updated = db
+        .update_dataset_item(Uuid::from_u128(12345), item.id, &expected)
+        .await
+        .expect("Updating item in another dataset");
+    assert!(!updated);
+
+    let v2 = db
+        .create_dataset(&NewDataset {
+            name: "math".to_string(),
+            description: None,
+        })
+        .await
+        .expect("Creating a new version");
+    assert_eq!(v2.version, 2);
+    assert_eq!(v2.item_count, 3);
+
+    let v2_items = db
+        .list_dataset_items(v2.id)
+        .await
+        .expect("Listing new version items");
+    assert_eq!(item_for(&v2_items, 22).expected_response, Some(expected));
+    db.update_dataset_item(v2.id, item_for(&v2_items, 20).id, &json!(null))
+        .await
+        .expect("Updating new version");
+    let v1_items = db.list_dataset_items(v1.id).await.unwrap();
+    assert!(
+        item_for(&v1_items, 20).expected_response.is_some(),
+        "editing a version leaves the earlier versions alone"
+    );
+
+    let datasets = db.list_datasets().await.expect("Listing datasets");
+    assert_eq!(
+        datasets
+            .iter()
+            .map(|d| (d.name.as_str(), d.version, d.item_count))
+            .collect::<Vec<_>>(),
+        vec![("math", 1, 3), ("math", 2, 3)]
+    );
+
+    let dataset = db.get_dataset(v1.id).await.expect("Loading dataset");
+    assert_eq!(
+        dataset.map(|d| d.description),
+        Some(Some("Arithmetic".to_string()))
+    );
+    let dataset = db
+        .get_dataset(Uuid::from_u128(12345))
+        .await
+        .expect("Loading missing dataset");
+    assert!(dataset.is_none());
+}
+
+pub async fn test_replay(db: Database) {
+    let original_id = Uuid::from_u128(30);
+    db.write_log_batch(vec![chat_event(original_id, "What is 2 + 2?", Some("5"))])
+        .await
+        .expect("Writing events");
+
+    let mut proxy = crate::Proxy::builder()
+        .without_default_providers()
+        .with_provider(Arc::new(TestProvider {
+            response: "4".to_string(),
+            ..Default::default()
+        }))
+        .with_database(db.clone())
+        .log_to_database(true)
+        .build()
+        .await
+        .expect("Building proxy");
+
+    let overrides = ReplayOverrides {
+        model: Some("test-model".to_string()),
+        provider: Some("test".to_string()),
+        temperature: Some(0.5),
+        system: None,
+    };
+    let result = proxy
+        .replay(original_id, &overrides, Default::default())
+        .await
+        .expect("Replaying event");
+    assert_eq!(result.original.id, original_id);
+    assert_eq!(
+        result.choices,
+        vec![ChoiceComparison {
+            index: 0,
+            original: Some("5".to_string()),
+            replay: Some("4".to_string()),
+            changed: true,
+        }]
+    );
+
+    let err = proxy
+        .replay(Uuid::from_u128(12345), &overrides, Default::default())
+        .await
+        .expect_err("Replaying a missing event");
+    assert!(matches!(err.current_context(), Error::EventNotFound(_)));
+
+    // Wait for the new event to be written
+    proxy.shutdown().await;
+
+    let replay = db
+        .get_event(result.replay.request_info.id)
+        .await
+        .expect("Loading replayed event")
+        .expect("Replayed event should be logged");
+    assert_eq!(replay.replay_of, Some(original_id));
+    assert_eq!(replay.model.as_deref(), Some("test-model"));
+    assert_eq!(
+        replay.chat_request.as_ref().unwrap()["temperature"],
+        json!(0.5)
+    );
+
+    let original = db
+        .get_event(original_id)
+        .await
+        .expect("Loading original event")
+        .expect("Original event exists");
+    assert_eq!(original.replay_of, None);
+}
+
+pub async fn test_prompts(db: Database) {
+    let new_prompt = |system: &str, labels: &[&str]| NewPrompt {
+        id: "greet".to_string(),
+        description: Some("Greet the user".to_string()),
+        template: PromptTemplate {
+            system: Some(system.to_string()),
+            messages: vec![PromptMessage {
+                role: "user".to_string(),
+                content: "My name is {{name}}".to_string(),
+            }],
+            model: Some("test-model".to_string()),
+            provider: Some("test".to_string()),
+            ..Default::default()
+        },
+        labels: labels.iter().map(|l| l.to_string()).collect(),
+    };
+
+    let v1 = db
+        .create_prompt(&new_prompt("Say hello", &["production", "production"]))
+        .await
+        .expect("Creating prompt");
+    assert_eq!(v1.version, 1);
+    assert_eq!(v1.labels, vec!["production"]);
+    let v2 = db
+        .create_prompt(&new_prompt("Say hello politely", &["production"]))
+        .await
+        .expect("Creating second version");
+    assert_eq!(v2.version, 2);
+
+    let prompts = db.list_prompts().await.expect("Listing prompts");
+    assert_eq!(prompts.len(), 1);
+    assert_eq!(prompts[0].version, 2);
+    assert_eq!(prompts[0].labels, vec!["production"]);
+    assert_eq!(prompts[0].template, v2.template);
+
+    let versions = db
+        .list_prompt_versions("greet")
+        .await
+        .expect("Listing versions");
+    assert_eq!(
+        versions.iter().map(|p| p.version).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    assert!(versions[0].labels.is_empty(), "label moved to version 2");
+
+    let get = |version: PromptVersion| {
+        let db = db.clone();
+        async move {
+            db.get_prompt("greet", &version)
+                .await
+                .expect("Loading prompt")
+                .map(|p| p.version)
+        }
+    };
+    assert_eq!(get(PromptVersion::Latest).await, Some(2));
+    assert_eq!(get(PromptVersion::Version(1)).await, Some(1));
+    assert_eq!(get(PromptVersion::Version(3)).await, None);
+    assert_eq!(
+        get(PromptVersion::Label("production".to_string())).await,
+        Some(2)
+    );
+    assert_eq!(get(PromptVersion::Label("stable".to_string())).await, None);
+
+    assert!(db.set_prompt_label("greet", "stable", 1).await.unwrap());
+    assert!(!db.set_prompt_label("greet", "stable", 9).await.unwrap());
+    assert_eq!(
+        get(PromptVersion::Label("stable".to_string())).await,
+        Some(1)
+    );
+    assert!(db.remove_prompt_label("greet", "stable").await.unwrap());
+    assert!(!db.remove_prompt_label("greet", "stable").await.unwrap());
+
+    let mut proxy = crate::Proxy::builder()
+        .without_default_providers()
+        .with_provider(Arc::new(TestProvider::default()))
+        .with_prompt(Prompt {
+            id: "configured".to_string(),
+            version: 1,
+            labels: vec![],
+            description: None,
+            template: Default::default(),
+            created_at: None,
+        })
+        .with_database(db.clone())
+        .log_to_database(true)
+        .build()
+        .await
+        .expect("Building proxy");
+
+    let configured = proxy
+        .get_prompt("configured", &PromptVersion::Latest)
+        .await
+        .expect("Loading configured prompt");
+    assert_eq!(configured.map(|p| p.version), Some(1));
+
+    let options = crate::ProxyRequestOptions {
+        prompt: Some(PromptReference {
+            id: "greet".to_string(),
+            label: Some("production".to_string()),
+            variables: json!({ "name": "Ada" }).as_object().cloned().unwrap(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let response = proxy
+        .send(options, ChatRequest::default())
+        .await
+        .expect("Sending request with a prompt");
+    let response = crate::collect_response(response, 1)
+        .await
+        .expect("Collecting response");
+
+    let missing = crate::ProxyRequestOptions {
+        prompt: Some(PromptReference {
+            id: "greet".to_string(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let err = proxy
+        .send(missing, ChatRequest::default())
+        .await
+        .expect_err("Sending without the prompt's variables");
+    assert!(matches!(
+        err.current_context(),
+        Error::MissingPromptVariable(_, name) if name == "name"
+    ));
+
+    proxy.shutdown().await;
+
+    let event = db
+        .get_event(response.request_info.id)
+        .await
+        .expect("Loading event")
+        .expect("Event should be logged");
+    assert_eq!(event.model.as_deref(), Some("test-model"));
+    let request = event.chat_request.expect("request");
+    assert_eq!(request["system"], json!("Say hello politely"));
+    assert_eq!(request["messages"][0]["content"], json!("My name is Ada"));
+
+    assert_eq!(db.delete_prompt("greet", Some(1)).await.unwrap(), 1);
+    assert_eq!(db.delete_prompt("greet", None).await.unwrap(), 1);
+    assert!(db.list_prompts().await.unwrap().is_empty());
+    assert_eq!(get(PromptVersion::Latest).await, None);
+}
+
+pub async fn test_export(db: Database) {
+    let labeled = |id: u128, request: &str, response: Option<&str>, label: &str| {
+        let mut entry = chat_event(Uuid::from_u128(id), request, response);
+        if let ProxyLogEntry::Proxied(event) = &mut entry {
+            event.options.metadata.extra = json!({ "label": label }).as_object().cloned();
+        }
+        entry
+    };
+
+    let mut failed = labeled(43, "What is 5 + 5?", Some("11"), "good");
+    if let ProxyLogEntry::Proxied(event) = &mut failed {
+        event.error = Some(json!({ "message": "an error" }));
+    }
+
+    db.write_log_batch(vec![
+        labeled(40, "What is 2 + 2?", Some("4"), "good"),
+        labeled(41, "What is 2 + 2?", Some("4"), "good"),
+        labeled(42, "What is 3 + 3?", None, "good"),
+        failed,
+        labeled(44, "What is 4 + 4?", Some("8"), "bad"),
+    ])
+    .await
+    .expect("Writing events");
+
+    let query = ExportEventQuery {
+        metadata: [("label".to_string(), "good".to_string())].into(),
+        ..Default::default()
+    };
+    let list = |query: ExportEventQuery, after: Option<u128>, limit: u32| {
+        let db = db.clone();
+        async move {
+            let page = ExportPage {
+                after: after.map(Uuid::from_u128),
+                limit,
+            };
+            db.list_events_for_export(&query, page)
+                .await
+                .expect("Listing events for export")
+                .into_iter()
+                .map(|event| event.id.as_u128())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(
+        list(query.clone(), None, 10).await,
+        vec![40, 41],
+        "events with errors or without a response are skipped"
+    );
+    assert_eq!(list(query.clone(), None, 1).await, vec![40]);
+    assert_eq!(list(query.clone(), Some(40), 1).await, vec![41]);
+
+    let with_errors = ExportEventQuery {
+        include_errors: true,
+        ..query.clone()
+    };
+    assert_eq!(list(with_errors, None, 10).await, vec![40, 41, 43]);
+
+    let examples = export_fine_tuning(
+        db.clone(),
+        FineTuningOptions {
+            query,
+            ..Default::default()
+        },
+    )
+    .try_collect::<Vec<_>>()
+    .await
+    .expect("Exporting events");
+    assert_eq!(examples.len(), 1, "duplicate examples are skipped");
+    assert_eq!(examples[0].event_id, Uuid::from_u128(40));
+    assert_eq!(examples[0].split, FineTuningSplit::Train);
+    let example: serde_json::Value = serde_json::from_str(&examples[0].line).unwrap();
+    assert_eq!(
+        example,
+        json!({
+            "messages": [
+                { "role": "user", "content": "What is 2 + 2?" },
+                { "role": "assistant", "content": "4" },
+            ]
+        })
+    );
+}
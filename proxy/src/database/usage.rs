@@ -0,0 +1,234 @@
+//! Rolling up the LLM usage of events into their steps and runs
+use ahash::AHashMap;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::logging::ProxyLogEvent;
+
+/// The usage columns of the steps and runs tables, in the same order as the fields of [LlmUsage]
+pub(super) const USAGE_COLUMNS: &str =
+    "llm_calls, prompt_tokens, completion_tokens, llm_errors, llm_latency_ms";
+
+/// In the upsert of a run or step `t`, add the inserted usage to the existing totals
+pub(super) const ADD_USAGE: &str = "llm_calls = t.llm_calls + EXCLUDED.llm_calls,
+    prompt_tokens = t.prompt_tokens + EXCLUDED.prompt_tokens,
+    completion_tokens = t.completion_tokens + EXCLUDED.completion_tokens,
+    llm_errors = t.llm_errors + EXCLUDED.llm_errors,
+    llm_latency_ms = t.llm_latency_ms + EXCLUDED.llm_latency_ms";
+
+/// Totals of the LLM calls made in a step or run. The totals for a step include its child steps.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct LlmUsage {
+    /// The number of LLM calls
+    pub llm_calls: i64,
+    /// The number of input tokens across all calls
+    pub prompt_tokens: i64,
+    /// The number of output tokens across all calls
+    pub completion_tokens: i64,
+    /// The number of calls that returned an error
+    pub llm_errors: i64,
+    /// The sum of the latency of each call, including retries
+    pub llm_latency_ms: i64,
+}
+
+impl LlmUsage {
+    /// The usage of a single LLM call
+    pub fn from_event(event: &ProxyLogEvent) -> Self {
+        let usage = event
+            .response
+            .as_ref()
+            .and_then(|response| response.body.usage.as_ref());
+        let latency = event.total_latency.or(event.latency);
+
+        LlmUsage {
+            llm_calls: 1,
+            prompt_tokens: usage.and_then(|u| u.prompt_tokens).unwrap_or(0) as i64,
+            completion_tokens: usage.and_then(|u| u.completion_tokens).unwrap_or(0) as i64,
+            llm_errors: i64::from(event.error.is_some()),
+            llm_latency_ms: latency.map(|l| l.as_millis() as i64).unwrap_or(0),
+        }
+    }
+
+    pub fn add(&mut self, other: &LlmUsage) {
+        self.llm_calls += other.llm_calls;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.llm_errors += other.llm_errors;
+        self.llm_latency_ms += other.llm_latency_ms;
+    }
+}
+
+/// The usage of a single LLM call, along with the step and run it was made in
+#[derive(Debug, Clone, Copy)]
+pub struct LlmCall {
+    pub step_id: Option<Uuid>,
+    pub run_id: Option<Uuid>,
+    pub usage: LlmUsage,
+    pub time: DateTime<Utc>,
+}
+
+impl LlmCall {
+    /// Get the usage of an event, if it was made in a step or run
+    pub fn from_event(event: &ProxyLogEvent) -> Option<Self> {
+        let metadata = &event.options.metadata;
+        if metadata.step_id.is_none() && metadata.run_id.is_none() {
+            return None;
+        }
+
+        Some(LlmCall {
+            step_id: metadata.step_id,
+            run_id: metadata.run_id,
+            usage: LlmUsage::from_event(event),
+            time: event.timestamp,
+        })
+    }
+}
+
+/// The usage of the LLM calls made in one step or run
+#[derive(Debug, Clone, Copy)]
+pub struct PendingUsage {
+    pub usage: LlmUsage,
+    /// The run that the calls were made in, if known. This is used to create a placeholder for a
+    /// step that hasn't started yet.
+    pub run_id: Option<Uuid>,
+    /// The time of the latest call, used as the update time of placeholders
+    pub last_call: DateTime<Utc>,
+}
+
+impl PendingUsage {
+    fn add(&mut self, usage: &LlmUsage, run_id: Option<Uuid>, time: DateTime<Utc>) {
+        self.usage.add(usage);
+        self.run_id = self.run_id.or(run_id);
+        self.last_call = self.last_call.max(time);
+    }
+}
+
+/// The usage of the LLM calls in a batch of events, grouped by step and by run.
+///
+/// Steps and runs which don't exist yet, such as when their start arrives in a later batch, are
+/// written as placeholders which hold the usage until the start fills them in.
+#[derive(Debug, Default)]
+pub struct UsageRollup {
+    /// Usage for the step that each call was made in. This does not include the step's ancestors,
+    /// which are added when the rollup is written.
+    pub steps: AHashMap<Uuid, PendingUsage>,
+    pub runs: AHashMap<Uuid, PendingUsage>,
+}
+
+impl UsageRollup {
+    /// Add an LLM call to the totals of its step and run
+    pub fn add_event(&mut self, event: &ProxyLogEvent) {
+        if let Some(call) = LlmCall::from_event(event) {
+            self.add_call(&call);
+        }
+    }
+
+    /// Add the usage of an LLM call to the totals of its step and run
+    pub fn add_call(&mut self, call: &LlmCall) {
+        let add = |map: &mut AHashMap<Uuid, PendingUsage>, id| {
+            map.entry(id)
+                .or_insert(PendingUsage {
+                    usage: LlmUsage::default(),
+                    run_id: None,
+                    last_call: call.time,
+                })
+                .add(&call.usage, call.run_id, call.time);
+        };
+
+        if let Some(step_id) = call.step_id {
+            add(&mut self.steps, step_id);
+        }
+        if let Some(run_id) = call.run_id {
+            add(&mut self.runs, run_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        database::logging::CollectedProxiedResult,
+        format::{ResponseInfo, SingleChatResponse, UsageResponse},
+        ProxyRequestMetadata, ProxyRequestOptions,
+    };
+
+    fn event(step: Option<u128>, run: Option<u128>, error: bool) -> ProxyLogEvent {
+        ProxyLogEvent {
+            id: Uuid::now_v7(),
+            event_type: std::borrow::Cow::Borrowed("chronicle_llm_request"),
+            timestamp: Utc::now(),
+            request: None,
+            response: (!error).then(|| CollectedProxiedResult {
+                body: SingleChatResponse {
+                    usage: Some(UsageResponse {
+                        prompt_tokens: Some(10),
+                        completion_tokens: Some(5),
+                        total_tokens: Some(15),
+                    }),
+                    ..SingleChatResponse::new_for_collection(1)
+                },
+                info: ResponseInfo {
+                    meta: None,
+                    model: "a_model".to_string(),
+                },
+                provider: "a_provider".to_string(),
+            }),
+            latency: Some(Duration::from_millis(100)),
+            total_latency: error.then_some(Duration::from_millis(250)),
+            was_rate_limited: None,
+            num_retries: None,
+            error: error.then(|| serde_json::json!({ "message": "failed" })),
+            options: ProxyRequestOptions {
+                metadata: ProxyRequestMetadata {
+                    step_id: step.map(Uuid::from_u128),
+                    run_id: run.map(Uuid::from_u128),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            offloaded: Default::default(),
+        }
+    }
+
+    #[test]
+    fn rolls_up_events() {
+        let mut rollup = UsageRollup::default();
+        rollup.add_event(&event(Some(1), Some(100), false));
+        rollup.add_event(&event(Some(1), Some(100), true));
+        rollup.add_event(&event(Some(2), Some(100), false));
+        rollup.add_event(&event(None, None, false));
+
+        assert_eq!(
+            rollup.steps[&Uuid::from_u128(1)].usage,
+            LlmUsage {
+                llm_calls: 2,
+                prompt_tokens: 10,
+                completion_tokens: 5,
+                llm_errors: 1,
+                llm_latency_ms: 350,
+            }
+        );
+        assert_eq!(
+            rollup.steps[&Uuid::from_u128(1)].run_id,
+            Some(Uuid::from_u128(100))
+        );
+        assert_eq!(rollup.steps[&Uuid::from_u128(2)].usage.llm_calls, 1);
+        assert_eq!(
+            rollup.runs[&Uuid::from_u128(100)].usage,
+            LlmUsage {
+                llm_calls: 3,
+                prompt_tokens: 20,
+                completion_tokens: 10,
+                llm_errors: 1,
+                llm_latency_ms: 450,
+            }
+        );
+        assert_eq!(rollup.runs.len(), 1);
+    }
+}
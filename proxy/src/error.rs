@@ -1,3 +1,7 @@
+use uuid::Uuid;
+
+use crate::validate::ConfigProblem;
+
 /// Proxy errors
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -37,14 +41,14 @@ pub enum Error {
     #[error("API key not provided")]
     MissingApiKey,
 
-    /// The environment variable for the API key was not found
-    #[error("Did not find environment variable {1} for API key {0}")]
-    MissingApiKeyEnv(String, String),
-
     /// Failed to parse the model provider's output
     #[error("Failed to parse model provider's output")]
     ResultParseError,
 
+    /// Failed to write data to the database
+    #[error("Failed to write to the database")]
+    WritingDatabase,
+
     /// Failed to read the configuration file
     #[error("Failed to read configuration file")]
     ReadingConfig,
@@ -60,4 +64,84 @@ pub enum Error {
     /// A required piece of information was missing from the response stream
     #[error("Did not see {0} in response stream")]
     MissingStreamInformation(&'static str),
+
+    /// Failed to send or receive live events
+    #[error("Failed to process live events")]
+    LiveEvents,
+
+    /// Failed to write log entries to a sink
+    #[error("Failed to write to log sink")]
+    LogSink,
+
+    /// Failed to set up or encode metrics
+    #[error("Failed to process metrics")]
+    Metrics,
+
+    /// Validation found problems in the configuration
+    #[error("Found {} problems in the configuration", .0.len())]
+    InvalidConfig(Vec<ConfigProblem>),
+
+    /// Failed to purge old data from the database
+    #[error("Failed to purge old data")]
+    Purge,
+
+    /// Failed to find or mark runs that stopped sending updates
+    #[error("Failed to mark stale runs")]
+    StaleRuns,
+
+    /// A redaction rule or mask could not be parsed
+    #[error("{0}")]
+    InvalidRedaction(String),
+
+    /// Failed to create or drop partitions of the events table
+    #[error("Failed to manage event partitions")]
+    Partition,
+
+    /// Failed to read or write the blob store
+    #[error("Failed to access the blob store")]
+    BlobStore,
+
+    /// The database's full-text search index has not been enabled
+    #[error("Full-text search is not enabled for this database")]
+    SearchNotEnabled,
+
+    /// A table prefix or schema name contains characters that are not allowed
+    #[error("Invalid table prefix or schema {0}")]
+    InvalidTableName(String),
+
+    /// A score was missing information or had an invalid value
+    #[error("{0}")]
+    InvalidScore(String),
+
+    /// A scorer's configuration was invalid
+    #[error("{0}")]
+    InvalidScorer(String),
+
+    /// Failed to run an item in an eval
+    #[error("Failed to run eval item")]
+    Eval,
+
+    /// The operation needs a database, but the proxy does not have one
+    #[error("The proxy has no database")]
+    NoDatabase,
+
+    /// The requested event does not exist
+    #[error("Event {0} not found")]
+    EventNotFound(Uuid),
+
+    /// The event does not have a request that can be sent again
+    #[error("Event {0} does not have a request to replay")]
+    NotReplayable(Uuid),
+
+    /// The requested prompt does not exist
+    #[error("Prompt {0} not found")]
+    PromptNotFound(String),
+
+    /// A prompt template uses a variable which was not provided
+    #[error("Prompt {0} requires the variable {1}")]
+    MissingPromptVariable(String, String),
+
+    /// The fine-tuning export format is not supported
+    #[error("Unknown fine-tuning format {0}")]
+    UnknownFineTuningFormat(String),
 }
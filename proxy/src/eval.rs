@@ -0,0 +1,511 @@
+//! Running the items in a dataset through the proxy and scoring the responses
+use std::{collections::BTreeMap, sync::Arc};
+
+use chrono::Utc;
+use error_stack::{Report, ResultExt};
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::json;
+use smallvec::SmallVec;
+use uuid::Uuid;
+
+pub mod scorers;
+
+use scorers::{ScoreInput, Scorer};
+
+use crate::{
+    collect_response,
+    database::{
+        datasets::{Dataset, DatasetItem},
+        scores::{summarize_scores, Score, ScoreSummary},
+    },
+    format::ChatRequest,
+    workflow_events::{
+        ErrorData, RunStartEvent, RunUpdateEvent, ScoreEvent, ScoreSource, StepEndData,
+        StepEventData, StepStartData, WorkflowEvent,
+    },
+    Error, Proxy, ProxyRequestOptions,
+};
+
+/// The default number of items that an eval sends at once
+pub const DEFAULT_EVAL_CONCURRENCY: usize = 4;
+
+/// Options for [run_eval]
+#[derive(Debug, Clone, Default)]
+pub struct EvalOptions {
+    /// The model, alias, or list of models to send the requests to, and any other request
+    /// options. The run and step IDs in the metadata are set for each item.
+    pub request_options: ProxyRequestOptions,
+    /// How many items to send at once. Defaults to [DEFAULT_EVAL_CONCURRENCY].
+    pub concurrency: Option<usize>,
+    /// The name of the run. Defaults to `eval <dataset name> v<version>`.
+    pub run_name: Option<String>,
+    /// The scorers to run on each response
+    pub scorers: Vec<Arc<dyn Scorer>>,
+}
+
+/// The result of running one dataset item
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalItemResult {
+    pub item_id: Uuid,
+    /// The step recorded for the item
+    pub step_id: Uuid,
+    /// The LLM call made for the item, if the request succeeded
+    pub event_id: Option<Uuid>,
+    /// The content of the response's first choice
+    pub output: Option<String>,
+    /// Why the item failed, if it did
+    pub error: Option<String>,
+    pub scores: Vec<Score>,
+}
+
+/// The result of an eval
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalResult {
+    /// The run recorded for the eval
+    pub run_id: Uuid,
+    pub dataset_id: Uuid,
+    /// The results for each item, in the same order as the items
+    pub items: Vec<EvalItemResult>,
+    /// Statistics for each scorer across all of the items
+    pub summary: Vec<ScoreSummary>,
+}
+
+impl EvalResult {
+    /// The number of items that failed
+    pub fn errors(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.error.is_some())
+            .count()
+    }
+}
+
+/// Send each item in a dataset through the proxy, and score the responses.
+///
+/// The eval is recorded as a run with one step for each item, and the scores are attached to
+/// the steps. Items whose requests fail are recorded as step errors and are not scored.
+pub async fn run_eval(
+    proxy: &Proxy,
+    dataset: &Dataset,
+    items: &[DatasetItem],
+    options: &EvalOptions,
+) -> EvalResult {
+    let run_id = Uuid::now_v7();
+    let request_options = &options.request_options;
+    proxy
+        .record_workflow_event(WorkflowEvent::RunStart(RunStartEvent {
+            id: run_id,
+            name: options
+                .run_name
+                .clone()
+                .unwrap_or_else(|| format!("eval {} v{}", dataset.name, dataset.version)),
+            description: dataset.description.clone(),
+            application: request_options.metadata.application.clone(),
+            environment: request_options.metadata.environment.clone(),
+            input: Some(json!({
+                "dataset_id": dataset.id,
+                "dataset": dataset.name,
+                "version": dataset.version,
+            })),
+            trace_id: None,
+            span_id: None,
+            status: None,
+            tags: vec!["eval".to_string()],
+            info: Some(json!({
+                "model": request_options.model,
+                "provider": request_options.provider,
+                "models": request_options.models,
+                "scorers": options.scorers.iter().map(|s| s.name()).collect::<Vec<_>>(),
+            })),
+            time: Some(Utc::now()),
+            event_id: None,
+        }))
+        .await;
+
+    let concurrency = options
+        .concurrency
+        .unwrap_or(DEFAULT_EVAL_CONCURRENCY)
+        .max(1);
+    let results = futures::stream::iter(items.iter().enumerate())
+        .map(|(index, item)| run_item(proxy, options, run_id, index, item))
+        .buffered(concurrency)
+        .collect::<Vec<_>>()
+        .await;
+
+    let scores = results
+        .iter()
+        .flat_map(|result| result.scores.iter().cloned())
+        .collect::<Vec<_>>();
+    let result = EvalResult {
+        run_id,
+        dataset_id: dataset.id,
+        items: results,
+        summary: summarize_scores(&scores),
+    };
+
+    proxy
+        .record_workflow_event(WorkflowEvent::RunUpdate(RunUpdateEvent {
+            id: run_id,
+            status: Some("finished".to_string()),
+            output: Some(json!({
+                "items": result.items.len(),
+                "errors": result.errors(),
+                "scores": result.summary,
+            })),
+            info: None,
+            time: Some(Utc::now()),
+            event_id: None,
+        }))
+        .await;
+
+    result
+}
+
+async fn run_item(
+    proxy: &Proxy,
+    options: &EvalOptions,
+    run_id: Uuid,
+    index: usize,
+    item: &DatasetItem,
+) -> EvalItemResult {
+    let step_id = Uuid::now_v7();
+    proxy
+        .record_workflow_event(WorkflowEvent::StepStart(StepEventData {
+            step_id,
+            run_id,
+            time: Some(Utc::now()),
+            event_id: None,
+            data: StepStartData {
+                typ: "eval_item".to_string(),
+                name: Some(format!("item {}", index + 1)),
+                parent_step: None,
+                span_id: None,
+                tags: vec![],
+                info: None,
+                input: json!({ "item_id": item.id, "event_id": item.event_id }),
+            },
+        }))
+        .await;
+
+    let mut result = EvalItemResult {
+        item_id: item.id,
+        step_id,
+        event_id: None,
+        output: None,
+        error: None,
+        scores: Vec::new(),
+    };
+
+    let mut events = SmallVec::<[WorkflowEvent; 1]>::new();
+    match send_and_score(proxy, options, run_id, step_id, item, &mut result).await {
+        Ok(()) => {
+            events.extend(result.scores.iter().map(|score| {
+                WorkflowEvent::Score(ScoreEvent {
+                    id: Some(score.id),
+                    run_id: score.run_id,
+                    step_id: score.step_id,
+                    event_id: score.event_id,
+                    name: score.name.clone(),
+                    value: score.value.clone(),
+                    source: score.source,
+                    comment: None,
+                    time: Some(score.created_at),
+                })
+            }));
+            events.push(WorkflowEvent::StepEnd(StepEventData {
+                step_id,
+                run_id,
+                time: Some(Utc::now()),
+                event_id: None,
+                data: StepEndData {
+                    output: json!({ "output": result.output }),
+                    info: None,
+                },
+            }));
+        }
+        Err(e) => {
+            result.error = Some(e.to_string());
+            events.push(WorkflowEvent::StepError(StepEventData {
+                step_id,
+                run_id,
+                time: Some(Utc::now()),
+                event_id: None,
+                data: ErrorData {
+                    error: json!(format!("{e:?}")),
+                },
+            }));
+        }
+    }
+
+    proxy.record_event_batch(events).await;
+    result
+}
+
+async fn send_and_score(
+    proxy: &Proxy,
+    options: &EvalOptions,
+    run_id: Uuid,
+    step_id: Uuid,
+    item: &DatasetItem,
+    result: &mut EvalItemResult,
+) -> Result<(), Report<Error>> {
+    let mut request: ChatRequest = serde_json::from_value(item.request.clone())
+        .change_context(Error::Eval)
+        .attach_printable("Dataset item has an invalid request")?;
+    request.stream = false;
+    let n = request.n.unwrap_or(1) as usize;
+
+    let mut request_options = options.request_options.clone();
+    request_options.metadata.run_id = Some(run_id);
+    request_options.metadata.step_id = Some(step_id);
+
+    let response = proxy.send(request_options, request).await?;
+    let response = collect_response(response, n).await?;
+    let output = response
+        .response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .unwrap_or_default();
+    let expected = item
+        .expected_response
+        .as_ref()
+        .and_then(|expected| expected["choices"][0]["message"]["content"].as_str());
+
+    let event_id = response.request_info.id;
+    result.event_id = Some(event_id);
+    for scorer in &options.scorers {
+        let value = scorer
+            .score(ScoreInput {
+                item,
+                response: &response.response,
+                output: &output,
+                expected,
+            })
+            .await
+            .attach_printable_lazy(|| format!("Scorer {} failed", scorer.name()))?;
+
+        if let Some(value) = value {
+            result.scores.push(Score {
+                id: Uuid::now_v7(),
+                run_id: Some(run_id),
+                step_id: Some(step_id),
+                event_id: Some(event_id),
+                name: scorer.name().to_string(),
+                value,
+                source: scorer.source(),
+                comment: None,
+                created_at: Utc::now(),
+            });
+        }
+    }
+    result.output = Some(output);
+
+    Ok(())
+}
+
+/// How a score from an eval compares to the same score in a baseline run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScoreComparison {
+    pub name: String,
+    pub source: ScoreSource,
+    /// The mean of the score in the baseline run
+    pub baseline: Option<f64>,
+    /// The mean of the score in this run
+    pub current: Option<f64>,
+    /// Whether the score got worse. Higher scores are better, so this is true when the mean
+    /// dropped by more than the tolerance, or when a score in the baseline is missing.
+    pub regressed: bool,
+}
+
+/// The baseline and current means of each score, by score name and source
+type ScoreMeans<'a> = BTreeMap<(&'a str, ScoreSource), (Option<f64>, Option<f64>)>;
+
+/// Compare the score summaries of a run against a baseline run, by score name and source
+pub fn compare_to_baseline(
+    current: &[ScoreSummary],
+    baseline: &[ScoreSummary],
+    tolerance: f64,
+) -> Vec<ScoreComparison> {
+    let mut means: ScoreMeans = BTreeMap::new();
+    for summary in baseline {
+        means
+            .entry((summary.name.as_str(), summary.source))
+            .or_default()
+            .0 = summary.mean;
+    }
+    for summary in current {
+        means
+            .entry((summary.name.as_str(), summary.source))
+            .or_default()
+            .1 = summary.mean;
+    }
+
+    means
+        .into_iter()
+        .map(|((name, source), (baseline, current))| ScoreComparison {
+            name: name.to_string(),
+            source,
+            baseline,
+            current,
+            regressed: match (baseline, current) {
+                (Some(baseline), Some(current)) => current < baseline - tolerance,
+                (Some(_), None) => true,
+                _ => false,
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{eval::scorers::ScorerConfig, testing::TestProvider, workflow_events::ScoreValue};
+
+    fn item(request: serde_json::Value, expected: &str) -> DatasetItem {
+        DatasetItem {
+            id: Uuid::now_v7(),
+            dataset_id: Uuid::from_u128(1),
+            event_id: None,
+            request,
+            expected_response: Some(json!({
+                "choices": [{ "message": { "role": "assistant", "content": expected } }]
+            })),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_and_scores_items() {
+        let proxy = Proxy::builder()
+            .without_default_providers()
+            .with_provider(Arc::new(TestProvider {
+                response: "4".to_string(),
+                ..Default::default()
+            }))
+            .build()
+            .await
+            .expect("Building proxy");
+
+        let dataset = Dataset {
+            id: Uuid::from_u128(1),
+            name: "math".to_string(),
+            version: 1,
+            description: None,
+            item_count: 3,
+            created_at: Utc::now(),
+        };
+        let request = json!({ "messages": [{ "role": "user", "content": "What is 2 + 2?" }] });
+        let items = vec![
+            item(request.clone(), "4"),
+            item(json!("not a request"), "4"),
+            item(request, "5"),
+        ];
+
+        let options = EvalOptions {
+            request_options: ProxyRequestOptions {
+                model: Some("test-model".to_string()),
+                provider: Some("test".to_string()),
+                ..Default::default()
+            },
+            concurrency: Some(2),
+            scorers: vec![
+                ScorerConfig::ExactMatch.build().unwrap(),
+                ScorerConfig::Regex {
+                    pattern: r"^\d$".to_string(),
+                }
+                .build()
+                .unwrap(),
+            ],
+            ..Default::default()
+        };
+
+        let result = run_eval(&proxy, &dataset, &items, &options).await;
+        assert_eq!(result.items.len(), 3);
+        assert_eq!(result.errors(), 1);
+
+        let item_ids = result.items.iter().map(|r| r.item_id).collect::<Vec<_>>();
+        assert_eq!(
+            item_ids,
+            items.iter().map(|i| i.id).collect::<Vec<_>>(),
+            "results are in the same order as the items"
+        );
+
+        let first = &result.items[0];
+        assert_eq!(first.output.as_deref(), Some("4"));
+        assert!(first.event_id.is_some());
+        assert_eq!(first.scores.len(), 2);
+        assert!(first
+            .scores
+            .iter()
+            .all(|s| s.run_id == Some(result.run_id) && s.step_id == Some(first.step_id)));
+
+        assert!(result.items[1].error.is_some());
+        assert!(result.items[1].scores.is_empty());
+        assert_eq!(
+            result.items[2].scores[0].value,
+            ScoreValue::Boolean(false),
+            "the response does not match the expected output"
+        );
+
+        let means = result
+            .summary
+            .iter()
+            .map(|s| (s.name.as_str(), s.count, s.mean))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            means,
+            vec![("exact_match", 2, Some(0.5)), ("regex", 2, Some(1.0))]
+        );
+    }
+
+    #[test]
+    fn baseline_comparison() {
+        let summary = |name: &str, mean: Option<f64>| ScoreSummary {
+            name: name.to_string(),
+            source: ScoreSource::Heuristic,
+            count: 10,
+            mean,
+            min: None,
+            max: None,
+            categories: BTreeMap::new(),
+        };
+
+        let baseline = vec![
+            summary("contains", Some(0.5)),
+            summary("exact_match", Some(0.8)),
+            summary("label", None),
+            summary("regex", Some(1.0)),
+        ];
+        let current = vec![
+            summary("contains", Some(0.45)),
+            summary("exact_match", Some(0.7)),
+            summary("json_schema", Some(0.2)),
+            summary("label", None),
+        ];
+
+        let comparisons = compare_to_baseline(&current, &baseline, 0.05);
+        let regressed = comparisons
+            .iter()
+            .map(|c| (c.name.as_str(), c.regressed))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            regressed,
+            vec![
+                ("contains", false),
+                ("exact_match", true),
+                ("json_schema", false),
+                ("label", false),
+                ("regex", true),
+            ]
+        );
+        assert_eq!(comparisons[1].baseline, Some(0.8));
+        assert_eq!(comparisons[1].current, Some(0.7));
+    }
+}
@@ -0,0 +1,293 @@
+//! Scoring the responses from an eval
+use std::sync::Arc;
+
+use error_stack::Report;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::datasets::DatasetItem,
+    format::SingleChatResponse,
+    workflow_events::{ScoreSource, ScoreValue},
+    Error,
+};
+
+/// The response to a dataset item, as passed to a [Scorer]
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreInput<'a> {
+    pub item: &'a DatasetItem,
+    pub response: &'a SingleChatResponse,
+    /// The content of the response's first choice
+    pub output: &'a str,
+    /// The content of the first choice in the item's expected response, if it has one
+    pub expected: Option<&'a str>,
+}
+
+/// Scores the responses from an eval
+#[async_trait::async_trait]
+pub trait Scorer: std::fmt::Debug + Send + Sync {
+    /// The name to record the scores under
+    fn name(&self) -> &str;
+
+    /// Where the scores come from
+    fn source(&self) -> ScoreSource {
+        ScoreSource::Heuristic
+    }
+
+    /// Score a response. Returns `None` if the scorer does not apply to the item, such as when
+    /// it compares against an expected response that the item does not have.
+    async fn score(&self, input: ScoreInput<'_>) -> Result<Option<ScoreValue>, Report<Error>>;
+}
+
+/// Configuration for the built-in scorers. Each one passes or fails the response.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScorerConfig {
+    /// The output matches the expected output, ignoring leading and trailing whitespace
+    ExactMatch,
+    /// The output contains `text`, or the expected output if `text` is omitted
+    Contains { text: Option<String> },
+    /// The output matches a regular expression
+    Regex { pattern: String },
+    /// The output is JSON which is valid according to a JSON schema
+    JsonSchema { schema: serde_json::Value },
+    /// The output and the expected output are numbers within `tolerance` of each other
+    NumericTolerance { tolerance: f64 },
+}
+
+impl ScorerConfig {
+    /// Create the scorer
+    pub fn build(&self) -> Result<Arc<dyn Scorer>, Report<Error>> {
+        let scorer: Arc<dyn Scorer> = match self {
+            ScorerConfig::ExactMatch => Arc::new(ExactMatchScorer),
+            ScorerConfig::Contains { text } => Arc::new(ContainsScorer { text: text.clone() }),
+            ScorerConfig::Regex { pattern } => Arc::new(RegexScorer {
+                regex: regex::Regex::new(pattern).map_err(|e| {
+                    Error::InvalidScorer(format!("Invalid regular expression {pattern}: {e}"))
+                })?,
+            }),
+            ScorerConfig::JsonSchema { schema } => Arc::new(JsonSchemaScorer::new(schema)?),
+            ScorerConfig::NumericTolerance { tolerance } => Arc::new(NumericToleranceScorer {
+                tolerance: *tolerance,
+            }),
+        };
+
+        Ok(scorer)
+    }
+}
+
+/// Passes if the output matches the expected output, ignoring leading and trailing whitespace
+#[derive(Debug)]
+pub struct ExactMatchScorer;
+
+#[async_trait::async_trait]
+impl Scorer for ExactMatchScorer {
+    fn name(&self) -> &str {
+        "exact_match"
+    }
+
+    async fn score(&self, input: ScoreInput<'_>) -> Result<Option<ScoreValue>, Report<Error>> {
+        Ok(input
+            .expected
+            .map(|expected| ScoreValue::Boolean(input.output.trim() == expected.trim())))
+    }
+}
+
+/// Passes if the output contains some text, or the expected output if no text is given
+#[derive(Debug)]
+pub struct ContainsScorer {
+    pub text: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl Scorer for ContainsScorer {
+    fn name(&self) -> &str {
+        "contains"
+    }
+
+    async fn score(&self, input: ScoreInput<'_>) -> Result<Option<ScoreValue>, Report<Error>> {
+        let text = self.text.as_deref().or(input.expected);
+        Ok(text.map(|text| ScoreValue::Boolean(input.output.contains(text.trim()))))
+    }
+}
+
+/// Passes if the output matches a regular expression
+#[derive(Debug)]
+pub struct RegexScorer {
+    pub regex: regex::Regex,
+}
+
+#[async_trait::async_trait]
+impl Scorer for RegexScorer {
+    fn name(&self) -> &str {
+        "regex"
+    }
+
+    async fn score(&self, input: ScoreInput<'_>) -> Result<Option<ScoreValue>, Report<Error>> {
+        Ok(Some(ScoreValue::Boolean(self.regex.is_match(input.output))))
+    }
+}
+
+/// Passes if the output is JSON which matches a schema
+pub struct JsonSchemaScorer {
+    schema: jsonschema::JSONSchema,
+}
+
+impl JsonSchemaScorer {
+    /// Compile the schema
+    pub fn new(schema: &serde_json::Value) -> Result<Self, Report<Error>> {
+        let schema = jsonschema::JSONSchema::compile(schema)
+            .map_err(|e| Error::InvalidScorer(format!("Invalid JSON schema: {e}")))?;
+        Ok(Self { schema })
+    }
+}
+
+impl std::fmt::Debug for JsonSchemaScorer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonSchemaScorer").finish_non_exhaustive()
+    }
+}
+
+#[async_trait::async_trait]
+impl Scorer for JsonSchemaScorer {
+    fn name(&self) -> &str {
+        "json_schema"
+    }
+
+    async fn score(&self, input: ScoreInput<'_>) -> Result<Option<ScoreValue>, Report<Error>> {
+        let valid = serde_json::from_str::<serde_json::Value>(input.output.trim())
+            .map(|output| self.schema.is_valid(&output))
+            .unwrap_or(false);
+        Ok(Some(ScoreValue::Boolean(valid)))
+    }
+}
+
+/// Passes if the output and the expected output are numbers which are within `tolerance` of each
+/// other
+#[derive(Debug)]
+pub struct NumericToleranceScorer {
+    pub tolerance: f64,
+}
+
+#[async_trait::async_trait]
+impl Scorer for NumericToleranceScorer {
+    fn name(&self) -> &str {
+        "numeric_tolerance"
+    }
+
+    async fn score(&self, input: ScoreInput<'_>) -> Result<Option<ScoreValue>, Report<Error>> {
+        let Some(expected) = input
+            .expected
+            .and_then(|expected| expected.trim().parse::<f64>().ok())
+        else {
+            return Ok(None);
+        };
+
+        let within = input
+            .output
+            .trim()
+            .parse::<f64>()
+            .map(|output| (output - expected).abs() <= self.tolerance)
+            .unwrap_or(false);
+        Ok(Some(ScoreValue::Boolean(within)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+
+    async fn score(config: ScorerConfig, output: &str, expected: Option<&str>) -> Option<bool> {
+        let item = DatasetItem {
+            id: Uuid::now_v7(),
+            dataset_id: Uuid::now_v7(),
+            event_id: None,
+            request: json!({}),
+            expected_response: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let response = SingleChatResponse::new_for_collection(1);
+
+        let value = config
+            .build()
+            .unwrap()
+            .score(ScoreInput {
+                item: &item,
+                response: &response,
+                output,
+                expected,
+            })
+            .await
+            .unwrap();
+        value.map(|v| v == ScoreValue::Boolean(true))
+    }
+
+    #[tokio::test]
+    async fn built_in_scorers() {
+        let exact = || ScorerConfig::ExactMatch;
+        assert_eq!(score(exact(), " 4\n", Some("4")).await, Some(true));
+        assert_eq!(score(exact(), "5", Some("4")).await, Some(false));
+        assert_eq!(score(exact(), "4", None).await, None);
+
+        let contains = |text: Option<&str>| ScorerConfig::Contains {
+            text: text.map(|t| t.to_string()),
+        };
+        assert_eq!(
+            score(contains(Some("Paris")), "It is Paris.", None).await,
+            Some(true)
+        );
+        assert_eq!(
+            score(contains(None), "It is Paris.", Some("Paris")).await,
+            Some(true)
+        );
+        assert_eq!(
+            score(contains(None), "It is Lyon.", Some("Paris")).await,
+            Some(false)
+        );
+
+        let regex = ScorerConfig::Regex {
+            pattern: r"^\d+$".to_string(),
+        };
+        assert_eq!(score(regex.clone(), "123", None).await, Some(true));
+        assert_eq!(score(regex, "12a", None).await, Some(false));
+
+        let schema = ScorerConfig::JsonSchema {
+            schema: json!({
+                "type": "object",
+                "properties": { "answer": { "type": "number" } },
+                "required": ["answer"]
+            }),
+        };
+        assert_eq!(
+            score(schema.clone(), r#"{"answer": 4}"#, None).await,
+            Some(true)
+        );
+        assert_eq!(
+            score(schema.clone(), r#"{"answer": "4"}"#, None).await,
+            Some(false)
+        );
+        assert_eq!(score(schema, "not json", None).await, Some(false));
+
+        let numeric = || ScorerConfig::NumericTolerance { tolerance: 0.01 };
+        assert_eq!(score(numeric(), "3.141", Some("3.14")).await, Some(true));
+        assert_eq!(score(numeric(), "3.2", Some("3.14")).await, Some(false));
+        assert_eq!(score(numeric(), "pi", Some("3.14")).await, Some(false));
+        assert_eq!(score(numeric(), "3.14", Some("pi")).await, None);
+    }
+
+    #[test]
+    fn invalid_scorers() {
+        let regex = ScorerConfig::Regex {
+            pattern: "(".to_string(),
+        };
+        assert!(regex.build().is_err(), "invalid regex");
+        let schema = ScorerConfig::JsonSchema {
+            schema: json!({ "type": "not_a_type" }),
+        };
+        assert!(schema.build().is_err(), "invalid schema");
+    }
+}
@@ -0,0 +1,483 @@
+//! Exporting logged conversations as fine-tuning data
+use std::{collections::HashSet, str::FromStr};
+
+use error_stack::Report;
+use futures::{Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        export::{ExportEventQuery, ExportPage, EXPORT_PAGE_SIZE},
+        runs::RunEvent,
+        Database,
+    },
+    format::{ChatMessage, ChatRequest, ChatRequestTransformation},
+    providers::anthropic::{AnthropicChatMessage, AnthropicTool},
+    Error,
+};
+
+/// The format of the exported training examples
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FineTuningFormat {
+    /// OpenAI chat fine-tuning, with a `messages` array and optional `tools`
+    #[default]
+    #[serde(rename = "openai")]
+    OpenAi,
+    /// Anthropic-style conversations, as used for fine-tuning Claude on Bedrock, with a
+    /// top-level `system` prompt and alternating `user` and `assistant` messages
+    #[serde(alias = "bedrock")]
+    Anthropic,
+}
+
+impl FromStr for FineTuningFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "openai" => Ok(Self::OpenAi),
+            "anthropic" | "bedrock" => Ok(Self::Anthropic),
+            _ => Err(Error::UnknownFineTuningFormat(s.to_string())),
+        }
+    }
+}
+
+/// Options for [export_fine_tuning]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FineTuningOptions {
+    /// Which events to export
+    #[serde(flatten)]
+    pub query: ExportEventQuery,
+    #[serde(default)]
+    pub format: FineTuningFormat,
+    /// The fraction of examples, between 0 and 1, to hold out for validation. Examples are
+    /// assigned to a split based on their content, so the same examples land in the same split
+    /// across exports.
+    pub validation_fraction: Option<f64>,
+    /// Export every matching event, even when it produces the same example as an earlier one
+    #[serde(default)]
+    pub keep_duplicates: bool,
+}
+
+/// Which file an example belongs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FineTuningSplit {
+    Train,
+    Validation,
+}
+
+/// A training example built from a logged event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FineTuningExample {
+    /// The event that the example was built from
+    pub event_id: Uuid,
+    pub split: FineTuningSplit,
+    /// The example as a line of JSON, without the trailing newline
+    pub line: String,
+}
+
+/// Converts events to examples, skipping duplicates and assigning each example to a split
+#[derive(Debug)]
+pub struct FineTuningExporter {
+    options: FineTuningOptions,
+    seen: HashSet<[u8; 32]>,
+    exported: u32,
+}
+
+impl FineTuningExporter {
+    pub fn new(options: FineTuningOptions) -> Self {
+        Self {
+            options,
+            seen: HashSet::new(),
+            exported: 0,
+        }
+    }
+
+    /// Returns true once the export has reached `query.limit`
+    pub fn is_full(&self) -> bool {
+        self.options
+            .query
+            .limit
+            .is_some_and(|limit| self.exported >= limit)
+    }
+
+    /// Build the example for an event. This returns `None` if the event can not be converted,
+    /// is a duplicate, or the export is already full.
+    pub fn add(&mut self, event: &RunEvent) -> Option<FineTuningExample> {
+        if self.is_full() {
+            return None;
+        }
+
+        let example = match event_to_example(event, self.options.format) {
+            Ok(example) => example,
+            Err(e) => {
+                tracing::warn!(event_id = %event.id, error = ?e, "Skipping event in export");
+                return None;
+            }
+        };
+
+        let line = example.to_string();
+        let digest: [u8; 32] = Sha256::digest(line.as_bytes()).into();
+        if !self.options.keep_duplicates && !self.seen.insert(digest) {
+            return None;
+        }
+
+        self.exported += 1;
+        Some(FineTuningExample {
+            event_id: event.id,
+            split: choose_split(&digest, self.options.validation_fraction.unwrap_or(0.0)),
+            line,
+        })
+    }
+}
+
+/// Place an example in the validation split if its digest falls within `fraction`
+fn choose_split(digest: &[u8; 32], fraction: f64) -> FineTuningSplit {
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    let position = u64::from_be_bytes(prefix) as f64 / u64::MAX as f64;
+    if position < fraction {
+        FineTuningSplit::Validation
+    } else {
+        FineTuningSplit::Train
+    }
+}
+
+/// Convert a logged request and its response into a training example
+pub fn event_to_example(
+    event: &RunEvent,
+    format: FineTuningFormat,
+) -> Result<serde_json::Value, serde_json::Error> {
+    let request = event.chat_request.clone().unwrap_or_default();
+    let request: ChatRequest = serde_json::from_value(request)?;
+
+    let response = event
+        .chat_response
+        .as_ref()
+        .map(|response| response["choices"][0]["message"].clone())
+        .unwrap_or_default();
+    let mut response: ChatMessage = serde_json::from_value(response)?;
+    if response.role.is_none() {
+        response.role = Some("assistant".to_string());
+    }
+
+    match format {
+        FineTuningFormat::OpenAi => Ok(openai_example(request, response)),
+        FineTuningFormat::Anthropic => anthropic_example(request, response),
+    }
+}
+
+fn openai_example(mut request: ChatRequest, response: ChatMessage) -> serde_json::Value {
+    request.transform(&ChatRequestTransformation::default());
+
+    let mut messages = request.messages;
+    messages.push(response);
+    for message in &mut messages {
+        message.cache_control = None;
+        for call in &mut message.tool_calls {
+            call.index = None;
+        }
+    }
+
+    let mut example = json!({ "messages": messages });
+    if !request.tools.is_empty() {
+        example["tools"] = json!(request.tools);
+    }
+    example
+}
+
+fn anthropic_example(
+    mut request: ChatRequest,
+    response: ChatMessage,
+) -> Result<serde_json::Value, serde_json::Error> {
+    request.transform(&ChatRequestTransformation {
+        supports_message_name: false,
+        system_in_messages: false,
+        strip_model_prefix: None,
+    });
+
+    // Anthropic requires the roles to alternate, so consecutive messages with the same role,
+    // such as several tool results, are merged into one.
+    let mut messages: Vec<AnthropicChatMessage> = Vec::with_capacity(request.messages.len() + 1);
+    for mut message in request
+        .messages
+        .into_iter()
+        .chain(std::iter::once(response))
+    {
+        message.cache_control = None;
+        let message = AnthropicChatMessage::try_from(message)?;
+        match messages.last_mut() {
+            Some(last) if last.role == message.role => last.content.extend(message.content),
+            _ => messages.push(message),
+        }
+    }
+
+    let mut example = json!({ "messages": messages });
+    if let Some(system) = request.system {
+        example["system"] = json!(system);
+    }
+    if !request.tools.is_empty() {
+        let tools = request
+            .tools
+            .into_iter()
+            .map(AnthropicTool::from)
+            .collect::<Vec<_>>();
+        example["tools"] = json!(tools);
+    }
+    Ok(example)
+}
+
+/// Export the events matching `options.query` as training examples, loading them from the
+/// database a page at a time.
+pub fn export_fine_tuning(
+    db: Database,
+    options: FineTuningOptions,
+) -> impl Stream<Item = Result<FineTuningExample, Report<Error>>> + Send {
+    let exporter = FineTuningExporter::new(options);
+    futures::stream::try_unfold(
+        (db, exporter, None::<Uuid>, false),
+        |(db, mut exporter, after, done)| async move {
+            if done || exporter.is_full() {
+                return Ok::<_, Report<Error>>(None);
+            }
+
+            let page = ExportPage {
+                after,
+                limit: EXPORT_PAGE_SIZE,
+            };
+            let events = db
+                .list_events_for_export(&exporter.options.query, page)
+                .await?;
+            let done = events.len() < EXPORT_PAGE_SIZE as usize;
+            let after = events.last().map(|event| event.id).or(after);
+            let examples = events
+                .iter()
+                .filter_map(|event| exporter.add(event))
+                .collect::<Vec<_>>();
+
+            Ok(Some((examples, (db, exporter, after, done))))
+        },
+    )
+    .map_ok(|examples| futures::stream::iter(examples.into_iter().map(Ok)))
+    .try_flatten()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn event(id: u128, request: serde_json::Value, response: serde_json::Value) -> RunEvent {
+        RunEvent {
+            id: Uuid::from_u128(id),
+            event_type: Some("chronicle_llm_request".to_string()),
+            step_id: None,
+            provider: Some("openai".to_string()),
+            model: Some("gpt-4o".to_string()),
+            chat_request: Some(request),
+            chat_response: Some(json!({ "choices": [{ "index": 0, "message": response }] })),
+            error: None,
+            meta: None,
+            retries: None,
+            rate_limited: None,
+            request_latency_ms: None,
+            total_latency_ms: None,
+            replay_of: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn tool_event() -> RunEvent {
+        event(
+            1,
+            json!({
+                "system": "Use the tools",
+                "messages": [
+                    { "role": "user", "content": "Weather in Paris and Lyon?" },
+                    {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [
+                            {
+                                "index": 0,
+                                "id": "call_1",
+                                "type": "function",
+                                "function": {
+                                    "name": "weather",
+                                    "arguments": "{\"city\":\"Paris\"}"
+                                }
+                            },
+                            {
+                                "index": 1,
+                                "id": "call_2",
+                                "type": "function",
+                                "function": {
+                                    "name": "weather",
+                                    "arguments": "{\"city\":\"Lyon\"}"
+                                }
+                            }
+                        ]
+                    },
+                    { "role": "tool", "tool_call_id": "call_1", "content": "Sunny" },
+                    { "role": "tool", "tool_call_id": "call_2", "content": "Rainy" },
+                ],
+                "tools": [{
+                    "type": "function",
+                    "function": {
+                        "name": "weather",
+                        "description": "Get the weather",
+                        "parameters": { "type": "object" }
+                    }
+                }]
+            }),
+            json!({ "role": "assistant", "content": "Sunny in Paris, rainy in Lyon" }),
+        )
+    }
+
+    #[test]
+    fn openai_format() {
+        let example = event_to_example(&tool_event(), FineTuningFormat::OpenAi).unwrap();
+
+        let messages = example["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(messages[0]["content"], "Use the tools");
+        assert_eq!(messages[2]["tool_calls"][1]["id"], "call_2");
+        assert_eq!(messages[2]["tool_calls"][1].get("index"), None);
+        assert_eq!(messages[4]["tool_call_id"], "call_2");
+        assert_eq!(messages[5]["role"], "assistant");
+        assert_eq!(messages[5]["content"], "Sunny in Paris, rainy in Lyon");
+        assert_eq!(example["tools"][0]["function"]["name"], "weather");
+    }
+
+    #[test]
+    fn anthropic_format() {
+        let example = event_to_example(&tool_event(), FineTuningFormat::Anthropic).unwrap();
+
+        assert_eq!(example["system"], "Use the tools");
+        let messages = example["messages"].as_array().unwrap();
+        let roles = messages
+            .iter()
+            .map(|m| m["role"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(roles, vec!["user", "assistant", "user", "assistant"]);
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+        assert_eq!(
+            messages[1]["content"][1]["input"],
+            json!({ "city": "Lyon" })
+        );
+        let results = messages[2]["content"].as_array().unwrap();
+        assert_eq!(results.len(), 2, "tool results are merged");
+        assert_eq!(results[1]["tool_use_id"], "call_2");
+        assert_eq!(
+            messages[3]["content"][0]["text"],
+            "Sunny in Paris, rainy in Lyon"
+        );
+        assert_eq!(
+            example["tools"][0]["input_schema"],
+            json!({ "type": "object" })
+        );
+    }
+
+    #[test]
+    fn parses_format() {
+        assert_eq!(
+            "bedrock".parse::<FineTuningFormat>().unwrap(),
+            FineTuningFormat::Anthropic
+        );
+        assert_eq!(
+            serde_json::from_value::<FineTuningFormat>(json!("openai")).unwrap(),
+            FineTuningFormat::OpenAi
+        );
+        "csv"
+            .parse::<FineTuningFormat>()
+            .expect_err("unknown format");
+    }
+
+    fn simple_events() -> Vec<RunEvent> {
+        (0..20)
+            .map(|i| {
+                event(
+                    i,
+                    json!({
+                        "messages": [{ "role": "user", "content": format!("Question {}", i % 10) }]
+                    }),
+                    json!({ "role": "assistant", "content": "Answer" }),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skips_duplicates() {
+        let mut exporter = FineTuningExporter::new(FineTuningOptions::default());
+        let examples = simple_events()
+            .iter()
+            .filter_map(|e| exporter.add(e))
+            .collect::<Vec<_>>();
+        assert_eq!(examples.len(), 10);
+        assert_eq!(examples[9].event_id, Uuid::from_u128(9));
+
+        let mut exporter = FineTuningExporter::new(FineTuningOptions {
+            keep_duplicates: true,
+            ..Default::default()
+        });
+        let count = simple_events()
+            .iter()
+            .filter_map(|e| exporter.add(e))
+            .count();
+        assert_eq!(count, 20);
+    }
+
+    #[test]
+    fn skips_invalid_events() {
+        let mut exporter = FineTuningExporter::new(FineTuningOptions::default());
+        let mut invalid = simple_events().remove(0);
+        invalid.chat_response = Some(json!({ "choices": [] }));
+        assert_eq!(exporter.add(&invalid), None);
+    }
+
+    #[test]
+    fn applies_limit() {
+        let mut exporter = FineTuningExporter::new(FineTuningOptions {
+            query: ExportEventQuery {
+                limit: Some(3),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let count = simple_events()
+            .iter()
+            .filter_map(|e| exporter.add(e))
+            .count();
+        assert_eq!(count, 3);
+        assert!(exporter.is_full());
+    }
+
+    #[test]
+    fn validation_split() {
+        let splits = |fraction: f64| {
+            let mut exporter = FineTuningExporter::new(FineTuningOptions {
+                validation_fraction: Some(fraction),
+                ..Default::default()
+            });
+            simple_events()
+                .iter()
+                .filter_map(|e| exporter.add(e))
+                .map(|example| example.split)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(splits(0.0).iter().all(|s| *s == FineTuningSplit::Train));
+        assert!(splits(1.0)
+            .iter()
+            .all(|s| *s == FineTuningSplit::Validation));
+        let half = splits(0.5);
+        assert_eq!(half, splits(0.5), "splits are deterministic");
+    }
+}
@@ -356,7 +356,9 @@ impl<'a> Default for ChatRequestTransformation<'a> {
 #[serde_as]
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct ChatRequest {
-    /// The messages in the chat so far.
+    /// The messages in the chat so far. This can be omitted when the request renders a prompt
+    /// template.
+    #[serde(default)]
     pub messages: Vec<ChatMessage>,
     /// A separate field for system message as an alternative to specifying it in
     /// `messages`.
@@ -8,19 +8,30 @@ pub mod builder;
 pub mod config;
 pub mod database;
 pub mod error;
+pub mod eval;
+pub mod fine_tuning;
 pub mod format;
+pub mod metrics;
+pub mod prompts;
 mod provider_lookup;
 pub mod providers;
+pub mod redact;
+pub mod replay;
 pub mod request;
 mod response;
 mod streaming;
 #[cfg(test)]
 mod testing;
+pub mod validate;
 pub mod workflow_events;
 
 use builder::ProxyBuilder;
 use config::{AliasConfig, ApiKeyConfig};
-use database::logging::{LogSender, ProxyLogEntry, ProxyLogEvent};
+use database::{
+    live_tail::{LiveEventReceiver, LiveEventSender},
+    logging::{LogSender, ProxyLogEntry, ProxyLogEvent},
+    Database,
+};
 pub use error::Error;
 use error_stack::{Report, ResultExt};
 use format::{
@@ -28,8 +39,12 @@ use format::{
     StreamingResponseSender,
 };
 use http::HeaderMap;
+use metrics::{ProxyMetrics, RequestMetrics};
+use prompts::{select_prompt, Prompt, PromptReference, PromptVersion};
 use provider_lookup::{ModelLookupResult, ProviderLookup};
 use providers::ChatModelProvider;
+use redact::LogMode;
+use replay::{compare_choices, ReplayOverrides, ReplayResult};
 use request::RetryOptions;
 pub use response::{collect_response, CollectedResponse};
 use response::{handle_response, record_error};
@@ -38,7 +53,8 @@ use serde_with::{serde_as, DurationMilliSeconds};
 use smallvec::{smallvec, SmallVec};
 use tracing::{instrument, Span};
 use uuid::Uuid;
-use workflow_events::{EventPayload, WorkflowEvent};
+use validate::ConfigProblem;
+use workflow_events::{EventPayload, ScoreEvent, WorkflowEvent};
 
 use crate::request::try_model_choices;
 
@@ -70,6 +86,17 @@ pub struct Proxy {
     log_task: Option<tokio::task::JoinHandle<()>>,
     lookup: ProviderLookup,
     default_timeout: Option<Duration>,
+    metrics: Arc<ProxyMetrics>,
+    live_tx: LiveEventSender,
+    live_listener_task: Option<tokio::task::JoinHandle<()>>,
+    retention_task: Option<tokio::task::JoinHandle<()>>,
+    stale_run_task: Option<tokio::task::JoinHandle<()>>,
+    partition_task: Option<tokio::task::JoinHandle<()>>,
+    database: Option<Database>,
+    /// Prompt templates from the configuration
+    prompts: Vec<Prompt>,
+    /// Problems found while building the proxy
+    config_problems: Vec<ConfigProblem>,
 }
 
 impl Proxy {
@@ -89,21 +116,62 @@ impl Proxy {
 
         let log_entry = ProxyLogEntry::Proxied(Box::new(ProxyLogEvent::from_payload(id, body)));
 
-        log_tx.send_async(smallvec![log_entry]).await.ok();
+        log_tx.send(smallvec![log_entry]).await;
 
         id
     }
 
+    /// Subscribe to a stream of events as they are logged. This requires database logging to be
+    /// enabled.
+    pub fn subscribe_live_events(&self) -> LiveEventReceiver {
+        self.live_tx.subscribe()
+    }
+
     /// Record a step event to the database
     pub async fn record_workflow_event(&self, event: WorkflowEvent) {
         let Some(log_tx) = &self.log_tx else {
             return;
         };
 
-        log_tx
-            .send_async(smallvec![ProxyLogEntry::Workflow(event)])
-            .await
-            .ok();
+        log_tx.send(smallvec![ProxyLogEntry::Workflow(event)]).await;
+    }
+
+    /// Record a score for an LLM call, step, or run, and return its ID. A score needs at least one
+    /// of `run_id`, `step_id`, or `event_id`.
+    pub async fn record_score(&self, mut score: ScoreEvent) -> Result<Uuid, Report<Error>> {
+        score.validate()?;
+        let id = *score.id.get_or_insert_with(Uuid::now_v7);
+        self.record_workflow_event(WorkflowEvent::Score(score))
+            .await;
+        Ok(id)
+    }
+
+    /// Send the request from a logged event again, with some of its settings changed, and
+    /// compare the new response to the original. The new event is linked to the original through
+    /// its `replay_of` metadata.
+    pub async fn replay(
+        &self,
+        event_id: Uuid,
+        overrides: &ReplayOverrides,
+        mut options: ProxyRequestOptions,
+    ) -> Result<ReplayResult, Report<Error>> {
+        let db = self.database.as_ref().ok_or(Error::NoDatabase)?;
+        let original = db
+            .get_event(event_id)
+            .await?
+            .ok_or(Error::EventNotFound(event_id))?;
+
+        let request = overrides.apply(&original, &mut options)?;
+        let n = request.n.unwrap_or(1) as usize;
+        let response = self.send(options, request).await?;
+        let replay = collect_response(response, n).await?;
+        let choices = compare_choices(original.chat_response.as_ref(), &replay.response);
+
+        Ok(ReplayResult {
+            original,
+            replay,
+            choices,
+        })
     }
 
     /// Record multiple events, steps, and run updates
@@ -118,14 +186,52 @@ impl Proxy {
             .map(ProxyLogEntry::Workflow)
             .collect::<_>();
 
-        log_tx.send_async(events).await.ok();
+        log_tx.send(events).await;
+    }
+
+    /// Find a prompt by ID. Prompts in the configuration take precedence over prompts with the
+    /// same ID in the database.
+    pub async fn get_prompt(
+        &self,
+        id: &str,
+        version: &PromptVersion,
+    ) -> Result<Option<Prompt>, Report<Error>> {
+        if self.prompts.iter().any(|p| p.id == id) {
+            return Ok(select_prompt(&self.prompts, id, version).cloned());
+        }
+
+        match &self.database {
+            Some(db) => db.get_prompt(id, version).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Render a prompt template into the request, and record the prompt's ID and version in the
+    /// request metadata.
+    pub async fn render_prompt(
+        &self,
+        reference: &PromptReference,
+        options: &mut ProxyRequestOptions,
+        body: &mut ChatRequest,
+    ) -> Result<Prompt, Report<Error>> {
+        let prompt = self
+            .get_prompt(&reference.id, &reference.selector())
+            .await?
+            .ok_or_else(|| Error::PromptNotFound(reference.id.clone()))?;
+        prompt.apply(&reference.variables, body, options)?;
+        Ok(prompt)
     }
 
     pub async fn send(
         &self,
-        options: ProxyRequestOptions,
-        body: ChatRequest,
+        mut options: ProxyRequestOptions,
+        mut body: ChatRequest,
     ) -> Result<StreamingResponseReceiver, Report<Error>> {
+        if let Some(reference) = options.prompt.take() {
+            self.render_prompt(&reference, &mut options, &mut body)
+                .await?;
+        }
+
         let (chunk_tx, chunk_rx) = if body.stream {
             flume::unbounded()
         } else {
@@ -141,6 +247,11 @@ impl Proxy {
         let parent_span = tracing::Span::current();
         let log_tx = self.log_tx.clone();
         let default_timeout = self.default_timeout;
+        let metrics = RequestMetrics::new(
+            self.metrics.clone(),
+            &models.alias,
+            options.metadata.application.as_deref(),
+        );
         tokio::task::spawn(async move {
             Self::send_request(
                 parent_span,
@@ -150,6 +261,7 @@ impl Proxy {
                 default_timeout,
                 chunk_tx,
                 log_tx,
+                metrics,
             )
             .await
         });
@@ -166,7 +278,7 @@ impl Proxy {
     #[instrument(
         name = "llm.send_request",
         parent=&parent_span,
-        skip(options),
+        skip(options, metrics),
         fields(
             error,
             llm.options=serde_json::to_string(&options).ok(),
@@ -223,6 +335,7 @@ impl Proxy {
         default_timeout: Option<Duration>,
         output_tx: StreamingResponseSender,
         log_tx: Option<LogSender>,
+        metrics: RequestMetrics,
     ) {
         let id = uuid::Uuid::now_v7();
         let current_span = tracing::Span::current();
@@ -287,6 +400,7 @@ impl Proxy {
                 .unwrap_or_else(|| Duration::from_millis(60_000)),
             body.clone(),
             chunk_tx,
+            Some(&metrics),
         )
         .await;
 
@@ -305,6 +419,7 @@ impl Proxy {
             was_rate_limited: None,
             error: None,
             options,
+            offloaded: Default::default(),
         };
 
         match response {
@@ -328,10 +443,18 @@ impl Proxy {
                     chunk_rx,
                     output_tx,
                     log_tx,
+                    metrics,
                 )
                 .await;
             }
             Err(e) => {
+                metrics.record_failure(
+                    &e.provider,
+                    &e.model,
+                    global_start.elapsed(),
+                    e.num_retries,
+                    &e.error.current_context().kind,
+                );
                 record_error(
                     log_entry,
                     &e.error,
@@ -379,18 +502,74 @@ impl Proxy {
 
     /// Shutdown the proxy, making sure to write any queued logging events
     pub async fn shutdown(&mut self) {
+        // The stale run task holds a sender for the log queue, so stop it before waiting for the
+        // queue to drain.
+        if let Some(stale_run_task) = self.stale_run_task.take() {
+            stale_run_task.abort();
+            stale_run_task.await.ok();
+        }
+
         let log_tx = self.log_tx.take();
         drop(log_tx);
         let log_task = self.log_task.take();
         if let Some(log_task) = log_task {
             log_task.await.ok();
         }
+
+        if let Some(live_listener_task) = self.live_listener_task.take() {
+            live_listener_task.abort();
+        }
+
+        if let Some(retention_task) = self.retention_task.take() {
+            retention_task.abort();
+        }
+
+        if let Some(partition_task) = self.partition_task.take() {
+            partition_task.abort();
+        }
+    }
+
+    /// The database used by the proxy, if any. When blob storage is enabled, this resolves
+    /// references to bodies stored outside the database when reading runs.
+    pub fn database(&self) -> Option<&Database> {
+        self.database.as_ref()
+    }
+
+    /// The Prometheus metrics recorded by the proxy
+    pub fn metrics(&self) -> &ProxyMetrics {
+        &self.metrics
+    }
+
+    /// The number of log events that were dropped without being written to a log sink.
+    pub fn dropped_log_events(&self) -> u64 {
+        self.metrics.dropped_log_events()
+    }
+
+    /// The number of log events whose request and response bodies were removed because the log
+    /// queue was full.
+    pub fn dropped_log_bodies(&self) -> u64 {
+        self.metrics.dropped_log_bodies()
+    }
+
+    /// The number of messages currently waiting in the log queue
+    pub fn log_queue_depth(&self) -> usize {
+        self.log_tx.as_ref().map(|tx| tx.len()).unwrap_or(0)
+    }
+
+    /// Encode the proxy's metrics in the Prometheus text exposition format.
+    pub fn gather_metrics(&self) -> Result<String, Report<Error>> {
+        self.metrics.set_log_queue_depth(self.log_queue_depth());
+        self.metrics.encode()
     }
 
     /// Validate the loaded configuration, and return a list of problems found.
-    // todo this doesn't do anything yet
-    fn validate(&self) -> Vec<String> {
-        self.lookup.validate()
+    ///
+    /// This includes the problems found when the proxy was built, and a fresh check of the
+    /// current aliases, which may have changed since then.
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems = self.config_problems.clone();
+        problems.extend(self.lookup.validate());
+        problems
     }
 }
 
@@ -405,7 +584,7 @@ pub struct ModelAndProvider {
 }
 
 #[serde_as]
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ProxyRequestOptions {
     /// Override the model from the request body or select an alias.
     /// This can also be set by passing the x-chronicle-model HTTP header.
@@ -436,6 +615,13 @@ pub struct ProxyRequestOptions {
     /// Customize the retry behavior. This can also be set by passing the
     /// x-chronicle-retry HTTP header.
     pub retry: Option<RetryOptions>,
+    /// How much of the request to log. `metadata-only` skips logging the request and response
+    /// bodies. This can also be set by passing the x-chronicle-log HTTP header.
+    pub log: Option<LogMode>,
+
+    /// Render a stored prompt template into the request. This can also be set by passing the
+    /// x-chronicle-prompt HTTP header using JSON syntax.
+    pub prompt: Option<PromptReference>,
 
     /// Metadata to record for the request
     #[serde(default)]
@@ -475,6 +661,13 @@ impl ProxyRequestOptions {
             "boolean",
         )?;
         get_header_json(&mut self.retry, headers, "x-chronicle-retry")?;
+        get_header_json(&mut self.prompt, headers, "x-chronicle-prompt")?;
+        get_header_t(
+            &mut self.log,
+            headers,
+            "x-chronicle-log",
+            "\"full\" or \"metadata-only\"",
+        )?;
 
         let timeout = headers
             .get("x-chronicle-timeout")
@@ -520,12 +713,18 @@ impl ProxyRequestOptions {
         if self.retry.is_none() {
             self.retry = other.retry.clone();
         }
+        if self.log.is_none() {
+            self.log = other.log;
+        }
+        if self.prompt.is_none() {
+            self.prompt = other.prompt.clone();
+        }
         self.metadata.merge_from(&other.metadata);
         self.internal_metadata.merge_from(&other.internal_metadata);
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 /// Metadata about the internal source of this request. Mostly useful for multi-tenant
 /// scenarios where one proxy server is handling requests from multiple unrelated applications.
 pub struct ProxyRequestInternalMetadata {
@@ -551,7 +750,7 @@ impl ProxyRequestInternalMetadata {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 /// Metadata about the request and how it fits into the system as a whole. All of these
 /// fields are optional, and the `extra` field can be used to add anything else that useful
 /// for your use case.
@@ -592,6 +791,9 @@ pub struct ProxyRequestMetadata {
     /// The version of this prompt. This can also be set by passing the
     /// x-chronicle-prompt-version HTTP header.
     pub prompt_version: Option<u32>,
+    /// The ID of the logged event that this request replays, when it was sent by
+    /// [Proxy::replay].
+    pub replay_of: Option<Uuid>,
 
     /// Any other metadata to include. When passing this in the request body, any unknown fields
     /// are collected here. This can also be set by passing a JSON object to the
@@ -674,6 +876,9 @@ impl ProxyRequestMetadata {
         if self.prompt_version.is_none() {
             self.prompt_version = other.prompt_version;
         }
+        if self.replay_of.is_none() {
+            self.replay_of = other.replay_of;
+        }
         if self.extra.is_none() {
             self.extra = other.extra.clone();
         }
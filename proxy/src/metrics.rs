@@ -0,0 +1,406 @@
+//! Prometheus metrics for proxied requests and the logging pipeline
+use std::{sync::Arc, time::Duration};
+
+use error_stack::{Report, ResultExt};
+use prometheus::{
+    core::Collector, exponential_buckets, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, Opts, Registry, TextEncoder,
+};
+
+use crate::{format::UsageResponse, providers::ProviderErrorKind, Error};
+
+/// The labels applied to every request metric
+const REQUEST_LABELS: &[&str] = &["provider", "model", "alias", "application"];
+
+/// Prometheus metrics recorded by the proxy.
+#[derive(Debug, Clone)]
+pub struct ProxyMetrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    errors: IntCounterVec,
+    latency: HistogramVec,
+    try_latency: HistogramVec,
+    time_to_first_token: HistogramVec,
+    retries: IntCounterVec,
+    rate_limits: IntCounterVec,
+    tokens: IntCounterVec,
+    log_queue_depth: IntGauge,
+    db_write_failures: IntCounter,
+    log_events_dropped: IntCounterVec,
+    log_bodies_dropped: IntCounter,
+}
+
+impl ProxyMetrics {
+    /// Create the metrics in a new registry
+    pub fn new() -> Result<Self, Report<Error>> {
+        Self::with_registry(Registry::new())
+    }
+
+    /// Create the metrics and register them in an existing registry
+    pub fn with_registry(registry: Registry) -> Result<Self, Report<Error>> {
+        // 50ms to ~100 seconds
+        let latency_buckets = exponential_buckets(0.05, 2.0, 12).change_context(Error::Metrics)?;
+
+        let requests = IntCounterVec::new(
+            Opts::new("chronicle_requests_total", "Number of proxied requests"),
+            REQUEST_LABELS,
+        )
+        .change_context(Error::Metrics)?;
+
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "chronicle_request_errors_total",
+                "Number of proxied requests that failed, by error kind",
+            ),
+            &[REQUEST_LABELS, &["kind"]].concat(),
+        )
+        .change_context(Error::Metrics)?;
+
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "chronicle_request_duration_seconds",
+                "Total time taken by a proxied request, including retries",
+            )
+            .buckets(latency_buckets.clone()),
+            REQUEST_LABELS,
+        )
+        .change_context(Error::Metrics)?;
+
+        let try_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "chronicle_request_try_duration_seconds",
+                "Time taken by each attempt to send a request to a provider",
+            )
+            .buckets(latency_buckets.clone()),
+            REQUEST_LABELS,
+        )
+        .change_context(Error::Metrics)?;
+
+        let time_to_first_token = HistogramVec::new(
+            HistogramOpts::new(
+                "chronicle_time_to_first_token_seconds",
+                "Time from the start of the successful attempt until the first response chunk",
+            )
+            .buckets(latency_buckets),
+            REQUEST_LABELS,
+        )
+        .change_context(Error::Metrics)?;
+
+        let retries = IntCounterVec::new(
+            Opts::new(
+                "chronicle_retries_total",
+                "Number of retried provider requests",
+            ),
+            REQUEST_LABELS,
+        )
+        .change_context(Error::Metrics)?;
+
+        let rate_limits = IntCounterVec::new(
+            Opts::new(
+                "chronicle_rate_limits_total",
+                "Number of times a provider returned a rate limit error",
+            ),
+            REQUEST_LABELS,
+        )
+        .change_context(Error::Metrics)?;
+
+        let tokens = IntCounterVec::new(
+            Opts::new("chronicle_tokens_total", "Number of tokens used, by type"),
+            &[REQUEST_LABELS, &["type"]].concat(),
+        )
+        .change_context(Error::Metrics)?;
+
+        let log_queue_depth = IntGauge::new(
+            "chronicle_log_queue_depth",
+            "Number of log entries waiting to be written to the database",
+        )
+        .change_context(Error::Metrics)?;
+
+        let db_write_failures = IntCounter::new(
+            "chronicle_db_write_failures_total",
+            "Number of log batches that failed to write to the database",
+        )
+        .change_context(Error::Metrics)?;
+
+        let log_events_dropped = IntCounterVec::new(
+            Opts::new(
+                "chronicle_log_events_dropped_total",
+                "Number of log events that were dropped without being written, by reason",
+            ),
+            &["reason"],
+        )
+        .change_context(Error::Metrics)?;
+
+        let log_bodies_dropped = IntCounter::new(
+            "chronicle_log_bodies_dropped_total",
+            "Number of log events whose bodies were removed because the log queue was full",
+        )
+        .change_context(Error::Metrics)?;
+
+        registry
+            .register(Box::new(requests.clone()))
+            .and_then(|_| registry.register(Box::new(errors.clone())))
+            .and_then(|_| registry.register(Box::new(latency.clone())))
+            .and_then(|_| registry.register(Box::new(try_latency.clone())))
+            .and_then(|_| registry.register(Box::new(time_to_first_token.clone())))
+            .and_then(|_| registry.register(Box::new(retries.clone())))
+            .and_then(|_| registry.register(Box::new(rate_limits.clone())))
+            .and_then(|_| registry.register(Box::new(tokens.clone())))
+            .and_then(|_| registry.register(Box::new(log_queue_depth.clone())))
+            .and_then(|_| registry.register(Box::new(db_write_failures.clone())))
+            .and_then(|_| registry.register(Box::new(log_events_dropped.clone())))
+            .and_then(|_| registry.register(Box::new(log_bodies_dropped.clone())))
+            .change_context(Error::Metrics)?;
+
+        Ok(Self {
+            registry,
+            requests,
+            errors,
+            latency,
+            try_latency,
+            time_to_first_token,
+            retries,
+            rate_limits,
+            tokens,
+            log_queue_depth,
+            db_write_failures,
+            log_events_dropped,
+            log_bodies_dropped,
+        })
+    }
+
+    /// The registry that holds the metrics
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Encode the metrics in the Prometheus text exposition format.
+    pub fn encode(&self) -> Result<String, Report<Error>> {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .change_context(Error::Metrics)
+    }
+
+    /// Set the current number of batches waiting in the log queue
+    pub fn set_log_queue_depth(&self, depth: usize) {
+        self.log_queue_depth.set(depth as i64);
+    }
+
+    /// Record a batch of log entries that could not be written to the database
+    pub fn record_db_write_failure(&self) {
+        self.db_write_failures.inc();
+    }
+
+    /// Record log events that were dropped without being written
+    pub fn record_dropped_log_events(&self, reason: &str, count: usize) {
+        self.log_events_dropped
+            .with_label_values(&[reason])
+            .inc_by(count as u64);
+    }
+
+    /// The total number of log events that have been dropped, for any reason
+    pub fn dropped_log_events(&self) -> u64 {
+        self.log_events_dropped
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .map(|metric| metric.get_counter().get_value() as u64)
+            .sum()
+    }
+
+    /// Record log events whose bodies were removed to save space in the log queue
+    pub fn record_dropped_log_bodies(&self, count: usize) {
+        self.log_bodies_dropped.inc_by(count as u64);
+    }
+
+    /// The total number of log events whose bodies were removed
+    pub fn dropped_log_bodies(&self) -> u64 {
+        self.log_bodies_dropped.get()
+    }
+}
+
+/// Records metrics for a single proxied request.
+#[derive(Debug, Clone)]
+pub struct RequestMetrics {
+    metrics: Arc<ProxyMetrics>,
+    alias: String,
+    application: String,
+}
+
+impl RequestMetrics {
+    /// Create a recorder for a request
+    pub fn new(metrics: Arc<ProxyMetrics>, alias: &str, application: Option<&str>) -> Self {
+        Self {
+            metrics,
+            alias: alias.to_string(),
+            application: application.unwrap_or_default().to_string(),
+        }
+    }
+
+    fn labels<'a>(&'a self, provider: &'a str, model: &'a str) -> [&'a str; 4] {
+        [provider, model, &self.alias, &self.application]
+    }
+
+    /// Record a single attempt at sending a request to a provider. `error` should be set if the
+    /// attempt failed.
+    pub fn record_try(
+        &self,
+        provider: &str,
+        model: &str,
+        latency: Duration,
+        error: Option<&ProviderErrorKind>,
+    ) {
+        let labels = self.labels(provider, model);
+        self.metrics
+            .try_latency
+            .with_label_values(&labels)
+            .observe(latency.as_secs_f64());
+
+        if let Some(ProviderErrorKind::RateLimit { .. }) = error {
+            self.metrics.rate_limits.with_label_values(&labels).inc();
+        }
+    }
+
+    /// Record the time taken to receive the first chunk of the response.
+    pub fn record_time_to_first_token(&self, provider: &str, model: &str, latency: Duration) {
+        self.metrics
+            .time_to_first_token
+            .with_label_values(&self.labels(provider, model))
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Record a request that completed successfully
+    pub fn record_success(
+        &self,
+        provider: &str,
+        model: &str,
+        total_latency: Duration,
+        num_retries: u32,
+        usage: Option<&UsageResponse>,
+    ) {
+        let labels = self.labels(provider, model);
+        self.record_finished(&labels, total_latency, num_retries);
+
+        let Some(usage) = usage else {
+            return;
+        };
+
+        for (typ, count) in [
+            ("prompt", usage.prompt_tokens),
+            ("completion", usage.completion_tokens),
+        ] {
+            if let Some(count) = count {
+                self.metrics
+                    .tokens
+                    .with_label_values(&[&labels[..], &[typ]].concat())
+                    .inc_by(count as u64);
+            }
+        }
+    }
+
+    /// Record a request that failed
+    pub fn record_failure(
+        &self,
+        provider: &str,
+        model: &str,
+        total_latency: Duration,
+        num_retries: u32,
+        error: &ProviderErrorKind,
+    ) {
+        let labels = self.labels(provider, model);
+        self.record_finished(&labels, total_latency, num_retries);
+        self.metrics
+            .errors
+            .with_label_values(&[&labels[..], &[error.as_str()]].concat())
+            .inc();
+    }
+
+    fn record_finished(&self, labels: &[&str], total_latency: Duration, num_retries: u32) {
+        self.metrics.requests.with_label_values(labels).inc();
+        self.metrics
+            .latency
+            .with_label_values(labels)
+            .observe(total_latency.as_secs_f64());
+        if num_retries > 0 {
+            self.metrics
+                .retries
+                .with_label_values(labels)
+                .inc_by(num_retries as u64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{ProxyMetrics, RequestMetrics};
+    use crate::{format::UsageResponse, providers::ProviderErrorKind};
+
+    #[test]
+    fn encode_metrics() {
+        let metrics = Arc::new(ProxyMetrics::new().unwrap());
+        let request = RequestMetrics::new(metrics.clone(), "my-alias", Some("my-app"));
+
+        request.record_try(
+            "test",
+            "model-1",
+            Duration::from_millis(100),
+            Some(&ProviderErrorKind::RateLimit { retry_after: None }),
+        );
+        request.record_try("test", "model-1", Duration::from_millis(200), None);
+        request.record_time_to_first_token("test", "model-1", Duration::from_millis(50));
+        request.record_success(
+            "test",
+            "model-1",
+            Duration::from_millis(300),
+            1,
+            Some(&UsageResponse {
+                prompt_tokens: Some(10),
+                completion_tokens: Some(5),
+                total_tokens: Some(15),
+            }),
+        );
+        request.record_failure(
+            "test",
+            "model-1",
+            Duration::from_millis(300),
+            0,
+            &ProviderErrorKind::Timeout,
+        );
+        metrics.set_log_queue_depth(3);
+        metrics.record_db_write_failure();
+        metrics.record_dropped_log_events("write_failed", 3);
+        metrics.record_dropped_log_events("spill_full", 2);
+        assert_eq!(metrics.dropped_log_events(), 5);
+        metrics.record_dropped_log_bodies(4);
+        assert_eq!(metrics.dropped_log_bodies(), 4);
+
+        let output = metrics.encode().unwrap();
+        // The encoder sorts the labels by name
+        let labels = r#"alias="my-alias",application="my-app",model="model-1",provider="test""#;
+
+        assert!(output.contains(&format!("chronicle_requests_total{{{labels}}} 2")));
+        assert!(output.contains(
+            r#"chronicle_request_errors_total{alias="my-alias",application="my-app",kind="timeout",model="model-1",provider="test"} 1"#
+        ));
+        assert!(output.contains(&format!("chronicle_retries_total{{{labels}}} 1")));
+        assert!(output.contains(&format!("chronicle_rate_limits_total{{{labels}}} 1")));
+        assert!(output.contains(&format!(
+            r#"chronicle_tokens_total{{{labels},type="prompt"}} 10"#
+        )));
+        assert!(output.contains(&format!(
+            r#"chronicle_tokens_total{{{labels},type="completion"}} 5"#
+        )));
+        assert!(output.contains(&format!(
+            "chronicle_request_try_duration_seconds_count{{{labels}}} 2"
+        )));
+        assert!(output.contains(&format!(
+            "chronicle_time_to_first_token_seconds_count{{{labels}}} 1"
+        )));
+        assert!(output.contains("chronicle_log_queue_depth 3"));
+        assert!(output.contains("chronicle_db_write_failures_total 1"));
+        assert!(output.contains(r#"chronicle_log_events_dropped_total{reason="write_failed"} 3"#));
+        assert!(output.contains("chronicle_log_bodies_dropped_total 4"));
+    }
+}
@@ -0,0 +1,377 @@
+//! Versioned prompt templates, stored in the configuration or the database
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use error_stack::Report;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    format::{ChatMessage, ChatRequest},
+    Error, ProxyRequestOptions,
+};
+
+pub(crate) const PROMPT_COLUMNS: &str = "prompt_id, version, description, template, created_at";
+
+/// A message in a prompt template. `{{ name }}` in the content is replaced by the variable
+/// `name` when the prompt is rendered.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptMessage {
+    pub role: String,
+    pub content: String,
+}
+
+/// The contents of a prompt template
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PromptTemplate {
+    /// The system prompt, which may contain variables
+    pub system: Option<String>,
+    /// Messages to place before any messages in the request
+    #[serde(default)]
+    pub messages: Vec<PromptMessage>,
+    /// The model or alias to use when the request does not choose one
+    pub model: Option<String>,
+    /// The provider to use with `model`
+    pub provider: Option<String>,
+    /// The default sampling temperature
+    pub temperature: Option<f32>,
+    /// The default maximum number of tokens to generate
+    pub max_tokens: Option<u32>,
+    /// The default top-P value
+    pub top_p: Option<f32>,
+}
+
+/// A version of a prompt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Prompt {
+    /// The ID of the prompt, shared by all of its versions
+    pub id: String,
+    /// The version of the prompt, starting at 1
+    pub version: u32,
+    /// Labels, such as `production`, that point to this version
+    #[serde(default)]
+    pub labels: Vec<String>,
+    pub description: Option<String>,
+    #[serde(flatten)]
+    pub template: PromptTemplate,
+    /// When the version was created. This is empty for prompts from the configuration.
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// A prompt version to create
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NewPrompt {
+    /// The ID of the prompt. If the prompt already exists, this creates its next version.
+    pub id: String,
+    pub description: Option<String>,
+    #[serde(flatten)]
+    pub template: PromptTemplate,
+    /// Point these labels at the new version, moving them from older versions if needed
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+/// Which version of a prompt to use
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PromptVersion {
+    /// The newest version
+    #[default]
+    Latest,
+    /// A specific version number
+    Version(u32),
+    /// The version that a label, such as `production`, points to
+    Label(String),
+}
+
+impl FromStr for PromptVersion {
+    type Err = std::convert::Infallible;
+
+    /// Parse a version number, `latest`, or a label
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let version = match s.parse::<u32>() {
+            Ok(version) => PromptVersion::Version(version),
+            Err(_) if s == "latest" => PromptVersion::Latest,
+            Err(_) => PromptVersion::Label(s.to_string()),
+        };
+        Ok(version)
+    }
+}
+
+impl std::fmt::Display for PromptVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PromptVersion::Latest => f.write_str("latest"),
+            PromptVersion::Version(version) => write!(f, "{version}"),
+            PromptVersion::Label(label) => f.write_str(label),
+        }
+    }
+}
+
+/// A request to render a stored prompt instead of, or in addition to, sending `messages`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptReference {
+    /// The ID of the prompt
+    pub id: String,
+    /// The version of the prompt to use
+    pub version: Option<u32>,
+    /// Use the version that this label points to. This is ignored if `version` is set. If
+    /// neither is set, the latest version is used.
+    pub label: Option<String>,
+    /// The values of the template's variables
+    #[serde(default)]
+    pub variables: serde_json::Map<String, serde_json::Value>,
+}
+
+impl PromptReference {
+    /// The version that this reference selects
+    pub fn selector(&self) -> PromptVersion {
+        match (self.version, &self.label) {
+            (Some(version), _) => PromptVersion::Version(version),
+            (None, Some(label)) => PromptVersion::Label(label.clone()),
+            (None, None) => PromptVersion::Latest,
+        }
+    }
+}
+
+/// Choose a version from a list of prompts. Prompts with other IDs are ignored.
+pub fn select_prompt<'a>(
+    prompts: &'a [Prompt],
+    id: &str,
+    version: &PromptVersion,
+) -> Option<&'a Prompt> {
+    let mut versions = prompts.iter().filter(|p| p.id == id);
+    match version {
+        PromptVersion::Latest => versions.max_by_key(|p| p.version),
+        PromptVersion::Version(version) => versions.find(|p| p.version == *version),
+        PromptVersion::Label(label) => versions
+            .filter(|p| p.labels.contains(label))
+            .max_by_key(|p| p.version),
+    }
+}
+
+/// Add labels, given as `(prompt_id, label, version)`, to the prompt versions they point to
+pub(crate) fn attach_labels(prompts: &mut [Prompt], labels: Vec<(String, String, u32)>) {
+    for (id, label, version) in labels {
+        if let Some(prompt) = prompts
+            .iter_mut()
+            .find(|p| p.id == id && p.version == version)
+        {
+            prompt.labels.push(label);
+        }
+    }
+}
+
+/// Replace each `{{ name }}` in `template` with the value of the variable `name`. String values
+/// are inserted as-is, and other values are inserted as JSON.
+pub fn render_template(
+    prompt_id: &str,
+    template: &str,
+    variables: &serde_json::Map<String, serde_json::Value>,
+) -> Result<String, Report<Error>> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+
+        let name = rest[start + 2..start + 2 + len].trim();
+        let is_variable = !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_variable {
+            // Not a variable, so keep the braces as they are.
+            output.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        output.push_str(&rest[..start]);
+        match variables.get(name) {
+            Some(serde_json::Value::String(value)) => output.push_str(value),
+            Some(value) => output.push_str(&value.to_string()),
+            None => {
+                return Err(Report::new(Error::MissingPromptVariable(
+                    prompt_id.to_string(),
+                    name.to_string(),
+                )))
+            }
+        }
+        rest = &rest[start + 2 + len + 2..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+impl Prompt {
+    /// Render the template into `body`. The template's messages go before the messages already
+    /// in the request, and its system prompt, model, and parameters are only used when the request
+    /// does not set them. The prompt ID and version are recorded in the request metadata.
+    pub fn apply(
+        &self,
+        variables: &serde_json::Map<String, serde_json::Value>,
+        body: &mut ChatRequest,
+        options: &mut ProxyRequestOptions,
+    ) -> Result<(), Report<Error>> {
+        let template = &self.template;
+        let render = |text: &str| render_template(&self.id, text, variables);
+
+        if body.system.is_none() {
+            body.system = template.system.as_deref().map(render).transpose()?;
+        }
+
+        let messages = template
+            .messages
+            .iter()
+            .map(|message| {
+                Ok::<_, Report<Error>>(ChatMessage {
+                    role: Some(message.role.clone()),
+                    content: Some(render(&message.content)?),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        body.messages.splice(0..0, messages);
+
+        if body.model.is_none() && options.model.is_none() && options.models.is_empty() {
+            options.model = template.model.clone();
+            if options.provider.is_none() {
+                options.provider = template.provider.clone();
+            }
+        }
+        body.temperature = body.temperature.or(template.temperature);
+        body.max_tokens = body.max_tokens.or(template.max_tokens);
+        body.top_p = body.top_p.or(template.top_p);
+
+        options.metadata.prompt_id = Some(self.id.clone());
+        options.metadata.prompt_version = Some(self.version);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn variables(value: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        value.as_object().cloned().unwrap()
+    }
+
+    fn prompt(version: u32, labels: &[&str]) -> Prompt {
+        Prompt {
+            id: "summarize".to_string(),
+            version,
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            description: None,
+            template: PromptTemplate {
+                system: Some("Summarize text about {{topic}}".to_string()),
+                messages: vec![PromptMessage {
+                    role: "user".to_string(),
+                    content: "{{ text }} in {{count}} words".to_string(),
+                }],
+                model: Some("gpt-4o".to_string()),
+                provider: Some("openai".to_string()),
+                temperature: Some(0.5),
+                ..Default::default()
+            },
+            created_at: None,
+        }
+    }
+
+    #[test]
+    fn render() {
+        let vars = variables(json!({ "name": "Ada", "n": 3, "list": ["a"] }));
+        assert_eq!(
+            render_template("p", "Hi {{name}}, {{ n }} {{list}}", &vars).unwrap(),
+            r#"Hi Ada, 3 ["a"]"#
+        );
+        assert_eq!(
+            render_template("p", "{{ not a var }} {{}} {{name", &vars).unwrap(),
+            "{{ not a var }} {{}} {{name"
+        );
+
+        let err = render_template("p", "Hi {{ missing }}", &vars).expect_err("missing variable");
+        assert!(matches!(
+            err.current_context(),
+            Error::MissingPromptVariable(id, name) if id == "p" && name == "missing"
+        ));
+    }
+
+    #[test]
+    fn parse_version() {
+        assert_eq!("3".parse::<PromptVersion>(), Ok(PromptVersion::Version(3)));
+        assert_eq!("latest".parse::<PromptVersion>(), Ok(PromptVersion::Latest));
+        assert_eq!(
+            "production".parse::<PromptVersion>(),
+            Ok(PromptVersion::Label("production".to_string()))
+        );
+    }
+
+    #[test]
+    fn select() {
+        let prompts = vec![prompt(1, &["production"]), prompt(2, &["staging"])];
+        let select = |version| select_prompt(&prompts, "summarize", &version).map(|p| p.version);
+        assert_eq!(select(PromptVersion::Latest), Some(2));
+        assert_eq!(select(PromptVersion::Version(1)), Some(1));
+        assert_eq!(select(PromptVersion::Version(3)), None);
+        assert_eq!(
+            select(PromptVersion::Label("production".to_string())),
+            Some(1)
+        );
+        assert_eq!(
+            select_prompt(&prompts, "other", &PromptVersion::Latest),
+            None
+        );
+    }
+
+    #[test]
+    fn apply() {
+        let vars = variables(json!({ "topic": "birds", "text": "Owls hoot", "count": 5 }));
+        let mut body = ChatRequest {
+            messages: vec![ChatMessage {
+                role: Some("user".to_string()),
+                content: Some("Be concise".to_string()),
+                ..Default::default()
+            }],
+            temperature: Some(1.0),
+            ..Default::default()
+        };
+        let mut options = ProxyRequestOptions::default();
+        prompt(2, &[])
+            .apply(&vars, &mut body, &mut options)
+            .unwrap();
+
+        assert_eq!(body.system.as_deref(), Some("Summarize text about birds"));
+        assert_eq!(body.messages.len(), 2);
+        assert_eq!(
+            body.messages[0].content.as_deref(),
+            Some("Owls hoot in 5 words")
+        );
+        assert_eq!(body.messages[1].content.as_deref(), Some("Be concise"));
+        assert_eq!(body.temperature, Some(1.0), "request value is kept");
+        assert_eq!(options.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(options.provider.as_deref(), Some("openai"));
+        assert_eq!(options.metadata.prompt_id.as_deref(), Some("summarize"));
+        assert_eq!(options.metadata.prompt_version, Some(2));
+    }
+
+    #[test]
+    fn apply_keeps_request_model() {
+        let vars = variables(json!({ "topic": "birds", "text": "Owls hoot", "count": 5 }));
+        let mut body = ChatRequest {
+            model: Some("claude-3-5-sonnet".to_string()),
+            ..Default::default()
+        };
+        let mut options = ProxyRequestOptions::default();
+        prompt(1, &[])
+            .apply(&vars, &mut body, &mut options)
+            .unwrap();
+
+        assert_eq!(options.model, None);
+        assert_eq!(options.provider, None);
+        assert_eq!(body.temperature, Some(0.5));
+    }
+}
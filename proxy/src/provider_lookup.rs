@@ -7,6 +7,7 @@ use crate::{
     config::{AliasConfig, ApiKeyConfig},
     format::ChatRequest,
     providers::ChatModelProvider,
+    validate::ConfigProblem,
     Error, ProxyRequestOptions,
 };
 
@@ -240,9 +241,40 @@ impl ProviderLookup {
         self.0.write().unwrap().api_keys.remove(name);
     }
 
-    pub(crate) fn validate(&self) -> Vec<String> {
-        todo!();
-        vec![]
+    /// Check the aliases for references to missing providers and API keys.
+    pub(crate) fn validate(&self) -> Vec<ConfigProblem> {
+        let lookup = self.0.read().unwrap();
+        let mut aliases = lookup.aliases.values().collect::<Vec<_>>();
+        aliases.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut problems = Vec::new();
+        for alias in aliases {
+            if alias.models.is_empty() {
+                problems.push(ConfigProblem::AliasEmpty {
+                    alias: alias.name.clone(),
+                });
+            }
+
+            for model in &alias.models {
+                if !lookup.providers.contains_key(&model.provider) {
+                    problems.push(ConfigProblem::AliasMissingProvider {
+                        alias: alias.name.clone(),
+                        provider: model.provider.clone(),
+                    });
+                }
+
+                if let Some(key) = &model.api_key_name {
+                    if !lookup.api_keys.contains_key(key) {
+                        problems.push(ConfigProblem::AliasMissingApiKey {
+                            alias: alias.name.clone(),
+                            api_key: key.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        problems
     }
 }
 
@@ -255,6 +287,7 @@ mod test {
         config::{AliasConfig, AliasConfigProvider, ApiKeyConfig},
         format::ChatRequest,
         providers::ChatModelProvider,
+        validate::ConfigProblem,
         Error, ModelAndProvider, ProxyRequestOptions,
     };
 
@@ -627,4 +660,36 @@ mod test {
 
         assert!(matches!(result, Error::NoAliasApiKey(_, _)));
     }
+
+    #[test]
+    fn validate() {
+        let lookup = generate_lookup();
+        lookup.set_alias(AliasConfig {
+            name: "empty-alias".to_string(),
+            random_order: false,
+            models: vec![],
+        });
+
+        let problems = lookup.validate();
+        assert_eq!(
+            problems,
+            vec![
+                ConfigProblem::AliasMissingApiKey {
+                    alias: "bad-key-alias".to_string(),
+                    api_key: "no-key".to_string(),
+                },
+                ConfigProblem::AliasMissingProvider {
+                    alias: "bad-key-alias".to_string(),
+                    provider: "no-provider".to_string(),
+                },
+                ConfigProblem::AliasMissingProvider {
+                    alias: "bad-provider-alias".to_string(),
+                    provider: "no-provider".to_string(),
+                },
+                ConfigProblem::AliasEmpty {
+                    alias: "empty-alias".to_string(),
+                },
+            ]
+        );
+    }
 }
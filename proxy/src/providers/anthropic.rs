@@ -225,7 +225,7 @@ struct AnthropicMetadata {
 }
 
 #[derive(Serialize, Debug, Clone)]
-struct AnthropicTool {
+pub(crate) struct AnthropicTool {
     name: String,
     description: Option<String>,
     input_schema: Option<serde_json::Value>,
@@ -397,9 +397,9 @@ fn convert_from_response(
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct AnthropicChatMessage {
-    role: String,
-    content: SmallVec<[AnthropicChatContent; 1]>,
+pub(crate) struct AnthropicChatMessage {
+    pub(crate) role: String,
+    pub(crate) content: SmallVec<[AnthropicChatContent; 1]>,
     #[serde(skip_serializing_if = "Option::is_none")]
     cache_control: Option<CacheControl>,
 }
@@ -443,7 +443,7 @@ impl TryFrom<ChatMessage> for AnthropicChatMessage {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
-enum AnthropicChatContent {
+pub(crate) enum AnthropicChatContent {
     Text {
         text: String,
     },
@@ -456,7 +456,7 @@ enum AnthropicChatContent {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct AnthropicToolUse {
+pub(crate) struct AnthropicToolUse {
     id: String,
     name: String,
     input: serde_json::Value,
@@ -0,0 +1,765 @@
+//! Redaction of sensitive data from events before they are logged
+use std::{borrow::Cow, collections::BTreeMap, str::FromStr};
+
+use ahash::AHashMap;
+use error_stack::{Report, ResultExt};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    database::logging::{ProxyLogEntry, ProxyLogEvent},
+    workflow_events::WorkflowEvent,
+    Error,
+};
+
+/// The default text that replaces redacted values
+pub const DEFAULT_REPLACEMENT: &str = "[REDACTED]";
+
+/// How much of a request to log
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogMode {
+    /// Log the request and response, subject to the redaction policy
+    #[default]
+    Full,
+    /// Log only the metadata, omitting the request and response bodies
+    MetadataOnly,
+}
+
+/// An unrecognized [LogMode]
+#[derive(thiserror::Error, Debug)]
+#[error("Unknown log mode {0}")]
+pub struct UnknownLogMode(String);
+
+impl FromStr for LogMode {
+    type Err = UnknownLogMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(LogMode::Full),
+            "metadata-only" => Ok(LogMode::MetadataOnly),
+            _ => Err(UnknownLogMode(s.to_string())),
+        }
+    }
+}
+
+/// Built-in detectors for common kinds of sensitive data
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Detector {
+    /// Email addresses
+    Email,
+    /// Phone numbers, in North American or international format
+    Phone,
+    /// Credit card numbers that pass the Luhn check
+    CreditCard,
+    /// Strings that look like API keys or access tokens
+    ApiKey,
+}
+
+impl Detector {
+    fn pattern(&self) -> &'static str {
+        match self {
+            Detector::Email => r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}",
+            Detector::Phone => {
+                r"(?:\+\d{1,3}[-.\s]?)?(?:\(\d{3}\)|\b\d{3})[-.\s]?\d{3}[-.\s]?\d{4}\b"
+            }
+            Detector::CreditCard => r"\b\d(?:[ -]?\d){12,18}\b",
+            Detector::ApiKey => concat!(
+                r"\b(?:",
+                // OpenAI, Anthropic, Stripe, and similar
+                r"(?:sk|pk|rk)-[A-Za-z0-9_-]{16,}",
+                // AWS access key IDs
+                r"|AKIA[0-9A-Z]{16}",
+                // GitHub tokens
+                r"|gh[pousr]_[A-Za-z0-9]{36,}",
+                // Slack tokens
+                r"|xox[abprs]-[A-Za-z0-9-]{10,}",
+                // Google API keys
+                r"|AIza[0-9A-Za-z_-]{35}",
+                r")\b"
+            ),
+        }
+    }
+
+    fn replacement(&self) -> &'static str {
+        match self {
+            Detector::Email => "[EMAIL]",
+            Detector::Phone => "[PHONE]",
+            Detector::CreditCard => "[CREDIT_CARD]",
+            Detector::ApiKey => "[API_KEY]",
+        }
+    }
+}
+
+/// Replace text matching a regular expression
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RedactionRule {
+    /// The regular expression to match
+    pub pattern: String,
+    /// The text to replace each match with. Capture groups can be referenced as `$1` or
+    /// `$name`. Defaults to the policy's replacement.
+    pub replacement: Option<String>,
+}
+
+/// The part of an event that a [FieldMask] applies to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskTarget {
+    /// The [ChatRequest](crate::format::ChatRequest) of a proxied request
+    Request,
+    /// The [SingleChatResponse](crate::format::SingleChatResponse) of a proxied request
+    Response,
+    /// The input of a workflow run or step
+    Input,
+    /// The output of a workflow run or step
+    Output,
+    /// The data of a custom event
+    Data,
+}
+
+/// Replace the values at a JSON path
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldMask {
+    /// The part of the event that the path applies to
+    pub target: MaskTarget,
+    /// A path such as `$.messages[*].content`. Supports field names, array indexes, and `*` to
+    /// match every field or array element.
+    pub path: String,
+}
+
+/// A set of redactions to apply to logged events
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RedactionPolicy {
+    /// Built-in detectors to run on every string
+    #[serde(default)]
+    pub detectors: Vec<Detector>,
+    /// Regular expressions to run on every string
+    #[serde(default)]
+    pub rules: Vec<RedactionRule>,
+    /// Values to replace entirely
+    #[serde(default)]
+    pub masks: Vec<FieldMask>,
+    /// Omit the request and response bodies and workflow inputs and outputs, logging only the
+    /// metadata.
+    #[serde(default)]
+    pub metadata_only: bool,
+    /// The text that replaces masked values and rule matches. Defaults to `[REDACTED]`.
+    pub replacement: Option<String>,
+}
+
+/// Configuration for redacting logged events
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RedactionConfig {
+    /// The policy to use for events that don't have an application-specific policy
+    #[serde(flatten)]
+    pub default: RedactionPolicy,
+    /// Policies for specific applications, used instead of the default policy. Proxied requests
+    /// and run start events are matched by their `application`. Other workflow events don't
+    /// carry an application and always use the default policy.
+    #[serde(default)]
+    pub applications: BTreeMap<String, RedactionPolicy>,
+}
+
+#[derive(Debug, Clone)]
+enum PathSegment {
+    Field(String),
+    Index(usize),
+    Wildcard,
+}
+
+fn parse_path(path: &str) -> Result<Vec<PathSegment>, Report<Error>> {
+    let invalid = || Error::InvalidRedaction(format!("Invalid JSON path {path}"));
+
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        if let Some(index) = rest.strip_prefix('[') {
+            let end = index.find(']').ok_or_else(invalid)?;
+            let segment = match &index[..end] {
+                "*" => PathSegment::Wildcard,
+                i => PathSegment::Index(i.parse::<usize>().change_context_lazy(invalid)?),
+            };
+            segments.push(segment);
+            rest = &index[end + 1..];
+        } else {
+            let field = rest.strip_prefix('.').unwrap_or(rest);
+            let end = field.find(['.', '[']).unwrap_or(field.len());
+            let segment = match &field[..end] {
+                "" => return Err(Report::new(invalid())),
+                "*" => PathSegment::Wildcard,
+                name => PathSegment::Field(name.to_string()),
+            };
+            segments.push(segment);
+            rest = &field[end..];
+        }
+    }
+
+    Ok(segments)
+}
+
+fn apply_mask(value: &mut Value, path: &[PathSegment], replacement: &str) {
+    let Some((segment, rest)) = path.split_first() else {
+        *value = Value::String(replacement.to_string());
+        return;
+    };
+
+    match (segment, value) {
+        (PathSegment::Field(name), Value::Object(map)) => {
+            if let Some(v) = map.get_mut(name) {
+                apply_mask(v, rest, replacement);
+            }
+        }
+        (PathSegment::Index(i), Value::Array(items)) => {
+            if let Some(v) = items.get_mut(*i) {
+                apply_mask(v, rest, replacement);
+            }
+        }
+        (PathSegment::Wildcard, Value::Object(map)) => {
+            for v in map.values_mut() {
+                apply_mask(v, rest, replacement);
+            }
+        }
+        (PathSegment::Wildcard, Value::Array(items)) => {
+            for v in items {
+                apply_mask(v, rest, replacement);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn luhn_valid(digits: &str) -> bool {
+    let digits = digits
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .collect::<Vec<_>>();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| {
+            if i % 2 == 1 {
+                let doubled = d * 2;
+                if doubled > 9 {
+                    doubled - 9
+                } else {
+                    doubled
+                }
+            } else {
+                d
+            }
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    regex: Regex,
+    replacement: String,
+    /// Only replace matches that pass the Luhn check
+    luhn: bool,
+}
+
+#[derive(Debug)]
+struct CompiledPolicy {
+    rules: Vec<CompiledRule>,
+    masks: Vec<(MaskTarget, Vec<PathSegment>)>,
+    metadata_only: bool,
+    replacement: String,
+}
+
+impl CompiledPolicy {
+    fn new(policy: &RedactionPolicy) -> Result<Self, Report<Error>> {
+        let replacement = policy
+            .replacement
+            .clone()
+            .unwrap_or_else(|| DEFAULT_REPLACEMENT.to_string());
+
+        let detectors = policy.detectors.iter().map(|detector| {
+            Ok(CompiledRule {
+                regex: Regex::new(detector.pattern()).change_context(Error::InvalidRedaction(
+                    format!("Invalid detector {detector:?}"),
+                ))?,
+                replacement: detector.replacement().to_string(),
+                luhn: *detector == Detector::CreditCard,
+            })
+        });
+
+        let rules = policy.rules.iter().map(|rule| {
+            Ok(CompiledRule {
+                regex: Regex::new(&rule.pattern).change_context_lazy(|| {
+                    Error::InvalidRedaction(format!("Invalid pattern {}", rule.pattern))
+                })?,
+                replacement: rule
+                    .replacement
+                    .clone()
+                    .unwrap_or_else(|| replacement.clone()),
+                luhn: false,
+            })
+        });
+
+        let masks = policy
+            .masks
+            .iter()
+            .map(|mask| Ok((mask.target, parse_path(&mask.path)?)))
+            .collect::<Result<Vec<_>, Report<Error>>>()?;
+
+        Ok(Self {
+            rules: detectors
+                .chain(rules)
+                .collect::<Result<Vec<_>, Report<Error>>>()?,
+            masks,
+            metadata_only: policy.metadata_only,
+            replacement,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.masks.is_empty() && !self.metadata_only
+    }
+
+    fn redact_str<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        let mut result = Cow::Borrowed(s);
+        for rule in &self.rules {
+            let replaced = if rule.luhn {
+                rule.regex.replace_all(&result, |caps: &regex::Captures| {
+                    if luhn_valid(&caps[0]) {
+                        rule.replacement.clone()
+                    } else {
+                        caps[0].to_string()
+                    }
+                })
+            } else {
+                rule.regex.replace_all(&result, rule.replacement.as_str())
+            };
+
+            if let Cow::Owned(replaced) = replaced {
+                result = Cow::Owned(replaced);
+            }
+        }
+
+        result
+    }
+
+    fn redact_strings(&self, value: &mut Value) {
+        match value {
+            Value::String(s) => {
+                if let Cow::Owned(redacted) = self.redact_str(s) {
+                    *s = redacted;
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|v| self.redact_strings(v)),
+            Value::Object(map) => map.values_mut().for_each(|v| self.redact_strings(v)),
+            _ => {}
+        }
+    }
+
+    /// Redact a JSON value in place
+    fn redact_value(&self, target: MaskTarget, value: &mut Value) {
+        for (mask_target, path) in &self.masks {
+            if *mask_target == target {
+                apply_mask(value, path, &self.replacement);
+            }
+        }
+
+        self.redact_strings(value);
+    }
+
+    /// Redact a structure by converting it to JSON and back. If the redacted value no longer
+    /// fits the structure, for example because a mask replaced a number, this returns an error
+    /// so that the caller can drop the value instead of logging it unredacted.
+    fn redact_typed<T: Serialize + serde::de::DeserializeOwned>(
+        &self,
+        target: MaskTarget,
+        item: &mut T,
+    ) -> Result<(), serde_json::Error> {
+        let mut value = serde_json::to_value(&*item)?;
+        self.redact_value(target, &mut value);
+        *item = serde_json::from_value(value)?;
+        Ok(())
+    }
+
+    fn redact_proxied(&self, event: &mut ProxyLogEvent) -> Result<(), serde_json::Error> {
+        if let Some(request) = &mut event.request {
+            self.redact_typed(MaskTarget::Request, request)?;
+        }
+
+        if let Some(response) = &mut event.response {
+            self.redact_typed(MaskTarget::Response, &mut response.body)?;
+        }
+
+        if let Some(error) = &mut event.error {
+            self.redact_strings(error);
+        }
+
+        // Custom events recorded through the proxy store their data in the metadata.
+        if event.request.is_none() {
+            if let Some(extra) = event.options.metadata.extra.take() {
+                let mut value = Value::Object(extra);
+                self.redact_value(MaskTarget::Data, &mut value);
+                if let Value::Object(extra) = value {
+                    event.options.metadata.extra = Some(extra);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn redact_workflow(&self, event: &mut WorkflowEvent) {
+        let (target, value) = match event {
+            WorkflowEvent::RunStart(event) => (MaskTarget::Input, event.input.as_mut()),
+            WorkflowEvent::RunUpdate(event) => (MaskTarget::Output, event.output.as_mut()),
+            WorkflowEvent::StepStart(event) => (MaskTarget::Input, Some(&mut event.data.input)),
+            WorkflowEvent::StepEnd(event) => (MaskTarget::Output, Some(&mut event.data.output)),
+            WorkflowEvent::StepError(event) => {
+                self.redact_strings(&mut event.data.error);
+                return;
+            }
+            WorkflowEvent::Event(event) => {
+                if let Some(error) = &mut event.error {
+                    self.redact_strings(error);
+                }
+                (MaskTarget::Data, event.data.as_mut())
+            }
+            WorkflowEvent::Score(event) => {
+                if let Some(comment) = &mut event.comment {
+                    if let Cow::Owned(redacted) = self.redact_str(comment) {
+                        *comment = redacted;
+                    }
+                }
+                return;
+            }
+            WorkflowEvent::StepState(_) => return,
+        };
+
+        if let Some(value) = value {
+            self.redact_value(target, value);
+        }
+    }
+
+    fn redact(&self, entry: &mut ProxyLogEntry) {
+        if self.metadata_only {
+            entry.remove_bodies();
+            return;
+        }
+
+        match entry {
+            ProxyLogEntry::Proxied(event) => {
+                if let Err(e) = self.redact_proxied(event) {
+                    tracing::warn!(error = ?e, "Failed to redact event, omitting its bodies");
+                    entry.remove_bodies();
+                }
+            }
+            ProxyLogEntry::Workflow(event) => self.redact_workflow(event),
+        }
+    }
+}
+
+/// Applies a [RedactionConfig] to log entries
+#[derive(Debug)]
+pub struct Redactor {
+    default: CompiledPolicy,
+    applications: AHashMap<String, CompiledPolicy>,
+}
+
+impl Redactor {
+    /// Compile the policies in a [RedactionConfig]
+    pub fn new(config: &RedactionConfig) -> Result<Self, Report<Error>> {
+        let applications = config
+            .applications
+            .iter()
+            .map(|(app, policy)| {
+                let policy = CompiledPolicy::new(policy)
+                    .attach_printable_lazy(|| format!("In policy for application {app}"))?;
+                Ok((app.clone(), policy))
+            })
+            .collect::<Result<_, Report<Error>>>()?;
+
+        Ok(Self {
+            default: CompiledPolicy::new(&config.default)?,
+            applications,
+        })
+    }
+
+    fn policy(&self, application: Option<&str>) -> &CompiledPolicy {
+        application
+            .and_then(|app| self.applications.get(app))
+            .unwrap_or(&self.default)
+    }
+
+    /// Redact a log entry in place, using the policy for its application.
+    pub fn redact(&self, entry: &mut ProxyLogEntry) {
+        let application = match entry {
+            ProxyLogEntry::Proxied(event) => event.options.metadata.application.as_deref(),
+            ProxyLogEntry::Workflow(WorkflowEvent::RunStart(event)) => event.application.as_deref(),
+            ProxyLogEntry::Workflow(_) => None,
+        };
+
+        let policy = self.policy(application);
+        if !policy.is_empty() {
+            policy.redact(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        format::{ChatMessage, ChatRequest},
+        workflow_events::{StepEventData, StepStartData},
+    };
+
+    fn step_start(input: Value) -> ProxyLogEntry {
+        ProxyLogEntry::Workflow(WorkflowEvent::StepStart(StepEventData {
+            step_id: Uuid::nil(),
+            run_id: Uuid::nil(),
+            data: StepStartData {
+                typ: "step".to_string(),
+                name: None,
+                parent_step: None,
+                span_id: None,
+                tags: Vec::new(),
+                info: None,
+                input,
+            },
+            time: None,
+            event_id: None,
+        }))
+    }
+
+    fn input(entry: &ProxyLogEntry) -> &Value {
+        match entry {
+            ProxyLogEntry::Workflow(WorkflowEvent::StepStart(event)) => &event.data.input,
+            _ => panic!("unexpected entry type"),
+        }
+    }
+
+    fn proxied(application: &str, content: &str) -> ProxyLogEntry {
+        let mut event = ProxyLogEvent::from_payload(
+            Uuid::nil(),
+            crate::workflow_events::EventPayload {
+                typ: "test".to_string(),
+                data: None,
+                error: None,
+                run_id: Uuid::nil(),
+                step_id: Uuid::nil(),
+                time: None,
+                event_id: None,
+                internal_metadata: None,
+            },
+        );
+        event.options.metadata.application = Some(application.to_string());
+        event.request = Some(ChatRequest {
+            model: Some("the-model".to_string()),
+            messages: vec![ChatMessage {
+                role: Some("user".to_string()),
+                content: Some(content.to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        ProxyLogEntry::Proxied(Box::new(event))
+    }
+
+    fn request(entry: &ProxyLogEntry) -> &ChatRequest {
+        match entry {
+            ProxyLogEntry::Proxied(event) => event.request.as_ref().unwrap(),
+            _ => panic!("unexpected entry type"),
+        }
+    }
+
+    #[test]
+    fn detectors() {
+        let redactor = Redactor::new(&RedactionConfig {
+            default: RedactionPolicy {
+                detectors: vec![
+                    Detector::Email,
+                    Detector::Phone,
+                    Detector::CreditCard,
+                    Detector::ApiKey,
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut entry = step_start(json!({
+            "email": "Write to jane.doe@example.com today",
+            "phone": "Call (555) 123-4567 or +1 555-987-6543",
+            "card": "Card 4111 1111 1111 1111, order 1234567890123",
+            "key": "Use sk-abcdefghijklmnopqrstuvwx",
+            "nested": ["bob@example.org", 5],
+        }));
+        redactor.redact(&mut entry);
+
+        assert_eq!(
+            input(&entry),
+            &json!({
+                "email": "Write to [EMAIL] today",
+                "phone": "Call [PHONE] or [PHONE]",
+                "card": "Card [CREDIT_CARD], order 1234567890123",
+                "key": "Use [API_KEY]",
+                "nested": ["[EMAIL]", 5],
+            })
+        );
+    }
+
+    #[test]
+    fn rules_and_masks() {
+        let redactor = Redactor::new(&RedactionConfig {
+            default: RedactionPolicy {
+                rules: vec![
+                    RedactionRule {
+                        pattern: r"ACCT-\d+".to_string(),
+                        replacement: None,
+                    },
+                    RedactionRule {
+                        pattern: r"(?<label>patient) \w+".to_string(),
+                        replacement: Some("$label [NAME]".to_string()),
+                    },
+                ],
+                masks: vec![
+                    FieldMask {
+                        target: MaskTarget::Input,
+                        path: "$.user.ssn".to_string(),
+                    },
+                    FieldMask {
+                        target: MaskTarget::Input,
+                        path: "$.history[*].text".to_string(),
+                    },
+                    FieldMask {
+                        target: MaskTarget::Output,
+                        path: "$.user".to_string(),
+                    },
+                ],
+                replacement: Some("***".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut entry = step_start(json!({
+            "user": { "ssn": "123-45-6789", "account": "ACCT-991" },
+            "history": [{ "text": "a" }, { "text": "b", "id": 2 }],
+            "note": "Saw patient Smith",
+        }));
+        redactor.redact(&mut entry);
+
+        assert_eq!(
+            input(&entry),
+            &json!({
+                "user": { "ssn": "***", "account": "***" },
+                "history": [{ "text": "***" }, { "text": "***", "id": 2 }],
+                "note": "Saw patient [NAME]",
+            })
+        );
+    }
+
+    #[test]
+    fn application_policies() {
+        let redactor = Redactor::new(&RedactionConfig {
+            default: RedactionPolicy {
+                detectors: vec![Detector::Email],
+                ..Default::default()
+            },
+            applications: [
+                (
+                    "masked".to_string(),
+                    RedactionPolicy {
+                        masks: vec![FieldMask {
+                            target: MaskTarget::Request,
+                            path: "messages[*].content".to_string(),
+                        }],
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "private".to_string(),
+                    RedactionPolicy {
+                        metadata_only: true,
+                        ..Default::default()
+                    },
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        })
+        .unwrap();
+
+        let mut entry = proxied("other", "I am a@b.com");
+        redactor.redact(&mut entry);
+        let req = request(&entry);
+        assert_eq!(req.messages[0].content.as_deref(), Some("I am [EMAIL]"));
+
+        let mut entry = proxied("masked", "I am a@b.com");
+        redactor.redact(&mut entry);
+        let req = request(&entry);
+        assert_eq!(req.messages[0].content.as_deref(), Some("[REDACTED]"));
+        assert_eq!(req.messages[0].role.as_deref(), Some("user"));
+
+        let mut entry = proxied("private", "I am a@b.com");
+        redactor.redact(&mut entry);
+        let req = request(&entry);
+        assert!(req.messages.is_empty());
+        assert_eq!(req.model.as_deref(), Some("the-model"));
+    }
+
+    #[test]
+    fn mask_that_breaks_structure_removes_bodies() {
+        let redactor = Redactor::new(&RedactionConfig {
+            default: RedactionPolicy {
+                masks: vec![FieldMask {
+                    target: MaskTarget::Request,
+                    path: "$.messages".to_string(),
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut entry = proxied("app", "secret");
+        redactor.redact(&mut entry);
+        assert!(request(&entry).messages.is_empty());
+    }
+
+    #[test]
+    fn invalid_config() {
+        let err = Redactor::new(&RedactionConfig {
+            default: RedactionPolicy {
+                rules: vec![RedactionRule {
+                    pattern: "(".to_string(),
+                    replacement: None,
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(matches!(err.current_context(), Error::InvalidRedaction(_)));
+
+        let err = parse_path("$.messages[x]").unwrap_err();
+        assert!(matches!(err.current_context(), Error::InvalidRedaction(_)));
+    }
+
+    #[test]
+    fn parse_log_mode() {
+        assert_eq!(
+            "metadata-only".parse::<LogMode>().unwrap(),
+            LogMode::MetadataOnly
+        );
+        assert_eq!("full".parse::<LogMode>().unwrap(), LogMode::Full);
+        assert!("none".parse::<LogMode>().is_err());
+    }
+}
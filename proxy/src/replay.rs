@@ -0,0 +1,254 @@
+//! Resending logged requests with different settings
+use error_stack::{Report, ResultExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::runs::RunEvent,
+    format::{ChatRequest, SingleChatResponse},
+    CollectedResponse, Error, ProxyRequestOptions,
+};
+
+/// Changes to make to a logged request before replaying it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayOverrides {
+    /// The model or alias to send the request to. Defaults to the model and provider that
+    /// handled the original request.
+    pub model: Option<String>,
+    /// The provider to use with `model`. If omitted, the provider is chosen based on the model.
+    pub provider: Option<String>,
+    /// Replace the sampling temperature
+    pub temperature: Option<f32>,
+    /// Replace the system prompt. This also removes any system messages from the request.
+    pub system: Option<String>,
+}
+
+impl ReplayOverrides {
+    /// Build the request to replay from a logged event, and set the model and `replay_of`
+    /// metadata in the request options.
+    pub fn apply(
+        &self,
+        original: &RunEvent,
+        options: &mut ProxyRequestOptions,
+    ) -> Result<ChatRequest, Report<Error>> {
+        let request = original
+            .chat_request
+            .clone()
+            .filter(|request| !request.is_null())
+            .ok_or(Error::NotReplayable(original.id))?;
+        let mut request: ChatRequest =
+            serde_json::from_value(request).change_context(Error::NotReplayable(original.id))?;
+
+        request.stream = false;
+        request.stream_options = None;
+        if let Some(temperature) = self.temperature {
+            request.temperature = Some(temperature);
+        }
+        if let Some(system) = &self.system {
+            request
+                .messages
+                .retain(|message| message.role.as_deref() != Some("system"));
+            request.system = Some(system.clone());
+        }
+
+        if self.model.is_some() {
+            options.model = self.model.clone();
+            options.provider = self.provider.clone();
+        } else if options.model.is_none() && options.models.is_empty() {
+            options.model = original.model.clone();
+            if options.provider.is_none() {
+                options.provider = original.provider.clone();
+            }
+        }
+        options.metadata.replay_of = Some(original.id);
+
+        Ok(request)
+    }
+}
+
+/// A choice from the original and replayed responses, side by side
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChoiceComparison {
+    pub index: usize,
+    /// The content of the choice in the original response
+    pub original: Option<String>,
+    /// The content of the choice in the replayed response
+    pub replay: Option<String>,
+    /// Whether the content of the choice changed
+    pub changed: bool,
+}
+
+/// The result of [Proxy::replay](crate::Proxy::replay)
+#[derive(Debug, Serialize)]
+pub struct ReplayResult {
+    /// The event that was replayed
+    pub original: RunEvent,
+    /// The new response. `request_info.id` is the ID of the new event.
+    pub replay: CollectedResponse,
+    /// The choices in the original and new responses, side by side
+    pub choices: Vec<ChoiceComparison>,
+}
+
+/// Pair up the choices of a logged response with the choices of a new response
+pub fn compare_choices(
+    original: Option<&serde_json::Value>,
+    replay: &SingleChatResponse,
+) -> Vec<ChoiceComparison> {
+    let original = original
+        .and_then(|response| response["choices"].as_array())
+        .map(|choices| {
+            choices
+                .iter()
+                .map(|choice| choice["message"]["content"].as_str().map(String::from))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let len = original.len().max(replay.choices.len());
+    (0..len)
+        .map(|index| {
+            let original = original.get(index).cloned().flatten();
+            let replay = replay
+                .choices
+                .get(index)
+                .and_then(|choice| choice.message.content.clone());
+            ChoiceComparison {
+                index,
+                changed: original != replay,
+                original,
+                replay,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::format::{ChatChoice, ChatMessage};
+
+    fn event(request: Option<serde_json::Value>) -> RunEvent {
+        RunEvent {
+            id: Uuid::from_u128(1),
+            event_type: Some("chronicle_llm_request".to_string()),
+            step_id: None,
+            provider: Some("openai".to_string()),
+            model: Some("gpt-4o".to_string()),
+            chat_request: request,
+            chat_response: Some(json!({
+                "choices": [
+                    { "index": 0, "message": { "role": "assistant", "content": "Paris" } },
+                    { "index": 1, "message": { "role": "assistant", "content": "Lyon" } },
+                ]
+            })),
+            error: None,
+            meta: None,
+            retries: None,
+            rate_limited: None,
+            request_latency_ms: None,
+            total_latency_ms: None,
+            replay_of: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn request() -> serde_json::Value {
+        json!({
+            "model": "gpt-4o",
+            "stream": true,
+            "temperature": 0.2,
+            "messages": [
+                { "role": "system", "content": "Be brief" },
+                { "role": "user", "content": "What is the capital of France?" },
+            ]
+        })
+    }
+
+    #[test]
+    fn uses_original_model_by_default() {
+        let original = event(Some(request()));
+        let mut options = ProxyRequestOptions::default();
+        let request = ReplayOverrides::default()
+            .apply(&original, &mut options)
+            .unwrap();
+
+        assert!(!request.stream);
+        assert_eq!(request.temperature, Some(0.2));
+        assert_eq!(request.messages.len(), 2);
+        assert_eq!(options.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(options.provider.as_deref(), Some("openai"));
+        assert_eq!(options.metadata.replay_of, Some(original.id));
+    }
+
+    #[test]
+    fn applies_overrides() {
+        let original = event(Some(request()));
+        let mut options = ProxyRequestOptions {
+            provider: Some("groq".to_string()),
+            ..Default::default()
+        };
+        let overrides = ReplayOverrides {
+            model: Some("fast".to_string()),
+            provider: None,
+            temperature: Some(1.0),
+            system: Some("Answer in French".to_string()),
+        };
+        let request = overrides.apply(&original, &mut options).unwrap();
+
+        assert_eq!(request.temperature, Some(1.0));
+        assert_eq!(request.system.as_deref(), Some("Answer in French"));
+        assert_eq!(request.messages.len(), 1);
+        assert_eq!(request.messages[0].role.as_deref(), Some("user"));
+        assert_eq!(options.model.as_deref(), Some("fast"));
+        assert_eq!(options.provider, None);
+    }
+
+    #[test]
+    fn requires_request() {
+        let mut options = ProxyRequestOptions::default();
+        for original in [event(None), event(Some(serde_json::Value::Null))] {
+            let err = ReplayOverrides::default()
+                .apply(&original, &mut options)
+                .expect_err("event without a request");
+            assert!(matches!(err.current_context(), Error::NotReplayable(_)));
+        }
+    }
+
+    #[test]
+    fn compares_choices() {
+        let original = event(None);
+        let replay = SingleChatResponse {
+            choices: vec![ChatChoice {
+                message: ChatMessage {
+                    role: Some("assistant".to_string()),
+                    content: Some("Paris".to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }],
+            ..SingleChatResponse::new_for_collection(1)
+        };
+
+        let choices = compare_choices(original.chat_response.as_ref(), &replay);
+        assert_eq!(
+            choices,
+            vec![
+                ChoiceComparison {
+                    index: 0,
+                    original: Some("Paris".to_string()),
+                    replay: Some("Paris".to_string()),
+                    changed: false,
+                },
+                ChoiceComparison {
+                    index: 1,
+                    original: Some("Lyon".to_string()),
+                    replay: None,
+                    changed: true,
+                },
+            ]
+        );
+    }
+}
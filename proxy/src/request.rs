@@ -10,6 +10,7 @@ use tracing::instrument;
 
 use crate::{
     format::{ChatRequest, StreamingResponseSender},
+    metrics::RequestMetrics,
     provider_lookup::{ModelLookupChoice, ModelLookupResult},
     providers::{ProviderError, ProviderErrorKind, SendRequestOptions},
 };
@@ -66,6 +67,13 @@ impl Default for RetryOptions {
     }
 }
 
+impl RetryOptions {
+    /// The number of times to try, including the first try.
+    pub(crate) fn max_tries(&self) -> u32 {
+        self.max_tries
+    }
+}
+
 const fn default_max_tries() -> u32 {
     4
 }
@@ -122,13 +130,13 @@ impl RepeatBackoffBehavior {
     }
 }
 
-struct BackoffValue<'a> {
+pub(crate) struct BackoffValue<'a> {
     next_backoff: Duration,
     options: &'a RetryOptions,
 }
 
 impl<'a> BackoffValue<'a> {
-    fn new(options: &'a RetryOptions) -> Self {
+    pub(crate) fn new(options: &'a RetryOptions) -> Self {
         Self {
             next_backoff: options.initial_backoff,
             options,
@@ -136,7 +144,7 @@ impl<'a> BackoffValue<'a> {
     }
 
     /// Return the next duration to wait for
-    fn next(&mut self) -> Duration {
+    pub(crate) fn next(&mut self) -> Duration {
         let mut backoff = self.next_backoff;
         self.next_backoff = self.options.increase.next(backoff);
 
@@ -167,12 +175,16 @@ pub struct TryModelChoicesResult {
 #[derive(Debug)]
 pub struct TryModelChoicesError {
     pub error: Report<ProviderError>,
+    /// The provider used for the last attempt
+    pub provider: String,
+    /// The model used for the last attempt
+    pub model: String,
     pub num_retries: u32,
     pub was_rate_limited: bool,
 }
 
 /// Run a provider request and retry on failure.
-#[instrument(level = "debug")]
+#[instrument(level = "debug", skip(metrics))]
 pub async fn try_model_choices(
     ModelLookupResult {
         alias,
@@ -184,6 +196,7 @@ pub async fn try_model_choices(
     timeout: Duration,
     request: ChatRequest,
     chunk_tx: StreamingResponseSender,
+    metrics: Option<&RequestMetrics>,
 ) -> Result<TryModelChoicesResult, TryModelChoicesError> {
     let single_choice = choices.len() == 1;
     let start_choice = if random_order && !single_choice {
@@ -250,6 +263,15 @@ pub async fn try_model_choices(
             was_rate_limited = true;
         }
 
+        if let Some(metrics) = metrics {
+            metrics.record_try(
+                provider_name,
+                model,
+                start_time.elapsed(),
+                provider_error.map(|e| &e.kind),
+            );
+        }
+
         // If we don't have any more fallback models, and this error is not retryable, then exit.
         if current_try == options.max_tries
             || (on_final_model_choice
@@ -257,6 +279,8 @@ pub async fn try_model_choices(
         {
             return Err(TryModelChoicesError {
                 error,
+                provider: provider_name.to_string(),
+                model: model.to_string(),
                 num_retries: current_try - 1,
                 was_rate_limited,
             });
@@ -290,6 +314,8 @@ pub async fn try_model_choices(
                         // Rate limited with a retry time that exceeds max backoff.
                         return Err(TryModelChoicesError {
                             error,
+                            provider: provider_name.to_string(),
+                            model: model.to_string(),
                             num_retries: current_try - 1,
                             was_rate_limited,
                         });
@@ -436,6 +462,7 @@ mod test {
                 ..Default::default()
             },
             chunk_tx,
+            None,
         )
         .await?;
         Ok((res, chunk_rx))
@@ -10,6 +10,7 @@ use crate::{
         RequestInfo, ResponseInfo, SingleChatResponse, StreamingResponse,
         StreamingResponseReceiver, StreamingResponseSender,
     },
+    metrics::RequestMetrics,
     request::TryModelChoicesResult,
     Error,
 };
@@ -23,6 +24,7 @@ pub async fn handle_response(
     chunk_rx: StreamingResponseReceiver,
     output_tx: StreamingResponseSender,
     log_tx: Option<LogSender>,
+    metrics: RequestMetrics,
 ) {
     let response = collect_stream(
         current_span.clone(),
@@ -33,6 +35,7 @@ pub async fn handle_response(
         chunk_rx,
         output_tx,
         log_tx.as_ref(),
+        &metrics,
     )
     .await;
     let Ok((response, info, mut log_entry)) = response else {
@@ -78,6 +81,15 @@ pub async fn handle_response(
         .unwrap_or_else(|| usage.prompt_tokens.unwrap_or(0) + usage.completion_tokens.unwrap_or(0));
     current_span.record("llm.usage.total_tokens", total_tokens);
 
+    metrics.record_try(&meta.provider, &meta.model, this_send_time, None);
+    metrics.record_success(
+        &meta.provider,
+        &meta.model,
+        global_send_time,
+        meta.num_retries,
+        response.usage.as_ref(),
+    );
+
     if let Some(log_tx) = log_tx {
         log_entry.total_latency = Some(global_send_time);
         log_entry.num_retries = Some(meta.num_retries);
@@ -89,9 +101,8 @@ pub async fn handle_response(
         });
 
         log_tx
-            .send_async(smallvec![ProxyLogEntry::Proxied(Box::new(log_entry))])
-            .await
-            .ok();
+            .send(smallvec![ProxyLogEntry::Proxied(Box::new(log_entry))])
+            .await;
     }
 }
 
@@ -105,8 +116,10 @@ async fn collect_stream(
     chunk_rx: StreamingResponseReceiver,
     output_tx: StreamingResponseSender,
     log_tx: Option<&LogSender>,
+    metrics: &RequestMetrics,
 ) -> Result<(SingleChatResponse, ResponseInfo, ProxyLogEvent), ()> {
     let mut response = SingleChatResponse::new_for_collection(request_n);
+    let mut seen_first_token = false;
 
     let mut res_stats = ResponseInfo {
         model: String::new(),
@@ -116,6 +129,20 @@ async fn collect_stream(
     // Collect the message chunks so we can log the result, while also passing them on to the output channel.
     while let Some(chunk) = chunk_rx.recv_async().await.ok() {
         tracing::info!(?chunk, "Got chunk");
+        if !seen_first_token
+            && matches!(
+                chunk,
+                Ok(StreamingResponse::Chunk(_)) | Ok(StreamingResponse::Single(_))
+            )
+        {
+            seen_first_token = true;
+            metrics.record_time_to_first_token(
+                &meta.provider,
+                &meta.model,
+                meta.start_time.elapsed(),
+            );
+        }
+
         match &chunk {
             Ok(StreamingResponse::Chunk(chunk)) => {
                 response.merge_delta(chunk);
@@ -131,6 +158,19 @@ async fn collect_stream(
                 response = res.clone();
             }
             Err(e) => {
+                metrics.record_try(
+                    &meta.provider,
+                    &meta.model,
+                    meta.start_time.elapsed(),
+                    Some(&e.current_context().kind),
+                );
+                metrics.record_failure(
+                    &meta.provider,
+                    &meta.model,
+                    global_start.elapsed(),
+                    meta.num_retries,
+                    &e.current_context().kind,
+                );
                 record_error(
                     log_entry,
                     e,
@@ -174,9 +214,8 @@ pub async fn record_error<E: std::fmt::Debug + std::fmt::Display>(
         log_entry.was_rate_limited = Some(was_rate_limited);
         log_entry.error = Some(json!(format!("{:?}", error)));
         log_tx
-            .send_async(smallvec![ProxyLogEntry::Proxied(Box::new(log_entry))])
-            .await
-            .ok();
+            .send(smallvec![ProxyLogEntry::Proxied(Box::new(log_entry))])
+            .await;
     }
 }
 
@@ -0,0 +1,477 @@
+//! Checks for problems in the proxy configuration
+use std::{collections::HashSet, sync::Arc};
+
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{AliasConfig, ApiKeyConfig, CustomProviderConfig},
+    prompts::Prompt,
+    providers::ChatModelProvider,
+};
+
+/// How [ProxyBuilder::build](crate::builder::ProxyBuilder::build) handles problems in the
+/// configuration
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationStrictness {
+    /// Don't report problems
+    Ignore,
+    /// Log a warning for each problem, and build the proxy anyway. API keys that can not be
+    /// loaded are skipped.
+    #[default]
+    Warn,
+    /// Fail to build the proxy if there are any problems
+    Error,
+}
+
+/// The kind of a named configuration item
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigItemKind {
+    Provider,
+    Alias,
+    ApiKey,
+    Prompt,
+}
+
+impl std::fmt::Display for ConfigItemKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ConfigItemKind::Provider => "provider",
+            ConfigItemKind::Alias => "alias",
+            ConfigItemKind::ApiKey => "API key",
+            ConfigItemKind::Prompt => "prompt",
+        };
+
+        f.write_str(s)
+    }
+}
+
+/// A problem found in the proxy configuration
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConfigProblem {
+    /// An alias references a provider that doesn't exist
+    AliasMissingProvider { alias: String, provider: String },
+    /// An alias references an API key that doesn't exist
+    AliasMissingApiKey { alias: String, api_key: String },
+    /// An alias does not have any models
+    AliasEmpty { alias: String },
+    /// A custom provider's URL could not be parsed
+    InvalidProviderUrl {
+        provider: String,
+        url: String,
+        error: String,
+    },
+    /// A custom provider has a header that can not be sent in an HTTP request. The header will
+    /// be omitted from requests.
+    InvalidProviderHeader { provider: String, header: String },
+    /// An API key is read from an environment variable which is not set
+    MissingApiKeyEnv { api_key: String, variable: String },
+    /// A custom provider reads its API key from an environment variable which is not set
+    MissingProviderApiKeyEnv { provider: String, variable: String },
+    /// Models starting with `prefix` could be sent to either provider by default
+    OverlappingPrefix {
+        provider: String,
+        other_provider: String,
+        prefix: String,
+    },
+    /// More than one item of the same kind has this name. Only the last one is used.
+    DuplicateName { kind: ConfigItemKind, name: String },
+    /// More than one version of a prompt has the same label. The newest version is used.
+    DuplicatePromptLabel { prompt: String, label: String },
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigProblem::AliasMissingProvider { alias, provider } => {
+                write!(f, "Alias {alias} references nonexistent provider {provider}")
+            }
+            ConfigProblem::AliasMissingApiKey { alias, api_key } => {
+                write!(f, "Alias {alias} references nonexistent API key {api_key}")
+            }
+            ConfigProblem::AliasEmpty { alias } => write!(f, "Alias {alias} has no models"),
+            ConfigProblem::InvalidProviderUrl {
+                provider,
+                url,
+                error,
+            } => write!(f, "Provider {provider} has invalid URL {url}: {error}"),
+            ConfigProblem::InvalidProviderHeader { provider, header } => {
+                write!(f, "Provider {provider} has invalid header {header}")
+            }
+            ConfigProblem::MissingApiKeyEnv { api_key, variable } => write!(
+                f,
+                "API key {api_key} reads from environment variable {variable}, which is not set"
+            ),
+            ConfigProblem::MissingProviderApiKeyEnv { provider, variable } => write!(
+                f,
+                "Provider {provider} reads its API key from environment variable {variable}, which is not set"
+            ),
+            ConfigProblem::OverlappingPrefix {
+                provider,
+                other_provider,
+                prefix,
+            } => write!(
+                f,
+                "Provider {provider} has prefix {prefix}, which overlaps with provider {other_provider}"
+            ),
+            ConfigProblem::DuplicateName { kind, name } => {
+                write!(f, "Found multiple {kind} configurations named {name}")
+            }
+            ConfigProblem::DuplicatePromptLabel { prompt, label } => {
+                write!(f, "Prompt {prompt} has multiple versions labeled {label}")
+            }
+        }
+    }
+}
+
+fn env_var_missing(name: &str) -> bool {
+    std::env::var(name).is_err()
+}
+
+/// Check custom provider configurations for unparsable URLs and headers, and missing environment
+/// variables.
+pub(crate) fn check_custom_providers(configs: &[CustomProviderConfig]) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+
+    for config in configs {
+        if let Err(e) = url::Url::parse(&config.url) {
+            problems.push(ConfigProblem::InvalidProviderUrl {
+                provider: config.name.clone(),
+                url: config.url.clone(),
+                error: e.to_string(),
+            });
+        }
+
+        for (name, value) in &config.headers {
+            if HeaderName::from_bytes(name.as_bytes()).is_err()
+                || HeaderValue::from_str(value).is_err()
+            {
+                problems.push(ConfigProblem::InvalidProviderHeader {
+                    provider: config.name.clone(),
+                    header: name.clone(),
+                });
+            }
+        }
+
+        if config.api_key_source.as_deref() == Some("env") {
+            if let Some(var) = config.api_key.as_deref().filter(|v| env_var_missing(v)) {
+                problems.push(ConfigProblem::MissingProviderApiKeyEnv {
+                    provider: config.name.clone(),
+                    variable: var.to_string(),
+                });
+            }
+        }
+    }
+
+    problems
+}
+
+/// Check that environment variables referenced by API keys exist
+pub(crate) fn check_api_keys(api_keys: &[ApiKeyConfig]) -> Vec<ConfigProblem> {
+    api_keys
+        .iter()
+        .filter(|key| key.source == "env" && env_var_missing(&key.value))
+        .map(|key| ConfigProblem::MissingApiKeyEnv {
+            api_key: key.name.clone(),
+            variable: key.value.clone(),
+        })
+        .collect()
+}
+
+/// Find names which are used by more than one item of the same kind. Only custom providers are
+/// checked, since a custom provider can replace a built-in provider by using its name.
+pub(crate) fn check_duplicate_names(
+    providers: &[CustomProviderConfig],
+    aliases: &[AliasConfig],
+    api_keys: &[ApiKeyConfig],
+) -> Vec<ConfigProblem> {
+    fn find_duplicates<'a>(
+        kind: ConfigItemKind,
+        names: impl Iterator<Item = &'a str>,
+        problems: &mut Vec<ConfigProblem>,
+    ) {
+        let mut seen = HashSet::new();
+        let mut reported = HashSet::new();
+        for name in names {
+            if !seen.insert(name) && reported.insert(name) {
+                problems.push(ConfigProblem::DuplicateName {
+                    kind,
+                    name: name.to_string(),
+                });
+            }
+        }
+    }
+
+    let mut problems = Vec::new();
+    find_duplicates(
+        ConfigItemKind::Provider,
+        providers.iter().map(|p| p.name.as_str()),
+        &mut problems,
+    );
+    find_duplicates(
+        ConfigItemKind::Alias,
+        aliases.iter().map(|a| a.name.as_str()),
+        &mut problems,
+    );
+    find_duplicates(
+        ConfigItemKind::ApiKey,
+        api_keys.iter().map(|k| k.name.as_str()),
+        &mut problems,
+    );
+    problems
+}
+
+/// Find prompt versions that are configured more than once, and labels that point to more than
+/// one version of a prompt.
+pub(crate) fn check_prompts(prompts: &[Prompt]) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    let mut versions = HashSet::new();
+    let mut labels = HashSet::new();
+    let mut reported = HashSet::new();
+
+    for prompt in prompts {
+        if !versions.insert((prompt.id.as_str(), prompt.version))
+            && reported.insert((prompt.id.as_str(), None, prompt.version))
+        {
+            problems.push(ConfigProblem::DuplicateName {
+                kind: ConfigItemKind::Prompt,
+                name: format!("{} v{}", prompt.id, prompt.version),
+            });
+        }
+
+        for label in &prompt.labels {
+            if !labels.insert((prompt.id.as_str(), label.as_str()))
+                && reported.insert((prompt.id.as_str(), Some(label.as_str()), 0))
+            {
+                problems.push(ConfigProblem::DuplicatePromptLabel {
+                    prompt: prompt.id.clone(),
+                    label: label.clone(),
+                });
+            }
+        }
+    }
+
+    problems
+}
+
+/// Find providers which would also be the default for models that start with another provider's
+/// prefix. `prefixes` contains the name and prefix of each provider that has one.
+pub(crate) fn check_prefixes(
+    prefixes: &[(String, String)],
+    providers: &[Arc<dyn ChatModelProvider>],
+) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    let mut reported = HashSet::new();
+
+    for (name, prefix) in prefixes {
+        for other in providers {
+            let other_name = other.name();
+            if other_name == name || !other.is_default_for_model(prefix) {
+                continue;
+            }
+
+            // Two providers with the same prefix would match each other, so only report each pair
+            // once.
+            let pair = if name.as_str() < other_name {
+                (name.as_str(), other_name)
+            } else {
+                (other_name, name.as_str())
+            };
+            if !reported.insert(pair) {
+                continue;
+            }
+
+            problems.push(ConfigProblem::OverlappingPrefix {
+                provider: name.clone(),
+                other_provider: other_name.to_string(),
+                prefix: prefix.clone(),
+            });
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use super::{
+        check_custom_providers, check_duplicate_names, check_prefixes, check_prompts, ConfigProblem,
+    };
+    use crate::{
+        config::{AliasConfig, CustomProviderConfig},
+        prompts::Prompt,
+        providers::ChatModelProvider,
+        validate::ConfigItemKind,
+    };
+
+    fn custom_provider(name: &str, url: &str, prefix: Option<&str>) -> CustomProviderConfig {
+        CustomProviderConfig {
+            name: name.to_string(),
+            label: None,
+            url: url.to_string(),
+            api_key: None,
+            api_key_source: None,
+            format: Default::default(),
+            headers: BTreeMap::new(),
+            prefix: prefix.map(|p| p.to_string()),
+        }
+    }
+
+    fn providers(configs: &[CustomProviderConfig]) -> Vec<Arc<dyn ChatModelProvider>> {
+        let client = reqwest::Client::new();
+        configs
+            .iter()
+            .map(|c| {
+                Arc::new(c.clone().into_provider(client.clone())) as Arc<dyn ChatModelProvider>
+            })
+            .chain(std::iter::once(
+                Arc::new(crate::providers::openai::OpenAi::new(client.clone(), None))
+                    as Arc<dyn ChatModelProvider>,
+            ))
+            .collect()
+    }
+
+    #[test]
+    fn custom_provider_problems() {
+        let mut bad = custom_provider("bad", "not a url", None);
+        bad.headers
+            .insert("bad header".to_string(), "value".to_string());
+        bad.headers
+            .insert("good-header".to_string(), "value".to_string());
+        bad.api_key = Some("CHRONICLE_TEST_VAR_THAT_DOES_NOT_EXIST".to_string());
+        bad.api_key_source = Some("env".to_string());
+
+        let good = custom_provider("good", "https://example.com/v1/chat/completions", None);
+
+        let problems = check_custom_providers(&[bad, good]);
+        assert_eq!(problems.len(), 3, "{problems:#?}");
+        assert!(matches!(
+            &problems[0],
+            ConfigProblem::InvalidProviderUrl { provider, .. } if provider == "bad"
+        ));
+        assert_eq!(
+            problems[1],
+            ConfigProblem::InvalidProviderHeader {
+                provider: "bad".to_string(),
+                header: "bad header".to_string()
+            }
+        );
+        assert_eq!(
+            problems[2],
+            ConfigProblem::MissingProviderApiKeyEnv {
+                provider: "bad".to_string(),
+                variable: "CHRONICLE_TEST_VAR_THAT_DOES_NOT_EXIST".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn overlapping_prefixes() {
+        let configs = [
+            custom_provider("a", "https://example.com", Some("model-")),
+            custom_provider("b", "https://example.com", Some("model-b-")),
+            custom_provider("c", "https://example.com", Some("model-")),
+            custom_provider("d", "https://example.com", Some("other-")),
+            // Overlaps with the OpenAI provider
+            custom_provider("e", "https://example.com", Some("gpt-4o-custom")),
+        ];
+
+        let prefixes = configs
+            .iter()
+            .filter_map(|c| Some((c.name.clone(), c.prefix.clone()?)))
+            .collect::<Vec<_>>();
+        let problems = check_prefixes(&prefixes, &providers(&configs));
+
+        let mut pairs = problems
+            .iter()
+            .map(|p| match p {
+                ConfigProblem::OverlappingPrefix {
+                    provider,
+                    other_provider,
+                    ..
+                } => {
+                    let mut pair = [provider.as_str(), other_provider.as_str()];
+                    pair.sort();
+                    pair
+                }
+                _ => panic!("Unexpected problem {p:?}"),
+            })
+            .collect::<Vec<_>>();
+        pairs.sort();
+
+        assert_eq!(
+            pairs,
+            vec![["a", "b"], ["a", "c"], ["b", "c"], ["e", "openai"]]
+        );
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let configs = [
+            custom_provider("a", "https://example.com", None),
+            custom_provider("a", "https://example.com", None),
+            custom_provider("a", "https://example.com", None),
+            custom_provider("openai", "https://example.com", None),
+        ];
+
+        let alias = AliasConfig {
+            name: "alias".to_string(),
+            random_order: false,
+            models: vec![],
+        };
+
+        // The custom openai provider replaces the built-in one, so it isn't a duplicate.
+        let problems = check_duplicate_names(&configs, &[alias.clone(), alias], &[]);
+        assert_eq!(
+            problems,
+            vec![
+                ConfigProblem::DuplicateName {
+                    kind: ConfigItemKind::Provider,
+                    name: "a".to_string()
+                },
+                ConfigProblem::DuplicateName {
+                    kind: ConfigItemKind::Alias,
+                    name: "alias".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_prompts() {
+        let prompt = |id: &str, version: u32, labels: &[&str]| Prompt {
+            id: id.to_string(),
+            version,
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            description: None,
+            template: Default::default(),
+            created_at: None,
+        };
+
+        let problems = check_prompts(&[
+            prompt("a", 1, &["production"]),
+            prompt("a", 2, &["production", "staging"]),
+            prompt("a", 2, &[]),
+            prompt("a", 3, &["production"]),
+            prompt("b", 1, &["production", "staging"]),
+        ]);
+        assert_eq!(
+            problems,
+            vec![
+                ConfigProblem::DuplicatePromptLabel {
+                    prompt: "a".to_string(),
+                    label: "production".to_string()
+                },
+                ConfigProblem::DuplicateName {
+                    kind: ConfigItemKind::Prompt,
+                    name: "a v2".to_string()
+                },
+            ]
+        );
+    }
+}
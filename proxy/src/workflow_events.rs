@@ -1,13 +1,17 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, str::FromStr};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{ProxyRequestInternalMetadata, ProxyRequestMetadata};
+use crate::{Error, ProxyRequestInternalMetadata, ProxyRequestMetadata};
 
 /// Type-specific data for an event.
-#[derive(Debug, Deserialize)]
+///
+/// The events for a run or step may be recorded in any order. An event for a run or step that
+/// hasn't started yet creates a placeholder, which the start event fills in, and fields set by
+/// more than one event take their value from the newest one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum WorkflowEvent {
     #[serde(rename = "run:start")]
@@ -26,11 +30,14 @@ pub enum WorkflowEvent {
     /// Event data for a DAG node state change.
     #[serde(rename = "step:state")]
     StepState(StepEventData<StepStateData>),
+    /// A score or piece of feedback for an LLM call, step, or run.
+    #[serde(rename = "score")]
+    Score(ScoreEvent),
     #[serde(untagged)]
     Event(EventPayload),
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EventPayload {
     #[serde(rename = "type")]
     pub typ: String,
@@ -41,10 +48,30 @@ pub struct EventPayload {
     pub time: Option<DateTime<Utc>>,
     #[serde(skip_deserializing)]
     pub internal_metadata: Option<ProxyRequestInternalMetadata>,
+    /// A UUID for the event, which is used as its ID in the database. An event that is sent again
+    /// with the same ID is only recorded once.
+    pub event_id: Option<Uuid>,
+}
+
+impl EventPayload {
+    /// The time of the event
+    pub fn event_time(&self) -> DateTime<Utc> {
+        event_time(self.time, self.event_id)
+    }
+}
+
+/// The time of a workflow event. When the event has no time, the timestamp of a UUIDv7 event ID
+/// is used instead, so that an event which is sent again has the same time as the original.
+pub fn event_time(time: Option<DateTime<Utc>>, event_id: Option<Uuid>) -> DateTime<Utc> {
+    time.or_else(|| {
+        let (secs, nanos) = event_id?.get_timestamp()?.to_unix();
+        DateTime::from_timestamp(secs as i64, nanos)
+    })
+    .unwrap_or_else(Utc::now)
 }
 
 /// An event that starts a run in a workflow.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RunStartEvent {
     pub id: Uuid,
     pub name: String,
@@ -58,11 +85,20 @@ pub struct RunStartEvent {
     pub status: Option<String>,
     #[serde(default)]
     pub tags: Vec<String>,
+    /// Extra info for the run. A numeric `stale_timeout` key sets how many seconds the run can
+    /// go without updates before it is marked as timed out, overriding the proxy configuration.
     pub info: Option<serde_json::Value>,
     pub time: Option<DateTime<chrono::Utc>>,
+    /// A UUIDv7 identifying this event. If `time` is omitted, the time is taken from this ID.
+    pub event_id: Option<Uuid>,
 }
 
 impl RunStartEvent {
+    /// The time of the event
+    pub fn event_time(&self) -> DateTime<Utc> {
+        event_time(self.time, self.event_id)
+    }
+
     /// Merge metadata into the event.
     pub fn merge_metadata(&mut self, other: &ProxyRequestMetadata) {
         if self.application.is_none() {
@@ -140,7 +176,7 @@ impl RunStartEvent {
 }
 
 /// An event that updates a run in a workflow.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RunUpdateEvent {
     /// The run ID
     pub id: Uuid,
@@ -150,10 +186,19 @@ pub struct RunUpdateEvent {
     /// Extra info for the run. This is merged with any existing info.
     pub info: Option<serde_json::Value>,
     pub time: Option<DateTime<chrono::Utc>>,
+    /// A UUIDv7 identifying this event. If `time` is omitted, the time is taken from this ID.
+    pub event_id: Option<Uuid>,
+}
+
+impl RunUpdateEvent {
+    /// The time of the event
+    pub fn event_time(&self) -> DateTime<Utc> {
+        event_time(self.time, self.event_id)
+    }
 }
 
 /// An event that updates a run or step in a workflow.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StepEventData<DATA> {
     /// A UUIDv7 identifying the step the event belongs to
     pub step_id: Uuid,
@@ -162,6 +207,15 @@ pub struct StepEventData<DATA> {
     /// The event's type and data
     pub data: DATA,
     pub time: Option<DateTime<chrono::Utc>>,
+    /// A UUIDv7 identifying this event. If `time` is omitted, the time is taken from this ID.
+    pub event_id: Option<Uuid>,
+}
+
+impl<DATA> StepEventData<DATA> {
+    /// The time of the event
+    pub fn event_time(&self) -> DateTime<Utc> {
+        event_time(self.time, self.event_id)
+    }
 }
 
 /// Data structure for the start of a step.
@@ -208,8 +262,112 @@ pub struct StepStateData {
     pub state: String,
 }
 
+/// A score for an LLM call, step, or run, such as a thumbs up from a user or the result of an
+/// automated evaluation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoreEvent {
+    /// A UUID for the score. If omitted, one is generated. Scores with the same ID are only
+    /// recorded once.
+    pub id: Option<Uuid>,
+    /// The run that the score is for. If omitted, this is filled in from the step or LLM call
+    /// when they are already in the database.
+    pub run_id: Option<Uuid>,
+    /// The step that the score is for
+    pub step_id: Option<Uuid>,
+    /// The LLM call that the score is for, as returned in `meta.id` of a proxied response
+    pub event_id: Option<Uuid>,
+    /// What the score measures, such as `helpfulness` or `thumbs_up`
+    pub name: String,
+    pub value: ScoreValue,
+    pub source: ScoreSource,
+    pub comment: Option<String>,
+    pub time: Option<DateTime<chrono::Utc>>,
+}
+
+impl ScoreEvent {
+    /// Check that the score is attached to a run, step, or LLM call.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.run_id.is_none() && self.step_id.is_none() && self.event_id.is_none() {
+            return Err(Error::InvalidScore(
+                "A score needs a run_id, step_id, or event_id".to_string(),
+            ));
+        }
+
+        if let ScoreValue::Numeric(n) = self.value {
+            if !n.is_finite() {
+                return Err(Error::InvalidScore(format!(
+                    "Score value {n} is not finite"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The value of a score
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScoreValue {
+    /// A pass/fail or thumbs up/down score
+    Boolean(bool),
+    /// A number, such as a rating or a similarity metric
+    Numeric(f64),
+    /// A label, such as `correct` or `off_topic`
+    Categorical(String),
+}
+
+impl ScoreValue {
+    /// The value as a number, with booleans counting as 1 or 0. Categorical values have no
+    /// number.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ScoreValue::Boolean(b) => Some(if *b { 1.0 } else { 0.0 }),
+            ScoreValue::Numeric(n) => Some(*n),
+            ScoreValue::Categorical(_) => None,
+        }
+    }
+}
+
+/// Where a score came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoreSource {
+    /// Feedback from a person, such as an end user or a reviewer
+    Human,
+    /// A judgement from another model
+    Model,
+    /// A rule or metric computed by code
+    Heuristic,
+}
+
+impl ScoreSource {
+    /// The name of the source, as it is stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScoreSource::Human => "human",
+            ScoreSource::Model => "model",
+            ScoreSource::Heuristic => "heuristic",
+        }
+    }
+}
+
+impl FromStr for ScoreSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ScoreSource::Human),
+            "model" => Ok(ScoreSource::Model),
+            "heuristic" => Ok(ScoreSource::Heuristic),
+            _ => Err(Error::InvalidScore(format!("Unknown score source {s}"))),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use chrono::TimeZone;
     use serde_json::json;
 
     use super::*;
@@ -461,4 +619,92 @@ mod tests {
             "2023-06-28T12:00:00+00:00"
         );
     }
+
+    #[test]
+    fn test_workflow_event_score_deserialization() {
+        let json_data = json!({
+            "type": "score",
+            "event_id": "01234567-89ab-cdef-0123-456789abcdef",
+            "name": "thumbs_up",
+            "value": true,
+            "source": "human",
+            "comment": "Great answer"
+        });
+
+        let event: WorkflowEvent = serde_json::from_value(json_data).unwrap();
+        let WorkflowEvent::Score(event) = event else {
+            panic!("Expected Score event");
+        };
+
+        assert_eq!(
+            event.event_id.unwrap().to_string(),
+            "01234567-89ab-cdef-0123-456789abcdef"
+        );
+        assert_eq!(event.run_id, None);
+        assert_eq!(event.name, "thumbs_up");
+        assert_eq!(event.value, ScoreValue::Boolean(true));
+        assert_eq!(event.source, ScoreSource::Human);
+        assert_eq!(event.comment.as_deref(), Some("Great answer"));
+        event.validate().unwrap();
+
+        let values = [
+            (json!(0.75), ScoreValue::Numeric(0.75)),
+            (json!(4), ScoreValue::Numeric(4.0)),
+            (
+                json!("off_topic"),
+                ScoreValue::Categorical("off_topic".to_string()),
+            ),
+        ];
+        for (value, expected) in values {
+            let event: WorkflowEvent = serde_json::from_value(json!({
+                "type": "score",
+                "run_id": "01234567-89ab-cdef-0123-456789abcdef",
+                "name": "quality",
+                "value": value,
+                "source": "model",
+            }))
+            .unwrap();
+            let WorkflowEvent::Score(event) = event else {
+                panic!("Expected Score event");
+            };
+            assert_eq!(event.value, expected);
+        }
+
+        let untargeted = ScoreEvent {
+            event_id: None,
+            ..event
+        };
+        untargeted.validate().expect_err("score without a target");
+    }
+
+    #[test]
+    fn test_event_time_from_event_id() {
+        let id = Uuid::new_v7(uuid::Timestamp::from_unix(
+            uuid::NoContext,
+            1_700_000_000,
+            123_000_000,
+        ));
+        let event: WorkflowEvent = serde_json::from_value(json!({
+            "type": "run:update",
+            "id": "01234567-89ab-cdef-0123-456789abcdef",
+            "status": "finished",
+            "event_id": id,
+        }))
+        .unwrap();
+        let WorkflowEvent::RunUpdate(event) = event else {
+            panic!("Expected RunUpdate event");
+        };
+
+        assert_eq!(
+            event.event_time(),
+            Utc.timestamp_opt(1_700_000_000, 123_000_000).unwrap()
+        );
+
+        let time = Utc.timestamp_opt(5, 0).unwrap();
+        assert_eq!(
+            event_time(Some(time), Some(id)),
+            time,
+            "time takes precedence"
+        );
+    }
 }